@@ -0,0 +1,173 @@
+// Benchmarks of the pieces of the renderer that the acceleration structures
+// affect: intersecting each kind of shape, building and traversing the BVH,
+// and small renders of fixed scenes (run with `cargo bench`)
+
+#[macro_use]
+extern crate criterion;
+extern crate raytracer;
+
+use criterion::Criterion;
+use criterion::black_box;
+
+use raytracer::vector::Vector;
+use raytracer::vector::Float;
+use raytracer::color::Color;
+use raytracer::ray::Ray;
+use raytracer::shape::Shape;
+use raytracer::shape::Sphere;
+use raytracer::shape::Plane;
+use raytracer::shape::Triangle;
+use raytracer::shape::Quad;
+use raytracer::sphere_list::SphereList;
+use raytracer::material::Material;
+use raytracer::material::Lambertian;
+use raytracer::material::Metallic;
+use raytracer::material::Dielectric;
+use raytracer::primitive::Primitive;
+use raytracer::scene::Scene;
+use raytracer::builder::SceneBuilder;
+use raytracer::scenes;
+use raytracer::camera::Camera;
+use raytracer::integrator::trace;
+use raytracer::rng;
+
+use std::sync::Arc;
+
+// A fan of rays from the origin towards -z, some of which hit the shapes
+// below and some of which miss
+fn rays(count: usize) -> Vec<Ray> {
+    (0..count)
+        .map(|i| {
+            let x = (i % 32) as Float / 16.0 - 1.0;
+            let y = (i / 32 % 32) as Float / 16.0 - 1.0;
+            Ray::new(&Vector::zero(), &Vector::new(x, y, -1.0), 0.001, Float::MAX)
+        })
+        .collect()
+}
+
+// The scene that `main` renders: a box of planes around seven metal spheres
+fn spheres_scene() -> Scene {
+    let mut builder = SceneBuilder::new()
+        .define_material("white", Lambertian::new(&Color::white()))
+        .plane(&Vector::new(0.0, -0.6, 0.0), &Vector::new(0.0, 1.0, 0.0))
+        .material_named("white")
+        .plane(&Vector::new(1.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0))
+        .material(Lambertian::new(&Color::new(1.0, 0.0, 0.0)))
+        .plane(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(-1.0, 0.0, 0.0))
+        .material(Lambertian::new(&Color::new(0.0, 1.0, 0.0)))
+        .plane(&Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 0.0, -1.0))
+        .material_named("white");
+    for i in 0..7 {
+        let pct = i as Float / 7.0;
+        let x = pct * 2.0 - 1.0;
+        builder = builder.sphere(&Vector::new(x + 0.05, 0.0, -1.0), (pct * 0.5 + 0.1) * 0.25)
+            .material(Metallic::new(&Color::white(), x));
+    }
+    builder.build().unwrap()
+}
+
+// A grid of small spheres (alternately diffuse, metal, and glass), for
+// measuring the BVH
+fn grid_scene(side: usize) -> Scene {
+    let mut scene = Scene::new();
+    let materials: Vec<Arc<dyn Material>> = vec![Arc::new(Lambertian::new(&Color::new(0.8, 0.3, 0.3))),
+                                                 Arc::new(Metallic::new(&Color::white(), 0.1)),
+                                                 Arc::new(Dielectric::new(1.5))];
+    let spacing = 2.0 / side as Float;
+    for i in 0..side * side {
+        let x = (i % side) as Float * spacing - 1.0;
+        let y = (i / side) as Float * spacing - 1.0;
+        let z = -2.0 - ((i * 7) % 5) as Float * spacing;
+        scene.add(Primitive::new(Sphere::new(&Vector::new(x, y, z), spacing * 0.4), materials[i % 3].clone()));
+    }
+    scene.build_bvh();
+    scene
+}
+
+// Render a small image, one sample per pixel, on the calling thread, from
+// the scene's first camera (if it has one)
+fn render(scene: &Scene, width: u32, height: u32) -> Color {
+    let camera = scene.cameras.first().map(|&(_, camera)| camera)
+        .unwrap_or_else(|| Camera::new(60.0, width as Float / height as Float));
+    let mut sum = Color::black();
+    for y in 0..height {
+        for x in 0..width {
+            rng::reseed(y * width + x);
+            let u = (x as Float + 0.5) / width as Float;
+            let v = (height as Float - (y as Float + 0.5)) / height as Float;
+            sum += trace(&camera.generate_ray(u, v), scene, 0, 5, None);
+        }
+    }
+    sum
+}
+
+fn bench_shapes(c: &mut Criterion) {
+    let rays = rays(1024);
+    let mut list = SphereList::new();
+    for i in 0..16 {
+        list.push(&Vector::new((i % 4) as Float * 0.5 - 0.75, (i / 4) as Float * 0.5 - 0.75, -3.0), 0.2, 0);
+    }
+    let shapes: Vec<(&str, Box<dyn Shape>)> =
+        vec![("sphere", Box::new(Sphere::new(&Vector::new(0.0, 0.0, -2.0), 0.5))),
+             ("plane", Box::new(Plane::new(&Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 0.0, 1.0)))),
+             ("triangle",
+              Box::new(Triangle::new(&Vector::new(-0.5, -0.5, -2.0),
+                                     &Vector::new(0.5, -0.5, -2.0),
+                                     &Vector::new(0.0, 0.5, -2.0)))),
+             ("quad",
+              Box::new(Quad::new(&Vector::new(-0.5, -0.5, -2.0), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0)))),
+             ("sphere list (16)", Box::new(list))];
+
+    let mut group = c.benchmark_group("intersect");
+    for &(name, ref shape) in &shapes {
+        group.bench_function(name, |b| {
+            b.iter(|| rays.iter().filter(|r| shape.intersect(black_box(r)).is_some()).count())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("hit_any");
+    for &(name, ref shape) in &shapes {
+        group.bench_function(name, |b| b.iter(|| rays.iter().filter(|r| shape.hit_any(black_box(r))).count()));
+    }
+    group.finish();
+}
+
+fn bench_bvh(c: &mut Criterion) {
+    let rays = rays(1024);
+    let mut scene = grid_scene(100);
+    let bounds: Vec<_> = scene.items.iter().map(|item| item.bounds()).collect();
+
+    let mut group = c.benchmark_group("bvh");
+    group.bench_function("build (10000 spheres)", |b| b.iter(|| raytracer::bvh::Bvh::new(black_box(&bounds))));
+    group.bench_function("closest hit (10000 spheres)", |b| {
+        b.iter(|| rays.iter().filter(|r| scene.intersect_primitive(black_box(r)).is_some()).count())
+    });
+    group.bench_function("any hit (10000 spheres)", |b| {
+        b.iter(|| rays.iter().filter(|r| scene.hit_any(black_box(r))).count())
+    });
+    scene.bvh = None;
+    group.sample_size(10);
+    group.bench_function("closest hit without the BVH (10000 spheres)", |b| {
+        b.iter(|| rays.iter().filter(|r| scene.intersect_primitive(black_box(r)).is_some()).count())
+    });
+    group.finish();
+}
+
+fn bench_renders(c: &mut Criterion) {
+    let spheres = spheres_scene();
+    let grid = grid_scene(20);
+    let random_spheres = scenes::random_spheres(1, 1.0);
+    let cornell_box = scenes::cornell_box(1.0);
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    group.bench_function("spheres (64 x 64)", |b| b.iter(|| render(&spheres, 64, 64)));
+    group.bench_function("grid of 400 spheres (64 x 64)", |b| b.iter(|| render(&grid, 64, 64)));
+    group.bench_function("random spheres (64 x 64)", |b| b.iter(|| render(&random_spheres, 64, 64)));
+    group.bench_function("cornell box (64 x 64)", |b| b.iter(|| render(&cornell_box, 64, 64)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_shapes, bench_bvh, bench_renders);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+/*
+ * Embeds the tracer in C and C++ applications (see: src/capi.rs, for what
+ * each function does): scenes and images are opaque, and freed with
+ * tracer_scene_free and tracer_image_free, every pointer given must be null
+ * or valid, and functions that fail return null (or -1), after which
+ * tracer_last_error says why
+ */
+
+#ifndef TRACER_H
+#define TRACER_H
+
+/* Generated by cbindgen from src/capi.rs: don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct TracerImage TracerImage;
+
+typedef struct TracerScene TracerScene;
+
+typedef struct TracerRenderOptions {
+  uint32_t width;
+  uint32_t height;
+  uint32_t samples;
+  uint32_t max_depth;
+  uint32_t seed;
+  const char *camera;
+} TracerRenderOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *tracer_last_error(void);
+
+struct TracerScene *tracer_scene_new(void);
+
+struct TracerScene *tracer_scene_named(const char *name, double aspect_ratio);
+
+struct TracerScene *tracer_scene_load(const char *path, double aspect_ratio);
+
+void tracer_scene_free(struct TracerScene *scene);
+
+int32_t tracer_scene_add_lambertian(struct TracerScene *scene, const double *albedo);
+
+int32_t tracer_scene_add_metallic(struct TracerScene *scene,
+                                  const double *albedo,
+                                  double glossiness);
+
+int32_t tracer_scene_add_dielectric(struct TracerScene *scene, double ior);
+
+int32_t tracer_scene_add_sphere(struct TracerScene *scene,
+                                const double *center,
+                                double radius,
+                                int32_t material);
+
+int32_t tracer_scene_add_plane(struct TracerScene *scene,
+                               const double *center,
+                               const double *normal,
+                               int32_t material);
+
+int32_t tracer_scene_add_triangle(struct TracerScene *scene,
+                                  const double *a,
+                                  const double *b,
+                                  const double *c,
+                                  int32_t material);
+
+int32_t tracer_scene_add_quad(struct TracerScene *scene,
+                              const double *corner,
+                              const double *u,
+                              const double *v,
+                              int32_t material);
+
+int32_t tracer_scene_add_camera(struct TracerScene *scene,
+                                const char *name,
+                                const double *from,
+                                const double *to,
+                                double fov,
+                                double aspect_ratio);
+
+struct TracerRenderOptions tracer_render_options_default(void);
+
+struct TracerImage *tracer_render(struct TracerScene *scene,
+                                  const struct TracerRenderOptions *options);
+
+uint32_t tracer_image_width(const struct TracerImage *image);
+
+uint32_t tracer_image_height(const struct TracerImage *image);
+
+const float *tracer_image_pixels(const struct TracerImage *image);
+
+size_t tracer_image_to_rgb8(const struct TracerImage *image, uint8_t *out, size_t length);
+
+void tracer_image_free(struct TracerImage *image);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TRACER_H */
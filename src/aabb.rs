@@ -0,0 +1,174 @@
+use vector::Vector;
+use vector::Float;
+#[cfg(feature = "simd")]
+use vector::Lanes;
+use ray::Ray;
+use transform::Transform;
+
+#[cfg(feature = "simd")]
+use wide::{CmpGt, CmpLe, CmpLt};
+
+// Slightly more than one, by (a bound on) the relative error of the three
+// floating-point operations that find the distance to a slab (see: "Robust
+// BVH Ray Traversal", by Thiago Ize)
+const ROUNDING: Float = 1.0 + 2.0 * (3.0 * Float::EPSILON * 0.5) / (1.0 - 3.0 * Float::EPSILON * 0.5);
+
+// An axis-aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    pub fn new(min: &Vector, max: &Vector) -> Aabb {
+        Aabb {
+            min: *min,
+            max: *max,
+        }
+    }
+
+    // A box that contains nothing, and so can be grown to fit anything
+    pub fn empty() -> Aabb {
+        Aabb::new(&Vector::new(Float::MAX, Float::MAX, Float::MAX),
+                  &Vector::new(-Float::MAX, -Float::MAX, -Float::MAX))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(&self.min.min(&other.min), &self.max.max(&other.max))
+    }
+
+    pub fn grow(&self, p: &Vector) -> Aabb {
+        Aabb::new(&self.min.min(p), &self.max.max(p))
+    }
+
+    pub fn centroid(&self) -> Vector {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vector {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> Float {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // The axis along which the box is longest (0, 1, or 2 for x, y, or z)
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // The box around this box once transformed into world space (which fits
+    // its corners)
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        if transform.is_translation() {
+            let t = transform.translation();
+            return Aabb::new(&(self.min + t), &(self.max + t));
+        }
+        (0..8).fold(Aabb::empty(), |bounds, i| {
+            let corner = Vector::new(if i & 1 == 0 { self.min.x } else { self.max.x },
+                                     if i & 2 == 0 { self.min.y } else { self.max.y },
+                                     if i & 4 == 0 { self.min.z } else { self.max.z });
+            bounds.grow(&transform.point_to_world(&corner))
+        })
+    }
+
+    // Does the ray pass through the box between `t_min` and `t_max`? The
+    // reciprocal of the ray's direction is passed in, since it is the same
+    // for every box that the ray is tested against (where a component of the
+    // direction that is zero has an infinite reciprocal, so that the ray is
+    // either always or never between that pair of slabs)
+    pub fn intersect(&self, r: &Ray, inverse_direction: &Vector, t_max: Float) -> bool {
+        let mut t0 = r.t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let inverse = inverse_direction.component(axis);
+            let origin = r.origin.component(axis);
+            // (Which slab is nearer only depends on the direction's sign, so
+            // the distances are picked rather than compared)
+            let (near, far) = if inverse < 0.0 {
+                (self.max.component(axis), self.min.component(axis))
+            } else {
+                (self.min.component(axis), self.max.component(axis))
+            };
+            let near = (near - origin) * inverse;
+            // (The farther distance is rounded up, so that rounding can't make
+            // a ray that grazes the box miss it)
+            let far = (far - origin) * inverse * ROUNDING;
+            // (NaNs, from rays in the plane of a slab, leave the bounds as
+            // they are)
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+        }
+        t0 <= t1
+    }
+
+    // As above, for four rays at once (see: `Bvh::traverse_packet`), whose
+    // origins and reciprocal directions are given by axis: the lanes of the
+    // mask that's returned are set for the rays that pass through the box
+    #[cfg(feature = "simd")]
+    pub fn intersect_lanes(&self, origin: &[Lanes; 3], inverse_direction: &[Lanes; 3], t_min: Lanes, t_max: Lanes) -> Lanes {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for (axis, (&origin, &inverse)) in origin.iter().zip(inverse_direction).enumerate() {
+            let (min, max) = (Lanes::splat(self.min.component(axis)), Lanes::splat(self.max.component(axis)));
+            let negative = inverse.cmp_lt(Lanes::splat(0.0));
+            let near = (negative.blend(max, min) - origin) * inverse;
+            let far = (negative.blend(min, max) - origin) * inverse * Lanes::splat(ROUNDING);
+            t0 = near.cmp_gt(t0).blend(near, t0);
+            t1 = far.cmp_lt(t1).blend(far, t1);
+        }
+        t0.cmp_le(t1)
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
+#[test]
+fn test_aabb_intersect() {
+    let bounds = Aabb::new(&Vector::new(-1.0, -1.0, -1.0), &Vector::new(1.0, 1.0, 1.0));
+    let hits = |origin: Vector, direction: Vector, t_max: Float| {
+        let r = Ray::new(&origin, &direction, 0.0, t_max);
+        let inverse = Vector::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        bounds.intersect(&r, &inverse, t_max)
+    };
+    assert!(hits(Vector::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, -1.0), Float::MAX));
+    assert!(!hits(Vector::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, 1.0), Float::MAX));
+    assert!(!hits(Vector::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, -1.0), 3.0));
+    // Rays that start inside the box hit it
+    assert!(hits(Vector::zero(), Vector::new(1.0, 2.0, 3.0), Float::MAX));
+
+    // Directions with zero (or negative zero) components, which are parallel
+    // to a pair of slabs
+    assert!(hits(Vector::new(0.5, 5.0, 0.5), Vector::new(0.0, -1.0, 0.0), Float::MAX));
+    assert!(hits(Vector::new(0.5, 5.0, 0.5), Vector::new(-0.0, -1.0, -0.0), Float::MAX));
+    assert!(!hits(Vector::new(1.5, 5.0, 0.5), Vector::new(0.0, -1.0, 0.0), Float::MAX));
+    // Rays in the plane of a slab (whose distances to it are NaN)
+    assert!(hits(Vector::new(1.0, 5.0, 0.0), Vector::new(0.0, -1.0, 0.0), Float::MAX));
+    assert!(!hits(Vector::new(1.0, 5.0, 0.0), Vector::new(0.0, 1.0, 0.0), Float::MAX));
+
+    // A ray that only grazes the box's edge
+    assert!(hits(Vector::new(-3.0, 1.0, 0.0), Vector::new(1.0, 0.0, 0.0), Float::MAX));
+    assert!(!Aabb::empty().intersect(&Ray::new(&Vector::zero(), &Vector::one(), 0.0, Float::MAX),
+                                     &Vector::one(),
+                                     Float::MAX));
+}
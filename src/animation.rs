@@ -0,0 +1,171 @@
+use vector::Vector;
+use vector::Float;
+use camera::Camera;
+use transform::Transform;
+
+use std::ops::{Add, Mul, Sub};
+
+// Values that can be blended between keyframes
+pub trait Interpolate: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Float, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Float, Output = T>> Interpolate for T {}
+
+// The value of some parameter at a particular time (measured in frames, so
+// that keys can fall between frames)
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe<T> {
+    pub time: Float,
+    pub value: T,
+}
+
+impl<T> Keyframe<T> {
+    pub fn new(time: Float, value: T) -> Keyframe<T> {
+        Keyframe { time, value }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    // A cubic Hermite spline whose tangents are estimated from the
+    // neighboring keys (Catmull-Rom, generalized to unevenly spaced keys),
+    // which passes through every key without the kinks of linear motion
+    Cubic,
+}
+
+// The keyframes of one animated parameter
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    // Sorted by time
+    pub keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new(keys: Vec<Keyframe<T>>, interpolation: Interpolation) -> Track<T> {
+        Track {
+            keys,
+            interpolation,
+        }
+    }
+
+    pub fn linear(keys: Vec<Keyframe<T>>) -> Track<T> {
+        Track::new(keys, Interpolation::Linear)
+    }
+
+    pub fn cubic(keys: Vec<Keyframe<T>>) -> Track<T> {
+        Track::new(keys, Interpolation::Cubic)
+    }
+
+    // The value at `time`, holding the first and last values outside of the
+    // keys' range (or `None`, if there are no keys)
+    pub fn evaluate(&self, time: Float) -> Option<T> {
+        let keys = &self.keys;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        let i = keys.iter().position(|k| k.time > time)?;
+        let (a, b) = (&keys[i - 1], &keys[i]);
+        let h = b.time - a.time;
+        let s = (time - a.time) / h;
+        match self.interpolation {
+            Interpolation::Linear => Some(a.value + (b.value - a.value) * s),
+            Interpolation::Cubic => {
+                let (s2, s3) = (s * s, s * s * s);
+                Some(a.value * (2.0 * s3 - 3.0 * s2 + 1.0) + self.tangent(i - 1) * (h * (s3 - 2.0 * s2 + s)) +
+                     b.value * (3.0 * s2 - 2.0 * s3) + self.tangent(i) * (h * (s3 - s2)))
+            }
+        }
+    }
+
+    // The rate of change at key i (one-sided at the first and last keys)
+    fn tangent(&self, i: usize) -> T {
+        let keys = &self.keys;
+        let previous = &keys[i.saturating_sub(1)];
+        let next = &keys[(i + 1).min(keys.len() - 1)];
+        (next.value - previous.value) * (1.0 / (next.time - previous.time))
+    }
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Track<T> {
+        Track {
+            keys: Vec::new(),
+            interpolation: Interpolation::Linear,
+        }
+    }
+}
+
+// The animated transform of one primitive: parameters without keys keep
+// their rest values (no translation or rotation, and unit scale)
+#[derive(Clone, Debug)]
+pub struct ObjectAnimation {
+    pub object_id: u32,
+    pub position: Track<Vector>,
+    // Euler angles, in degrees (see: `Transform`)
+    pub rotation: Track<Vector>,
+    pub scale: Track<Vector>,
+    // The point that the primitive rotates and scales about
+    pub pivot: Vector,
+}
+
+impl ObjectAnimation {
+    pub fn new(object_id: u32) -> ObjectAnimation {
+        ObjectAnimation {
+            object_id,
+            position: Track::default(),
+            rotation: Track::default(),
+            scale: Track::default(),
+            pivot: Vector::zero(),
+        }
+    }
+
+    pub fn evaluate(&self, time: Float) -> Transform {
+        Transform::new(&self.position.evaluate(time).unwrap_or_else(Vector::zero),
+                       &self.rotation.evaluate(time).unwrap_or_else(Vector::zero),
+                       &self.scale.evaluate(time).unwrap_or_else(Vector::one),
+                       &self.pivot)
+    }
+}
+
+// The animated camera: parameters without keys default to a camera at the
+// origin looking down -z
+#[derive(Clone, Debug, Default)]
+pub struct CameraAnimation {
+    pub position: Track<Vector>,
+    pub target: Track<Vector>,
+    // The vertical field of view, in degrees
+    pub fov: Track<Float>,
+}
+
+impl CameraAnimation {
+    pub fn evaluate(&self, time: Float, default_fov: Float, aspect_ratio: Float) -> Camera {
+        let from = self.position.evaluate(time).unwrap_or_else(Vector::zero);
+        let to = self.target.evaluate(time).unwrap_or_else(|| from + Vector::new(0.0, 0.0, -1.0));
+        let fov = self.fov.evaluate(time).unwrap_or(default_fov);
+        Camera::look_at(&from, &to, &Vector::new(0.0, 1.0, 0.0), fov, aspect_ratio)
+    }
+}
+
+#[test]
+fn test_evaluate_tracks() {
+    let keys = vec![Keyframe::new(0.0, 1.0), Keyframe::new(10.0, 3.0), Keyframe::new(20.0, 2.0)];
+    let linear = Track::linear(keys.clone());
+    assert_eq!(linear.evaluate(-5.0), Some(1.0));
+    assert_eq!(linear.evaluate(5.0), Some(2.0));
+    assert_eq!(linear.evaluate(15.0), Some(2.5));
+    assert_eq!(linear.evaluate(25.0), Some(2.0));
+    assert_eq!(Track::<Float>::default().evaluate(0.0), None);
+
+    // Cubic tracks pass through their keys, but curve between them
+    let cubic = Track::cubic(keys);
+    assert_eq!(cubic.evaluate(10.0), Some(3.0));
+    assert!((cubic.evaluate(20.0).unwrap() - 2.0).abs() < 1e-12);
+    assert!(cubic.evaluate(5.0).unwrap() > 2.0);
+}
@@ -0,0 +1,105 @@
+use vector::Float;
+use color::Color;
+use color::srgb_to_linear;
+use error::Result;
+use error::TracerError;
+
+use exr::prelude::read_first_flat_layer_from_file;
+use image;
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+// A photograph that the camera sees behind the scene, in place of the
+// environment, for rendering objects into footage: it's stretched over the
+// whole frame, so that the camera rays that miss the scene (and the shadow
+// catchers that let it through, see: `ShadowCatcher`) see the part of the
+// photograph that their pixel covers. It lights nothing, since the scene is
+// meant to be lit by an environment that matches it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Backplate {
+    pub width: u32,
+    pub height: u32,
+    // One linear color per texel, in row-major order with the top row first
+    pixels: Vec<Color>,
+}
+
+impl Backplate {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Backplate {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        Backplate { width, height, pixels }
+    }
+
+    // Load a backplate from an image: OpenEXR images hold linear radiance,
+    // while the rest (e.g. PNG and JPEG) are taken to be sRGB-encoded
+    pub fn load(path: &Path) -> Result<Backplate> {
+        let is_exr = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+        let plate = if is_exr {
+            let image = read_first_flat_layer_from_file(path).map_err(|why| TracerError::Parse(why.to_string()))?;
+            let size = image.layer_data.size;
+            let channels = &image.layer_data.channel_data.list;
+            let channel = |name: &str| {
+                channels.iter()
+                    .find(|c| c.name == *name)
+                    .ok_or_else(|| TracerError::Parse(format!("{} has no {} channel", path.display(), name)))
+            };
+            let (r, g, b) = (channel("R")?, channel("G")?, channel("B")?);
+            let pixels = (0..size.0 * size.1)
+                .map(|i| {
+                    Color::new(r.sample_data.value_by_flat_index(i).to_f32() as Float,
+                               g.sample_data.value_by_flat_index(i).to_f32() as Float,
+                               b.sample_data.value_by_flat_index(i).to_f32() as Float)
+                })
+                .collect();
+            Backplate::new(size.0 as u32, size.1 as u32, pixels)
+        } else {
+            let image = image::open(path)?.to_rgb8();
+            let pixels = image.pixels()
+                .map(|p| {
+                    Color::new(srgb_to_linear(p[0] as Float / 255.0),
+                               srgb_to_linear(p[1] as Float / 255.0),
+                               srgb_to_linear(p[2] as Float / 255.0))
+                })
+                .collect();
+            Backplate::new(image.width(), image.height(), pixels)
+        };
+        if plate.width == 0 || plate.height == 0 {
+            return Err(TracerError::InvalidParameter(format!("{} is empty", path.display())));
+        }
+        Ok(plate)
+    }
+
+    // The color at (u, v) across the frame, each in [0, 1] from its top left
+    // corner, interpolated between the four nearest texels
+    pub fn lookup(&self, u: Float, v: Float) -> Color {
+        let x = (u * self.width as Float - 0.5).clamp(0.0, (self.width - 1) as Float);
+        let y = (v * self.height as Float - 0.5).clamp(0.0, (self.height - 1) as Float);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as Float, y - y0 as Float);
+        let texel = |x: u32, y: u32| self.pixels[(y * self.width + x) as usize];
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x1, y0) * fx;
+        let bottom = texel(x0, y1) * (1.0 - fx) + texel(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[test]
+fn test_backplate() {
+    use std::env;
+
+    // A black and white plate, halfway between them across its middle
+    let plate = Backplate::new(2, 1, vec![Color::black(), Color::white()]);
+    assert_eq!(plate.lookup(0.0, 0.5), Color::black());
+    assert_eq!(plate.lookup(1.0, 0.5), Color::white());
+    assert_eq!(plate.lookup(0.5, 0.0), Color::gray(0.5));
+
+    // 8-bit images are decoded from sRGB
+    let path = env::temp_dir().join("tracer_test_backplate.png");
+    image::RgbImage::from_pixel(3, 2, image::Rgb([255, 188, 0])).save(&path).unwrap();
+    let loaded = Backplate::load(&path).unwrap();
+    assert_eq!((loaded.width, loaded.height), (3, 2));
+    let c = loaded.lookup(0.5, 0.5);
+    assert!(c.r == 1.0 && (c.g - 0.5).abs() < 0.01 && c.b == 0.0);
+    assert!(Backplate::load(&env::temp_dir().join("tracer_test_no_such_backplate.png")).is_err());
+}
@@ -0,0 +1,378 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use ray::Ray;
+use mesh::Mesh;
+use shape::Geometry;
+use primitive::Primitive;
+use scene::Scene;
+use framebuffer::Framebuffer;
+use integrator::trace;
+use sampler::hash_combine;
+use sampling;
+use rng;
+use rng::ThreadRng;
+use error::Result;
+use error::TracerError;
+
+use rayon::prelude::*;
+
+use std::collections::HashMap;
+
+// Baking renders what lights a mesh into its texture space, rather than what
+// a camera sees, so that the lighting can be played back (e.g. in a game) at
+// the cost of a texture lookup: each texel that the mesh's triangles cover
+// (in its texture coordinates) is traced from the point on the surface under
+// it, and the rest are filled in from their neighbors, so that filtering
+// near the edges of the mesh's charts doesn't bleed black into them
+
+#[derive(Copy, Clone, Debug)]
+pub struct BakeSettings {
+    pub width: u32,
+    pub height: u32,
+    // The number of rays traced from each texel
+    pub samples: u32,
+    pub max_depth: u32,
+    pub seed: u32,
+    // How many texels to grow the covered texels by
+    pub padding: u32,
+    // How far away surfaces occlude ambient light (see: `bake_occlusion`)
+    pub occlusion_distance: Float,
+    // The radius of the tightest curves that curvature maps tell apart (see:
+    // `bake_curvature`)
+    pub curvature_radius: Float,
+}
+
+impl BakeSettings {
+    pub fn new() -> BakeSettings {
+        BakeSettings {
+            width: 512,
+            height: 512,
+            samples: 256,
+            max_depth: 8,
+            seed: 0,
+            padding: 2,
+            occlusion_distance: 1.0,
+            curvature_radius: 0.1,
+        }
+    }
+}
+
+impl Default for BakeSettings {
+    fn default() -> BakeSettings {
+        BakeSettings::new()
+    }
+}
+
+// The point on the surface under a texel's center, in world space
+#[derive(Copy, Clone, Debug)]
+pub struct Texel {
+    pub position: Vector,
+    pub normal: Vector,
+    // Which of the mesh's triangles is under it, and where
+    pub triangle: usize,
+    pub barycentric: [Float; 3],
+}
+
+// The mesh of the scene's object, which must have texture coordinates to bake
+// into
+fn mesh(scene: &Scene, object_id: u32) -> Result<(&Primitive, &Mesh)> {
+    let item = scene.items
+        .get(object_id as usize)
+        .ok_or_else(|| TracerError::InvalidParameter(format!("the scene has no object {}", object_id)))?;
+    let mesh = match item.shape {
+        Geometry::Mesh(ref mesh) => &**mesh,
+        Geometry::Lod(ref lod) => lod.mesh(),
+        _ => return Err(TracerError::InvalidParameter(format!("object {} isn't a mesh", object_id))),
+    };
+    if mesh.uvs.is_none() {
+        return Err(TracerError::InvalidParameter(format!("object {} has no texture coordinates", object_id)));
+    }
+    Ok((item, mesh))
+}
+
+// The texels that the mesh's triangles cover, in row-major order with the top
+// row first (as `TextureCache::lookup` reads them): where triangles overlap in
+// texture space, the last one wins
+fn rasterize(item: &Primitive, mesh: &Mesh, width: u32, height: u32) -> Vec<Option<Texel>> {
+    let mut texels = vec![None; (width * height) as usize];
+    let uvs = mesh.uvs.as_ref().expect("the mesh has texture coordinates");
+    for (i, triangle) in mesh.triangles.iter().enumerate() {
+        // The triangle's corners in texel space
+        let corners: Vec<(Float, Float)> =
+            uvs[i].iter().map(|&(u, v)| (u * width as Float, (1.0 - v) * height as Float)).collect();
+        let (a, b, c) = (corners[0], corners[1], corners[2]);
+        let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+        if area.abs() < 1e-12 {
+            continue;
+        }
+        let [p0, p1, p2] = triangle.vertices;
+        let face = (p1 - p0).cross(&(p2 - p0)).normalize();
+
+        let x0 = a.0.min(b.0).min(c.0).floor().max(0.0) as u32;
+        let y0 = a.1.min(b.1).min(c.1).floor().max(0.0) as u32;
+        let x1 = (a.0.max(b.0).max(c.0).ceil() as u32).min(width);
+        let y1 = (a.1.max(b.1).max(c.1).ceil() as u32).min(height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // The barycentric coordinates of the texel's center
+                let (px, py) = (x as Float + 0.5, y as Float + 0.5);
+                let w1 = ((px - a.0) * (c.1 - a.1) - (c.0 - a.0) * (py - a.1)) / area;
+                let w2 = ((b.0 - a.0) * (py - a.1) - (px - a.0) * (b.1 - a.1)) / area;
+                let w0 = 1.0 - w1 - w2;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+                let normal = match mesh.normals {
+                    Some(ref normals) => normals[i][0] * w0 + normals[i][1] * w1 + normals[i][2] * w2,
+                    None => face,
+                };
+                texels[(y * width + x) as usize] = Some(Texel {
+                    position: item.transform.point_to_world(&(p0 * w0 + p1 * w1 + p2 * w2)),
+                    normal: item.transform.normal_to_world(&normal),
+                    triangle: i,
+                    barycentric: [w0, w1, w2],
+                });
+            }
+        }
+    }
+    texels
+}
+
+// Grow the covered texels outward by `padding` texels, each new one the mean
+// of its covered neighbors
+fn dilate(image: &mut Framebuffer, covered: &mut [bool], padding: u32) {
+    let (width, height) = (image.width as i64, image.height as i64);
+    for _ in 0..padding {
+        let before = covered.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                if before[index] {
+                    continue;
+                }
+                let mut sum = Color::black();
+                let mut count = 0;
+                for (dx, dy) in (-1..2).flat_map(|dy| (-1..2).map(move |dx| (dx, dy))) {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx >= 0 && ny >= 0 && nx < width && ny < height && before[(ny * width + nx) as usize] {
+                        sum += image.pixels[(ny * width + nx) as usize];
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    image.pixels[index] = sum / count as Float;
+                    covered[index] = true;
+                }
+            }
+        }
+    }
+}
+
+// Bake the object's texels with `shade`, which is given each texel's surface
+// point and an index (to seed its random numbers with)
+pub fn bake<F>(scene: &Scene, object_id: u32, settings: &BakeSettings, shade: F) -> Result<Framebuffer>
+    where F: Fn(&Texel, u32) -> Color + Sync
+{
+    let (item, mesh) = mesh(scene, object_id)?;
+    if settings.width == 0 || settings.height == 0 {
+        return Err(TracerError::InvalidParameter("the lightmap must be at least a texel across".to_string()));
+    }
+    let texels = rasterize(item, mesh, settings.width, settings.height);
+    let mut image = Framebuffer::new(settings.width, settings.height);
+    image.pixels = texels.par_iter()
+        .enumerate()
+        .map(|(index, texel)| texel.as_ref().map_or_else(Color::black, |texel| shade(texel, index as u32)))
+        .collect();
+    let mut covered: Vec<bool> = texels.iter().map(|t| t.is_some()).collect();
+    dilate(&mut image, &mut covered, settings.padding);
+    Ok(image)
+}
+
+// Bake the light arriving at the object's surface: each texel is the mean
+// radiance over the hemisphere above it, weighted by the cosine (i.e. its
+// irradiance over pi), so that multiplying it by a diffuse surface's albedo
+// gives the radiance that the surface reflects
+pub fn bake_lightmap(scene: &Scene, object_id: u32, settings: &BakeSettings) -> Result<Framebuffer> {
+    bake(scene, object_id, settings, |texel, index| {
+        let texel_hash = hash_combine(settings.seed, index);
+        let mut sum = Color::black();
+        for sample in 0..settings.samples {
+            rng::reseed(hash_combine(texel_hash, sample));
+            let direction = sampling::cosine_hemisphere(&mut ThreadRng, &texel.normal);
+            let ray = Ray::spawn(&texel.position, &texel.normal, &direction, Float::MAX);
+            let radiance = trace(&ray, scene, 0, settings.max_depth, None);
+            // (As in the renderer, samples that aren't finite are taken as
+            // black)
+            if radiance.r.is_finite() && radiance.g.is_finite() && radiance.b.is_finite() {
+                sum += radiance;
+            }
+        }
+        sum / settings.samples.max(1) as Float
+    })
+}
+
+// Bake ambient occlusion: each texel is the fraction of the (cosine
+// weighted) hemisphere above it that's open, within `occlusion_distance`, so
+// that creases and contacts are dark and open surfaces white
+pub fn bake_occlusion(scene: &Scene, object_id: u32, settings: &BakeSettings) -> Result<Framebuffer> {
+    bake(scene, object_id, settings, |texel, index| {
+        let texel_hash = hash_combine(settings.seed, index);
+        let mut open = 0;
+        for sample in 0..settings.samples {
+            rng::reseed(hash_combine(texel_hash, sample));
+            let direction = sampling::cosine_hemisphere(&mut ThreadRng, &texel.normal);
+            let ray = Ray::spawn(&texel.position, &texel.normal, &direction, settings.occlusion_distance);
+            if !scene.hit_any(&ray) {
+                open += 1;
+            }
+        }
+        Color::white() * (open as Float / settings.samples.max(1) as Float)
+    })
+}
+
+// The mean curvature at each of the mesh's triangles' corners, in world space
+// and positive where the surface is convex: normals are averaged over the
+// corners that share a position (so that creases between flat faces curve,
+// too), and the curvature of each edge is how quickly the normal turns along
+// it
+fn corner_curvatures(item: &Primitive, mesh: &Mesh) -> Vec<[Float; 3]> {
+    let key = |p: &Vector| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+    let corners: Vec<[Vector; 3]> = mesh.triangles
+        .iter()
+        .map(|t| {
+            let [a, b, c] = t.vertices;
+            [item.transform.point_to_world(&a), item.transform.point_to_world(&b), item.transform.point_to_world(&c)]
+        })
+        .collect();
+
+    let mut normals = HashMap::new();
+    for (i, &[a, b, c]) in corners.iter().enumerate() {
+        // (Faces are weighted by their areas, and smooth meshes by their own
+        // normals)
+        let face = (b - a).cross(&(c - a));
+        for (k, p) in [a, b, c].iter().enumerate() {
+            let normal = match mesh.normals {
+                Some(ref n) => item.transform.normal_to_world(&n[i][k]),
+                None => face,
+            };
+            *normals.entry(key(p)).or_insert_with(Vector::zero) += normal;
+        }
+    }
+    let normal = |p: &Vector| normals[&key(p)].normalize();
+
+    let mut curvatures: HashMap<_, (Float, u32)> = HashMap::new();
+    for triangle in &corners {
+        for k in 0..3 {
+            let (p, q) = (triangle[k], triangle[(k + 1) % 3]);
+            let edge = q - p;
+            let curvature = (normal(&q) - normal(&p)).dot(&edge) / edge.dot(&edge);
+            for end in &[p, q] {
+                let entry = curvatures.entry(key(end)).or_insert((0.0, 0));
+                entry.0 += curvature;
+                entry.1 += 1;
+            }
+        }
+    }
+    corners.iter()
+        .map(|triangle| {
+            let mean = |p: &Vector| {
+                let (sum, count) = curvatures[&key(p)];
+                sum / count as Float
+            };
+            [mean(&triangle[0]), mean(&triangle[1]), mean(&triangle[2])]
+        })
+        .collect()
+}
+
+// Bake the surface's curvature, as gray: flat surfaces are middle gray, and
+// convex ones lighter (and concave ones darker) up to white (or black) for
+// curves of `curvature_radius` or tighter, e.g. to wear paint off edges
+pub fn bake_curvature(scene: &Scene, object_id: u32, settings: &BakeSettings) -> Result<Framebuffer> {
+    let (item, mesh) = mesh(scene, object_id)?;
+    let curvatures = corner_curvatures(item, mesh);
+    bake(scene, object_id, settings, |texel, _| {
+        let corners = curvatures[texel.triangle];
+        let w = texel.barycentric;
+        let curvature = corners[0] * w[0] + corners[1] * w[1] + corners[2] * w[2];
+        Color::white() * (0.5 + 0.5 * (curvature * settings.curvature_radius).clamp(-1.0, 1.0))
+    })
+}
+
+#[test]
+fn test_bake_lightmap() {
+    use shape::Triangle;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    // A unit square facing up, whose texture coordinates cover the left half
+    // of the lightmap, under the sky
+    let corners = [Vector::new(0.0, 0.0, 0.0), Vector::new(1.0, 0.0, 0.0), Vector::new(1.0, 0.0, -1.0),
+                   Vector::new(0.0, 0.0, -1.0)];
+    let mut mesh = Mesh::new(vec![Triangle::new(&corners[0], &corners[1], &corners[2]),
+                                  Triangle::new(&corners[0], &corners[2], &corners[3])]);
+    mesh.uvs = Some(vec![[(0.0, 0.0), (0.5, 0.0), (0.5, 1.0)], [(0.0, 0.0), (0.5, 1.0), (0.0, 1.0)]]);
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(mesh, Arc::new(Lambertian::new(&Color::white()))));
+    scene.build_bvh();
+
+    let settings = BakeSettings { width: 16, height: 8, samples: 16, padding: 1, ..BakeSettings::new() };
+    let lightmap = bake_lightmap(&scene, 0, &settings).unwrap();
+    // The square sees only the sky (which is at least as bright as light
+    // blue), the padding grows it a texel to the right, and the rest is black
+    for y in 0..8 {
+        for x in 0..16 {
+            let texel = lightmap.get(x, y);
+            if x < 9 {
+                assert!(texel.r > 0.4 && texel.b > 0.9 && texel.b <= 1.0 + 1e-6, "{:?} at {}, {}", texel, x, y);
+            } else {
+                assert_eq!(texel, Color::black());
+            }
+        }
+    }
+    // Baking is reproducible
+    assert!(bake_lightmap(&scene, 0, &settings).unwrap().pixels == lightmap.pixels);
+
+    assert!(bake_lightmap(&scene, 1, &settings).is_err());
+}
+
+#[test]
+fn test_bake_occlusion_and_curvature() {
+    use shape::Sphere;
+    use shape::Triangle;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    // A unit square, folded along its diagonal by `fold` (up or down at the
+    // other two corners), whose texture coordinates cover the whole map
+    let folded = |fold: Float| {
+        let corners = [Vector::new(0.0, 0.0, 0.0), Vector::new(1.0, fold, 0.0), Vector::new(1.0, 0.0, -1.0),
+                       Vector::new(0.0, fold, -1.0)];
+        let mut mesh = Mesh::new(vec![Triangle::new(&corners[0], &corners[1], &corners[2]),
+                                      Triangle::new(&corners[0], &corners[2], &corners[3])]);
+        mesh.uvs = Some(vec![[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], [(0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]]);
+        let mut scene = Scene::new();
+        scene.add(Primitive::new(mesh, Arc::new(Lambertian::new(&Color::white()))));
+        scene
+    };
+    let settings = BakeSettings { width: 8, height: 8, samples: 64, ..BakeSettings::new() };
+    let mean = |image: &Framebuffer| image.pixels.iter().map(|p| p.g).sum::<Float>() / image.pixels.len() as Float;
+
+    // Nothing occludes a flat square, until there's a sphere just above it
+    let mut scene = folded(0.0);
+    scene.build_bvh();
+    assert!(bake_occlusion(&scene, 0, &settings).unwrap().pixels.iter().all(|p| *p == Color::white()));
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.5, 0.3, -0.5), 0.2),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    scene.build_bvh();
+    let occlusion = bake_occlusion(&scene, 0, &settings).unwrap();
+    assert!(occlusion.get(4, 4).g < 0.9 && occlusion.get(0, 7).g > occlusion.get(4, 4).g);
+    assert!(bake_curvature(&scene, 1, &settings).is_err());
+
+    // Flat is gray, ridges are lighter, and valleys darker
+    let settings = BakeSettings { curvature_radius: 1.0, ..settings };
+    let flat = bake_curvature(&scene, 0, &settings).unwrap();
+    assert!(flat.pixels.iter().all(|p| (p.g - 0.5).abs() < 1e-6));
+    assert!(mean(&bake_curvature(&folded(-0.5), 0, &settings).unwrap()) > 0.55);
+    assert!(mean(&bake_curvature(&folded(0.5), 0, &settings).unwrap()) < 0.45);
+}
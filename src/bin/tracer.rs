@@ -0,0 +1,435 @@
+// Renders a scene file from the command line, without writing a Rust
+// program, e.g.
+//
+//     tracer scenes/spheres.toml --resolution 1280x720 --samples 64 -o render.exr
+//
+// from the scene's first camera (if it has one), to an image of the format
+// given by the output path's extension: the render settings start from a
+// preset or settings file (see: `RenderSettings`), which the scene file's
+// settings override, which the options given override in turn
+
+extern crate clap;
+extern crate raytracer;
+extern crate rayon;
+extern crate toml;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+use raytracer::scene_file;
+use raytracer::camera::Camera;
+use raytracer::scene::Scene;
+use raytracer::obj;
+use raytracer::output;
+use raytracer::output::OutputFormat;
+use raytracer::metadata::Metadata;
+use raytracer::film::Film;
+use raytracer::film::Aov;
+use raytracer::memory::MemoryUsage;
+use raytracer::integrator::DebugMode;
+use raytracer::settings::RenderSettings;
+use raytracer::renderer::RenderProgress;
+use raytracer::environment::Environment;
+use raytracer::scenes::FURNACE_RADIANCE;
+use raytracer::bake;
+use raytracer::bake::BakeSettings;
+use raytracer::tonemap::DisplayTransform;
+use raytracer::probe;
+use raytracer::probe::ProbeSettings;
+use raytracer::vector::Vector;
+use raytracer::vector::Float;
+use raytracer::bloom::Bloom;
+use raytracer::stereo;
+use raytracer::stereo::StereoLayout;
+use raytracer::texture::TextureCache;
+use raytracer::texture::DEFAULT_BUDGET;
+
+use clap::Parser;
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name = "tracer", version, about = "Render a scene file (TOML, PBRT, or Mitsuba XML)")]
+struct Args {
+    #[arg(help = "The scene file to render")]
+    scene: PathBuf,
+    #[arg(short, long, help = "Start from the \"preview\" or \"production\" render settings")]
+    preset: Option<String>,
+    #[arg(long, conflicts_with = "preset", help = "Start from the render settings in this TOML file")]
+    settings: Option<PathBuf>,
+    #[arg(short, long, value_parser = parse_resolution, help = "The size of the image, as WIDTHxHEIGHT")]
+    resolution: Option<(u32, u32)>,
+    #[arg(short, long, help = "The maximum number of samples per pixel")]
+    samples: Option<u32>,
+    #[arg(short = 'd', long, help = "The maximum number of bounces per path")]
+    max_depth: Option<u32>,
+    #[arg(short = 'j', long, default_value_t = 0, help = "The number of render threads (0 uses one per core)")]
+    threads: usize,
+    #[arg(long, help = "Seeds the sampler, so that renders can be reproduced")]
+    seed: Option<u32>,
+    #[arg(short, long, default_value = "output/render.png",
+          help = "Where to write the image: PPM, PNG, JPEG, EXR, or HDR, by its extension")]
+    output: PathBuf,
+    #[arg(long, value_name = "PATH", help = "Also write the scene's geometry to this OBJ file, to look at in other viewers")]
+    export_obj: Option<PathBuf>,
+    #[arg(long, value_name = "INTENSITY",
+          help = "Add glare around the brightest parts of the image, scattering this fraction of its light (e.g. 0.04)")]
+    bloom: Option<Float>,
+    #[arg(long, help = "Light the scene uniformly, as in a white furnace, to check that its materials don't gain or lose energy")]
+    furnace: bool,
+    #[arg(long, help = "Report what the scene is made of (and what its BVHs made of it) once it's built, and how much \
+                        memory each part of the render took once it's done")]
+    stats: bool,
+    #[arg(long, value_name = "X,Y", value_parser = parse_pixel,
+          help = "Instead of rendering, trace one sample of this pixel and print each bounce of its path (as TOML)")]
+    trace_pixel: Option<(u32, u32)>,
+    #[arg(long, value_name = "INDEX", default_value_t = 0, requires = "trace_pixel",
+          help = "Which of the pixel's samples to trace (from 0, in the order that the renderer takes them)")]
+    trace_sample: u32,
+    #[arg(long, value_name = "MODE", value_parser = parse_debug_mode,
+          help = "Render a false-color view instead of the lighting: normal, uv, bvh_cost, bounces, or distance")]
+    debug: Option<DebugMode>,
+    #[arg(long, value_name = "PATH",
+          help = "Also write the distance to the surface seen through each pixel to this image (32-bit, for EXR)")]
+    depth: Option<PathBuf>,
+    #[arg(long, value_name = "PATH",
+          help = "Also write the world-space position seen through each pixel to this image (32-bit, for EXR)")]
+    position: Option<PathBuf>,
+    #[arg(long, value_name = "PATH",
+          help = "Also write how much of each pixel the scene covers to this image, for compositing (see: shadow \
+                  catchers)")]
+    alpha: Option<PathBuf>,
+    #[arg(long, help = "Leave the background out, writing the image with its alpha for compositing (to a PNG or EXR)")]
+    transparent: bool,
+    #[arg(long, value_name = "PATH", help = "Render over this photograph, which the camera sees in place of the environment")]
+    backplate: Option<PathBuf>,
+    #[arg(long, value_name = "PATH",
+          help = "Also write a heatmap of how many samples each pixel took (from none, in blue, to all of them, in red)")]
+    sample_heatmap: Option<PathBuf>,
+    #[arg(long, value_name = "PATH",
+          help = "Also write a heatmap of how noisy each pixel is (green at the noise threshold, red at twice it)")]
+    variance_heatmap: Option<PathBuf>,
+    #[arg(long, value_name = "OBJECT",
+          help = "Instead of rendering, bake a map of this object (a mesh, by its index) in its texture space")]
+    bake: Option<u32>,
+    #[arg(long, value_enum, default_value_t = BakeMap::Lightmap, requires = "bake", help = "Which map to bake")]
+    bake_map: BakeMap,
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_position, conflicts_with = "bake",
+          help = "Instead of rendering, bake a light probe here (and at every other position given) as a cubemap \
+                  (with faces as tall as the image) and spherical harmonics (in a TOML file beside the output)")]
+    probe: Vec<Vector>,
+    #[arg(long, value_name = "LAYOUT", value_parser = parse_stereo_layout,
+          help = "How to put a stereo camera's eyes together: side-by-side, or anaglyph (for red/cyan glasses)")]
+    stereo: Option<StereoLayout>,
+}
+
+#[derive(Copy, Clone, PartialEq, clap::ValueEnum)]
+enum BakeMap {
+    // The light reaching the surface
+    Lightmap,
+    // How open the surface is to ambient light
+    Occlusion,
+    // How convex or concave the surface is
+    Curvature,
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("{:?} isn't of the form WIDTHxHEIGHT (e.g. 800x600)", s);
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_position(s: &str) -> Result<Vector, String> {
+    let coordinates: Vec<_> = s.split(',').map(|c| c.trim().parse()).collect();
+    match coordinates.as_slice() {
+        [Ok(x), Ok(y), Ok(z)] => Ok(Vector::new(*x, *y, *z)),
+        _ => Err(format!("{:?} isn't of the form X,Y,Z (e.g. 0,1.5,-2)", s)),
+    }
+}
+
+fn parse_pixel(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("{:?} isn't of the form X,Y (e.g. 320,240)", s);
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    match (x.trim().parse(), y.trim().parse()) {
+        (Ok(x), Ok(y)) => Ok((x, y)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_debug_mode(s: &str) -> Result<DebugMode, String> {
+    DebugMode::named(s).ok_or_else(|| {
+        let names: Vec<_> = DebugMode::ALL.iter().map(|m| m.name()).collect();
+        format!("{:?} isn't a debug mode (there are {})", s, names.join(", "))
+    })
+}
+
+fn parse_stereo_layout(s: &str) -> Result<StereoLayout, String> {
+    match s {
+        "side-by-side" => Ok(StereoLayout::SideBySide),
+        "anaglyph" => Ok(StereoLayout::Anaglyph),
+        _ => Err(format!("{:?} isn't a stereo layout (there are side-by-side and anaglyph)", s)),
+    }
+}
+
+// Say what was being done when an error happened (keeping its kind)
+fn context<E: Into<io::Error>>(doing: String) -> impl FnOnce(E) -> io::Error {
+    move |why| {
+        let why = why.into();
+        io::Error::new(why.kind(), format!("{}: {}", doing, why))
+    }
+}
+
+fn render(args: &Args) -> io::Result<()> {
+    let invalid = |why: String| io::Error::new(io::ErrorKind::InvalidInput, why);
+    let format = OutputFormat::from_path(&args.output)
+        .ok_or_else(|| invalid(format!("can't tell the image format of {}", args.output.display())))?;
+    rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global().map_err(io::Error::other)?;
+
+    let start = Instant::now();
+    let mut settings = match (&args.preset, &args.settings) {
+        (Some(name), _) => RenderSettings::preset(name).ok_or_else(|| invalid(format!("unknown preset {:?}", name)))?,
+        (None, Some(path)) => RenderSettings::load(path)
+            .map_err(context(format!("couldn't load {}", path.display())))?,
+        (None, None) => RenderSettings::new(),
+    };
+    if let Some((width, height)) = args.resolution {
+        settings.width = width;
+        settings.height = height;
+    }
+    let (width, height) = (settings.width, settings.height);
+    let aspect_ratio = settings.aspect_ratio();
+    let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+    let imported = scene_file::load(&args.scene, aspect_ratio, &textures)
+        .map_err(context(format!("couldn't load {}", args.scene.display())))?;
+    let mut scene = imported.scene;
+    for warning in imported.warnings.iter().chain(&scene.validate()) {
+        eprintln!("{}: {}", args.scene.display(), warning);
+    }
+    if let Some(ref path) = args.export_obj {
+        obj::save(&scene, path)
+            .map_err(context(format!("couldn't write to {}", path.display())))?;
+    }
+    if args.stats {
+        eprintln!("{}", scene.stats());
+    }
+    if args.furnace {
+        scene.environment = Environment::Uniform { radiance: FURNACE_RADIANCE };
+    }
+    let camera = scene.cameras.first().map_or_else(|| Camera::new(60.0, aspect_ratio), |c| c.1);
+    let mut stereo = camera.stereo;
+    if let Some(layout) = args.stereo {
+        stereo.as_mut()
+            .ok_or_else(|| invalid("the camera isn't a stereo camera (it needs a `stereo` table in the scene file)".to_string()))?
+            .layout = layout;
+    }
+
+    imported.settings.apply(&mut settings);
+    settings.samples = args.samples.unwrap_or(settings.samples);
+    settings.max_depth = args.max_depth.unwrap_or(settings.max_depth);
+    settings.seed = args.seed.unwrap_or(settings.seed);
+    if let Some(intensity) = args.bloom {
+        settings.bloom = Some(Bloom { intensity, ..settings.bloom.unwrap_or_default() });
+    }
+    settings.transparent |= args.transparent;
+    if let Some(ref path) = args.backplate {
+        settings.backplate = Some(path.clone());
+    }
+    if settings.writes_alpha() && !matches!(format, OutputFormat::Png8 | OutputFormat::Png16 | OutputFormat::Exr(_)) {
+        return Err(invalid(format!("{} has no alpha channel for a transparent render (write a PNG or EXR instead)",
+                                   args.output.display())));
+    }
+    if let Some(object_id) = args.bake {
+        return write_baked_map(args, &scene, object_id, &settings, format, start);
+    }
+    if !args.probe.is_empty() {
+        return write_probes(args, &scene, &settings, format, start);
+    }
+    let mut renderer = settings.renderer();
+    renderer.record_aovs = args.depth.is_some() || args.position.is_some() || args.alpha.is_some();
+    renderer.debug = args.debug;
+    renderer.backplate = settings.load_backplate().map_err(context("couldn't load the backplate".to_string()))?;
+    // (Debug views are written as they are, rather than exposed and tone
+    // mapped like light)
+    if args.debug.is_some() {
+        settings.display = DisplayTransform::raw();
+        settings.bloom = None;
+    }
+
+    let mut film = Film::new(width, height);
+    let bounds = renderer.bounds(width, height);
+    scene.select_lods(&camera, height);
+    if let Some((x, y)) = args.trace_pixel {
+        if x >= width || y >= height {
+            return Err(invalid(format!("pixel ({}, {}) is outside the {}x{} image", x, y, width, height)));
+        }
+        let path = renderer.trace_sample(&camera, &scene, width, height, x, y, args.trace_sample, settings.seed);
+        print!("{}", toml::to_string(&path).map_err(io::Error::other)?);
+        return Ok(());
+    }
+    let progress_bar = |progress: &RenderProgress| {
+        let remaining = progress.remaining.map_or_else(|| "?".to_string(), |r| format!("{}s", r.as_secs()));
+        eprint!("\rpass {} of {}: {:3}% ({:.1} million rays per second, {} left)   ",
+                progress.pass + 1,
+                progress.passes,
+                100 * progress.tiles_completed / progress.tiles,
+                progress.rays_per_second / 1e6,
+                remaining);
+        let _ = io::stderr().flush();
+    };
+    let (image, alpha) = match stereo {
+        // (Each eye is rendered in turn, the left eye last, so that the AOVs
+        // and heatmaps are of its film)
+        Some(stereo) => {
+            let (left, right) = stereo.eyes(&camera);
+            let mut right_film = Film::new(width, height);
+            renderer.render(&mut right_film, bounds, &right, &scene, settings.seed, &progress_bar);
+            eprintln!();
+            renderer.render(&mut film, bounds, &left, &scene, settings.seed, &progress_bar);
+            eprintln!();
+            (stereo::compose(stereo.layout, &film.to_framebuffer(), &right_film.to_framebuffer()),
+             stereo::compose(stereo.layout, &film.aov_framebuffer(Aov::Alpha), &right_film.aov_framebuffer(Aov::Alpha)))
+        }
+        None => {
+            renderer.render(&mut film, bounds, &camera, &scene, settings.seed, &progress_bar);
+            eprintln!();
+            (film.to_framebuffer(), film.aov_framebuffer(Aov::Alpha))
+        }
+    };
+    // (Which look up as magenta in the image)
+    for (path, why) in textures.failures() {
+        eprintln!("couldn't load the texture {}: {}", path.display(), why);
+    }
+
+    if let Some(directory) = args.output.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut metadata = Metadata::render(&settings, args.debug);
+    metadata.add_scene(&args.scene);
+    metadata.add_render_time(start.elapsed().as_secs_f64(), renderer.samples_per_pixel(&film));
+    let image = settings.post_process(image);
+    let written = if settings.writes_alpha() {
+        let alpha: Vec<Float> = alpha.pixels.iter().map(|p| p.r).collect();
+        output::write_image_with_alpha(&image, &alpha, &args.output, format, &settings.display, &metadata)
+    } else {
+        output::write_image_with_metadata(&image, &args.output, format, &settings.display, &metadata)
+    };
+    written.map_err(context(format!("couldn't write to {}", args.output.display())))?;
+    for (aov, path) in [(Aov::Depth, &args.depth), (Aov::Position, &args.position), (Aov::Alpha, &args.alpha)] {
+        if let Some(path) = path {
+            let format = OutputFormat::from_path(path)
+                .ok_or_else(|| invalid(format!("can't tell the image format of {}", path.display())))?;
+            output::write_aov(&film, aov, path, format).map_err(context(format!("couldn't write to {}", path.display())))?;
+        }
+    }
+    // (To see where adaptive sampling spent its samples, and where it gave up
+    // before the noise threshold)
+    let heatmaps = [args.sample_heatmap.as_ref().map(|p| (p, film.sample_heatmap(renderer.samples))),
+                    args.variance_heatmap.as_ref().map(|p| (p, film.variance_heatmap(renderer.noise_threshold)))];
+    for (path, heatmap) in heatmaps.iter().flatten() {
+        let format = OutputFormat::from_path(path)
+            .ok_or_else(|| invalid(format!("can't tell the image format of {}", path.display())))?;
+        output::write_image(heatmap, path, format, &DisplayTransform::raw())
+            .map_err(context(format!("couldn't write to {}", path.display())))?;
+    }
+    eprintln!("wrote {} in {:.1} seconds ({:.2} samples per pixel)",
+              args.output.display(),
+              start.elapsed().as_secs_f64(),
+              renderer.samples_per_pixel(&film));
+    if args.stats {
+        eprintln!("{}", MemoryUsage::new(&scene, &textures, Some(&film)));
+    }
+    Ok(())
+}
+
+// Bake the object's map at the render's resolution (with a ray per sample
+// from each texel), and write it to the output path
+fn write_baked_map(args: &Args,
+                  scene: &Scene,
+                  object_id: u32,
+                  settings: &RenderSettings,
+                  format: OutputFormat,
+                  start: Instant)
+                  -> io::Result<()> {
+    let bake_settings = BakeSettings {
+        width: settings.width,
+        height: settings.height,
+        samples: settings.samples,
+        max_depth: settings.max_depth,
+        seed: settings.seed,
+        ..BakeSettings::new()
+    };
+    let map = match args.bake_map {
+        BakeMap::Lightmap => bake::bake_lightmap(scene, object_id, &bake_settings)?,
+        BakeMap::Occlusion => bake::bake_occlusion(scene, object_id, &bake_settings)?,
+        BakeMap::Curvature => bake::bake_curvature(scene, object_id, &bake_settings)?,
+    };
+    if let Some(directory) = args.output.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    // (Occlusion and curvature are written as they are, rather than exposed
+    // and tone mapped like light)
+    let display = match args.bake_map {
+        BakeMap::Lightmap => settings.display,
+        _ => DisplayTransform::raw(),
+    };
+    output::write_image(&map, &args.output, format, &display)
+        .map_err(context(format!("couldn't write to {}", args.output.display())))?;
+    eprintln!("baked {} in {:.1} seconds", args.output.display(), start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+// Bake the probes, writing each one's cubemap beside the output path (e.g.
+// "probes.exr" as "probes_0_px.exr", and so on) and all of their spherical
+// harmonics to one TOML file ("probes.toml")
+fn write_probes(args: &Args,
+                scene: &Scene,
+                settings: &RenderSettings,
+                format: OutputFormat,
+                start: Instant)
+                -> io::Result<()> {
+    let probe_settings = ProbeSettings {
+        resolution: settings.height,
+        samples: settings.samples,
+        max_depth: settings.max_depth,
+        seed: settings.seed,
+    };
+    if let Some(directory) = args.output.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let stem = args.output.file_stem().and_then(|s| s.to_str()).unwrap_or("probes");
+    let mut probes = Vec::new();
+    for (i, (faces, probe)) in probe::bake_probes(scene, &args.probe, &probe_settings).into_iter().enumerate() {
+        let path = args.output.with_file_name(format!("{}_{}.{}", stem, i, format.extension()));
+        probe::write_cubemap(&faces, &path, format, &settings.display)
+            .map_err(context(format!("couldn't write to {}", path.display())))?;
+        probes.push(probe);
+    }
+    let path = args.output.with_extension("toml");
+    probe::write_sh_probes(&probes, &path).map_err(context(format!("couldn't write to {}", path.display())))?;
+    eprintln!("baked {} probes to {} in {:.1} seconds",
+              probes.len(),
+              path.display(),
+              start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+fn main() {
+    // (Spans are logged as they close, with how long they took)
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .init();
+    if let Err(why) = render(&Args::parse()) {
+        eprintln!("tracer: {}", why);
+        process::exit(1);
+    }
+}
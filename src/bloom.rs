@@ -0,0 +1,134 @@
+use vector::Float;
+use color::Color;
+use framebuffer::Framebuffer;
+
+use serde::{Deserialize, Serialize};
+
+// The glare of a camera's lens (or of an eye), which scatters a little of the
+// light reaching each point of the image over the points around it: it's
+// invisible but for the brightest parts of an image, which it surrounds with a
+// glow. The scattered light falls off steeply, but with a long tail (see:
+// "Physically-Based Glare Effects for Digital Images", Spencer et al.), which
+// the point spread function approximates with a sum of Gaussians, each half
+// as wide as the last
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bloom {
+    // The fraction of the light that's scattered
+    pub intensity: Float,
+    // The standard deviation of the widest Gaussian, as a fraction of the
+    // image's height (so that the glare looks the same at every resolution)
+    pub radius: Float,
+    // Only the radiance above this scatters, for glare that leaves the rest
+    // of the image sharp (which isn't physical, but can look it)
+    pub threshold: Float,
+}
+
+// The number of Gaussians in the point spread function
+const LEVELS: i32 = 5;
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom {
+            intensity: 0.04,
+            radius: 0.1,
+            threshold: 0.0,
+        }
+    }
+
+    // Scatter the (linear, high dynamic range) image's light, before it's
+    // tone mapped: as much light is taken away from each pixel as it spreads
+    // over the others, so the image is no brighter overall
+    pub fn apply(&self, image: &Framebuffer) -> Framebuffer {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let bright: Vec<Color> = image.pixels
+            .iter()
+            .map(|c| if c.is_finite() { c.map(|v| (v - self.threshold).max(0.0)) } else { Color::black() })
+            .collect();
+        let mut glare = vec![Color::black(); bright.len()];
+        for level in 0..LEVELS {
+            let sigma = self.radius * height as Float / Float::powi(2.0, level);
+            for (g, b) in glare.iter_mut().zip(gaussian(&bright, width, height, sigma)) {
+                *g += b / LEVELS as Float;
+            }
+        }
+        Framebuffer {
+            width: image.width,
+            height: image.height,
+            pixels: image.pixels
+                .iter()
+                .zip(bright.iter().zip(&glare))
+                .map(|(c, (b, g))| *c + (*g - *b) * self.intensity)
+                .collect(),
+        }
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom::new()
+    }
+}
+
+// Blur with (nearly) a Gaussian of standard deviation `sigma`, in pixels, as
+// three box blurs along the rows and then three along the columns
+fn gaussian(pixels: &[Color], width: usize, height: usize, sigma: Float) -> Vec<Color> {
+    // (Three boxes 2r + 1 pixels wide have a variance of (4r^2 + 4r) / 4)
+    let radius = (0.5 * ((4.0 * sigma * sigma + 1.0).sqrt() - 1.0)).round() as usize;
+    if radius == 0 {
+        return pixels.to_vec();
+    }
+    let rows = (0..3).fold(pixels.to_vec(), |p, _| box_blur_rows(&p, width, radius));
+    let columns = (0..3).fold(transpose(&rows, width, height), |p, _| box_blur_rows(&p, height, radius));
+    transpose(&columns, height, width)
+}
+
+// Average the pixels within `radius` of each pixel along its row (clipped to
+// the row), with a running sum of the window
+fn box_blur_rows(pixels: &[Color], width: usize, radius: usize) -> Vec<Color> {
+    let mut output = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks(width) {
+        let mut sum = row[..radius.min(width)].iter().fold(Color::black(), |s, c| s + *c);
+        for x in 0..width {
+            if x + radius < width {
+                sum += row[x + radius];
+            }
+            if x > radius {
+                sum = sum - row[x - radius - 1];
+            }
+            let count = (x + radius + 1).min(width) - x.saturating_sub(radius);
+            output.push(sum / count as Float);
+        }
+    }
+    output
+}
+
+fn transpose(pixels: &[Color], width: usize, height: usize) -> Vec<Color> {
+    let mut output = Vec::with_capacity(pixels.len());
+    for x in 0..width {
+        for y in 0..height {
+            output.push(pixels[y * width + x]);
+        }
+    }
+    output
+}
+
+#[test]
+fn test_bloom() {
+    // A bright pixel glows, without the image getting any brighter (away from
+    // its edges, where the glare is clipped)
+    let mut image = Framebuffer::new(64, 64);
+    image.set(32, 32, &Color::gray(100.0));
+    let bloom = Bloom::new();
+    let bloomed = bloom.apply(&image);
+    assert!(bloomed.get(32, 32).r < 100.0 && bloomed.get(36, 30).r > 0.0);
+    let total = |f: &Framebuffer| f.pixels.iter().map(|p| p.r).sum::<Float>();
+    assert!((total(&bloomed) - total(&image)).abs() < 0.01 * total(&image));
+
+    // Nothing below the threshold scatters
+    let mut dim = Framebuffer::new(16, 16);
+    dim.pixels = vec![Color::gray(0.5); 256];
+    dim.set(4, 4, &Color::gray(0.8));
+    let thresholded = Bloom { threshold: 1.0, ..bloom }.apply(&dim);
+    assert!(thresholded.pixels.iter().zip(&dim.pixels).all(|(a, b)| (a.r - b.r).abs() < 1e-6));
+}
@@ -0,0 +1,250 @@
+use vector::Float;
+use sampler::Sampler;
+use sampler::hash;
+use sampler::hash_combine;
+use rng::seeded_rng;
+use rng::Rng;
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+// The standard deviation of the Gaussian filter used to measure how tightly
+// clustered the points of a binary pattern are
+const SIGMA: Float = 1.5;
+
+// A tileable threshold mask whose values are distributed as blue noise, i.e.
+// neighboring texels tend to have very different values
+#[derive(Serialize, Deserialize)]
+pub struct BlueNoiseMask {
+    pub size: usize,
+    // One value in [0, 1) per texel, stored in row-major order
+    values: Vec<Float>,
+}
+
+impl BlueNoiseMask {
+    // Generate a `size` x `size` mask with the void-and-cluster method (see:
+    // "The void-and-cluster method for dither array generation", Ulichney)
+    pub fn new(size: usize, seed: u32) -> BlueNoiseMask {
+        let n = size * size;
+        let mut rng = seeded_rng(seed);
+
+        // Precompute the (toroidally wrapped) filter for every offset
+        let mut kernel = vec![0.0; n];
+        for dy in 0..size {
+            for dx in 0..size {
+                let wx = dx.min(size - dx) as Float;
+                let wy = dy.min(size - dy) as Float;
+                kernel[dy * size + dx] = (-(wx * wx + wy * wy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+
+        let mut pattern = Pattern::new(size, &kernel);
+
+        // Start with a random set of points...
+        let initial_points = (n / 10).max(1);
+        while pattern.count < initial_points {
+            let i = rng.gen_range(0, n);
+            if !pattern.bits[i] {
+                pattern.set(i, true);
+            }
+        }
+
+        // ...and repeatedly move the point in the tightest cluster into the
+        // largest void until the pattern is evenly distributed
+        loop {
+            let cluster = pattern.tightest_cluster();
+            pattern.set(cluster, false);
+            let void = pattern.largest_void();
+            if void == cluster {
+                pattern.set(cluster, true);
+                break;
+            }
+            pattern.set(void, true);
+        }
+
+        let mut ranks = vec![0; n];
+
+        // Phase 1: rank the initial points by removing clusters
+        let mut prototype = pattern.clone();
+        let mut rank = prototype.count;
+        while rank > 0 {
+            let cluster = prototype.tightest_cluster();
+            prototype.set(cluster, false);
+            rank -= 1;
+            ranks[cluster] = rank;
+        }
+
+        // Phase 2: fill the largest voids until half of the texels are set
+        let mut rank = pattern.count;
+        while rank < n / 2 {
+            let void = pattern.largest_void();
+            pattern.set(void, true);
+            ranks[void] = rank;
+            rank += 1;
+        }
+
+        // Phase 3: the unset texels are now the minority, so rank them by
+        // filling the tightest clusters of *unset* texels
+        let mut inverse = pattern.inverted();
+        while rank < n {
+            let cluster = inverse.tightest_cluster();
+            inverse.set(cluster, false);
+            ranks[cluster] = rank;
+            rank += 1;
+        }
+
+        BlueNoiseMask {
+            size,
+            values: ranks.iter().map(|&r| (r as Float + 0.5) / n as Float).collect(),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Float {
+        let x = x as usize % self.size;
+        let y = y as usize % self.size;
+        self.values[y * self.size + x]
+    }
+}
+
+// A binary pattern along with the filtered "energy" at every texel
+#[derive(Clone)]
+struct Pattern<'a> {
+    size: usize,
+    kernel: &'a [Float],
+    bits: Vec<bool>,
+    energy: Vec<Float>,
+    count: usize,
+}
+
+impl<'a> Pattern<'a> {
+    fn new(size: usize, kernel: &'a [Float]) -> Pattern<'a> {
+        Pattern {
+            size,
+            kernel,
+            bits: vec![false; size * size],
+            energy: vec![0.0; size * size],
+            count: 0,
+        }
+    }
+
+    fn inverted(&self) -> Pattern<'a> {
+        let mut inverse = Pattern::new(self.size, self.kernel);
+        for (i, &bit) in self.bits.iter().enumerate() {
+            if !bit {
+                inverse.set(i, true);
+            }
+        }
+        inverse
+    }
+
+    fn set(&mut self, i: usize, value: bool) {
+        if self.bits[i] == value {
+            return;
+        }
+        self.bits[i] = value;
+        let sign = if value { 1.0 } else { -1.0 };
+        if value {
+            self.count += 1;
+        } else {
+            self.count -= 1;
+        }
+
+        // Update the energy of every texel affected by this one
+        let (x, y) = (i % self.size, i / self.size);
+        for ty in 0..self.size {
+            let dy = (ty + self.size - y) % self.size;
+            for tx in 0..self.size {
+                let dx = (tx + self.size - x) % self.size;
+                self.energy[ty * self.size + tx] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    // The set texel with the highest energy
+    fn tightest_cluster(&self) -> usize {
+        let mut best = None;
+        for (i, &e) in self.energy.iter().enumerate() {
+            if self.bits[i] && best.is_none_or(|(_, b)| e > b) {
+                best = Some((i, e));
+            }
+        }
+        best.expect("pattern has no set texels").0
+    }
+
+    // The unset texel with the lowest energy
+    fn largest_void(&self) -> usize {
+        let mut best = None;
+        for (i, &e) in self.energy.iter().enumerate() {
+            if !self.bits[i] && best.is_none_or(|(_, b)| e < b) {
+                best = Some((i, e));
+            }
+        }
+        best.expect("pattern has no unset texels").0
+    }
+}
+
+// Wraps another sampler so that every pixel shares the same sample sequence,
+// offset per pixel by a blue-noise mask: at low sample counts, the resulting
+// error is distributed as blue noise rather than white noise
+pub struct BlueNoiseSampler {
+    inner: Box<dyn Sampler>,
+    mask: Arc<BlueNoiseMask>,
+    x: u32,
+    y: u32,
+    dimension: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(inner: Box<dyn Sampler>, mask: Arc<BlueNoiseMask>) -> BlueNoiseSampler {
+        BlueNoiseSampler {
+            inner,
+            mask,
+            x: 0,
+            y: 0,
+            dimension: 0,
+        }
+    }
+
+    fn offset(&mut self) -> Float {
+        // Use a different toroidal shift of the mask for each dimension so
+        // that the dimensions remain uncorrelated
+        let h = hash(self.dimension);
+        let shift_x = h & 0xffff;
+        let shift_y = hash_combine(h, 1) & 0xffff;
+        self.dimension += 1;
+        self.mask.get(self.x + shift_x, self.y + shift_y)
+    }
+}
+
+fn wrap(u: Float) -> Float {
+    if u >= 1.0 { u - 1.0 } else { u }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.inner.start_sample(0, 0, index);
+        self.x = x;
+        self.y = y;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let u = self.inner.next_1d();
+        wrap(u + self.offset())
+    }
+
+    fn next_2d(&mut self) -> (Float, Float) {
+        let (u, v) = self.inner.next_2d();
+        (wrap(u + self.offset()), wrap(v + self.offset()))
+    }
+}
+
+#[test]
+fn test_mask_is_permutation() {
+    // Every threshold value should appear exactly once
+    let mask = BlueNoiseMask::new(16, 1);
+    let mut ranks: Vec<usize> = mask.values.iter().map(|v| (v * 256.0) as usize).collect();
+    ranks.sort();
+    assert_eq!(ranks, (0..256).collect::<Vec<usize>>());
+}
@@ -0,0 +1,293 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Geometry;
+use shape::Sphere;
+use shape::Plane;
+use shape::Triangle;
+use shape::Quad;
+use mesh::Mesh;
+use material::Material;
+use material::Lambertian;
+use primitive::Primitive;
+use scene::Scene;
+use camera::Camera;
+use transform::Transform;
+use texture::TextureCache;
+use texture::DEFAULT_BUDGET;
+use obj;
+use obj::Obj;
+use error::Result;
+use error::TracerError;
+
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Builds a scene by chaining calls, where each shape takes the material and
+// placement given after it, e.g.
+//
+//     let scene = SceneBuilder::new()
+//         .define_material("white", Lambertian::new(&Color::white()))
+//         .plane(&Vector::new(0.0, -0.5, 0.0), &Vector::new(0.0, 1.0, 0.0)).material_named("white")
+//         .sphere(&Vector::zero(), 0.5).material(Dielectric::new(1.5)).at(&Vector::new(0.0, 0.0, -1.0))
+//         .mesh_from_obj(Path::new("models/bunny.obj")).scaled(&Vector::new(2.0, 2.0, 2.0))
+//         .camera("front", &Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 60.0)
+//         .build()?;
+//
+// Shapes without a material are a neutral gray (except for OBJ models,
+// which bring their own), and any mistake (e.g. a model that can't be
+// loaded) is returned by `build`
+pub struct SceneBuilder {
+    scene: Scene,
+    materials: HashMap<String, Arc<dyn Material>>,
+    default_material: Arc<dyn Material>,
+    textures: Arc<TextureCache>,
+    aspect_ratio: Float,
+    cameras: Vec<(String, Vector, Vector, Float)>,
+    // The most recent shape, which is added once the next one begins
+    object: Option<Object>,
+    error: Option<TracerError>,
+}
+
+enum Shape {
+    Geometry(Geometry),
+    Obj(Obj),
+}
+
+struct Object {
+    shape: Shape,
+    material: Option<Arc<dyn Material>>,
+    translation: Vector,
+    rotation: Vector,
+    scale: Vector,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::new(),
+            materials: HashMap::new(),
+            default_material: Arc::new(Lambertian::new(&Color::gray(0.5))),
+            textures: Arc::new(TextureCache::new(DEFAULT_BUDGET)),
+            aspect_ratio: 1.0,
+            cameras: Vec::new(),
+            object: None,
+            error: None,
+        }
+    }
+
+    // Load textures (of the OBJ models' materials) through this cache
+    pub fn textures(mut self, textures: &Arc<TextureCache>) -> SceneBuilder {
+        self.textures = textures.clone();
+        self
+    }
+
+    // The aspect ratio of the images that the cameras render (1 by default)
+    pub fn aspect_ratio(mut self, aspect_ratio: Float) -> SceneBuilder {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    // A camera at `from` looking towards `to`, with a vertical field of view
+    // of `fov` degrees
+    pub fn camera(mut self, name: &str, from: &Vector, to: &Vector, fov: Float) -> SceneBuilder {
+        self.cameras.push((name.to_string(), *from, *to, fov));
+        self
+    }
+
+    // Name a material, which any number of shapes can then share (see:
+    // `material_named`)
+    pub fn define_material<M: Material + 'static>(mut self, name: &str, material: M) -> SceneBuilder {
+        self.materials.insert(name.to_string(), Arc::new(material));
+        self
+    }
+
+    pub fn shape<G: Into<Geometry>>(mut self, shape: G) -> SceneBuilder {
+        self.begin(Shape::Geometry(shape.into()));
+        self
+    }
+
+    pub fn sphere(self, center: &Vector, radius: Float) -> SceneBuilder {
+        self.shape(Sphere::new(center, radius))
+    }
+
+    pub fn plane(self, center: &Vector, normal: &Vector) -> SceneBuilder {
+        self.shape(Plane::new(center, normal))
+    }
+
+    pub fn triangle(self, a: &Vector, b: &Vector, c: &Vector) -> SceneBuilder {
+        self.shape(Triangle::new(a, b, c))
+    }
+
+    pub fn quad(self, corner: &Vector, u: &Vector, v: &Vector) -> SceneBuilder {
+        self.shape(Quad::new(corner, u, v))
+    }
+
+    pub fn mesh(self, mesh: Mesh) -> SceneBuilder {
+        self.shape(mesh)
+    }
+
+    // An OBJ model (see: `obj::load`), whose groups are each a primitive
+    // with the model's own materials, unless it's given one
+    pub fn mesh_from_obj(mut self, path: &Path) -> SceneBuilder {
+        match obj::load(path, &self.textures) {
+            Ok(model) => self.begin(Shape::Obj(model)),
+            Err(why) => self.fail(format!("couldn't load {}: {}", path.display(), why)),
+        }
+        self
+    }
+
+    // The most recent shape's material
+    pub fn material<M: Material + 'static>(self, material: M) -> SceneBuilder {
+        self.set_material(Arc::new(material))
+    }
+
+    pub fn material_named(mut self, name: &str) -> SceneBuilder {
+        match self.materials.get(name).cloned() {
+            Some(material) => self.set_material(material),
+            None => {
+                self.fail(format!("no material named {:?}", name));
+                self
+            }
+        }
+    }
+
+    // Move the most recent shape (after it's scaled and rotated)
+    pub fn at(self, translation: &Vector) -> SceneBuilder {
+        self.modify("at", |object| object.translation = *translation)
+    }
+
+    // Rotate the most recent shape about its origin, by Euler angles in
+    // degrees (see: `Transform`)
+    pub fn rotated(self, rotation: &Vector) -> SceneBuilder {
+        self.modify("rotated", |object| object.rotation = *rotation)
+    }
+
+    pub fn scaled(self, scale: &Vector) -> SceneBuilder {
+        self.modify("scaled", |object| object.scale = *scale)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "build_scene", skip_all))]
+    pub fn build(mut self) -> Result<Scene> {
+        self.end();
+        if let Some(why) = self.error {
+            return Err(why);
+        }
+        for (name, from, to, fov) in &self.cameras {
+            self.scene.add_camera(name, Camera::look_at(from, to, &Vector::new(0.0, 1.0, 0.0), *fov, self.aspect_ratio));
+        }
+        self.scene.build_bvh();
+        Ok(self.scene)
+    }
+
+    // Record the first mistake, for `build` to return
+    fn fail(&mut self, why: String) {
+        if self.error.is_none() {
+            self.error = Some(TracerError::InvalidParameter(why));
+        }
+    }
+
+    fn modify<F: FnOnce(&mut Object)>(mut self, modifier: &str, f: F) -> SceneBuilder {
+        match self.object {
+            Some(ref mut object) => f(object),
+            None => self.fail(format!("{}() before any shape", modifier)),
+        }
+        self
+    }
+
+    fn set_material(self, material: Arc<dyn Material>) -> SceneBuilder {
+        self.modify("material", |object| object.material = Some(material))
+    }
+
+    fn begin(&mut self, shape: Shape) {
+        self.end();
+        self.object = Some(Object {
+            shape,
+            material: None,
+            translation: Vector::zero(),
+            rotation: Vector::zero(),
+            scale: Vector::one(),
+        });
+    }
+
+    // Add the most recent shape to the scene
+    fn end(&mut self) {
+        let object = match self.object.take() {
+            Some(object) => object,
+            None => return,
+        };
+        let first = self.scene.items.len();
+        match (object.shape, object.material) {
+            (Shape::Geometry(shape), material) => {
+                let material = material.unwrap_or_else(|| self.default_material.clone());
+                self.scene.add(Primitive::new(shape, material));
+            }
+            (Shape::Obj(model), Some(material)) => {
+                for group in model.groups {
+                    let mut mesh = group.mesh;
+                    mesh.material_ids = None;
+                    self.scene.add(Primitive::new(mesh, material.clone()));
+                }
+            }
+            (Shape::Obj(model), None) => {
+                model.add_to(&mut self.scene);
+            }
+        }
+        let transform = Transform::new(&object.translation, &object.rotation, &object.scale, &Vector::zero());
+        for item in &mut self.scene.items[first..] {
+            item.transform = transform;
+        }
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+}
+
+#[test]
+fn test_scene_builder() {
+    use material::Dielectric;
+    use ray::Ray;
+    use std::fs;
+
+    let path = ::std::env::temp_dir().join("tracer_test_scene_builder.obj");
+    fs::write(&path, "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    let scene = SceneBuilder::new()
+        .define_material("red", Lambertian::new(&Color::new(1.0, 0.0, 0.0)))
+        .sphere(&Vector::zero(), 0.5)
+        .material_named("red")
+        .at(&Vector::new(0.0, 0.0, -2.0))
+        .sphere(&Vector::new(0.0, 2.0, -2.0), 0.5)
+        .material_named("red")
+        .mesh_from_obj(&path)
+        .material(Dielectric::new(1.5))
+        .scaled(&Vector::new(2.0, 2.0, 2.0))
+        .at(&Vector::new(0.0, 0.0, -5.0))
+        .plane(&Vector::new(0.0, -1.0, 0.0), &Vector::new(0.0, 1.0, 0.0))
+        .camera("front", &Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 60.0)
+        .build()
+        .unwrap();
+    assert_eq!(scene.items.len(), 4);
+    // (The spheres share their material, and the plane has the default)
+    assert_eq!(scene.materials.len(), 3);
+    assert!(scene.camera("front").is_some());
+
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 1.5).abs() < 1e-6);
+    assert_eq!(material.albedo(), Color::new(1.0, 0.0, 0.0));
+    let r = Ray::new(&Vector::new(1.5, -1.5, 0.0), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let (dg, _) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 5.0).abs() < 1e-6);
+
+    // Mistakes surface when the scene is built
+    assert!(SceneBuilder::new().sphere(&Vector::zero(), 1.0).material_named("blue").build().is_err());
+    assert!(SceneBuilder::new().at(&Vector::zero()).build().is_err());
+    assert!(SceneBuilder::new().mesh_from_obj(Path::new("missing.obj")).build().is_err());
+}
@@ -0,0 +1,607 @@
+use vector::Vector;
+use vector::Float;
+#[cfg(feature = "simd")]
+use vector::Lanes;
+use ray::Ray;
+use aabb::Aabb;
+use stats;
+use stats::Counter;
+
+use rayon;
+use rayon::prelude::*;
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::cmp::Ordering;
+#[cfg(feature = "simd")]
+use std::marker::PhantomData;
+use std::mem;
+
+// Leaves hold at most this many primitives (unless they can't be separated)
+const MAX_LEAF_SIZE: usize = 4;
+// The number of bins that candidate splits are chosen between
+const BINS: usize = 12;
+// The tree is never deeper than this, so that traversal can keep the nodes
+// that it has yet to visit on a fixed stack: past `MEDIAN_DEPTH`, nodes are
+// split about their median, rather than by the surface area heuristic (which
+// may split primitives that are spread out unevenly, e.g. exponentially, off
+// one at a time), and at the limit they are left as leaves
+const MAX_DEPTH: usize = 64;
+const MEDIAN_DEPTH: usize = 48;
+// Nodes with fewer primitives than this are built on a single thread, since
+// spawning work for them costs more than it saves
+const PARALLEL_THRESHOLD: usize = 1024;
+// The most rays that are traced through the hierarchy together (see:
+// `Bvh::traverse_packet`)
+pub const PACKET_SIZE: usize = 4;
+
+// A node of the flattened tree: interior nodes are followed by their first
+// child, and `offset` is the index of their second child, while leaves
+// refer to `count` primitives starting at `offset` (in `Bvh::indices`)
+#[derive(Copy, Clone, Debug, Default)]
+struct Node {
+    bounds: Aabb,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+// The shape of a built hierarchy (see: `Bvh::stats`), to see what it made of
+// a scene
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    // The number of leaves at each depth (the root is at depth 0)
+    pub leaf_depths: Vec<usize>,
+    // The primitives in the leaves, and those without bounds (which every
+    // ray is tested against)
+    pub primitives: usize,
+    pub unbounded: usize,
+    // The (approximate) number of bytes that the hierarchy takes
+    pub memory: usize,
+}
+
+impl BvhStats {
+    pub fn max_depth(&self) -> usize {
+        self.leaf_depths.len().saturating_sub(1)
+    }
+
+    pub fn mean_leaf_size(&self) -> Float {
+        self.primitives as Float / self.leaves.max(1) as Float
+    }
+
+    // Add the stats of another hierarchy (e.g. to total those of many meshes)
+    pub fn add(&mut self, other: &BvhStats) {
+        self.nodes += other.nodes;
+        self.leaves += other.leaves;
+        if self.leaf_depths.len() < other.leaf_depths.len() {
+            self.leaf_depths.resize(other.leaf_depths.len(), 0);
+        }
+        for (total, count) in self.leaf_depths.iter_mut().zip(&other.leaf_depths) {
+            *total += count;
+        }
+        self.primitives += other.primitives;
+        self.unbounded += other.unbounded;
+        self.memory += other.memory;
+    }
+}
+
+// A primitive as seen by the builder
+#[derive(Copy, Clone, Debug)]
+struct BuildItem {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vector,
+}
+
+// A bounding volume hierarchy over the primitives of a scene, which refers
+// to them by their index: it's built with a binned surface area heuristic,
+// in parallel (both the binning of large nodes and the building of each
+// node's two subtrees), and then flattened into an array for traversal
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+    // Primitives without bounds, which every ray is tested against
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    // Build the hierarchy, given the bounds of each primitive
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "build_bvh", skip_all, fields(primitives = bounds.len())))]
+    pub fn new(bounds: &[Option<Aabb>]) -> Bvh {
+        let mut items: Vec<BuildItem> = bounds.par_iter()
+            .enumerate()
+            .filter_map(|(index, b)| {
+                b.map(|bounds| {
+                    BuildItem {
+                        index,
+                        bounds,
+                        centroid: bounds.centroid(),
+                    }
+                })
+            })
+            .collect();
+        let unbounded: Vec<usize> = bounds.iter().enumerate().filter(|&(_, b)| b.is_none()).map(|(i, _)| i).collect();
+
+        // The tree is built into one buffer, in which each subtree is given
+        // as many nodes as it could possibly need (a subtree over n
+        // primitives has at most 2n - 1 nodes), so that the two halves of the
+        // buffer can be handed to the threads building either subtree: the
+        // nodes are then packed together, so that building the tree takes
+        // two allocations, rather than one per node
+        let mut nodes = Vec::new();
+        if !items.is_empty() {
+            let mut buffer = vec![Node::default(); 2 * items.len() - 1];
+            let used = build(&mut items, 0, &mut buffer, 0, 0);
+            nodes.reserve_exact(used);
+            pack(&buffer, 0, &mut nodes);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(nodes = nodes.len(), unbounded = unbounded.len(), "built the BVH");
+        Bvh {
+            nodes,
+            indices: items.iter().map(|item| item.index).collect(),
+            unbounded,
+        }
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            nodes: self.nodes.len(),
+            unbounded: self.unbounded.len(),
+            memory: self.nodes.capacity() * mem::size_of::<Node>() +
+                    (self.indices.capacity() + self.unbounded.capacity()) * mem::size_of::<usize>(),
+            ..BvhStats::default()
+        };
+        let mut stack = if self.nodes.is_empty() { vec![] } else { vec![(0, 0)] };
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                if stats.leaf_depths.len() <= depth {
+                    stats.leaf_depths.resize(depth + 1, 0);
+                }
+                stats.leaf_depths[depth] += 1;
+                stats.leaves += 1;
+                stats.primitives += node.count;
+            } else {
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset, depth + 1));
+            }
+        }
+        stats
+    }
+
+    // The bounds of everything in the hierarchy
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map(|node| node.bounds).unwrap_or_default()
+    }
+
+    // Call `visit` with the index of every primitive whose bounds the ray
+    // passes through (nearest subtrees first), up until the closest hit so
+    // far: `visit` tests the primitive, and returns the distance to the
+    // closest hit found so far (or the ray's `t_max`, if there is none)
+    pub fn traverse<F>(&self, r: &Ray, mut visit: F)
+        where F: FnMut(usize) -> Float
+    {
+        self.walk(r, |index, t_max| {
+            *t_max = visit(index);
+            false
+        });
+    }
+
+    // Does `test` return true for any primitive whose bounds the ray passes
+    // through (before its `t_max`)? This stops at the first one that does,
+    // e.g. for shadow rays, which only need to know if anything is in the way
+    pub fn any<F>(&self, r: &Ray, mut test: F) -> bool
+        where F: FnMut(usize) -> bool
+    {
+        self.walk(r, |index, _| test(index))
+    }
+
+    // Call `visit` for every primitive that the ray might hit (nearest
+    // subtrees first), which may shorten the ray, until it returns true
+    // (returning whether it did)
+    fn walk<F>(&self, r: &Ray, mut visit: F) -> bool
+        where F: FnMut(usize, &mut Float) -> bool
+    {
+        let mut t_max = r.t_max;
+        for &index in &self.unbounded {
+            if visit(index, &mut t_max) {
+                return true;
+            }
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inverse_direction = Vector::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut current = 0;
+        loop {
+            stats::count(Counter::BvhNodeVisits);
+            let node = &self.nodes[current];
+            if node.bounds.intersect(r, &inverse_direction, t_max) {
+                if node.count > 0 {
+                    for &index in &self.indices[node.offset..node.offset + node.count] {
+                        if visit(index, &mut t_max) {
+                            return true;
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side of the split first
+                    let (near, far) = if r.direction.component(node.axis) < 0.0 {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack[stack_size] = far;
+                    stack_size += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if stack_size == 0 {
+                return false;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+    }
+
+    // As `traverse`, for a packet of rays (e.g. the camera rays of
+    // neighboring pixels), which are tested against each node's bounds
+    // together, `PACKET_SIZE` at a time: coherent rays pass through mostly
+    // the same nodes, so they share most of the work of finding them.
+    // `visit` is also given the index of the ray (in `rays`), and returns
+    // the distance to that ray's closest hit so far
+    pub fn traverse_packet<F>(&self, rays: &[Ray], mut visit: F)
+        where F: FnMut(usize, usize) -> Float
+    {
+        self.walk_packet(rays, |ray, index, t_max| {
+            *t_max = visit(ray, index);
+            false
+        });
+    }
+
+    // As `any`, for a packet of rays, returning which of them `test` returned
+    // true for
+    pub fn any_packet<F>(&self, rays: &[Ray], mut test: F) -> Vec<bool>
+        where F: FnMut(usize, usize) -> bool
+    {
+        let mut hits = vec![false; rays.len()];
+        self.walk_packet(rays, |ray, index, _| {
+            hits[ray] = test(ray, index);
+            hits[ray]
+        });
+        hits
+    }
+
+    // As `walk`, for a packet of rays: each ray stays active until `visit`
+    // returns true for it, and each node is visited by the active rays that
+    // pass through its bounds, in the order of the first of them
+    fn walk_packet<F>(&self, rays: &[Ray], mut visit: F)
+        where F: FnMut(usize, usize, &mut Float) -> bool
+    {
+        for (packet, rays) in rays.chunks(PACKET_SIZE).enumerate() {
+            let first = packet * PACKET_SIZE;
+            let mut active = [false; PACKET_SIZE];
+            let mut t_max = [0.0; PACKET_SIZE];
+            for (lane, r) in rays.iter().enumerate() {
+                active[lane] = true;
+                t_max[lane] = r.t_max;
+            }
+            let mut visit_lanes = |index: usize, lanes: &[bool; PACKET_SIZE], active: &mut [bool; PACKET_SIZE], t_max: &mut [Float; PACKET_SIZE]| {
+                for lane in 0..rays.len() {
+                    if lanes[lane] && active[lane] && visit(first + lane, index, &mut t_max[lane]) {
+                        active[lane] = false;
+                    }
+                }
+            };
+            for &index in &self.unbounded {
+                visit_lanes(index, &[true; PACKET_SIZE], &mut active, &mut t_max);
+            }
+            if self.nodes.is_empty() {
+                continue;
+            }
+
+            let packet = Packet::new(rays);
+            let mut stack = [0usize; MAX_DEPTH];
+            let mut stack_size = 0;
+            let mut current = 0;
+            loop {
+                stats::count(Counter::BvhNodeVisits);
+                let node = &self.nodes[current];
+                let hits = packet.intersect(&node.bounds, &active, &t_max);
+                if let Some(lead) = hits.iter().position(|&hit| hit) {
+                    if node.count > 0 {
+                        for &index in &self.indices[node.offset..node.offset + node.count] {
+                            visit_lanes(index, &hits, &mut active, &mut t_max);
+                        }
+                    } else {
+                        // Visit the child on the near side of the split (for
+                        // the first of the rays) first
+                        let (near, far) = if rays[lead].direction.component(node.axis) < 0.0 {
+                            (node.offset, current + 1)
+                        } else {
+                            (current + 1, node.offset)
+                        };
+                        stack[stack_size] = far;
+                        stack_size += 1;
+                        current = near;
+                        continue;
+                    }
+                }
+                if stack_size == 0 {
+                    break;
+                }
+                stack_size -= 1;
+                current = stack[stack_size];
+            }
+        }
+    }
+}
+
+// Up to `PACKET_SIZE` rays, set up to be tested against the bounds of many
+// nodes: with the `simd` feature, their origins and reciprocal directions are
+// laid out by axis, so that every ray's slabs are found at once
+struct Packet<'a> {
+    #[cfg(not(feature = "simd"))]
+    rays: &'a [Ray],
+    #[cfg(not(feature = "simd"))]
+    inverse_directions: [Vector; PACKET_SIZE],
+    #[cfg(feature = "simd")]
+    origin: [Lanes; 3],
+    #[cfg(feature = "simd")]
+    inverse_direction: [Lanes; 3],
+    #[cfg(feature = "simd")]
+    t_min: Lanes,
+    #[cfg(feature = "simd")]
+    rays: PhantomData<&'a [Ray]>,
+}
+
+impl<'a> Packet<'a> {
+    #[cfg(not(feature = "simd"))]
+    fn new(rays: &'a [Ray]) -> Packet<'a> {
+        let mut inverse_directions = [Vector::zero(); PACKET_SIZE];
+        for (inverse, r) in inverse_directions.iter_mut().zip(rays) {
+            *inverse = Vector::new(1.0 / r.direction.x, 1.0 / r.direction.y, 1.0 / r.direction.z);
+        }
+        Packet { rays, inverse_directions }
+    }
+
+    // (The lanes past the end of the packet are never active, so what they
+    // hold doesn't matter)
+    #[cfg(feature = "simd")]
+    fn new(rays: &'a [Ray]) -> Packet<'a> {
+        let lanes = |f: &dyn Fn(&Ray) -> Float| {
+            let mut values = [0.0; PACKET_SIZE];
+            for (value, r) in values.iter_mut().zip(rays) {
+                *value = f(r);
+            }
+            Lanes::new(values)
+        };
+        Packet {
+            origin: [lanes(&|r| r.origin.x), lanes(&|r| r.origin.y), lanes(&|r| r.origin.z)],
+            inverse_direction: [lanes(&|r| 1.0 / r.direction.x),
+                                lanes(&|r| 1.0 / r.direction.y),
+                                lanes(&|r| 1.0 / r.direction.z)],
+            t_min: lanes(&|r| r.t_min),
+            rays: PhantomData,
+        }
+    }
+
+    // Which of the active rays pass through the bounds before their `t_max`
+    #[cfg(not(feature = "simd"))]
+    fn intersect(&self, bounds: &Aabb, active: &[bool; PACKET_SIZE], t_max: &[Float; PACKET_SIZE]) -> [bool; PACKET_SIZE] {
+        let mut hits = [false; PACKET_SIZE];
+        for (lane, r) in self.rays.iter().enumerate() {
+            hits[lane] = active[lane] && bounds.intersect(r, &self.inverse_directions[lane], t_max[lane]);
+        }
+        hits
+    }
+
+    #[cfg(feature = "simd")]
+    fn intersect(&self, bounds: &Aabb, active: &[bool; PACKET_SIZE], t_max: &[Float; PACKET_SIZE]) -> [bool; PACKET_SIZE] {
+        let mask = bounds.intersect_lanes(&self.origin, &self.inverse_direction, self.t_min, Lanes::new(*t_max)).move_mask();
+        let mut hits = [false; PACKET_SIZE];
+        for (lane, hit) in hits.iter_mut().enumerate() {
+            *hit = active[lane] && mask & (1 << lane) != 0;
+        }
+        hits
+    }
+}
+
+fn bin_of(centroid: &Vector, centroid_bounds: &Aabb, axis: usize) -> usize {
+    let extent = centroid_bounds.diagonal().component(axis);
+    let offset = (centroid.component(axis) - centroid_bounds.min.component(axis)) / extent;
+    ((offset * BINS as Float) as usize).min(BINS - 1)
+}
+
+// Build the subtree over the given primitives (the first of which is the
+// `first`th in the final order) into the given nodes (the first of which is
+// the `base`th in the buffer), at the given depth, returning the number of
+// nodes used
+fn build(items: &mut [BuildItem], first: usize, nodes: &mut [Node], base: usize, depth: usize) -> usize {
+    let bounds = items.iter().fold(Aabb::empty(), |b, item| b.union(&item.bounds));
+    let centroid_bounds = items.iter().fold(Aabb::empty(), |b, item| b.grow(&item.centroid));
+    let axis = centroid_bounds.longest_axis();
+    if items.len() <= MAX_LEAF_SIZE || centroid_bounds.diagonal().component(axis) <= 0.0 || depth + 1 >= MAX_DEPTH {
+        nodes[0] = Node {
+            bounds,
+            offset: first,
+            count: items.len(),
+            axis: 0,
+        };
+        return 1;
+    }
+
+    // Partition the primitives about the best split (or, if they all fell
+    // on one side of it, or the tree is getting too deep, about their median)
+    let mut mid = if depth < MEDIAN_DEPTH { partition(items, &centroid_bounds, axis) } else { 0 };
+    if mid == 0 || mid == items.len() {
+        mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |a, b| {
+            a.centroid.component(axis).partial_cmp(&b.centroid.component(axis)).unwrap_or(Ordering::Equal)
+        });
+    }
+
+    // The first child follows this node, and the second child follows the
+    // nodes set aside for the first
+    let second = 2 * mid;
+    nodes[0] = Node {
+        bounds,
+        offset: base + second,
+        count: 0,
+        axis,
+    };
+    let parallel = items.len() >= PARALLEL_THRESHOLD;
+    let (left, right) = items.split_at_mut(mid);
+    let (left_nodes, right_nodes) = nodes[1..].split_at_mut(second - 1);
+    let (left_used, right_used) = if parallel {
+        rayon::join(|| build(left, first, left_nodes, base + 1, depth + 1),
+                    || build(right, first + mid, right_nodes, base + second, depth + 1))
+    } else {
+        (build(left, first, left_nodes, base + 1, depth + 1),
+         build(right, first + mid, right_nodes, base + second, depth + 1))
+    };
+    1 + left_used + right_used
+}
+
+// Sort the primitives into bins along the axis, and partition them between
+// the two bins that minimize the surface area heuristic, returning the number
+// of them that fell on the first side of the split
+fn partition(items: &mut [BuildItem], centroid_bounds: &Aabb, axis: usize) -> usize {
+    let empty_bins = || [(Aabb::empty(), 0usize); BINS];
+    let add = |mut bins: [(Aabb, usize); BINS], item: &BuildItem| {
+        let b = bin_of(&item.centroid, centroid_bounds, axis);
+        bins[b] = (bins[b].0.union(&item.bounds), bins[b].1 + 1);
+        bins
+    };
+    let merge = |mut a: [(Aabb, usize); BINS], b: [(Aabb, usize); BINS]| {
+        for (x, y) in a.iter_mut().zip(b.iter()) {
+            *x = (x.0.union(&y.0), x.1 + y.1);
+        }
+        a
+    };
+    let bins = if items.len() >= PARALLEL_THRESHOLD {
+        items.par_iter().fold(&empty_bins, &add).reduce(&empty_bins, &merge)
+    } else {
+        items.iter().fold(empty_bins(), &add)
+    };
+
+    let mut best_split = 0;
+    let mut best_cost = Float::MAX;
+    for split in 0..BINS - 1 {
+        let (left, right) = bins.split_at(split + 1);
+        let sum = |side: &[(Aabb, usize)]| {
+            side.iter().fold((Aabb::empty(), 0), |(b, n), bin| (b.union(&bin.0), n + bin.1))
+        };
+        let (left_bounds, left_count) = sum(left);
+        let (right_bounds, right_count) = sum(right);
+        let cost = left_bounds.surface_area() * left_count as Float +
+                   right_bounds.surface_area() * right_count as Float;
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = split;
+        }
+    }
+
+    // Partition the primitives about the split
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin_of(&items[i].centroid, centroid_bounds, axis) <= best_split {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+// Copy the subtree rooted at the given node of the buffer to the end of the
+// packed array of nodes, returning the index of its root there
+fn pack(buffer: &[Node], index: usize, nodes: &mut Vec<Node>) -> usize {
+    let packed = nodes.len();
+    let node = buffer[index];
+    nodes.push(node);
+    if node.count == 0 {
+        pack(buffer, index + 1, nodes);
+        nodes[packed].offset = pack(buffer, node.offset, nodes);
+    }
+    packed
+}
+
+#[test]
+fn test_bvh_matches_linear_search() {
+    use scene::Scene;
+    use primitive::Primitive;
+    use shape::Sphere;
+    use shape::Plane;
+    use material::Lambertian;
+    use color::Color;
+    use std::sync::Arc;
+
+    // Enough spheres that the top of the tree is built in parallel
+    let mut scene = Scene::new();
+    let material = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, -2.0, 0.0), &Vector::new(0.0, 1.0, 0.0)), material.clone()));
+    for i in 0..2000 {
+        let center = Vector::new((i % 20) as Float * 0.3 - 3.0,
+                                 (i / 20 % 10) as Float * 0.3 - 1.5,
+                                 -(i / 200) as Float * 0.3 - 2.0);
+        let radius = 0.05 + (i % 7) as Float * 0.02;
+        scene.add(Primitive::new(Sphere::new(&center, radius), material.clone()));
+    }
+
+    let rays: Vec<Ray> = (0..400)
+        .map(|i| {
+            let d = Vector::new((i % 20) as Float / 10.0 - 1.0, (i / 20) as Float / 10.0 - 1.0, -1.0);
+            Ray::new(&Vector::zero(), &d, 0.001, Float::MAX)
+        })
+        .collect();
+    let linear: Vec<Option<(Float, u32)>> = rays.iter()
+        .map(|r| scene.intersect_primitive(r).map(|(dg, p)| (dg.t, p.object_id)))
+        .collect();
+    scene.build_bvh();
+    assert!(scene.bvh.is_some());
+    for (r, expected) in rays.iter().zip(linear) {
+        assert_eq!(scene.intersect_primitive(r).map(|(dg, p)| (dg.t, p.object_id)), expected);
+    }
+
+    // Every bounded primitive is in exactly one leaf, of a binary tree
+    let stats = scene.bvh.as_ref().unwrap().stats();
+    assert_eq!((stats.primitives, stats.unbounded), (2000, 1));
+    assert_eq!(stats.nodes, 2 * stats.leaves - 1);
+    assert_eq!(stats.leaf_depths.iter().sum::<usize>(), stats.leaves);
+    assert!(stats.mean_leaf_size() <= MAX_LEAF_SIZE as Float && stats.max_depth() < 64);
+}
+
+#[test]
+fn test_bvh_depth_is_bounded() {
+    // Boxes spread out exponentially along each axis in turn, which the
+    // surface area heuristic would split off one at a time
+    let corner = |i: usize| {
+        let mut corner = Vector::zero();
+        corner[i % 3] = (13.0 as Float).powi(i as i32 / 3);
+        corner
+    };
+    let bounds: Vec<Option<Aabb>> = (0..99)
+        .map(|i| Some(Aabb::new(&corner(i), &(corner(i) + Vector::new(1.0, 1.0, 1.0)))))
+        .collect();
+    let bvh = Bvh::new(&bounds);
+    let stats = bvh.stats();
+    assert!(stats.max_depth() < MAX_DEPTH);
+    assert_eq!(stats.primitives, 99);
+
+    // Every box is still found
+    for i in 0..bounds.len() {
+        let r = Ray::new(&(corner(i) + Vector::new(0.5, 0.5, -1.0)), &Vector::new(0.0, 0.0, 1.0), 0.0, Float::MAX);
+        let mut found = false;
+        bvh.traverse(&r, |index| {
+            found |= index == i;
+            r.t_max
+        });
+        assert!(found);
+    }
+}
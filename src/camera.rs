@@ -1,13 +1,20 @@
 use vector::Vector;
+use vector::Float;
+use vector::consts;
+#[cfg(test)]
+use vector::TEST_EPSILON;
 use ray::Ray;
+use stereo::Stereo;
 
-use std::f64;
+use serde::{Deserialize, Serialize};
 
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Camera {
     // The vertical field of view, in degrees
-    pub fov: f64,
+    pub fov: Float,
     // The aspect ratio of the image plane, i.e. 4:3
-    pub aspect_ratio: f64,
+    pub aspect_ratio: Float,
     // The position of the camera, in world-space
     origin: Vector,
     // A position vector describing the lower-left corner of the image plane
@@ -16,28 +23,182 @@ pub struct Camera {
     horizontal: Vector,
     // A direction vector that runs along the vertical edge of the image plane
     vertical: Vector,
+    // Whether the camera renders a stereo pair, with an eye either side of it
+    // (see: `stereo`)
+    #[serde(default)]
+    pub stereo: Option<Stereo>,
 }
 
 impl Camera {
-    pub fn new(fov: f64, aspect_ratio: f64) -> Camera {
+    pub fn new(fov: Float, aspect_ratio: Float) -> Camera {
+        Camera::look_at(&Vector::zero(),
+                        &Vector::new(0.0, 0.0, -1.0),
+                        &Vector::new(0.0, 1.0, 0.0),
+                        fov,
+                        aspect_ratio)
+    }
+
+    // A camera positioned at `from`, looking towards `to`
+    pub fn look_at(from: &Vector, to: &Vector, up: &Vector, fov: Float, aspect_ratio: Float) -> Camera {
         // Convert the field of view to radians
-        let theta = fov * (f64::consts::PI / 180.0);
+        let theta = fov * (consts::PI / 180.0);
         let half_height = (theta * 0.5).tan();
         let half_width = aspect_ratio * half_height;
+
+        // Build an orthonormal basis for the camera's orientation
+        let w = (*from - *to).normalize();
+        let u = up.cross(&w).normalize();
+        let v = w.cross(&u);
         Camera {
-            fov: fov,
-            aspect_ratio: aspect_ratio,
-            origin: Vector::zero(),
-            lower_left_corner: Vector::new(-half_width, -half_height, -1.0),
-            horizontal: Vector::new(2.0 * half_width, 0.0, 0.0),
-            vertical: Vector::new(0.0, 2.0 * half_height, 0.0),
+            fov,
+            aspect_ratio,
+            origin: *from,
+            lower_left_corner: *from - u * half_width - v * half_height - w,
+            horizontal: u * (2.0 * half_width),
+            vertical: v * (2.0 * half_height),
+            stereo: None,
         }
     }
 
-    pub fn generate_ray(&self, u: f64, v: f64) -> Ray {
+    pub fn origin(&self) -> Vector {
+        self.origin
+    }
+
+    // The lower-left corner of the image plane, and its horizontal and
+    // vertical edges
+    pub fn image_plane(&self) -> (Vector, Vector, Vector) {
+        (self.lower_left_corner, self.horizontal, self.vertical)
+    }
+
+    pub fn generate_ray(&self, u: Float, v: Float) -> Ray {
         Ray::new(&self.origin,
                  &(self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin),
                  0.001,
-                 f64::MAX)
+                 Float::MAX)
+    }
+
+    // The ray through the point (px, py) of an image `width` by `height`
+    // pixels, in pixels from its top-left corner (so that the center of
+    // pixel (x, y) is at (x + 0.5, y + 0.5))
+    pub fn pixel_ray(&self, px: Float, py: Float, width: u32, height: u32) -> Ray {
+        // (The image plane's v-axis points up, while the image's y-axis
+        // points down)
+        self.generate_ray(px / width as Float, (height as Float - py) / height as Float)
+    }
+
+    // The inverse of `generate_ray`: find the image plane coordinates at which
+    // the world-space point `p` appears, if it is in front of the camera
+    pub fn project(&self, p: &Vector) -> Option<(Float, Float)> {
+        let normal = self.horizontal.cross(&self.vertical);
+        let d = *p - self.origin;
+        let s = (self.lower_left_corner - self.origin).dot(&normal) / d.dot(&normal);
+        if !(s > 0.0 && s.is_finite()) {
+            return None;
+        }
+
+        let q = self.origin + d * s - self.lower_left_corner;
+        Some((q.dot(&self.horizontal) / self.horizontal.squared_length(),
+              q.dot(&self.vertical) / self.vertical.squared_length()))
+    }
+
+    // The same camera, with its image flipped horizontally (e.g. for cameras
+    // from left-handed scene formats)
+    pub fn mirrored(&self) -> Camera {
+        Camera {
+            lower_left_corner: self.lower_left_corner + self.horizontal,
+            horizontal: -self.horizontal,
+            ..*self
+        }
+    }
+
+    // The same camera, moved `offset` along its image plane's horizontal
+    // edge, looking through the same window `convergence` away from where it
+    // was (for the eyes of a stereo pair)
+    pub fn shifted(&self, offset: Float, convergence: Float) -> Camera {
+        Camera {
+            origin: self.origin + self.horizontal.normalize() * offset,
+            lower_left_corner: self.origin + (self.lower_left_corner - self.origin) * convergence,
+            horizontal: self.horizontal * convergence,
+            vertical: self.vertical * convergence,
+            ..*self
+        }
+    }
+}
+
+// Places a camera on a sphere around a target point, for interactively
+// orbiting, panning, and zooming around a scene
+#[derive(Copy, Clone, Debug)]
+pub struct Orbit {
+    pub target: Vector,
+    pub distance: Float,
+    // The angles (in radians) of the camera's position around the target,
+    // where a yaw and pitch of zero place the camera along the +z axis
+    pub yaw: Float,
+    pub pitch: Float,
+}
+
+impl Orbit {
+    // An orbit matching a camera positioned at `from`, looking towards `to`
+    pub fn new(from: &Vector, to: &Vector) -> Orbit {
+        let d = *from - *to;
+        let distance = d.length();
+        let d = d / distance;
+        Orbit {
+            target: *to,
+            distance,
+            yaw: d.x.atan2(d.z),
+            pitch: d.y.clamp(-1.0, 1.0).asin(),
+        }
+    }
+
+    pub fn position(&self) -> Vector {
+        let d = Vector::new(self.pitch.cos() * self.yaw.sin(),
+                            self.pitch.sin(),
+                            self.pitch.cos() * self.yaw.cos());
+        self.target + d * self.distance
+    }
+
+    pub fn rotate(&mut self, yaw: Float, pitch: Float) {
+        // Stop just short of the poles, where the camera's up vector flips
+        let limit = consts::FRAC_PI_2 - 1e-3;
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-limit, limit);
     }
+
+    // Move the target within the image plane, by fractions of the distance
+    // to the target
+    pub fn pan(&mut self, dx: Float, dy: Float) {
+        let w = (self.position() - self.target).normalize();
+        let u = Vector::new(0.0, 1.0, 0.0).cross(&w).normalize();
+        let v = w.cross(&u);
+        self.target += (u * dx + v * dy) * self.distance;
+    }
+
+    // Move towards (for positive amounts) or away from the target
+    pub fn zoom(&mut self, amount: Float) {
+        self.distance = (self.distance * (-amount).exp()).max(1e-3);
+    }
+
+    pub fn camera(&self, fov: Float, aspect_ratio: Float) -> Camera {
+        Camera::look_at(&self.position(), &self.target, &Vector::new(0.0, 1.0, 0.0), fov, aspect_ratio)
+    }
+}
+
+#[test]
+fn test_project_inverts_generate_ray() {
+    let camera = Camera::look_at(&Vector::new(1.0, 2.0, 3.0),
+                                 &Vector::new(0.0, 0.5, -1.0),
+                                 &Vector::new(0.0, 1.0, 0.0),
+                                 45.0,
+                                 1.5);
+    let p = camera.generate_ray(0.25, 0.75).point_at(4.0);
+    let (u, v) = camera.project(&p).unwrap();
+    assert!((u - 0.25).abs() < TEST_EPSILON && (v - 0.75).abs() < TEST_EPSILON);
+}
+
+#[test]
+fn test_orbit_matches_look_at() {
+    let from = Vector::new(1.0, 2.0, 3.0);
+    let orbit = Orbit::new(&from, &Vector::new(0.0, 0.5, -1.0));
+    assert!((orbit.position() - from).length() < TEST_EPSILON);
 }
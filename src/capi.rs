@@ -0,0 +1,427 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Sphere;
+use shape::Plane;
+use shape::Triangle;
+use shape::Quad;
+use material::Material;
+use material::Lambertian;
+use material::Metallic;
+use material::Dielectric;
+use primitive::Primitive;
+use scene::Scene;
+use camera::Camera;
+use film::Film;
+use texture::TextureCache;
+use texture::DEFAULT_BUDGET;
+use tonemap::DisplayTransform;
+use settings::RenderSettings;
+use scene_file;
+use scenes;
+use error::Result;
+use error::TracerError;
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::panic;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+// Functions for embedding the renderer in C and C++ applications, declared
+// in `include/tracer.h` (which is generated from this module by running
+// `cbindgen --config cbindgen.toml --output include/tracer.h`), e.g.
+//
+//     TracerScene *scene = tracer_scene_new();
+//     double white[3] = {1.0, 1.0, 1.0}, center[3] = {0.0, 0.0, -1.0};
+//     int32_t material = tracer_scene_add_lambertian(scene, white);
+//     tracer_scene_add_sphere(scene, center, 0.5, material);
+//
+//     TracerRenderOptions options = tracer_render_options_default();
+//     TracerImage *image = tracer_render(scene, &options);
+//     if (!image) {
+//         fprintf(stderr, "%s\n", tracer_last_error());
+//     }
+//
+// Scenes and images are opaque, and are freed with `tracer_scene_free` and
+// `tracer_image_free`. Every pointer given must be null or valid (for the
+// three doubles of a vector, or a string ending in a zero), and functions
+// that fail return null (or -1), after which `tracer_last_error` says why.
+// The library is built with the `capi` feature, as a static or dynamic
+// library:
+//
+//     cargo rustc --lib --release --features capi --crate-type staticlib
+
+pub struct TracerScene {
+    scene: Scene,
+}
+
+// A rendered image: its (linear) radiance, as RGB floats in row-major order
+// with the top row first
+pub struct TracerImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+}
+
+#[repr(C)]
+pub struct TracerRenderOptions {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_depth: u32,
+    pub seed: u32,
+    // The name of the camera to render from (or null, for the scene's first
+    // camera, or otherwise a default one at the origin)
+    pub camera: *const c_char,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Record why a call failed (for `tracer_last_error`), returning `failed`
+fn fail<T>(why: TracerError, failed: T) -> T {
+    let why = CString::new(why.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(why));
+    failed
+}
+
+fn invalid(why: &str) -> TracerError {
+    TracerError::InvalidParameter(why.to_string())
+}
+
+unsafe fn string<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(invalid("a string is null"));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| invalid("a string isn't UTF-8"))
+}
+
+unsafe fn vector(v: *const f64) -> Result<Vector> {
+    if v.is_null() {
+        return Err(invalid("a vector is null"));
+    }
+    let v = slice::from_raw_parts(v, 3);
+    Ok(Vector::new(v[0] as Float, v[1] as Float, v[2] as Float))
+}
+
+unsafe fn scene_mut<'a>(scene: *mut TracerScene) -> Result<&'a mut Scene> {
+    scene.as_mut().map(|s| &mut s.scene).ok_or_else(|| invalid("the scene is null"))
+}
+
+fn boxed(scene: Scene) -> *mut TracerScene {
+    Box::into_raw(Box::new(TracerScene { scene }))
+}
+
+// Why the last call that failed (on this thread) failed, or null: the
+// string is owned by the library, until the next call fails
+#[no_mangle]
+pub extern "C" fn tracer_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |why| why.as_ptr()))
+}
+
+#[no_mangle]
+pub extern "C" fn tracer_scene_new() -> *mut TracerScene {
+    boxed(Scene::new())
+}
+
+// One of the built-in scenes (see: `scenes::NAMES`), for images of the given
+// aspect ratio
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_named(name: *const c_char, aspect_ratio: f64) -> *mut TracerScene {
+    let name = match string(name) {
+        Ok(name) => name,
+        Err(why) => return fail(why, ptr::null_mut()),
+    };
+    match scenes::named(name, aspect_ratio as Float) {
+        Some(scene) => boxed(scene),
+        None => fail(invalid(&format!("no scene named {:?}", name)), ptr::null_mut()),
+    }
+}
+
+// A scene file of any format that the command-line renderer reads (see:
+// `scene_file::load`), whose render settings are ignored
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_load(path: *const c_char, aspect_ratio: f64) -> *mut TracerScene {
+    let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+    match string(path).and_then(|path| scene_file::load(Path::new(path), aspect_ratio as Float, &textures)) {
+        Ok(imported) => boxed(imported.scene),
+        Err(why) => fail(why, ptr::null_mut()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_free(scene: *mut TracerScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+// Add a material, returning its ID (for the primitives added after it)
+unsafe fn add_material<F>(scene: *mut TracerScene, material: F) -> i32
+    where F: FnOnce() -> Result<Arc<dyn Material>>
+{
+    match scene_mut(scene).and_then(|scene| Ok(scene.add_material(&material()?))) {
+        Ok(id) => id as i32,
+        Err(why) => fail(why, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_lambertian(scene: *mut TracerScene, albedo: *const f64) -> i32 {
+    add_material(scene, || Ok(Arc::new(Lambertian::new(&Color::from_vector(&vector(albedo)?)))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_metallic(scene: *mut TracerScene,
+                                                   albedo: *const f64,
+                                                   glossiness: f64)
+                                                   -> i32 {
+    add_material(scene, || Ok(Arc::new(Metallic::new(&Color::from_vector(&vector(albedo)?), glossiness as Float))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_dielectric(scene: *mut TracerScene, ior: f64) -> i32 {
+    add_material(scene, || Ok(Arc::new(Dielectric::new(ior as Float))))
+}
+
+// Add a primitive, of one of the scene's materials (by ID), returning 0 (or
+// -1, if what's given is invalid)
+unsafe fn add_primitive<F>(scene: *mut TracerScene, material: i32, primitive: F) -> i32
+    where F: FnOnce(Arc<dyn Material>) -> Result<Primitive>
+{
+    let added = scene_mut(scene).and_then(|scene| {
+        let material = usize::try_from(material)
+            .ok()
+            .and_then(|id| scene.materials.get(id).cloned())
+            .ok_or_else(|| invalid(&format!("no material with the ID {}", material)))?;
+        scene.add(primitive(material)?);
+        Ok(())
+    });
+    match added {
+        Ok(()) => 0,
+        Err(why) => fail(why, -1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_sphere(scene: *mut TracerScene,
+                                                 center: *const f64,
+                                                 radius: f64,
+                                                 material: i32)
+                                                 -> i32 {
+    add_primitive(scene, material, |m| Ok(Primitive::new(Sphere::new(&vector(center)?, radius as Float), m)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_plane(scene: *mut TracerScene,
+                                                center: *const f64,
+                                                normal: *const f64,
+                                                material: i32)
+                                                -> i32 {
+    add_primitive(scene, material, |m| Ok(Primitive::new(Plane::new(&vector(center)?, &vector(normal)?), m)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_triangle(scene: *mut TracerScene,
+                                                   a: *const f64,
+                                                   b: *const f64,
+                                                   c: *const f64,
+                                                   material: i32)
+                                                   -> i32 {
+    add_primitive(scene, material, |m| {
+        Ok(Primitive::new(Triangle::new(&vector(a)?, &vector(b)?, &vector(c)?), m))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_quad(scene: *mut TracerScene,
+                                               corner: *const f64,
+                                               u: *const f64,
+                                               v: *const f64,
+                                               material: i32)
+                                               -> i32 {
+    add_primitive(scene, material, |m| {
+        Ok(Primitive::new(Quad::new(&vector(corner)?, &vector(u)?, &vector(v)?), m))
+    })
+}
+
+// A camera at `from` looking towards `to` (with +y up), with a vertical
+// field of view of `fov` degrees, returning 0 (or -1)
+#[no_mangle]
+pub unsafe extern "C" fn tracer_scene_add_camera(scene: *mut TracerScene,
+                                                 name: *const c_char,
+                                                 from: *const f64,
+                                                 to: *const f64,
+                                                 fov: f64,
+                                                 aspect_ratio: f64)
+                                                 -> i32 {
+    let added = scene_mut(scene).and_then(|scene| {
+        let camera = Camera::look_at(&vector(from)?,
+                                     &vector(to)?,
+                                     &Vector::new(0.0, 1.0, 0.0),
+                                     fov as Float,
+                                     aspect_ratio as Float);
+        scene.add_camera(string(name)?, camera);
+        Ok(())
+    });
+    match added {
+        Ok(()) => 0,
+        Err(why) => fail(why, -1),
+    }
+}
+
+// The renderer's own defaults (see: `RenderSettings::new`), from the scene's
+// first camera
+#[no_mangle]
+pub extern "C" fn tracer_render_options_default() -> TracerRenderOptions {
+    let settings = RenderSettings::new();
+    TracerRenderOptions {
+        width: settings.width,
+        height: settings.height,
+        samples: settings.samples,
+        max_depth: settings.max_depth,
+        seed: settings.seed,
+        camera: ptr::null(),
+    }
+}
+
+// Render the scene (building its BVH first, if primitives were added since
+// it was last built), returning the image or null
+#[no_mangle]
+pub unsafe extern "C" fn tracer_render(scene: *mut TracerScene, options: *const TracerRenderOptions) -> *mut TracerImage {
+    match render(scene, options) {
+        Ok(image) => Box::into_raw(Box::new(image)),
+        Err(why) => fail(why, ptr::null_mut()),
+    }
+}
+
+unsafe fn render(scene: *mut TracerScene, options: *const TracerRenderOptions) -> Result<TracerImage> {
+    let scene = scene_mut(scene)?;
+    let options = options.as_ref().ok_or_else(|| invalid("the options are null"))?;
+    let (width, height) = (options.width, options.height);
+    if width == 0 || height == 0 {
+        return Err(invalid("the image must be at least a pixel across"));
+    }
+    let camera = if options.camera.is_null() {
+        scene.cameras.first().map_or_else(|| Camera::new(60.0, width as Float / height as Float), |c| c.1)
+    } else {
+        let name = string(options.camera)?;
+        *scene.camera(name).ok_or_else(|| invalid(&format!("no camera named {:?}", name)))?
+    };
+    if scene.bvh.is_none() {
+        scene.build_bvh();
+    }
+
+    let settings = RenderSettings {
+        width,
+        height,
+        samples: options.samples,
+        max_depth: options.max_depth,
+        seed: options.seed,
+        ..RenderSettings::new()
+    };
+    let renderer = settings.renderer();
+    let mut film = Film::new(width, height);
+    let bounds = renderer.bounds(width, height);
+    scene.select_lods(&camera, height);
+    // (Panics mustn't unwind into the caller)
+    let scene = &*scene;
+    panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        renderer.render(&mut film, bounds, &camera, scene, settings.seed, &|_: &_| {});
+    })).map_err(TracerError::from_panic)?;
+
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    for pixel in &film.to_framebuffer().pixels {
+        pixels.extend_from_slice(&[pixel.r as f32, pixel.g as f32, pixel.b as f32]);
+    }
+    Ok(TracerImage { width, height, pixels })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_image_width(image: *const TracerImage) -> u32 {
+    image.as_ref().map_or(0, |image| image.width)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_image_height(image: *const TracerImage) -> u32 {
+    image.as_ref().map_or(0, |image| image.height)
+}
+
+// The image's radiance: width * height * 3 floats, owned by the image
+#[no_mangle]
+pub unsafe extern "C" fn tracer_image_pixels(image: *const TracerImage) -> *const f32 {
+    image.as_ref().map_or(ptr::null(), |image| image.pixels.as_ptr())
+}
+
+// Tone map the image with the default display transform (see:
+// `DisplayTransform`) into `out`, as 8-bit RGB (width * height * 3 bytes,
+// of which no more than `length` are written), returning the number of
+// bytes written
+#[no_mangle]
+pub unsafe extern "C" fn tracer_image_to_rgb8(image: *const TracerImage, out: *mut u8, length: usize) -> usize {
+    let image = match image.as_ref() {
+        Some(image) if !out.is_null() => image,
+        _ => return fail(invalid("the image or the buffer is null"), 0),
+    };
+    let display = DisplayTransform::default();
+    let out = slice::from_raw_parts_mut(out, length);
+    let mut written = 0;
+    for (rgb, texel) in image.pixels.chunks(3).zip(out.chunks_mut(3)) {
+        let c = display.apply(&Color::new(rgb[0] as Float, rgb[1] as Float, rgb[2] as Float));
+        for (channel, &v) in texel.iter_mut().zip(&[c.r, c.g, c.b]) {
+            *channel = (255.99 * v) as u8;
+            written += 1;
+        }
+    }
+    written
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tracer_image_free(image: *mut TracerImage) {
+    if !image.is_null() {
+        drop(Box::from_raw(image));
+    }
+}
+
+#[test]
+fn test_capi() {
+    unsafe {
+        let scene = tracer_scene_new();
+        let white = [1.0, 1.0, 1.0];
+        let material = tracer_scene_add_lambertian(scene, white.as_ptr());
+        assert_eq!(material, 0);
+        assert_eq!(tracer_scene_add_sphere(scene, [0.0, 0.0, -1.0].as_ptr(), 0.5, material), 0);
+
+        // Mistakes fail, saying why
+        assert_eq!(tracer_scene_add_sphere(scene, [0.0; 3].as_ptr(), 0.5, 7), -1);
+        let why = CStr::from_ptr(tracer_last_error()).to_str().unwrap();
+        assert!(why.contains("no material"), "{}", why);
+        let name = CString::new("no such scene").unwrap();
+        assert!(tracer_scene_named(name.as_ptr(), 1.0).is_null());
+
+        let mut options = tracer_render_options_default();
+        options.width = 8;
+        options.height = 6;
+        options.samples = 2;
+        let image = tracer_render(scene, &options);
+        assert!(!image.is_null());
+        assert_eq!((tracer_image_width(image), tracer_image_height(image)), (8, 6));
+        let pixels = slice::from_raw_parts(tracer_image_pixels(image), 8 * 6 * 3);
+        assert!(pixels.iter().all(|v| v.is_finite() && *v >= 0.0));
+        let mut rgb = vec![0; 8 * 6 * 3];
+        assert_eq!(tracer_image_to_rgb8(image, rgb.as_mut_ptr(), rgb.len()), rgb.len());
+
+        let camera = CString::new("no such camera").unwrap();
+        options.camera = camera.as_ptr();
+        assert!(tracer_render(scene, &options).is_null());
+        tracer_image_free(image);
+        tracer_scene_free(scene);
+    }
+}
@@ -0,0 +1,209 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use film::Film;
+use film::Pixel;
+use film::IdCoverage;
+use film::ID_RANKS;
+use sampler::SamplerType;
+use error::Result;
+use error::TracerError;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::path::Path;
+
+// Identifies checkpoint files (and the version of their layout)
+const MAGIC: &[u8; 8] = b"TRCKPT03";
+
+// Along with the film, everything needed to pick a progressive render back
+// up where it left off: since samplers derive each sample from the seed, the
+// pixel, and the number of samples the pixel has already taken, the film
+// itself holds the rest of the sampler state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderState {
+    // The number of passes completed
+    pub pass: u32,
+    pub seed: u32,
+    pub sampler: SamplerType,
+}
+
+// Write a checkpoint to a temporary file that then replaces `path`, so that a
+// crash while saving never destroys the previous checkpoint
+pub fn save(path: &Path, state: &RenderState, film: &Film) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(File::create(&temporary)?);
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, state.pass)?;
+        write_u32(&mut w, state.seed)?;
+        write_u32(&mut w, sampler_id(state.sampler))?;
+        write_u32(&mut w, film.width)?;
+        write_u32(&mut w, film.height)?;
+        for p in &film.pixels {
+            write_pixel(&mut w, p)?;
+        }
+        w.flush()?;
+        w.get_ref().sync_all()?;
+    }
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<(RenderState, Film)> {
+    let mut r = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(TracerError::Parse("not a checkpoint file".to_string()));
+    }
+
+    let pass = read_u32(&mut r)?;
+    let seed = read_u32(&mut r)?;
+    let sampler = match read_u32(&mut r)? {
+        0 => SamplerType::Random,
+        1 => SamplerType::Halton,
+        2 => SamplerType::Sobol,
+        _ => return Err(TracerError::Parse("unknown sampler type".to_string())),
+    };
+    let width = read_u32(&mut r)?;
+    let height = read_u32(&mut r)?;
+
+    let mut film = Film::new(width, height);
+    for p in &mut film.pixels {
+        *p = read_pixel(&mut r)?;
+    }
+
+    let state = RenderState {
+        pass,
+        seed,
+        sampler,
+    };
+    Ok((state, film))
+}
+
+fn sampler_id(sampler: SamplerType) -> u32 {
+    match sampler {
+        SamplerType::Random => 0,
+        SamplerType::Halton => 1,
+        SamplerType::Sobol => 2,
+    }
+}
+
+// Pixels are also sent between machines when rendering is distributed (see:
+// `distributed`)
+pub fn write_pixel<W: Write>(w: &mut W, p: &Pixel) -> io::Result<()> {
+    write_color(w, &p.weighted_sum)?;
+    write_f64(w, p.weight)?;
+    write_u32(w, p.count)?;
+    write_f64(w, p.mean_luminance)?;
+    write_f64(w, p.m2)?;
+    write_vector(w, &p.aov_sum.normal)?;
+    write_f64(w, p.aov_sum.depth)?;
+    write_vector(w, &p.aov_sum.position)?;
+    write_color(w, &p.aov_sum.albedo)?;
+    write_f64(w, p.aov_sum.alpha)?;
+    write_u32(w, p.aov_count)?;
+    for ids in &[p.object_ids, p.material_ids] {
+        for &(id, count) in &ids.ranks {
+            write_u32(w, id)?;
+            write_u32(w, count)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_pixel<R: Read>(r: &mut R) -> io::Result<Pixel> {
+    let mut p = Pixel::new();
+    p.weighted_sum = read_color(r)?;
+    p.weight = read_f64(r)?;
+    p.count = read_u32(r)?;
+    p.mean_luminance = read_f64(r)?;
+    p.m2 = read_f64(r)?;
+    p.aov_sum.normal = read_vector(r)?;
+    p.aov_sum.depth = read_f64(r)?;
+    p.aov_sum.position = read_vector(r)?;
+    p.aov_sum.albedo = read_color(r)?;
+    p.aov_sum.alpha = read_f64(r)?;
+    p.aov_count = read_u32(r)?;
+    let mut coverage = [IdCoverage::new(); 2];
+    for ids in &mut coverage {
+        for rank in 0..ID_RANKS {
+            ids.ranks[rank] = (read_u32(r)?, read_u32(r)?);
+        }
+    }
+    p.object_ids = coverage[0];
+    p.material_ids = coverage[1];
+    Ok(p)
+}
+
+pub fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+// (Values are always stored in double precision, whatever the precision of
+// the render, so that checkpoints stay compatible)
+fn write_f64<W: Write>(w: &mut W, v: Float) -> io::Result<()> {
+    w.write_all(&(v as f64).to_le_bytes())
+}
+
+fn write_vector<W: Write>(w: &mut W, v: &Vector) -> io::Result<()> {
+    write_f64(w, v.x)?;
+    write_f64(w, v.y)?;
+    write_f64(w, v.z)
+}
+
+fn write_color<W: Write>(w: &mut W, c: &Color) -> io::Result<()> {
+    write_f64(w, c.r)?;
+    write_f64(w, c.g)?;
+    write_f64(w, c.b)
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<Float> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes) as Float)
+}
+
+fn read_vector<R: Read>(r: &mut R) -> io::Result<Vector> {
+    Ok(Vector::new(read_f64(r)?, read_f64(r)?, read_f64(r)?))
+}
+
+fn read_color<R: Read>(r: &mut R) -> io::Result<Color> {
+    Ok(Color::new(read_f64(r)?, read_f64(r)?, read_f64(r)?))
+}
+
+#[test]
+fn test_checkpoint_round_trip() {
+    let mut film = Film::new(3, 2);
+    film.pixel_mut(1, 1).add_sample(&Color::new(1.0, 2.0, 3.0));
+    film.pixel_mut(1, 1).add_sample(&Color::gray(0.5));
+    film.pixel_mut(1, 1).weighted_sum = Color::new(4.0, 5.0, 6.0);
+    film.pixel_mut(2, 0).object_ids.add(7);
+
+    let path = ::std::env::temp_dir().join("tracer_test_checkpoint_round_trip.checkpoint");
+    let state = RenderState {
+        pass: 2,
+        seed: 42,
+        sampler: SamplerType::Halton,
+    };
+    save(&path, &state, &film).unwrap();
+
+    let (loaded_state, loaded) = load(&path).unwrap();
+    assert_eq!(loaded_state, state);
+    let (a, b) = (film.pixel(1, 1), loaded.pixel(1, 1));
+    assert_eq!(a.weighted_sum, b.weighted_sum);
+    assert_eq!(a.count, b.count);
+    assert_eq!(a.variance(), b.variance());
+    assert_eq!(loaded.pixel(2, 0).object_ids, film.pixel(2, 0).object_ids);
+}
@@ -0,0 +1,313 @@
+use vector::Vector;
+use vector::Float;
+#[cfg(test)]
+use vector::TEST_EPSILON;
+
+use serde::{Deserialize, Serialize};
+
+use std::ops::{Add, AddAssign, Sub, Mul, MulAssign, Div, DivAssign};
+use std::sync::OnceLock;
+
+// A linear RGB color (radiance, or the fraction of it that a surface
+// reflects), which is kept apart from `Vector` so that colors can't be mixed
+// up with positions and directions: colors only convert to and from other
+// color spaces here
+//
+// (Saved as an array of its three channels)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[Float; 3]", into = "[Float; 3]")]
+pub struct Color {
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
+}
+
+impl From<[Float; 3]> for Color {
+    fn from(c: [Float; 3]) -> Color {
+        Color::new(c[0], c[1], c[2])
+    }
+}
+
+impl From<Color> for [Float; 3] {
+    fn from(c: Color) -> [Float; 3] {
+        [c.r, c.g, c.b]
+    }
+}
+
+impl Color {
+    pub fn new(r: Float, g: Float, b: Float) -> Color {
+        Color { r, g, b }
+    }
+
+    pub fn gray(v: Float) -> Color {
+        Color::new(v, v, v)
+    }
+
+    pub fn black() -> Color {
+        Color::gray(0.0)
+    }
+
+    pub fn white() -> Color {
+        Color::gray(1.0)
+    }
+
+    // A vector's coordinates as channels, for viewing vectors (e.g. normals)
+    // as an image: this is the only way from one to the other
+    pub fn from_vector(v: &Vector) -> Color {
+        Color::new(v.x, v.y, v.z)
+    }
+
+    // The relative luminance (of a color with Rec. 709 primaries)
+    pub fn luminance(&self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_channel(&self) -> Float {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r == 0.0 && self.g == 0.0 && self.b == 0.0
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    // Apply a function to each channel
+    pub fn map<F: Fn(Float) -> Float>(&self, f: F) -> Color {
+        Color::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    pub fn clamp(&self, min: Float, max: Float) -> Color {
+        self.map(|v| v.clamp(min, max))
+    }
+
+    pub fn powf(&self, exp: Float) -> Color {
+        self.map(|v| v.powf(exp))
+    }
+
+    pub fn lerp(&self, rhs: &Color, t: Float) -> Color {
+        *self * (1.0 - t) + *rhs * t
+    }
+
+    // Encode with the sRGB transfer function (e.g. for an 8-bit image)
+    pub fn to_srgb(&self) -> Color {
+        self.map(linear_to_srgb)
+    }
+
+    // Decode a color encoded with the sRGB transfer function (e.g. a texel of
+    // an 8-bit texture)
+    pub fn from_srgb(&self) -> Color {
+        self.map(srgb_to_linear)
+    }
+
+    // The color of the light that a black body glows with at `kelvin` (e.g.
+    // 1900 for a candle, 2700 for an incandescent bulb, or 6500 for daylight),
+    // with a luminance of one: Planck's law is integrated against the CIE 1931
+    // color matching functions (see: `cie_xyz`) over the visible spectrum,
+    // and the XYZ color is converted to linear RGB, leaving out what (for the
+    // hottest and coldest bodies) lies outside of its gamut
+    pub fn blackbody(kelvin: Float) -> Color {
+        // (The second radiation constant, hc / k, in nanometer kelvins, which is
+        // all that's left of Planck's law once it's normalized)
+        const C2: f64 = 1.4387769e7;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for nm in 360..831 {
+            let lambda = nm as f64;
+            let planck = 1.0 / (lambda.powi(5) * ((C2 / (lambda * kelvin as f64)).exp() - 1.0));
+            let (xb, yb, zb) = cie_xyz(lambda);
+            x += planck * xb;
+            y += planck * yb;
+            z += planck * zb;
+        }
+        let rgb = xyz_to_rgb(x, y, z);
+        rgb / rgb.luminance()
+    }
+
+    // The color of light of a single wavelength (in nanometers), scaled so
+    // that every visible wavelength in equal amounts (i.e. averaged over
+    // `WAVELENGTHS`) is white, which weights a path that carries just the one
+    // wavelength (e.g. through a dispersive dielectric, see: `Dispersion`)
+    pub fn wavelength(nm: Float) -> Color {
+        static WHITE: OnceLock<Color> = OnceLock::new();
+        let white = WHITE.get_or_init(|| {
+            let (low, high) = (WAVELENGTHS.0 as u32, WAVELENGTHS.1 as u32);
+            (low..high).fold(Color::black(), |sum, nm| sum + spectral_rgb(nm as f64 + 0.5)) / (high - low) as Float
+        });
+        let rgb = spectral_rgb(nm as f64);
+        Color::new(rgb.r / white.r, rgb.g / white.g, rgb.b / white.b)
+    }
+}
+
+// The visible wavelengths, in nanometers
+pub const WAVELENGTHS: (Float, Float) = (360.0, 830.0);
+
+// (Light of one wavelength is more saturated than any RGB color, so it's
+// clipped to what RGB can show)
+fn spectral_rgb(lambda: f64) -> Color {
+    let (x, y, z) = cie_xyz(lambda);
+    xyz_to_rgb(x, y, z)
+}
+
+// An XYZ color in linear sRGB's (Rec. 709) primaries, clipped to its gamut
+fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new((3.2404542 * x - 1.5371385 * y - 0.4985314 * z) as Float,
+               (-0.9692660 * x + 1.8760108 * y + 0.0415560 * z) as Float,
+               (0.0556434 * x - 0.2040259 * y + 1.0572252 * z) as Float)
+        .map(|v| v.max(0.0))
+}
+
+// The CIE 1931 color matching functions at `lambda` nanometers, as fitted by
+// sums of piecewise Gaussians ("Simple Analytic Approximations to the CIE XYZ
+// Color Matching Functions", Wyman et al.)
+fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    (1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+     0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+     1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8))
+}
+
+pub fn linear_to_srgb(v: Float) -> Float {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(v: Float) -> Float {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// A false color for `t` in [0, 1] (which is clamped), from blue through
+// cyan, green, and yellow to red, for heatmaps
+pub fn heatmap(t: Float) -> Color {
+    const STOPS: [Color; 5] = [Color { r: 0.0, g: 0.0, b: 1.0 },
+                               Color { r: 0.0, g: 1.0, b: 1.0 },
+                               Color { r: 0.0, g: 1.0, b: 0.0 },
+                               Color { r: 1.0, g: 1.0, b: 0.0 },
+                               Color { r: 1.0, g: 0.0, b: 0.0 }];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as Float;
+    let i = (x as usize).min(STOPS.len() - 2);
+    STOPS[i].lerp(&STOPS[i + 1], x - i as Float)
+}
+
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, other: Color) -> Color {
+        Color::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, other: Color) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Color) -> Color {
+        Color::new(self.r - other.r, self.g - other.g, self.b - other.b)
+    }
+}
+
+// (Channel by channel, as when filtering light by a surface's reflectance)
+impl Mul for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        Color::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl Mul<Float> for Color {
+    type Output = Color;
+
+    fn mul(self, other: Float) -> Color {
+        Color::new(self.r * other, self.g * other, self.b * other)
+    }
+}
+
+impl MulAssign for Color {
+    fn mul_assign(&mut self, other: Color) {
+        *self = *self * other;
+    }
+}
+
+impl MulAssign<Float> for Color {
+    fn mul_assign(&mut self, other: Float) {
+        *self = *self * other;
+    }
+}
+
+impl Div for Color {
+    type Output = Color;
+
+    fn div(self, other: Color) -> Color {
+        Color::new(self.r / other.r, self.g / other.g, self.b / other.b)
+    }
+}
+
+impl Div<Float> for Color {
+    type Output = Color;
+
+    fn div(self, other: Float) -> Color {
+        Color::new(self.r / other, self.g / other, self.b / other)
+    }
+}
+
+impl DivAssign<Float> for Color {
+    fn div_assign(&mut self, other: Float) {
+        *self = *self / other;
+    }
+}
+
+#[test]
+fn test_srgb_round_trip() {
+    for &v in &[0.0, 0.002, 0.0031308, 0.2, 0.5, 1.0] {
+        assert!((srgb_to_linear(linear_to_srgb(v)) - v).abs() < TEST_EPSILON);
+    }
+    let c = Color::new(0.1, 0.5, 0.9);
+    let back = c.to_srgb().from_srgb();
+    assert!((back - c).max_channel().abs() < TEST_EPSILON && (c - back).max_channel().abs() < TEST_EPSILON);
+    assert!((Color::white().luminance() - 1.0).abs() < TEST_EPSILON);
+}
+
+#[test]
+fn test_blackbody() {
+    // Cooler bodies glow red, hotter ones blue, and (nearly) white between,
+    // all as bright as each other
+    let candle = Color::blackbody(1900.0);
+    let daylight = Color::blackbody(6500.0);
+    let sky = Color::blackbody(12000.0);
+    assert!(candle.r > candle.g && candle.g > candle.b);
+    assert!(sky.b > sky.g && sky.g > sky.r);
+    assert!(daylight.max_channel() < 1.1 * daylight.r.min(daylight.g).min(daylight.b));
+    for c in &[candle, daylight, sky, Color::blackbody(800.0)] {
+        assert!((c.luminance() - 1.0).abs() < TEST_EPSILON && c.r >= 0.0 && c.g >= 0.0 && c.b >= 0.0);
+    }
+}
+
+#[test]
+fn test_wavelength() {
+    // Short wavelengths are violet and blue, long ones red, and all of them
+    // together white
+    let (violet, green, red) = (Color::wavelength(420.0), Color::wavelength(530.0), Color::wavelength(650.0));
+    assert!(violet.b > violet.g && green.g > green.r.max(green.b) && red.r > red.g.max(red.b));
+    let samples = 4700;
+    let white = (0..samples)
+        .map(|i| Color::wavelength(WAVELENGTHS.0 + (WAVELENGTHS.1 - WAVELENGTHS.0) * (i as Float + 0.5) / samples as Float))
+        .fold(Color::black(), |sum, c| sum + c) / samples as Float;
+    assert!((white - Color::white()).map(Float::abs).max_channel() < 1e-3, "{:?}", white);
+}
@@ -0,0 +1,315 @@
+use vector::Float;
+use color::Color;
+use film::Aov;
+use film::Film;
+use framebuffer::Framebuffer;
+
+#[cfg(feature = "oidn")]
+use oidn;
+#[cfg(all(feature = "oidn", feature = "tracing"))]
+use tracing;
+
+// Removes noise from the beauty image before it is written, guided by the
+// film's albedo and normal AOVs (when they were recorded)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Denoiser {
+    // Intel's Open Image Denoise, when built with the `oidn` feature:
+    // otherwise, this falls back to the joint bilateral filter
+    Oidn,
+    JointBilateral,
+    NonLocalMeans,
+}
+
+impl Denoiser {
+    // Denoise the film with this denoiser's usual parameters
+    pub fn denoise(&self, film: &Film) -> Framebuffer {
+        self.apply(&DenoiserInput::new(film))
+    }
+
+    pub fn apply(&self, input: &DenoiserInput) -> Framebuffer {
+        match *self {
+            Denoiser::Oidn => denoise_oidn(input),
+            Denoiser::JointBilateral => JointBilateralFilter::new(3, 2.0, 0.1, 0.2).apply(input),
+            Denoiser::NonLocalMeans => NonLocalMeansFilter::new(5, 1, 0.45).apply(input),
+        }
+    }
+}
+
+// Everything a denoiser is given to work with
+pub struct DenoiserInput {
+    pub beauty: Framebuffer,
+    // The albedo and normal AOVs, if they were recorded
+    pub guides: Option<(Framebuffer, Framebuffer)>,
+    // An estimate of the variance of each pixel's (luminance) estimate
+    pub variance: Vec<Float>,
+}
+
+impl DenoiserInput {
+    pub fn new(film: &Film) -> DenoiserInput {
+        let guides = if film.has_aovs() {
+            Some((film.aov_framebuffer(Aov::Albedo), film.aov_framebuffer(Aov::Normal)))
+        } else {
+            None
+        };
+
+        // Pixels with too few samples to estimate their variance are assumed
+        // to be as noisy as they are bright
+        let variance = film.pixels
+            .iter()
+            .map(|p| {
+                if p.count < 2 {
+                    let l = p.color().luminance();
+                    l * l
+                } else {
+                    p.variance() / p.count as Float
+                }
+            })
+            .collect();
+
+        DenoiserInput {
+            beauty: film.to_framebuffer(),
+            guides,
+            variance,
+        }
+    }
+
+    // How strongly the guides suggest that pixels i and j lie across an edge
+    // (as an exponent, where 0 means that they look like the same surface)
+    fn guide_distance(&self, i: usize, j: usize, sigma_albedo: Float, sigma_normal: Float) -> Float {
+        match self.guides {
+            Some((ref albedo, ref normal)) => {
+                distance_squared(&albedo.pixels[i], &albedo.pixels[j]) / (2.0 * sigma_albedo * sigma_albedo) +
+                distance_squared(&normal.pixels[i], &normal.pixels[j]) / (2.0 * sigma_normal * sigma_normal)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(feature = "oidn")]
+fn denoise_oidn(input: &DenoiserInput) -> Framebuffer {
+    fn to_f32(framebuffer: &Framebuffer) -> Vec<f32> {
+        framebuffer.pixels.iter().flat_map(|p| vec![p.r as f32, p.g as f32, p.b as f32]).collect()
+    }
+
+    let beauty = &input.beauty;
+    let color = to_f32(beauty);
+    let mut output = vec![0.0f32; color.len()];
+    let result = oidn::Device::new().and_then(|device| {
+        let mut filter = oidn::RayTracing::try_new(&device)?;
+        filter.hdr(true).image_dimensions(beauty.width as usize, beauty.height as usize);
+        if let Some((ref albedo, ref normal)) = input.guides {
+            filter.albedo_normal(&to_f32(albedo), &to_f32(normal));
+        }
+        filter.filter(&color, &mut output)?;
+        device.get_error()
+    });
+
+    match result {
+        Ok(()) => Framebuffer {
+            width: beauty.width,
+            height: beauty.height,
+            pixels: output.chunks(3).map(|c| Color::new(c[0] as Float, c[1] as Float, c[2] as Float)).collect(),
+        },
+        #[cfg(feature = "tracing")]
+        Err(why) => {
+            tracing::warn!(%why, "OIDN failed, falling back to the built-in denoiser");
+            Denoiser::JointBilateral.apply(input)
+        }
+        #[cfg(not(feature = "tracing"))]
+        Err(_) => Denoiser::JointBilateral.apply(input),
+    }
+}
+
+#[cfg(not(feature = "oidn"))]
+fn denoise_oidn(input: &DenoiserInput) -> Framebuffer {
+    Denoiser::JointBilateral.apply(input)
+}
+
+// A joint (or cross) bilateral filter: each pixel is replaced by a weighted
+// average of its neighbors, where neighbors whose albedo or normal differ
+// (i.e. that likely lie across an edge) are given little weight (without
+// AOVs, edges can only be detected by differences in the noisy color itself)
+pub struct JointBilateralFilter {
+    // The half-width of the filter's window, in pixels
+    pub radius: u32,
+    pub sigma_spatial: Float,
+    pub sigma_albedo: Float,
+    pub sigma_normal: Float,
+}
+
+impl JointBilateralFilter {
+    pub fn new(r: u32, spatial: Float, albedo: Float, normal: Float) -> JointBilateralFilter {
+        JointBilateralFilter {
+            radius: r,
+            sigma_spatial: spatial,
+            sigma_albedo: albedo,
+            sigma_normal: normal,
+        }
+    }
+
+    pub fn apply(&self, input: &DenoiserInput) -> Framebuffer {
+        const SIGMA_COLOR: Float = 1.0;
+
+        let beauty = &input.beauty;
+        let (width, height) = (beauty.width as i64, beauty.height as i64);
+        let radius = self.radius as i64;
+        let mut output = Framebuffer::new(beauty.width, beauty.height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * width + x) as usize;
+                let mut sum = Color::black();
+                let mut total = 0.0;
+                for ny in (y - radius).max(0)..(y + radius + 1).min(height) {
+                    for nx in (x - radius).max(0)..(x + radius + 1).min(width) {
+                        let j = (ny * width + nx) as usize;
+                        let d2 = ((nx - x) * (nx - x) + (ny - y) * (ny - y)) as Float;
+                        let mut exponent = d2 / (2.0 * self.sigma_spatial * self.sigma_spatial);
+                        exponent += if input.guides.is_some() {
+                            input.guide_distance(i, j, self.sigma_albedo, self.sigma_normal)
+                        } else {
+                            distance_squared(&beauty.pixels[i], &beauty.pixels[j]) /
+                            (2.0 * SIGMA_COLOR * SIGMA_COLOR)
+                        };
+                        let weight = (-exponent).exp();
+                        sum += beauty.pixels[j] * weight;
+                        total += weight;
+                    }
+                }
+                output.pixels[i] = sum / total;
+            }
+        }
+        output
+    }
+}
+
+// Non-local means: neighbors are weighted by how similar the patches around
+// them are to the patch around the pixel being filtered, where differences
+// are measured relative to the pixels' variance so that noise alone doesn't
+// make patches look dissimilar (see: "Robust Denoising using Feature and
+// Color Information", Rousselle et al.)
+pub struct NonLocalMeansFilter {
+    // The half-width of the window searched for similar patches
+    pub radius: u32,
+    // The half-width of the patches compared
+    pub patch_radius: u32,
+    // Larger values smooth more aggressively
+    pub strength: Float,
+}
+
+impl NonLocalMeansFilter {
+    pub fn new(r: u32, patch_r: u32, k: Float) -> NonLocalMeansFilter {
+        NonLocalMeansFilter {
+            radius: r,
+            patch_radius: patch_r,
+            strength: k,
+        }
+    }
+
+    pub fn apply(&self, input: &DenoiserInput) -> Framebuffer {
+        const SIGMA_ALBEDO: Float = 0.1;
+        const SIGMA_NORMAL: Float = 0.2;
+
+        let beauty = &input.beauty;
+        let (width, height) = (beauty.width as i64, beauty.height as i64);
+        let n = beauty.pixels.len();
+        let radius = self.radius as i64;
+        let k2 = self.strength * self.strength;
+
+        let mut sum = vec![Color::black(); n];
+        let mut total = vec![0.0; n];
+        let mut distances = vec![0.0; n];
+
+        // Rather than comparing every pair of patches separately, consider
+        // one offset at a time: the patch distances for an offset are then
+        // a box filtered image of per-pixel distances
+        for dy in -radius..radius + 1 {
+            for dx in -radius..radius + 1 {
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = (y * width + x) as usize;
+                        let (qx, qy) = (x + dx, y + dy);
+                        distances[i] = if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            Float::INFINITY
+                        } else {
+                            let j = (qy * width + qx) as usize;
+                            let (var_p, var_q) = (input.variance[i], input.variance[j]);
+                            let d2 = distance_squared(&beauty.pixels[i], &beauty.pixels[j]);
+                            (d2 / 3.0 - (var_p + var_p.min(var_q))) / (1e-10 + k2 * (var_p + var_q))
+                        };
+                    }
+                }
+                let patch_distances = box_filter(&distances, width, height, self.patch_radius as i64);
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = (y * width + x) as usize;
+                        let (qx, qy) = (x + dx, y + dy);
+                        if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            continue;
+                        }
+                        let j = (qy * width + qx) as usize;
+                        let exponent = patch_distances[i].max(0.0) +
+                                       input.guide_distance(i, j, SIGMA_ALBEDO, SIGMA_NORMAL);
+                        let weight = (-exponent).exp();
+                        sum[i] += beauty.pixels[j] * weight;
+                        total[i] += weight;
+                    }
+                }
+            }
+        }
+
+        Framebuffer {
+            width: beauty.width,
+            height: beauty.height,
+            pixels: sum.iter().zip(total.iter()).map(|(s, &t)| *s / t).collect(),
+        }
+    }
+}
+
+// Average the values within `radius` pixels of each pixel (clipped to the
+// image), separably: infinite values (e.g. from outside the image) remain
+// infinite, which excludes the patches that contain them
+fn box_filter(values: &[Float], width: i64, height: i64, radius: i64) -> Vec<Float> {
+    let mut horizontal = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (x0, x1) = ((x - radius).max(0), (x + radius + 1).min(width));
+            let row = &values[(y * width) as usize..((y + 1) * width) as usize];
+            horizontal[(y * width + x) as usize] =
+                row[x0 as usize..x1 as usize].iter().sum::<Float>() / (x1 - x0) as Float;
+        }
+    }
+
+    let mut output = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (y0, y1) = ((y - radius).max(0), (y + radius + 1).min(height));
+            let column_sum: Float = (y0..y1).map(|y| horizontal[(y * width + x) as usize]).sum();
+            output[(y * width + x) as usize] = column_sum / (y1 - y0) as Float;
+        }
+    }
+    output
+}
+
+fn distance_squared(a: &Color, b: &Color) -> Float {
+    let d = *a - *b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+#[test]
+fn test_denoisers_preserve_constant_images() {
+    let mut film = Film::new(8, 8);
+    for pixel in &mut film.pixels {
+        for _ in 0..4 {
+            pixel.add_sample(&Color::new(0.5, 0.25, 1.0));
+        }
+        pixel.weighted_sum = Color::new(0.5, 0.25, 1.0);
+        pixel.weight = 1.0;
+    }
+    for denoiser in &[Denoiser::JointBilateral, Denoiser::NonLocalMeans] {
+        for p in &denoiser.denoise(&film).pixels {
+            assert!(distance_squared(p, &Color::new(0.5, 0.25, 1.0)).sqrt() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use film::Film;
+use film::Pixel;
+use filter::Filter;
+use scheduler::Tile;
+use checkpoint::read_pixel;
+use checkpoint::read_u32;
+use checkpoint::write_pixel;
+use checkpoint::write_u32;
+
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// Sent by workers when they connect (identifying the version of the protocol)
+const MAGIC: &[u8; 8] = b"TRWORK03";
+
+// Sent by the coordinator ahead of each job, or once there are no jobs left
+const JOB: u32 = 1;
+const DONE: u32 = 0;
+
+// Whether this process hands out the work or does it, and at which address
+// the coordinator listens (or the workers connect)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+    Coordinator(&'static str),
+    Worker(&'static str),
+}
+
+// One unit of distributed work: every sample of one tile of one frame (of an
+// animation, or frame 0 of a single image)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Job {
+    pub frame: u32,
+    pub tile: Tile,
+}
+
+// A finished job: the film's pixels within the job's tile, plus a margin for
+// the filter, since samples near the tile's edges are splatted into the
+// pixels beyond it
+struct JobResult {
+    job: Job,
+    region: Tile,
+    pixels: Vec<Pixel>,
+}
+
+// The jobs that haven't been handed out yet, and the number that haven't
+// been finished (including those that are being rendered)
+struct Queue {
+    pending: VecDeque<Job>,
+    unfinished: usize,
+}
+
+// Hands out jobs to the workers that connect to it, over TCP, and merges
+// their results into one film per frame: workers can come and go as they
+// please, since the job of a worker that disconnects is simply handed out
+// again
+pub struct Coordinator {
+    pub address: SocketAddr,
+    listener: TcpListener,
+}
+
+impl Coordinator {
+    pub fn bind(address: &str) -> io::Result<Coordinator> {
+        let listener = TcpListener::bind(address)?;
+        // (Binding to port 0 picks a free port, so ask which one it was)
+        let address = listener.local_addr()?;
+        Ok(Coordinator {
+            address,
+            listener,
+        })
+    }
+
+    // Render the jobs on the workers, which must render (films of) the given
+    // size, calling `frame_done` with each frame's film once all of its jobs
+    // are finished, in the order of the frames
+    pub fn render<F>(self, width: u32, height: u32, jobs: Vec<Job>, mut frame_done: F) -> io::Result<()>
+        where F: FnMut(u32, Film)
+    {
+        // The number of jobs left in each frame, along with its film
+        let mut frames: BTreeMap<u32, (usize, Film)> = BTreeMap::new();
+        for job in &jobs {
+            frames.entry(job.frame).or_insert_with(|| (0, Film::new(width, height))).0 += 1;
+        }
+        let queue = Arc::new(Mutex::new(Queue {
+            unfinished: jobs.len(),
+            pending: jobs.into_iter().collect(),
+        }));
+
+        // Serve each worker on its own thread, all of which send their
+        // results back to this one
+        let (sender, receiver) = mpsc::channel();
+        let shared = queue.clone();
+        let listener = self.listener;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let queue = shared.clone();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    #[cfg(feature = "tracing")]
+                    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(%peer, "a worker connected");
+                    // (Its job, if it had one, is handed out again)
+                    match serve(stream, width, height, &queue, &sender) {
+                        Ok(()) => {}
+                        #[cfg(feature = "tracing")]
+                        Err(why) => tracing::warn!(%peer, %why, "a worker disconnected"),
+                        #[cfg(not(feature = "tracing"))]
+                        Err(_) => {}
+                    }
+                });
+            }
+        });
+
+        while !frames.is_empty() {
+            let result: JobResult = match receiver.recv() {
+                Ok(result) => result,
+                Err(why) => return Err(io::Error::other(why)),
+            };
+            if let Some(frame) = frames.get_mut(&result.job.frame) {
+                merge(&mut frame.1, &result);
+                frame.0 -= 1;
+            }
+            queue.lock().unwrap().unfinished -= 1;
+
+            // Hand over every finished frame that isn't waiting on an earlier one
+            while let Some((&number, &(0, _))) = frames.iter().next() {
+                let (_, film) = frames.remove(&number).unwrap();
+                frame_done(number, film);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Hand jobs to one worker until there are none left
+fn serve(stream: TcpStream,
+         width: u32,
+         height: u32,
+         queue: &Mutex<Queue>,
+         results: &mpsc::Sender<JobResult>)
+         -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a worker"));
+    }
+    write_u32(&mut writer, width)?;
+    write_u32(&mut writer, height)?;
+
+    loop {
+        // Jobs that are being rendered elsewhere may still be handed out
+        // again (if their workers disconnect), so wait until every job is
+        // finished before letting this worker go
+        let job = loop {
+            {
+                let mut queue = queue.lock().unwrap();
+                if let Some(job) = queue.pending.pop_front() {
+                    break job;
+                }
+                if queue.unfinished == 0 {
+                    write_u32(&mut writer, DONE)?;
+                    return writer.flush();
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        match run_job(&mut reader, &mut writer, &job, width, height) {
+            Ok(result) => {
+                // (The coordinator only stops listening once it has every
+                // result)
+                let _ = results.send(result);
+            }
+            Err(why) => {
+                queue.lock().unwrap().pending.push_front(job);
+                return Err(why);
+            }
+        }
+    }
+}
+
+fn run_job<R: Read, W: Write>(reader: &mut R,
+                              writer: &mut W,
+                              job: &Job,
+                              width: u32,
+                              height: u32)
+                              -> io::Result<JobResult> {
+    write_u32(writer, JOB)?;
+    write_u32(writer, job.frame)?;
+    write_tile(writer, &job.tile)?;
+    writer.flush()?;
+
+    let frame = read_u32(reader)?;
+    let tile = read_tile(reader)?;
+    if frame != job.frame || tile != job.tile {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the worker finished a different job"));
+    }
+    let region = read_tile(reader)?;
+    if region.x0 > tile.x0 || region.y0 > tile.y0 || region.x1 < tile.x1 || region.y1 < tile.y1 || region.x1 > width ||
+       region.y1 > height {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the worker's pixels are outside of the film"));
+    }
+    let mut pixels = Vec::with_capacity(region.area() as usize);
+    for _ in 0..region.area() {
+        pixels.push(read_pixel(reader)?);
+    }
+    Ok(JobResult {
+        job: *job,
+        region,
+        pixels,
+    })
+}
+
+// Add a job's samples to the film: pixels within the job's tile take all of
+// their statistics from the result, while the margin around it only
+// receives the samples splatted into it
+fn merge(film: &mut Film, result: &JobResult) {
+    let (region, tile) = (result.region, result.job.tile);
+    let width = region.x1 - region.x0;
+    for (i, p) in result.pixels.iter().enumerate() {
+        let x = region.x0 + i as u32 % width;
+        let y = region.y0 + i as u32 / width;
+        let pixel = film.pixel_mut(x, y);
+        let (weighted_sum, weight) = (pixel.weighted_sum + p.weighted_sum, pixel.weight + p.weight);
+        if x >= tile.x0 && x < tile.x1 && y >= tile.y0 && y < tile.y1 {
+            *pixel = *p;
+        }
+        pixel.weighted_sum = weighted_sum;
+        pixel.weight = weight;
+    }
+}
+
+// Connect to the coordinator at `address` and render the jobs that it hands
+// out until there are none left, returning the number rendered: `render`
+// must render every sample of the job's tile into the film (a film of the
+// given size, which is cleared around the tile beforehand), reconstructing
+// it with `filter`
+pub fn work<R>(address: &str, width: u32, height: u32, filter: &dyn Filter, mut render: R) -> io::Result<usize>
+    where R: FnMut(&Job, &mut Film)
+{
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    if read_u32(&mut reader)? != width || read_u32(&mut reader)? != height {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the coordinator renders at a different resolution"));
+    }
+
+    let margin = filter.radius().ceil() as u32;
+    let mut film = Film::new(width, height);
+    let mut jobs = 0;
+    while read_u32(&mut reader)? == JOB {
+        let frame = read_u32(&mut reader)?;
+        let tile = read_tile(&mut reader)?;
+        if tile.x0 >= tile.x1 || tile.y0 >= tile.y1 || tile.x1 > width || tile.y1 > height {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the job's tile is outside of the film"));
+        }
+        let region = Tile {
+            x0: tile.x0.saturating_sub(margin),
+            y0: tile.y0.saturating_sub(margin),
+            x1: (tile.x1 + margin).min(width),
+            y1: (tile.y1 + margin).min(height),
+        };
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                *film.pixel_mut(x, y) = Pixel::new();
+            }
+        }
+
+        let job = Job { frame, tile };
+        render(&job, &mut film);
+
+        write_u32(&mut writer, frame)?;
+        write_tile(&mut writer, &tile)?;
+        write_tile(&mut writer, &region)?;
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                write_pixel(&mut writer, film.pixel(x, y))?;
+            }
+        }
+        writer.flush()?;
+        jobs += 1;
+    }
+    Ok(jobs)
+}
+
+fn write_tile<W: Write>(w: &mut W, tile: &Tile) -> io::Result<()> {
+    write_u32(w, tile.x0)?;
+    write_u32(w, tile.y0)?;
+    write_u32(w, tile.x1)?;
+    write_u32(w, tile.y1)
+}
+
+fn read_tile<R: Read>(r: &mut R) -> io::Result<Tile> {
+    Ok(Tile {
+        x0: read_u32(r)?,
+        y0: read_u32(r)?,
+        x1: read_u32(r)?,
+        y1: read_u32(r)?,
+    })
+}
+
+#[test]
+fn test_distributed_matches_local() {
+    use vector::Float;
+    use color::Color;
+    use filter::FilterType;
+    use scheduler;
+
+    let (width, height) = (20, 12);
+    let filter = FilterType::Mitchell.create();
+    // Two samples in every pixel, whose color depends on the frame
+    let render = |frame: u32, film: &mut Film, tile: &Tile| {
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                for s in 0..2 {
+                    let c = Color::new(x as Float, y as Float, (frame + s) as Float) / 20.0;
+                    film.add_sample(x as Float + 0.25 + s as Float * 0.5, y as Float + 0.5, &c, &*filter);
+                }
+            }
+        }
+    };
+
+    let coordinator = Coordinator::bind("127.0.0.1:0").unwrap();
+    let address = coordinator.address.to_string();
+    let jobs: Vec<Job> = (0..2)
+        .flat_map(|frame| scheduler::tiles((0, 0, width, height), 8).into_iter().map(move |tile| Job { frame, tile }))
+        .collect();
+    let mut frames = Vec::new();
+    let finished: usize = thread::scope(|scope| {
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                scope.spawn(|| {
+                    work(&address, width, height, &*filter, |job, film| render(job.frame, film, &job.tile)).unwrap()
+                })
+            })
+            .collect();
+        coordinator.render(width, height, jobs, |frame, film| frames.push((frame, film))).unwrap();
+        workers.into_iter().map(|w| w.join().unwrap()).sum()
+    });
+    assert_eq!(finished, 12);
+
+    assert_eq!(frames.iter().map(|f| f.0).collect::<Vec<_>>(), vec![0, 1]);
+    for &(frame, ref film) in &frames {
+        let mut expected = Film::new(width, height);
+        render(frame, &mut expected, &Tile { x0: 0, y0: 0, x1: width, y1: height });
+        for (a, b) in film.pixels.iter().zip(expected.pixels.iter()) {
+            assert_eq!(a.count, b.count);
+            assert!((a.color() - b.color()).map(Float::abs).max_channel() < 1e-6);
+        }
+    }
+}
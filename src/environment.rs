@@ -0,0 +1,34 @@
+use vector::Vector;
+use color::Color;
+
+use serde::{Deserialize, Serialize};
+
+// What rays that leave the scene see, which lights it (along with any
+// emissive surfaces, see: `light::Lights`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Environment {
+    // A gradient from white straight down to light blue straight up
+    #[default]
+    Sky,
+    // The same radiance in every direction, as inside a furnace: a closed
+    // scene whose materials reflect everything that reaches them (e.g. white
+    // Lambertians, or glass) then looks the same as the environment, so any
+    // difference is energy that a material gained or lost (see:
+    // `scenes::furnace`)
+    Uniform { radiance: Color },
+}
+
+impl Environment {
+    // The radiance arriving from the environment along `direction`, which
+    // needn't be normalized
+    pub fn radiance(&self, direction: &Vector) -> Color {
+        match *self {
+            Environment::Sky => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                Color::white().lerp(&Color::new(0.5, 0.7, 1.0), t)
+            }
+            Environment::Uniform { radiance } => radiance,
+        }
+    }
+}
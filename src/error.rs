@@ -0,0 +1,101 @@
+use image::ImageError;
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// What can go wrong in loading a scene (or its models, textures, and
+// settings) and rendering it
+#[derive(Debug)]
+pub enum TracerError {
+    // Reading or writing a file
+    Io(io::Error),
+    // Decoding an image (e.g. a texture)
+    Image(ImageError),
+    // A malformed file (or string), with what's wrong with it (and where)
+    Parse(String),
+    // A value that's out of range, or that doesn't make sense where it's given
+    InvalidParameter(String),
+    // A render thread panicked, with its message
+    Render(String),
+}
+
+// (The error type can be given, so that this can stand in for the standard
+// library's `Result` wherever it's imported)
+pub type Result<T, E = TracerError> = ::std::result::Result<T, E>;
+
+impl TracerError {
+    // A panic's payload (as caught, or joined from a thread), with its message
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> TracerError {
+        let why = match payload.downcast_ref::<&str>() {
+            Some(why) => why.to_string(),
+            None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+        };
+        TracerError::Render(why)
+    }
+}
+
+impl fmt::Display for TracerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TracerError::Io(ref why) => write!(f, "{}", why),
+            TracerError::Image(ref why) => write!(f, "{}", why),
+            TracerError::Parse(ref why) | TracerError::InvalidParameter(ref why) => write!(f, "{}", why),
+            TracerError::Render(ref why) => write!(f, "the render panicked: {}", why),
+        }
+    }
+}
+
+impl Error for TracerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TracerError::Io(ref why) => Some(why),
+            TracerError::Image(ref why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TracerError {
+    fn from(why: io::Error) -> TracerError {
+        TracerError::Io(why)
+    }
+}
+
+impl From<ImageError> for TracerError {
+    fn from(why: ImageError) -> TracerError {
+        TracerError::Image(why)
+    }
+}
+
+// (So that code that only deals in I/O errors, like the binaries, can still
+// use `?` on results from the library)
+impl From<TracerError> for io::Error {
+    fn from(why: TracerError) -> io::Error {
+        match why {
+            TracerError::Io(why) => why,
+            TracerError::Image(ImageError::IoError(why)) => why,
+            TracerError::Image(_) | TracerError::Parse(_) => io::Error::new(io::ErrorKind::InvalidData, why),
+            TracerError::InvalidParameter(_) => io::Error::new(io::ErrorKind::InvalidInput, why),
+            TracerError::Render(_) => io::Error::other(why),
+        }
+    }
+}
+
+#[test]
+fn test_tracer_error() {
+    use settings::RenderSettings;
+    use std::path::Path;
+
+    match RenderSettings::load(Path::new("no/such/settings.toml")) {
+        Err(TracerError::Io(ref why)) => assert_eq!(why.kind(), io::ErrorKind::NotFound),
+        _ => panic!("expected an I/O error"),
+    }
+    let why = RenderSettings::parse("samples = \"many\"\n").unwrap_err();
+    assert!(matches!(why, TracerError::Parse(_)));
+    assert_eq!(io::Error::from(why).kind(), io::ErrorKind::InvalidData);
+    let why = TracerError::InvalidParameter("a negative radius".to_string());
+    assert_eq!(why.to_string(), "a negative radius");
+    assert_eq!(io::Error::from(why).kind(), io::ErrorKind::InvalidInput);
+}
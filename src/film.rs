@@ -0,0 +1,529 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use color::heatmap;
+use filter::Filter;
+use framebuffer::Framebuffer;
+use sampler::hash;
+
+use std::mem;
+
+// Arbitrary output variables (AOVs) are auxiliary images, recorded at the
+// first surface that each camera ray hits, which denoisers and compositing
+// rely on alongside the final (beauty) image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aov {
+    // The world-space surface normal
+    Normal,
+    // The distance from the camera to the surface
+    Depth,
+    // The world-space position of the surface
+    Position,
+    // The surface's base color
+    Albedo,
+    // The IDs of the primitive and material hit (see: `IdCoverage`)
+    ObjectId,
+    MaterialId,
+    // How much of the pixel the scene covers, for compositing it over
+    // something else: surfaces cover it, the environment doesn't, and shadow
+    // catchers cover it as much as the shadows on them (see: `ShadowCatcher`)
+    Alpha,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Alpha => "alpha",
+        }
+    }
+}
+
+// The number of distinct IDs tracked per pixel: pixels along edges usually
+// see only two or three
+pub const ID_RANKS: usize = 4;
+
+// Tracks which IDs were seen by a pixel's samples, and how often, in the
+// spirit of Cryptomatte: this is what allows objects to be isolated with
+// antialiased edges
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IdCoverage {
+    // (ID, sample count) pairs sorted from most to least common, where
+    // unused entries have a count of zero
+    pub ranks: [(u32, u32); ID_RANKS],
+}
+
+impl IdCoverage {
+    pub fn new() -> IdCoverage {
+        IdCoverage { ranks: [(0, 0); ID_RANKS] }
+    }
+
+    pub fn add(&mut self, id: u32) {
+        let i = match self.ranks.iter().position(|&(i, n)| n > 0 && i == id) {
+            Some(i) => i,
+            None => {
+                // Once every entry is in use, rarer IDs are dropped
+                match self.ranks.iter().position(|&(_, n)| n == 0) {
+                    Some(i) => {
+                        self.ranks[i] = (id, 0);
+                        i
+                    }
+                    None => return,
+                }
+            }
+        };
+        self.ranks[i].1 += 1;
+
+        // Keep the entries sorted by count
+        let mut i = i;
+        while i > 0 && self.ranks[i].1 > self.ranks[i - 1].1 {
+            self.ranks.swap(i, i - 1);
+            i -= 1;
+        }
+    }
+
+    // The number of samples that saw the given ID
+    pub fn count(&self, id: u32) -> u32 {
+        self.ranks.iter().find(|&&(i, n)| n > 0 && i == id).map_or(0, |&(_, n)| n)
+    }
+}
+
+impl Default for IdCoverage {
+    fn default() -> IdCoverage {
+        IdCoverage::new()
+    }
+}
+
+// An arbitrary but stable color for an ID, for viewing ID passes
+pub fn id_color(id: u32) -> Color {
+    let h = hash(id);
+    Color::new((h & 0xff) as Float / 255.0,
+               ((h >> 8) & 0xff) as Float / 255.0,
+               ((h >> 16) & 0xff) as Float / 255.0)
+}
+
+// The AOVs of a single camera ray: rays that miss the scene have a zero
+// normal, depth, and position, no IDs, and the background color as their albedo
+#[derive(Copy, Clone, Debug)]
+pub struct AovSample {
+    pub normal: Vector,
+    pub depth: Float,
+    pub position: Vector,
+    pub albedo: Color,
+    pub object_id: Option<u32>,
+    pub material_id: Option<u32>,
+    pub alpha: Float,
+}
+
+impl AovSample {
+    pub fn new() -> AovSample {
+        AovSample {
+            normal: Vector::zero(),
+            depth: 0.0,
+            position: Vector::zero(),
+            albedo: Color::black(),
+            object_id: None,
+            material_id: None,
+            alpha: 0.0,
+        }
+    }
+}
+
+impl Default for AovSample {
+    fn default() -> AovSample {
+        AovSample::new()
+    }
+}
+
+// A pixel accumulates the filtered contributions of all nearby samples,
+// along with running statistics for the samples taken within the pixel
+#[derive(Copy, Clone, Debug)]
+pub struct Pixel {
+    // The sum of all filter-weighted radiance samples
+    pub weighted_sum: Color,
+    // The sum of all filter weights
+    pub weight: Float,
+    // The number of samples taken within this pixel
+    pub count: u32,
+    // The sum of the AOVs of all samples taken within this pixel, which are
+    // simply averaged (filtering would blur normals and depths across edges)
+    pub aov_sum: AovSample,
+    pub aov_count: u32,
+    pub object_ids: IdCoverage,
+    pub material_ids: IdCoverage,
+    // The running mean and sum of squared differences of the sample
+    // luminance, which are updated with Welford's method
+    pub mean_luminance: Float,
+    pub m2: Float,
+}
+
+impl Pixel {
+    pub fn new() -> Pixel {
+        Pixel {
+            weighted_sum: Color::black(),
+            weight: 0.0,
+            count: 0,
+            aov_sum: AovSample::new(),
+            aov_count: 0,
+            object_ids: IdCoverage::new(),
+            material_ids: IdCoverage::new(),
+            mean_luminance: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    // Record a sample taken within this pixel (its contribution to the image
+    // is splatted separately, see: `FilmTile`)
+    pub fn add_sample(&mut self, radiance: &Color) {
+        self.count += 1;
+
+        let l = radiance.luminance();
+        let delta = l - self.mean_luminance;
+        self.mean_luminance += delta / self.count as Float;
+        self.m2 += delta * (l - self.mean_luminance);
+    }
+
+    pub fn add_aov_sample(&mut self, aovs: &AovSample) {
+        self.aov_sum.normal += aovs.normal;
+        self.aov_sum.depth += aovs.depth;
+        self.aov_sum.position += aovs.position;
+        self.aov_sum.albedo += aovs.albedo;
+        self.aov_sum.alpha += aovs.alpha;
+        self.aov_count += 1;
+        if let Some(id) = aovs.object_id {
+            self.object_ids.add(id);
+        }
+        if let Some(id) = aovs.material_id {
+            self.material_ids.add(id);
+        }
+    }
+
+    // The fraction of this pixel's samples that saw the given object (or
+    // material) ID
+    pub fn coverage(&self, aov: Aov, id: u32) -> Float {
+        if self.aov_count == 0 {
+            return 0.0;
+        }
+        let ids = match aov {
+            Aov::ObjectId => &self.object_ids,
+            Aov::MaterialId => &self.material_ids,
+            _ => return 0.0,
+        };
+        ids.count(id) as Float / self.aov_count as Float
+    }
+
+    // The average AOVs of the samples taken within this pixel
+    pub fn aovs(&self) -> AovSample {
+        if self.aov_count == 0 {
+            return AovSample::new();
+        }
+        let n = self.aov_count as Float;
+        let normal = self.aov_sum.normal;
+        AovSample {
+            normal: if normal.length() > 0.0 { normal.normalize() } else { normal },
+            depth: self.aov_sum.depth / n,
+            position: self.aov_sum.position / n,
+            albedo: self.aov_sum.albedo / n,
+            // The most common IDs
+            object_id: self.object_ids.ranks.first().filter(|r| r.1 > 0).map(|r| r.0),
+            material_id: self.material_ids.ranks.first().filter(|r| r.1 > 0).map(|r| r.0),
+            alpha: self.aov_sum.alpha / n,
+        }
+    }
+
+    // The filtered average of all samples that contribute to this pixel
+    pub fn color(&self) -> Color {
+        if self.weight == 0.0 {
+            return Color::black();
+        }
+        self.weighted_sum / self.weight
+    }
+
+    // The (unbiased) sample variance of the luminance
+    pub fn variance(&self) -> Float {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.count - 1) as Float
+    }
+
+    // The standard error of the pixel's estimate, relative to its brightness:
+    // dark pixels would otherwise never be considered converged
+    pub fn relative_error(&self) -> Float {
+        if self.count < 2 {
+            return Float::INFINITY;
+        }
+        let standard_error = (self.variance() / self.count as Float).sqrt();
+        standard_error / self.mean_luminance.max(1e-3)
+    }
+
+    pub fn is_converged(&self, threshold: Float) -> bool {
+        self.relative_error() <= threshold
+    }
+}
+
+impl Default for Pixel {
+    fn default() -> Pixel {
+        Pixel::new()
+    }
+}
+
+// A film is a 2D grid of pixels that accumulate filter-weighted radiance
+// samples: it is resolved into a framebuffer whenever an image is needed
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![Pixel::new(); (width * height) as usize],
+        }
+    }
+
+    // The (approximate) number of bytes that the film's pixels take
+    pub fn memory(&self) -> usize {
+        self.pixels.capacity() * mem::size_of::<Pixel>()
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &Pixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut Pixel {
+        &mut self.pixels[(y * self.width + x) as usize]
+    }
+
+    // Record a sample at continuous raster position (px, py), splatting it
+    // to every nearby pixel (see: `FilmTile` for splatting from many threads)
+    pub fn add_sample(&mut self, px: Float, py: Float, radiance: &Color, filter: &dyn Filter) {
+        let x = (px as u32).min(self.width - 1);
+        let y = (py as u32).min(self.height - 1);
+        self.pixel_mut(x, y).add_sample(radiance);
+
+        let (x0, y0, x1, y1) = footprint(px, py, filter.radius(), (0, 0, self.width, self.height));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = filter.evaluate(x as Float + 0.5 - px, y as Float + 0.5 - py);
+                if weight != 0.0 {
+                    let pixel = self.pixel_mut(x, y);
+                    pixel.weighted_sum += *radiance * weight;
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let (sum, weight) = tile.contributions[tile.index(x, y)];
+                let pixel = self.pixel_mut(x, y);
+                pixel.weighted_sum += sum;
+                pixel.weight += weight;
+            }
+        }
+    }
+
+    // Resolve the film into an image of (linear) radiance values
+    pub fn to_framebuffer(&self) -> Framebuffer {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|p| p.color()).collect(),
+        }
+    }
+
+    // Whether any AOVs have been recorded
+    pub fn has_aovs(&self) -> bool {
+        self.pixels.iter().any(|p| p.aov_count > 0)
+    }
+
+    // Resolve one of the film's AOVs into an image (depths are replicated
+    // across all three channels, and IDs are shown as a blend of the colors
+    // of the IDs seen by each pixel)
+    pub fn aov_framebuffer(&self, aov: Aov) -> Framebuffer {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels
+                .iter()
+                .map(|p| {
+                    let aovs = p.aovs();
+                    match aov {
+                        Aov::Normal => Color::from_vector(&aovs.normal),
+                        Aov::Depth => Color::gray(aovs.depth),
+                        Aov::Position => Color::from_vector(&aovs.position),
+                        Aov::Albedo => aovs.albedo,
+                        Aov::Alpha => Color::gray(aovs.alpha),
+                        Aov::ObjectId | Aov::MaterialId => {
+                            let ids = if aov == Aov::ObjectId { &p.object_ids } else { &p.material_ids };
+                            let n = p.aov_count.max(1) as Float;
+                            ids.ranks.iter().fold(Color::black(), |c, &(id, count)| {
+                                c + id_color(id) * (count as Float / n)
+                            })
+                        }
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    // A matte (white where the given object or material ID covers a pixel,
+    // black elsewhere) for isolating it in compositing
+    pub fn matte(&self, aov: Aov, id: u32) -> Framebuffer {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels
+                .iter()
+                .map(|p| Color::gray(p.coverage(aov, id)))
+                .collect(),
+        }
+    }
+
+    // Discard all samples
+    pub fn clear(&mut self) {
+        for pixel in &mut self.pixels {
+            *pixel = Pixel::new();
+        }
+    }
+
+    // The total number of samples taken across the entire film
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.count as u64).sum()
+    }
+
+    // How many samples each pixel took, as a heatmap from none (blue) to
+    // `max_samples` (red), to see where adaptive sampling spent them
+    pub fn sample_heatmap(&self, max_samples: u32) -> Framebuffer {
+        self.heatmap(|p| p.count as Float / max_samples.max(1) as Float)
+    }
+
+    // How noisy each pixel's estimate is, by the variance of its samples
+    // (see: `Pixel::relative_error`), as a heatmap whose middle (green) is
+    // `noise_threshold`: pixels on the blue side converged, and those that
+    // are red stopped at twice the threshold or more (or with too few samples
+    // to tell)
+    pub fn variance_heatmap(&self, noise_threshold: Float) -> Framebuffer {
+        self.heatmap(|p| p.relative_error() / (2.0 * noise_threshold))
+    }
+
+    fn heatmap<F: Fn(&Pixel) -> Float>(&self, f: F) -> Framebuffer {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|p| heatmap(f(p))).collect(),
+        }
+    }
+}
+
+// A rectangular region of the film into which samples are splatted: since a
+// sample contributes to every pixel within the filter's radius, tiles that
+// are rendered in parallel overlap and must be merged into the film
+// afterwards
+pub struct FilmTile {
+    // The (clipped) pixel bounds of the tile, where x1 and y1 are exclusive
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+    contributions: Vec<(Color, Float)>,
+}
+
+impl FilmTile {
+    // Create a tile covering the given pixels plus a margin for the filter
+    pub fn new(film: &Film, x0: u32, y0: u32, x1: u32, y1: u32, filter: &dyn Filter) -> FilmTile {
+        let margin = filter.radius().ceil() as u32;
+        let x0 = x0.saturating_sub(margin);
+        let y0 = y0.saturating_sub(margin);
+        let x1 = (x1 + margin).min(film.width);
+        let y1 = (y1 + margin).min(film.height);
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            contributions: vec![(Color::black(), 0.0); ((x1 - x0) * (y1 - y0)) as usize],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y0) * (self.x1 - self.x0) + (x - self.x0)) as usize
+    }
+
+    // Splat a sample at continuous raster position (px, py) to every pixel
+    // that the filter overlaps
+    pub fn add_sample(&mut self, px: Float, py: Float, radiance: &Color, filter: &dyn Filter) {
+        let bounds = (self.x0, self.y0, self.x1, self.y1);
+        let (x0, y0, x1, y1) = footprint(px, py, filter.radius(), bounds);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = filter.evaluate(x as Float + 0.5 - px, y as Float + 0.5 - py);
+                if weight != 0.0 {
+                    let i = self.index(x, y);
+                    self.contributions[i].0 += *radiance * weight;
+                    self.contributions[i].1 += weight;
+                }
+            }
+        }
+    }
+}
+
+// The pixels (clipped to `bounds`) whose centers lie within `radius` of the
+// continuous raster position (px, py), as exclusive bounds (x0, y0, x1, y1)
+fn footprint(px: Float, py: Float, radius: Float, bounds: (u32, u32, u32, u32)) -> (u32, u32, u32, u32) {
+    // Pixel centers are located at half-integer coordinates
+    let x0 = (px - 0.5 - radius).ceil().max(bounds.0 as Float) as u32;
+    let y0 = (py - 0.5 - radius).ceil().max(bounds.1 as Float) as u32;
+    let x1 = ((px - 0.5 + radius).floor() + 1.0).min(bounds.2 as Float).max(0.0) as u32;
+    let y1 = ((py - 0.5 + radius).floor() + 1.0).min(bounds.3 as Float).max(0.0) as u32;
+    (x0, y0, x1, y1)
+}
+
+#[test]
+fn test_id_coverage() {
+    let mut ids = IdCoverage::new();
+    for &id in &[7, 3, 3, 9, 3, 7] {
+        ids.add(id);
+    }
+    assert_eq!(ids.ranks[0], (3, 3));
+    assert_eq!(ids.ranks[1], (7, 2));
+    assert_eq!(ids.count(9), 1);
+    assert_eq!(ids.count(1), 0);
+}
+
+#[test]
+fn test_pixel_variance() {
+    let mut pixel = Pixel::new();
+    for l in &[1.0, 2.0, 3.0, 4.0] {
+        pixel.add_sample(&Color::gray(*l));
+    }
+    assert_eq!(pixel.count, 4);
+    assert!((pixel.variance() - 5.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_heatmaps() {
+    // A pixel that took every sample without converging is red, and one that
+    // took none is blue
+    let mut film = Film::new(2, 1);
+    for l in &[1.0, 3.0, 1.0, 3.0] {
+        film.pixel_mut(0, 0).add_sample(&Color::gray(*l));
+    }
+    let samples = film.sample_heatmap(4);
+    assert_eq!((samples.get(0, 0), samples.get(1, 0)), (heatmap(1.0), heatmap(0.0)));
+    let variance = film.variance_heatmap(0.01);
+    assert_eq!((variance.get(0, 0), variance.get(1, 0)), (heatmap(1.0), heatmap(1.0)));
+    // (Its relative error is about 0.29)
+    assert!(film.variance_heatmap(1.0).get(0, 0).b > 0.0);
+}
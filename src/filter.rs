@@ -0,0 +1,199 @@
+use vector::Float;
+
+use serde::{Deserialize, Serialize};
+
+// Reconstruction filters determine how much each radiance sample contributes
+// to the pixels around it
+pub trait Filter: Sync + Send {
+    // The filter's half-width along each axis, in pixels
+    fn radius(&self) -> Float;
+
+    // The filter's weight at an offset (x, y) from the pixel center
+    fn evaluate(&self, x: Float, y: Float) -> Float;
+
+    // Filters that can't be described by `FilterData` (and so can't be
+    // saved along with the render settings) return `None`
+    fn data(&self) -> Option<FilterData> {
+        None
+    }
+}
+
+// A plain description of one of the built-in filters, with its parameters,
+// as saved along with the render settings (see: `Renderer`)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterData {
+    Box { radius: Float },
+    Tent { radius: Float },
+    Gaussian { radius: Float, alpha: Float },
+    Mitchell { radius: Float, b: Float, c: Float },
+}
+
+impl FilterData {
+    pub fn create(&self) -> Box<dyn Filter> {
+        match *self {
+            FilterData::Box { radius } => Box::new(BoxFilter::new(radius)),
+            FilterData::Tent { radius } => Box::new(TentFilter::new(radius)),
+            FilterData::Gaussian { radius, alpha } => Box::new(GaussianFilter::new(radius, alpha)),
+            FilterData::Mitchell { radius, b, c } => Box::new(MitchellFilter::new(radius, b, c)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl FilterType {
+    // Construct a filter of this type with its usual parameters
+    pub fn create(&self) -> Box<dyn Filter> {
+        match *self {
+            FilterType::Box => Box::new(BoxFilter::new(0.5)),
+            FilterType::Tent => Box::new(TentFilter::new(1.0)),
+            FilterType::Gaussian => Box::new(GaussianFilter::new(1.5, 2.0)),
+            FilterType::Mitchell => Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+        }
+    }
+}
+
+// Weighs every sample within the filter's extent equally: with a radius of
+// half a pixel, this simply averages the samples taken inside each pixel
+pub struct BoxFilter {
+    pub radius: Float,
+}
+
+impl BoxFilter {
+    pub fn new(r: Float) -> BoxFilter {
+        BoxFilter { radius: r }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        if x.abs() <= self.radius && y.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+
+    fn data(&self) -> Option<FilterData> {
+        Some(FilterData::Box { radius: self.radius })
+    }
+}
+
+// A separable filter whose weight falls off linearly from the pixel center
+pub struct TentFilter {
+    pub radius: Float,
+}
+
+impl TentFilter {
+    pub fn new(r: Float) -> TentFilter {
+        TentFilter { radius: r }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+
+    fn data(&self) -> Option<FilterData> {
+        Some(FilterData::Tent { radius: self.radius })
+    }
+}
+
+// A separable Gaussian, offset so that it falls to zero at the filter's edge
+pub struct GaussianFilter {
+    pub radius: Float,
+    // The falloff rate: larger values produce a narrower, sharper filter
+    pub alpha: Float,
+}
+
+impl GaussianFilter {
+    pub fn new(r: Float, a: Float) -> GaussianFilter {
+        GaussianFilter {
+            radius: r,
+            alpha: a,
+        }
+    }
+
+    fn gaussian(&self, d: Float) -> Float {
+        let edge = (-self.alpha * self.radius * self.radius).exp();
+        ((-self.alpha * d * d).exp() - edge).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.gaussian(x) * self.gaussian(y)
+    }
+
+    fn data(&self) -> Option<FilterData> {
+        Some(FilterData::Gaussian {
+            radius: self.radius,
+            alpha: self.alpha,
+        })
+    }
+}
+
+// The separable cubic filter described in "Reconstruction Filters in
+// Computer Graphics" (Mitchell and Netravali): its negative lobes sharpen
+// edges, with B and C trading off between blurring and ringing
+pub struct MitchellFilter {
+    pub radius: Float,
+    pub b: Float,
+    pub c: Float,
+}
+
+impl MitchellFilter {
+    pub fn new(r: Float, b: Float, c: Float) -> MitchellFilter {
+        MitchellFilter { radius: r, b, c }
+    }
+
+    fn mitchell(&self, d: Float) -> Float {
+        // Remap the offset to [-2, 2], the domain of the cubic
+        let x = (2.0 * d / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x +
+             (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x +
+             (6.0 - 2.0 * b)) / 6.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Float {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Float, y: Float) -> Float {
+        self.mitchell(x) * self.mitchell(y)
+    }
+
+    fn data(&self) -> Option<FilterData> {
+        Some(FilterData::Mitchell {
+            radius: self.radius,
+            b: self.b,
+            c: self.c,
+        })
+    }
+}
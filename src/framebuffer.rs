@@ -0,0 +1,76 @@
+use vector::Float;
+use color::Color;
+use tonemap::DisplayTransform;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+// A framebuffer is a resolved image: one (linear) radiance value per pixel,
+// stored in row-major order with the top row first
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::black(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: &Color) {
+        self.pixels[(y * self.width + x) as usize] = *color;
+    }
+
+    // Tone map every pixel to [0, 1], with the channels in RGB order
+    fn display_values(&self, display: &DisplayTransform) -> Vec<Float> {
+        let mut values = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in &self.pixels {
+            let c = display.apply(pixel);
+            values.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+        values
+    }
+
+    // Convert to 8-bit RGB triplets for display
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> Vec<u8> {
+        // Convert colors to 0..255
+        self.display_values(display).iter().map(|c| (255.99 * c) as u8).collect()
+    }
+
+    // Convert to 16-bit RGB triplets for display
+    pub fn to_rgb16(&self, display: &DisplayTransform) -> Vec<u16> {
+        self.display_values(display).iter().map(|c| (65535.99 * c) as u16).collect()
+    }
+
+    // Apply only the exposure adjustment of a display transform, leaving the
+    // result in linear (high dynamic range) radiance
+    pub fn exposed(&self, display: &DisplayTransform) -> Framebuffer {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|p| display.expose(p)).collect(),
+        }
+    }
+
+    pub fn write_ppm(&self, path: &Path, display: &DisplayTransform) -> io::Result<()> {
+        let mut file_contents: String = format!("P3\n{} {}\n255\n", self.width, self.height);
+        for rgb in self.to_rgb8(display).chunks(3) {
+            file_contents.push_str(&format!("{} {} {}\n", rgb[0], rgb[1], rgb[2]));
+        }
+
+        let mut file = File::create(path)?;
+        file.write_all(file_contents.as_bytes())
+    }
+}
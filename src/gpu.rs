@@ -0,0 +1,304 @@
+use camera::Camera;
+use scene::Scene;
+use framebuffer::Framebuffer;
+
+#[cfg(feature = "gpu")]
+use vector::Vector;
+#[cfg(feature = "gpu")]
+use vector::Float;
+#[cfg(feature = "gpu")]
+use color::Color;
+#[cfg(feature = "gpu")]
+use environment::Environment;
+#[cfg(feature = "gpu")]
+use material::MaterialData;
+#[cfg(feature = "gpu")]
+use shape::Shape;
+#[cfg(feature = "gpu")]
+use shape::ShapeData;
+#[cfg(feature = "gpu")]
+use primitive::Visibility;
+#[cfg(feature = "gpu")]
+use pollster;
+#[cfg(feature = "gpu")]
+use wgpu;
+
+// The flattened scene, laid out as the compute shader (`gpu.wgsl`) expects
+#[cfg(feature = "gpu")]
+struct SceneBuffers {
+    primitives: Vec<u8>,
+    materials: Vec<u8>,
+    primitive_count: u32,
+}
+
+// Flatten the scene's primitives and materials into tables of plain data:
+// primitives refer to materials by their material IDs
+#[cfg(feature = "gpu")]
+fn flatten(scene: &Scene) -> Result<SceneBuffers, String> {
+    if scene.environment != Environment::Sky {
+        return Err("only the sky is supported as the environment".to_string());
+    }
+    let mut primitives = Vec::new();
+    let mut materials = Vec::new();
+
+    for item in &scene.items {
+        if !item.transform.is_translation() {
+            return Err(format!("object {} is rotated or scaled", item.object_id));
+        }
+        if item.visibility != Visibility::new() {
+            return Err(format!("object {} is hidden from some rays", item.object_id));
+        }
+        let offset = item.transform.translation();
+        let (kind, a, b) = match item.shape.data() {
+            Some(ShapeData::Sphere { center, radius }) => (0u32, (center + offset, radius), Vector::zero()),
+            Some(ShapeData::Plane { center, normal }) => (1u32, (center + offset, 0.0), normal),
+            None => return Err(format!("object {} has an unsupported shape", item.object_id)),
+        };
+        push_u32s(&mut primitives, &[kind, item.material_id, 0, 0]);
+        push_vector(&mut primitives, &a.0, a.1);
+        push_vector(&mut primitives, &b, 0.0);
+    }
+
+    for (id, material) in scene.materials.iter().enumerate() {
+        let (albedo, kind, parameter) = match material.data() {
+            Some(MaterialData::Lambertian { albedo }) => (albedo, 0u32, 0.0),
+            Some(MaterialData::Metallic { albedo, glossiness }) => (albedo, 1, glossiness),
+            Some(MaterialData::Dielectric { ior, priority: None, dispersion: None }) => (Color::white(), 2, ior),
+            // (Nor are dielectrics that nest or disperse light, see: `MediumStack`)
+            Some(MaterialData::Dielectric { .. }) | Some(MaterialData::TexturedLambertian { .. }) |
+            Some(MaterialData::Emissive { .. }) | Some(MaterialData::ThinDielectric { .. }) | Some(MaterialData::Volume { .. }) |
+            Some(MaterialData::ShadowCatcher { .. }) | Some(MaterialData::Graph(_)) | None => return Err(format!("material {} is unsupported", id)),
+        };
+        push_color(&mut materials, &albedo, 0.0);
+        push_u32s(&mut materials, &[kind]);
+        materials.extend_from_slice(&(parameter as f32).to_le_bytes());
+        push_u32s(&mut materials, &[0, 0]);
+    }
+
+    // (Storage buffers can't be empty)
+    if primitives.is_empty() {
+        primitives.resize(48, 0);
+    }
+    if materials.is_empty() {
+        materials.resize(32, 0);
+    }
+    Ok(SceneBuffers {
+        primitives,
+        materials,
+        primitive_count: scene.items.len() as u32,
+    })
+}
+
+#[cfg(feature = "gpu")]
+fn push_u32s(buffer: &mut Vec<u8>, values: &[u32]) {
+    for v in values {
+        buffer.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn push_vector(buffer: &mut Vec<u8>, v: &Vector, w: Float) {
+    for c in &[v.x as f32, v.y as f32, v.z as f32, w as f32] {
+        buffer.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+#[cfg(feature = "gpu")]
+fn push_color(buffer: &mut Vec<u8>, c: &Color, w: Float) {
+    for c in &[c.r as f32, c.g as f32, c.b as f32, w as f32] {
+        buffer.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+// Path traces (in single precision) on the GPU, in a compute shader, for
+// previewing at interactive rates: the scene is flattened into tables of
+// primitives and materials (so only spheres, planes, and the built-in
+// materials are supported, and primitives can only be translated), which
+// are uploaded once and traced progressively, one sample per pixel per pass
+#[cfg(feature = "gpu")]
+pub struct GpuRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    uniforms: wgpu::Buffer,
+    accumulation: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    primitive_count: u32,
+    pub max_depth: u32,
+    pub passes: u32,
+}
+
+#[cfg(feature = "gpu")]
+impl GpuRenderer {
+    // Upload the scene, or explain why it can't be rendered on the GPU
+    pub fn new(scene: &Scene, width: u32, height: u32, max_depth: u32) -> Result<GpuRenderer, String> {
+        use wgpu::util::DeviceExt;
+
+        let buffers = flatten(scene)?;
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .map_err(|why| format!("no GPU adapter available ({})", why))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default()))
+            .map_err(|why| format!("couldn't open the GPU ({})", why))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("tracer"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("tracer"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let storage = |contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let primitives = storage(&buffers.primitives);
+        let materials = storage(&buffers.materials);
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 112,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let size = (width * height) as u64 * 16;
+        let accumulation = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniforms.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: primitives.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: materials.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: accumulation.as_entire_binding(),
+                        }],
+        });
+
+        Ok(GpuRenderer {
+            device,
+            queue,
+            pipeline,
+            bind_group,
+            uniforms,
+            accumulation,
+            readback,
+            width,
+            height,
+            primitive_count: buffers.primitive_count,
+            max_depth,
+            passes: 0,
+        })
+    }
+
+    // Start over (e.g. after the camera moves)
+    pub fn clear(&mut self) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.clear_buffer(&self.accumulation, 0, None);
+        self.queue.submit(Some(encoder.finish()));
+        self.passes = 0;
+    }
+
+    // Take one more sample in every pixel
+    pub fn render_pass(&mut self, camera: &Camera, seed: u32) {
+        let mut uniforms = Vec::with_capacity(112);
+        let (lower_left_corner, horizontal, vertical) = camera.image_plane();
+        for v in &[camera.origin(), lower_left_corner, horizontal, vertical] {
+            push_vector(&mut uniforms, v, 0.0);
+        }
+        push_u32s(&mut uniforms,
+                  &[self.width, self.height, seed, self.passes, self.primitive_count, self.max_depth, 0, 0]);
+        self.queue.write_buffer(&self.uniforms, 0, &uniforms);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups(self.width.div_ceil(8), self.height.div_ceil(8), 1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.passes += 1;
+    }
+
+    // Read back the average of the samples taken so far
+    pub fn framebuffer(&self) -> Result<Framebuffer, String> {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&self.accumulation, 0, &self.readback, 0, self.readback.size());
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::Wait).map_err(|why| format!("couldn't read back the render ({})", why))?;
+
+        let pixels = {
+            let data = slice.get_mapped_range();
+            data.chunks(16)
+                .map(|c| {
+                    let f = |i: usize| f32::from_le_bytes([c[i], c[i + 1], c[i + 2], c[i + 3]]) as Float;
+                    let count = f(12).max(1.0);
+                    Color::new(f(0) / count, f(4) / count, f(8) / count)
+                })
+                .collect()
+        };
+        self.readback.unmap();
+        Ok(Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+pub struct GpuRenderer {
+    pub max_depth: u32,
+    pub passes: u32,
+}
+
+#[cfg(not(feature = "gpu"))]
+impl GpuRenderer {
+    pub fn new(scene: &Scene, width: u32, height: u32, max_depth: u32) -> Result<GpuRenderer, String> {
+        Err("built without the `gpu` feature".to_string())
+    }
+
+    pub fn clear(&mut self) {}
+
+    pub fn render_pass(&mut self, camera: &Camera, seed: u32) {}
+
+    pub fn framebuffer(&self) -> Result<Framebuffer, String> {
+        Err("built without the `gpu` feature".to_string())
+    }
+}
@@ -0,0 +1,201 @@
+// Progressive path tracing of a flattened scene: each invocation traces one
+// path through one pixel and adds its radiance to the pixel's running sum
+// (see: `gpu.rs`, which lays out these buffers, and `trace` in `integrator.rs`,
+// which this mirrors)
+
+struct Uniforms {
+    origin: vec4<f32>,
+    lower_left_corner: vec4<f32>,
+    horizontal: vec4<f32>,
+    vertical: vec4<f32>,
+    width: u32,
+    height: u32,
+    seed: u32,
+    pass_index: u32,
+    primitive_count: u32,
+    max_depth: u32,
+    padding0: u32,
+    padding1: u32,
+};
+
+const SPHERE: u32 = 0u;
+const PLANE: u32 = 1u;
+
+struct Primitive {
+    kind: u32,
+    material: u32,
+    padding0: u32,
+    padding1: u32,
+    // Spheres: the center, with the radius in w (planes: a point on the plane)
+    a: vec4<f32>,
+    // Planes: the normal
+    b: vec4<f32>,
+};
+
+const LAMBERTIAN: u32 = 0u;
+const METALLIC: u32 = 1u;
+const DIELECTRIC: u32 = 2u;
+
+struct Material {
+    albedo: vec4<f32>,
+    kind: u32,
+    // The glossiness of metals, or the index of refraction of dielectrics
+    parameter: f32,
+    padding0: u32,
+    padding1: u32,
+};
+
+@group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var<storage, read> primitives: array<Primitive>;
+@group(0) @binding(2) var<storage, read> materials: array<Material>;
+@group(0) @binding(3) var<storage, read_write> accumulation: array<vec4<f32>>;
+
+const EPSILON: f32 = 0.001;
+const T_MIN: f32 = 0.001;
+const T_MAX: f32 = 3.4e38;
+
+var<private> rng_state: u32;
+
+// The same hash as `sampler::hash`
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn next_f32() -> f32 {
+    rng_state = hash(rng_state);
+    return f32(rng_state >> 8u) / 16777216.0;
+}
+
+fn random_in_unit_sphere() -> vec3<f32> {
+    loop {
+        let p = vec3<f32>(next_f32(), next_f32(), next_f32()) * 2.0 - vec3<f32>(1.0);
+        if dot(p, p) <= 1.0 {
+            return p;
+        }
+    }
+    return vec3<f32>(0.0);
+}
+
+struct Hit {
+    t: f32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    material: u32,
+};
+
+fn intersect(origin: vec3<f32>, direction: vec3<f32>, hit: ptr<function, Hit>) -> bool {
+    var found = false;
+    var closest = T_MAX;
+    for (var i = 0u; i < uniforms.primitive_count; i++) {
+        let primitive = primitives[i];
+        var t = -1.0;
+        var normal = vec3<f32>(0.0);
+        if primitive.kind == SPHERE {
+            let center = primitive.a.xyz;
+            let radius = primitive.a.w;
+            let b = dot((origin - center) * 2.0, direction);
+            let c = dot(origin - center, origin - center) - radius * radius;
+            let discriminant = b * b - 4.0 * c;
+            if discriminant >= 0.0 {
+                let root = sqrt(discriminant);
+                if -b - root > EPSILON {
+                    t = (-b - root) * 0.5;
+                } else if -b + root > EPSILON {
+                    t = (-b + root) * 0.5;
+                }
+                normal = (origin + direction * t - center) / radius;
+            }
+        } else {
+            let n = primitive.b.xyz;
+            let denominator = dot(direction, n);
+            if abs(denominator) > EPSILON {
+                let candidate = dot(primitive.a.xyz - origin, n) / denominator;
+                if candidate >= EPSILON && (origin + direction * candidate).y < 1.0 {
+                    t = candidate;
+                    normal = n;
+                }
+            }
+        }
+
+        if t > 0.0 && t < closest {
+            closest = t;
+            found = true;
+            (*hit).t = t;
+            (*hit).position = origin + direction * t;
+            (*hit).normal = normal;
+            (*hit).material = primitive.material;
+        }
+    }
+    return found;
+}
+
+fn background(direction: vec3<f32>) -> vec3<f32> {
+    let t = 0.5 * (direction.y + 1.0);
+    return mix(vec3<f32>(1.0), vec3<f32>(0.5, 0.7, 1.0), t);
+}
+
+fn scatter(material: Material, direction: vec3<f32>, hit: Hit) -> vec3<f32> {
+    if material.kind == LAMBERTIAN {
+        return hit.normal + random_in_unit_sphere();
+    }
+    if material.kind == METALLIC {
+        return reflect(direction, hit.normal) + random_in_unit_sphere() * material.parameter;
+    }
+
+    // Dielectrics refract or reflect according to Schlick's approximation
+    var ior = material.parameter;
+    var r0 = (1.0 - ior) / (1.0 + ior);
+    r0 = r0 * r0;
+    var outward_normal = hit.normal;
+    if dot(direction, outward_normal) > 0.0 {
+        outward_normal = -outward_normal;
+        ior = 1.0 / ior;
+    }
+    ior = 1.0 / ior;
+    let cos_theta_i = -dot(direction, outward_normal);
+    let cos_theta_t = 1.0 - ior * ior * (1.0 - cos_theta_i * cos_theta_i);
+    let probability_of_reflection = r0 + (1.0 - r0) * pow(1.0 - cos_theta_i, 5.0);
+    if cos_theta_t > 0.0 && next_f32() > probability_of_reflection {
+        return direction * ior + outward_normal * (ior * cos_theta_i - sqrt(cos_theta_t));
+    }
+    return reflect(direction, outward_normal);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= uniforms.width || id.y >= uniforms.height {
+        return;
+    }
+    let pixel = id.y * uniforms.width + id.x;
+    rng_state = hash(hash(hash(uniforms.seed ^ id.x) ^ id.y) ^ uniforms.pass_index);
+
+    let u = (f32(id.x) + next_f32()) / f32(uniforms.width);
+    let v = (f32(uniforms.height) - (f32(id.y) + next_f32())) / f32(uniforms.height);
+    var origin = uniforms.origin.xyz;
+    var direction = normalize(uniforms.lower_left_corner.xyz + uniforms.horizontal.xyz * u +
+                              uniforms.vertical.xyz * v - origin);
+
+    var throughput = vec3<f32>(1.0);
+    var radiance = vec3<f32>(0.0);
+    for (var depth = 0u; depth <= uniforms.max_depth; depth++) {
+        var hit: Hit;
+        if !intersect(origin, direction, &hit) {
+            radiance = throughput * background(direction);
+            break;
+        }
+        if depth == uniforms.max_depth {
+            break;
+        }
+
+        let material = materials[hit.material];
+        if material.kind != DIELECTRIC {
+            throughput *= material.albedo.xyz;
+        }
+        origin = hit.position;
+        direction = normalize(scatter(material, direction, hit));
+    }
+
+    accumulation[pixel] += vec4<f32>(radiance, 1.0);
+}
@@ -0,0 +1,949 @@
+use vector::Vector;
+use vector::Float;
+use vector::consts;
+use color::Color;
+use color::heatmap;
+use color::WAVELENGTHS;
+use ray::Ray;
+use scene::Scene;
+use shape::DifferentialGeometry;
+use primitive::Primitive;
+use primitive::RayKind;
+use material;
+use material::MaterialData;
+use material::Volume;
+use medium::Medium;
+use medium::MediumStack;
+use phase;
+use film::AovSample;
+use rng;
+use rng::ThreadRng;
+use light;
+use stats;
+use stats::Counter;
+
+use serde::{Deserialize, Serialize};
+
+// Trace a ray through the scene (bouncing at most `max_depth` times),
+// optionally recording the AOVs of the first surface that it hits: the light
+// that reaches each diffuse surface straight from the scene's lights is
+// sampled there (see: `light`), while paths pick up any other light by
+// scattering into it
+pub fn trace(r: &Ray, scene: &Scene, depth: u32, max_depth: u32, aovs: Option<&mut AovSample>) -> Color {
+    trace_from(r, scene, depth, max_depth, aovs, false, None, &PathState::new())
+}
+
+// As `trace`, for a ray that leaves the camera, which sees `behind` rather
+// than the environment where it misses the scene (or where a shadow catcher
+// lets the background through): black for a transparent background, whose
+// alpha is only that of the scene (see: `Aov::Alpha`), or a backplate's
+// color (see: `Backplate`). The scene is still lit by its environment
+pub fn trace_camera(r: &Ray, scene: &Scene, max_depth: u32, behind: Option<Color>, aovs: Option<&mut AovSample>) -> Color {
+    trace_from(r, scene, 0, max_depth, aovs, false, behind, &PathState::new())
+}
+
+// As `trace_camera`, given the first surface that the ray hits (that the
+// camera sees), e.g. as found for a whole packet of camera rays at once (see:
+// `Scene::intersect_packet`)
+pub fn trace_camera_hit<'a>(r: &Ray,
+                            hit: Option<(DifferentialGeometry<'a>, &'a Primitive)>,
+                            scene: &'a Scene,
+                            max_depth: u32,
+                            behind: Option<Color>,
+                            aovs: Option<&mut AovSample>)
+                            -> Color {
+    let surface_interaction = nest_from(r, hit, scene, RayKind::Camera, &PathState::new());
+    trace_hit(r, surface_interaction, scene, 0, max_depth, aovs, false, behind)
+}
+
+// (Where `sampled` is whether the surface that the ray left sampled the
+// lights already)
+#[allow(clippy::too_many_arguments)]
+fn trace_from(r: &Ray,
+              scene: &Scene,
+              depth: u32,
+              max_depth: u32,
+              aovs: Option<&mut AovSample>,
+              sampled: bool,
+              behind: Option<Color>,
+              path: &PathState)
+              -> Color {
+    let surface_interaction = intersect_nested(r, scene, ray_kind(depth), path);
+    trace_hit(r, surface_interaction, scene, depth, max_depth, aovs, sampled, behind)
+}
+
+// The light that a ray carries back from the surface that it hits (along
+// with the path's state there), or from the background if it hits nothing
+#[allow(clippy::too_many_arguments)]
+fn trace_hit(r: &Ray,
+             surface_interaction: Option<(DifferentialGeometry, &Primitive, PathState)>,
+             scene: &Scene,
+             depth: u32,
+             max_depth: u32,
+             aovs: Option<&mut AovSample>,
+             sampled: bool,
+             behind: Option<Color>)
+             -> Color {
+    match surface_interaction {
+        // Hit
+        Some((dg, item, path)) => {
+            let material_id = dg.material_id.unwrap_or(item.material_id);
+            let mtl = scene.material(material_id);
+            let (radiance, alpha) = if depth == 0 && mtl.catches_shadows() {
+                let behind = behind.unwrap_or_else(|| scene.environment.radiance(&r.direction));
+                catch_shadows(r, scene, &dg, item, &path, max_depth, behind)
+            } else {
+                let shading = shade(r, scene, &dg, item, &path, sampled, depth < max_depth);
+                let mut radiance = shading.emitted + shading.direct;
+                if let Some((bounce_ray, attenuation)) = shading.bounce {
+                    let bounced = trace_from(&bounce_ray,
+                                             scene,
+                                             depth + 1,
+                                             max_depth,
+                                             None,
+                                             shading.diffuse,
+                                             None,
+                                             &shading.path);
+                    radiance += attenuation * bounced;
+                }
+                (radiance, 1.0)
+            };
+            if let Some(aovs) = aovs {
+                aovs.normal = dg.normal;
+                aovs.depth = (dg.position - r.origin).length();
+                aovs.position = dg.position;
+                aovs.albedo = mtl.albedo();
+                aovs.object_id = Some(item.object_id);
+                aovs.material_id = Some(material_id);
+                aovs.alpha = alpha;
+            }
+            radiance
+        }
+        // Miss
+        None => {
+            let background = behind.unwrap_or_else(|| background(scene, r, sampled));
+            if let Some(aovs) = aovs {
+                *aovs = AovSample::new();
+                aovs.albedo = background;
+            }
+            background
+        }
+    }
+}
+
+// What a ray that leaves the scene sees, other than the environment seen
+// through a portal that the surface it left sampled already (see:
+// `light::portal_pdf`)
+fn background(scene: &Scene, r: &Ray, sampled: bool) -> Color {
+    if sampled && light::portal_pdf(&scene.portals, &r.origin, &r.direction) > 0.0 {
+        return Color::black();
+    }
+    scene.environment.radiance(&r.direction)
+}
+
+// A shadow catcher seen from the camera (see: `ShadowCatcher`): the light
+// that it reflects is estimated along with (by the same samples) the light
+// that it would reflect without the rest of the scene there, which lights it
+// only from the environment. So much less light as the shadows take away is
+// its alpha, letting through the rest of what's behind it, and any more
+// light (which the rest of the scene reflects onto it) is added over that
+fn catch_shadows(r: &Ray,
+                 scene: &Scene,
+                 dg: &DifferentialGeometry,
+                 item: &Primitive,
+                 path: &PathState,
+                 max_depth: u32,
+                 behind: Color)
+                 -> (Color, Float) {
+    let shading = shade(r, scene, dg, item, path, false, max_depth > 0);
+    let (mut reflected, mut unshadowed) = (shading.direct, shading.unshadowed);
+    if let Some((bounce_ray, attenuation)) = shading.bounce {
+        reflected += attenuation * trace_from(&bounce_ray, scene, 1, max_depth, None, shading.diffuse, None, &shading.path);
+        unshadowed += attenuation * background(scene, &bounce_ray, shading.diffuse);
+    }
+    let alpha = if unshadowed.luminance() > 0.0 {
+        (1.0 - reflected.luminance() / unshadowed.luminance()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    ((reflected - unshadowed).map(|v| v.max(0.0)) + behind * (1.0 - alpha), alpha)
+}
+
+// What a path carries from one surface to the next, besides its weight: the
+// nested dielectrics that it's inside (see: `MediumStack`), and the one
+// wavelength of light that it carries (in nanometers), once it has met a
+// dispersive dielectric (see: `Dispersion`)
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PathState {
+    media: MediumStack,
+    wavelength: Option<Float>,
+}
+
+impl PathState {
+    fn new() -> PathState {
+        PathState {
+            media: MediumStack::new(),
+            wavelength: None,
+        }
+    }
+}
+
+// The surface that a ray hits, passing through the surfaces of any nested
+// dielectrics within others of a higher priority (see: `MediumStack`) along
+// the way, along with the path's state there
+fn intersect_nested<'a>(r: &Ray,
+                        scene: &'a Scene,
+                        kind: RayKind,
+                        path: &PathState)
+                        -> Option<(DifferentialGeometry<'a>, &'a Primitive, PathState)> {
+    nest_from(r, scene.intersect_visible(r, kind), scene, kind, path)
+}
+
+// As above, given the first surface that the ray hits
+fn nest_from<'a>(r: &Ray,
+                 hit: Option<(DifferentialGeometry<'a>, &'a Primitive)>,
+                 scene: &'a Scene,
+                 kind: RayKind,
+                 path: &PathState)
+                 -> Option<(DifferentialGeometry<'a>, &'a Primitive, PathState)> {
+    let mut path = path.clone();
+    let mut hit = hit;
+    let mut skipped: Option<Ray> = None;
+    loop {
+        let ray = skipped.as_ref().unwrap_or(r);
+        let (dg, item) = hit?;
+        match nested_medium(scene, &dg, item, path.wavelength) {
+            Some(medium) if path.media.passes_through(&medium) => {
+                path.media = path.media.crossed(&medium, ray.direction.dot(&dg.normal) < 0.0);
+                let next = Ray::spawn(&dg.position, &dg.normal, &ray.direction, ray.t_max);
+                hit = scene.intersect_visible(&next, kind);
+                skipped = Some(next);
+            }
+            _ => return Some((dg, item, path)),
+        }
+    }
+}
+
+// The medium that the surface bounds, if it's a dielectric that nests (with
+// its index of refraction at the path's wavelength, if it has one)
+fn nested_medium(scene: &Scene, dg: &DifferentialGeometry, item: &Primitive, wavelength: Option<Float>) -> Option<Medium> {
+    let mtl = scene.material(dg.material_id.unwrap_or(item.material_id));
+    let (ior, priority) = mtl.medium()?;
+    let ior = match (mtl.dispersion(), wavelength) {
+        (Some(dispersion), Some(nm)) => dispersion.ior(nm),
+        _ => ior,
+    };
+    Some(Medium { object_id: item.object_id, ior, priority })
+}
+
+// Rays that leave the camera are seen by what the camera sees, and the rest of
+// a path by what indirect light bounces off (see: `Visibility`)
+fn ray_kind(depth: u32) -> RayKind {
+    if depth == 0 {
+        RayKind::Camera
+    } else {
+        RayKind::Indirect
+    }
+}
+
+// What a path picks up at a surface that it hits: the light that the surface
+// emits (unless the path's last bounce sampled it already, see:
+// `Lights::contains`), the light that it reflects straight from the lights
+// (if it's diffuse, along with what it would if nothing cast shadows on it),
+// and, if the path may bounce again (and the surface reflects anything), the
+// ray that it scatters along with its weight (and the path's state along it),
+// and whether what that ray finds was sampled already
+struct Shading {
+    emitted: Color,
+    direct: Color,
+    unshadowed: Color,
+    bounce: Option<(Ray, Color)>,
+    path: PathState,
+    diffuse: bool,
+}
+
+fn shade(r: &Ray,
+         scene: &Scene,
+         dg: &DifferentialGeometry,
+         item: &Primitive,
+         path: &PathState,
+         sampled: bool,
+         bounce: bool)
+         -> Shading {
+    let mtl = scene.material(dg.material_id.unwrap_or(item.material_id));
+    let emitted = if sampled && scene.lights.contains(item.object_id) {
+        Color::black()
+    } else {
+        mtl.emission()
+    };
+    if !bounce {
+        return Shading {
+            emitted,
+            direct: Color::black(),
+            unshadowed: Color::black(),
+            bounce: None,
+            path: path.clone(),
+            diffuse: false,
+        };
+    }
+
+    // (Only scenes with lights or portals look for diffuse surfaces)
+    let diffuse = if scene.lights.is_empty() && scene.portals.is_empty() { None } else { mtl.diffuse(dg) };
+    let (direct, unshadowed) = diffuse.map_or_else(|| (Color::black(), Color::black()), |reflectance| {
+        let (lit, unshadowed) = direct_lighting(r, scene, dg);
+        (reflectance * lit, reflectance * unshadowed)
+    });
+    let mut attenuation = Color::white();
+    let mut path = path.clone();
+    // (A path that meets its first dispersive dielectric picks the wavelength
+    // that it carries from then on, uniformly, weighted by its color)
+    if let (None, Some(_)) = (path.wavelength, mtl.dispersion()) {
+        let nm = WAVELENGTHS.0 + (WAVELENGTHS.1 - WAVELENGTHS.0) * rng::next_float();
+        attenuation = Color::wavelength(nm);
+        path.wavelength = Some(nm);
+    }
+    let entering = r.direction.dot(&dg.normal) < 0.0;
+    // (A ray that passes straight through a surface, as the rays towards the
+    // lights do, sees what they did, see: `Scene::transmittance`)
+    let mut passed = false;
+    let bounce_ray = match (mtl.volume(), nested_medium(scene, dg, item, path.wavelength), mtl.dispersion()) {
+        // (A path through a volume is one bounce, however many times it
+        // scatters inside)
+        (Some(volume), _, _) => {
+            let (bounce_ray, weight, scattered) = scatter_volume(r, dg, item, volume);
+            attenuation = weight;
+            passed = !scattered;
+            bounce_ray
+        }
+        // (A nested dielectric refracts between the media either side of it,
+        // and the ray is inside the one that it refracts into)
+        (None, Some(medium), _) => {
+            let (eta_i, eta_t) = path.media.interface(&medium, entering);
+            let bounce_ray = material::refract(r, dg, eta_i, eta_t);
+            if (bounce_ray.direction.dot(&dg.normal) < 0.0) == entering {
+                path.media = path.media.crossed(&medium, entering);
+            }
+            bounce_ray
+        }
+        (None, None, Some(dispersion)) => {
+            let ior = path.wavelength.map_or(1.0, |nm| dispersion.ior(nm));
+            let (eta_i, eta_t) = if entering { (1.0, ior) } else { (ior, 1.0) };
+            material::refract(r, dg, eta_i, eta_t)
+        }
+        (None, None, None) => {
+            let bounce_ray = mtl.scatter(r, dg, &mut attenuation);
+            passed = mtl.transmittance(1.0).is_some() && (bounce_ray.direction.dot(&dg.normal) < 0.0) == entering;
+            bounce_ray
+        }
+    };
+    Shading {
+        emitted,
+        direct,
+        unshadowed,
+        bounce: if attenuation.is_black() { None } else { Some((bounce_ray, attenuation)) },
+        path,
+        diffuse: diffuse.is_some() || (passed && sampled),
+    }
+}
+
+// The most times that a path scatters inside a volume before it's given up on
+// (which only the densest volumes, with an albedo of nearly one, come to)
+const MAX_SCATTERING: u32 = 256;
+
+// The walk that a path entering a volume at `dg` takes through it, from one
+// scattering to the next (see: `Volume`), until it leaves: the ray leaving
+// it, the path's weight (the albedo, once for every time it scattered), and
+// whether it scattered at all.
+// Each scattering's direction is picked by the phase function's own density,
+// and its distance by the probability of travelling that far unscattered, so
+// nothing else weighs the path
+fn scatter_volume(r: &Ray, dg: &DifferentialGeometry, item: &Primitive, volume: &Volume) -> (Ray, Color, bool) {
+    let mut ray = Ray::spawn(&dg.position, &dg.normal, &r.direction, r.t_max);
+    // (A path that started inside the volume, e.g. from a camera inside a
+    // cloud, only leaves it)
+    if r.direction.dot(&dg.normal) > 0.0 {
+        return (ray, Color::white(), false);
+    }
+    let mut weight = Color::white();
+    for i in 0..MAX_SCATTERING {
+        let distance = volume.free_path(rng::next_float());
+        match item.intersect_shape(&ray) {
+            Some(ref exit) if exit.t <= distance => {
+                return (Ray::spawn(&exit.position, &exit.normal, &ray.direction, r.t_max), weight, i > 0);
+            }
+            Some(_) => {}
+            // (Through a shape that doesn't close, e.g. a plane)
+            None => return (ray, weight, i > 0),
+        }
+        weight *= volume.albedo;
+        let direction = phase::sample(&*volume.phase, &ray.direction, &mut ThreadRng);
+        ray = Ray::new(&ray.point_at(distance), &direction, 0.0, r.t_max);
+    }
+    (ray, Color::black(), true)
+}
+
+// The light reaching a diffuse surface (on the side that the ray arrived
+// from) straight from a point picked on the scene's lights, and from the
+// environment through a point picked on its portals, over pi (i.e. the
+// surface's BSDF, but for its reflectance), along with the light that would
+// if nothing were in the way
+fn direct_lighting(r: &Ray, scene: &Scene, dg: &DifferentialGeometry) -> (Color, Color) {
+    let normal = if dg.normal.dot(&r.direction) > 0.0 { -dg.normal } else { dg.normal };
+    let light = light_sample(scene, dg, &normal);
+    let portal = light::sample_portals(&scene.portals, &mut ThreadRng).and_then(|p| portal_sample(scene, dg, &normal, &p));
+    // (The shadow rays leave the same point, so they're traced as a packet)
+    let (radiance, shadows): (Vec<Color>, Vec<Ray>) = light.into_iter().chain(portal).unzip();
+    let transmittance = scene.transmittance_packet(&shadows);
+    radiance.iter().zip(transmittance).fold((Color::black(), Color::black()), |(lit, unshadowed), (&radiance, t)| {
+        (lit + radiance * t, unshadowed + radiance)
+    })
+}
+
+// (Each sample is the light that arrives, unless the ray towards it is
+// blocked)
+fn light_sample(scene: &Scene, dg: &DifferentialGeometry, normal: &Vector) -> Option<(Color, Ray)> {
+    let light = scene.lights.sample(&mut ThreadRng)?;
+    let d = light.position - dg.position;
+    let distance_squared = d.squared_length();
+    let wi = d / distance_squared.sqrt();
+    // (Lights emit from both of their sides)
+    let (cos_surface, cos_light) = (normal.dot(&wi), light.normal.dot(&wi).abs());
+    if !(cos_surface > 0.0 && cos_light > 0.0) {
+        return None;
+    }
+    Some((light.radiance * (cos_surface * cos_light / (consts::PI * distance_squared * light.pdf)),
+          Ray::spawn_to(&dg.position, normal, &light.position)))
+}
+
+// (The environment is only seen through the portal if nothing, inside or
+// out, is in the way)
+fn portal_sample(scene: &Scene, dg: &DifferentialGeometry, normal: &Vector, point: &Vector) -> Option<(Color, Ray)> {
+    let wi = (*point - dg.position).normalize();
+    let cos_surface = normal.dot(&wi);
+    let pdf = light::portal_pdf(&scene.portals, &dg.position, &wi);
+    if !(cos_surface > 0.0 && pdf > 0.0) {
+        return None;
+    }
+    Some((scene.environment.radiance(&wi) * (cos_surface / (consts::PI * pdf)),
+          Ray::spawn(&dg.position, normal, &wi, Float::MAX)))
+}
+
+// False-color views of the scene, rather than of its lighting, for finding
+// what's wrong with its geometry (see: `trace_debug`)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugMode {
+    // The shading normal, remapped from [-1, 1] to [0, 1]
+    Normal,
+    // The texture coordinates, wrapped into [0, 1), as red and green
+    Uv,
+    // How many BVH nodes were visited and shapes tested to find the first
+    // hit, as a heatmap up to `DEBUG_COST_SCALE`
+    BvhCost,
+    // How many times the path bounced before leaving the scene, as a
+    // heatmap up to the maximum depth
+    Bounces,
+    // The distance to the first hit, as gray (so that it's best written to
+    // a data format, or viewed with a lower exposure)
+    Distance,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 5] =
+        [DebugMode::Normal, DebugMode::Uv, DebugMode::BvhCost, DebugMode::Bounces, DebugMode::Distance];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DebugMode::Normal => "normal",
+            DebugMode::Uv => "uv",
+            DebugMode::BvhCost => "bvh_cost",
+            DebugMode::Bounces => "bounces",
+            DebugMode::Distance => "distance",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<DebugMode> {
+        DebugMode::ALL.iter().find(|m| m.name() == name).cloned()
+    }
+}
+
+// The traversal cost (BVH nodes visited, plus shapes and triangles tested)
+// that's shown as the hottest color
+pub const DEBUG_COST_SCALE: Float = 128.0;
+
+// Trace a camera ray for one of the debug views: rays that miss the scene
+// are black (or, for the BVH's cost, colored by the cost of missing)
+pub fn trace_debug(mode: DebugMode, r: &Ray, scene: &Scene, max_depth: u32) -> Color {
+    let cost = || {
+        stats::local(Counter::BvhNodeVisits) + stats::local(Counter::PrimitiveTests) +
+        stats::local(Counter::TriangleTests)
+    };
+    let before = cost();
+    let hit = scene.intersect_primitive(r);
+    let traversal = (cost() - before) as Float;
+    match (mode, hit) {
+        (DebugMode::BvhCost, _) => heatmap(traversal / DEBUG_COST_SCALE),
+        (_, None) => Color::black(),
+        (DebugMode::Normal, Some((dg, _))) => Color::from_vector(&(dg.normal * 0.5 + 0.5)),
+        (DebugMode::Uv, Some((dg, _))) => Color::new(dg.uv.0 - dg.uv.0.floor(), dg.uv.1 - dg.uv.1.floor(), 0.0),
+        (DebugMode::Distance, Some((dg, _))) => Color::gray((dg.position - r.origin).length()),
+        (DebugMode::Bounces, Some((dg, item))) => {
+            let material = scene.material(dg.material_id.unwrap_or(item.material_id));
+            let mut ray = material.scatter(r, &dg, &mut Color::white());
+            let mut bounces = 1;
+            while bounces < max_depth {
+                match scene.intersect_primitive(&ray) {
+                    Some((dg, item)) => {
+                        let material = scene.material(dg.material_id.unwrap_or(item.material_id));
+                        ray = material.scatter(&ray, &dg, &mut Color::white());
+                        bounces += 1;
+                    }
+                    None => break,
+                }
+            }
+            heatmap(bounces as Float / max_depth.max(1) as Float)
+        }
+    }
+}
+
+// A surface that a path traced by `trace_path` hit
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PathVertex {
+    pub object_id: u32,
+    pub material_id: u32,
+    // (If it can be described, see: `Material::data`)
+    pub material: Option<MaterialData>,
+    pub position: Vector,
+    pub normal: Vector,
+    // How far the ray travelled to reach the surface
+    pub distance: Float,
+    // The direction that the material scattered the path in, and the weight
+    // of that sample: materials sample a direction and weight it in one step
+    // (see: `Material::scatter`), so the weight is the BSDF times the cosine
+    // over the sample's PDF, which isn't computed on its own. Paths that end
+    // here (at the maximum depth, or at a surface that reflects nothing)
+    // don't scatter
+    pub direction: Option<Vector>,
+    pub weight: Option<Color>,
+    // The light that the surface emits, and that it reflects straight from
+    // the scene's lights (see: `trace`), before the throughput
+    pub emitted: Color,
+    pub direct: Color,
+    // The product of the weights of the path's bounces so far
+    pub throughput: Color,
+}
+
+// Every bounce of a path, from the camera's ray onward
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PathRecord {
+    pub origin: Vector,
+    pub direction: Vector,
+    pub vertices: Vec<PathVertex>,
+    // What the path saw of the environment, if it left the scene
+    pub environment: Option<Color>,
+    // The radiance that the path carries back to the camera (as `trace`
+    // returns it)
+    pub radiance: Color,
+}
+
+// Trace a ray as `trace` does (drawing the same random numbers), but
+// recording each bounce along the way (where the camera sees shadow catchers
+// as the diffuse surfaces that the rest of the path does)
+pub fn trace_path(r: &Ray, scene: &Scene, max_depth: u32) -> PathRecord {
+    let mut record = PathRecord {
+        origin: r.origin,
+        direction: r.direction,
+        vertices: Vec::new(),
+        environment: None,
+        radiance: Color::black(),
+    };
+    let mut ray = Ray::new(&r.origin, &r.direction, r.t_min, r.t_max);
+    let mut throughput = Color::white();
+    let mut sampled = false;
+    let mut path = PathState::new();
+    for depth in 0.. {
+        let (dg, item, state) = match intersect_nested(&ray, scene, ray_kind(depth), &path) {
+            Some(hit) => hit,
+            None => {
+                record.environment = Some(scene.environment.radiance(&ray.direction));
+                record.radiance += throughput * background(scene, &ray, sampled);
+                break;
+            }
+        };
+        let material_id = dg.material_id.unwrap_or(item.material_id);
+        let shading = shade(&ray, scene, &dg, item, &state, sampled, depth < max_depth);
+        record.radiance += throughput * (shading.emitted + shading.direct);
+        let mut vertex = PathVertex {
+            object_id: item.object_id,
+            material_id,
+            material: scene.material(material_id).data(),
+            position: dg.position,
+            normal: dg.normal,
+            distance: (dg.position - ray.origin).length(),
+            direction: None,
+            weight: None,
+            emitted: shading.emitted,
+            direct: shading.direct,
+            throughput,
+        };
+        let bounce = shading.bounce.map(|(bounce, attenuation)| {
+            throughput *= attenuation;
+            vertex.direction = Some(bounce.direction);
+            vertex.weight = Some(attenuation);
+            vertex.throughput = throughput;
+            bounce
+        });
+        record.vertices.push(vertex);
+        match bounce {
+            Some(bounce) => {
+                ray = bounce;
+                sampled = shading.diffuse;
+                path = shading.path;
+            }
+            None => break,
+        }
+    }
+    record
+}
+
+#[test]
+fn test_debug_modes() {
+    use vector::Vector;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 1.0),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    scene.build_bvh();
+    let hit = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    let miss = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, 1.0), 0.0, Float::MAX);
+
+    // The sphere faces the ray head on, two units away
+    let normal = trace_debug(DebugMode::Normal, &hit, &scene, 4);
+    assert!((normal.r - 0.5).abs() < 1e-6 && (normal.b - 1.0).abs() < 1e-6);
+    assert!((trace_debug(DebugMode::Distance, &hit, &scene, 4).r - 2.0).abs() < 1e-6);
+    let uv = trace_debug(DebugMode::Uv, &hit, &scene, 4);
+    assert!(uv.r >= 0.0 && uv.r < 1.0 && uv.g >= 0.0 && uv.g < 1.0);
+    // A single convex sphere can only be hit once
+    assert_eq!(trace_debug(DebugMode::Bounces, &hit, &scene, 4), heatmap(0.25));
+    assert_eq!(trace_debug(DebugMode::Bounces, &miss, &scene, 4), Color::black());
+    // Finding the sphere costs something
+    assert!(trace_debug(DebugMode::BvhCost, &hit, &scene, 4) != heatmap(0.0));
+    assert!(DebugMode::ALL.iter().all(|&m| DebugMode::named(m.name()) == Some(m)));
+}
+
+#[test]
+fn test_trace_path() {
+    use scenes;
+    use rng;
+
+    // A recorded path sees what the traced one does, since it draws the same
+    // random numbers
+    let scene = scenes::cornell_box(1.0);
+    let camera = scene.camera("main").unwrap();
+    for y in 0..8 {
+        let r = camera.pixel_ray(4.5, y as Float + 0.5, 8, 8);
+        rng::reseed(y);
+        let radiance = trace(&r, &scene, 0, 8, None);
+        rng::reseed(y);
+        let record = trace_path(&r, &scene, 8);
+        let error = (record.radiance - radiance).map(Float::abs).max_channel();
+        assert!(error <= 1e-5 * radiance.max_channel().max(1.0), "{:?} {:?}", record, radiance);
+        assert!(record.vertices.len() <= 9);
+        if let Some(last) = record.vertices.last() {
+            assert!(record.environment.is_some() == last.direction.is_some());
+        }
+    }
+}
+
+#[test]
+fn test_nested_dielectrics() {
+    use vector::Vector;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Dielectric;
+    use rng;
+    use std::sync::Arc;
+
+    // A dense sphere inside a glass one: while it has the lower priority, the
+    // glass fills it, so that paths never see its surface
+    let sphere_inside = |priority| {
+        let mut scene = Scene::new();
+        scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 1.0),
+                                 Arc::new(Dielectric { priority: Some(2), ..Dielectric::new(1.5) })));
+        scene.add(Primitive::new(Sphere::new(&Vector::new(0.2, 0.0, -3.0), 0.5),
+                                 Arc::new(Dielectric { priority: Some(priority), ..Dielectric::new(2.0) })));
+        scene.build_bvh();
+        scene
+    };
+    let hits = |scene: &Scene| {
+        (0..64)
+            .flat_map(|i| {
+                rng::reseed(i);
+                let r = Ray::new(&Vector::zero(), &Vector::new(0.01 * i as Float - 0.3, 0.1, -3.0), 0.0, Float::MAX);
+                trace_path(&r, scene, 8).vertices
+            })
+            .filter(|v| v.object_id == 1)
+            .count()
+    };
+    assert_eq!(hits(&sphere_inside(1)), 0);
+    assert!(hits(&sphere_inside(3)) > 0);
+
+    // The interface between media of the same IOR doesn't bend the path
+    let mut scene = sphere_inside(3);
+    scene.materials[1] = Arc::new(Dielectric { priority: Some(3), ..Dielectric::new(1.5) });
+    let r = Ray::new(&Vector::new(0.0, 0.3, 0.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    rng::reseed(1);
+    let record = trace_path(&r, &scene, 8);
+    let directions: Vec<Vector> = record.vertices.iter().filter_map(|v| v.direction).collect();
+    assert!(directions.len() > 2 && (directions[1] - directions[0]).length() < 1e-6, "{:?}", record);
+}
+
+#[test]
+fn test_dispersion() {
+    use vector::Vector;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Dielectric;
+    use material::Dispersion;
+    use material::D_LINE;
+    use rng;
+    use std::sync::Arc;
+
+    // BK7 glass, from its Sellmeier coefficients, and its Cauchy fit
+    let sellmeier = Dispersion::Sellmeier {
+        b: [1.039612, 0.2317923, 1.010469],
+        c: [0.006000699, 0.02001791, 103.5607],
+    };
+    let cauchy = Dispersion::Cauchy { a: 1.5046, b: 0.0042, c: 0.0 };
+    assert!((sellmeier.ior(D_LINE) - 1.5168).abs() < 1e-4);
+    assert!((cauchy.ior(D_LINE) - sellmeier.ior(D_LINE)).abs() < 2e-3);
+    assert!(sellmeier.ior(400.0) > sellmeier.ior(700.0));
+
+    // Light that passes through a dispersive sphere off its center leaves in
+    // a different direction for each wavelength (weighted by its color),
+    // where it would all leave together through glass that doesn't disperse
+    let through_sphere = |glass: Dielectric| {
+        let mut scene = Scene::new();
+        scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 1.0), Arc::new(glass)));
+        scene.build_bvh();
+        let r = Ray::new(&Vector::new(0.0, 0.5, 0.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+        (0..64)
+            .filter_map(|i| {
+                rng::reseed(i);
+                let record = trace_path(&r, &scene, 2);
+                let weight = record.vertices[0].weight?;
+                // (Of the paths that refract in and then out again)
+                match record.vertices.as_slice() {
+                    [a, b] if a.direction?.z < 0.0 && b.direction?.z < 0.0 => Some((b.direction?, weight)),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<(Vector, Color)>>()
+    };
+    let plain = through_sphere(Dielectric::new(1.5168));
+    assert!(plain.len() > 32 && plain.iter().all(|&(d, w)| (d - plain[0].0).length() < 1e-6 && w == Color::white()));
+    let dispersed = through_sphere(Dielectric::dispersive(Dispersion::Cauchy { a: 1.45, b: 0.03, c: 0.0 }));
+    let spread = dispersed.iter().map(|&(d, _)| (d - dispersed[0].0).length()).fold(0.0, Float::max);
+    assert!(dispersed.len() > 32 && spread > 0.01, "{}", spread);
+    assert!(dispersed.iter().any(|&(_, w)| w.r > w.b) && dispersed.iter().any(|&(_, w)| w.b > w.r));
+}
+
+#[test]
+fn test_volume() {
+    use vector::Vector;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use environment::Environment;
+    use phase::HenyeyGreenstein;
+    use phase::Isotropic;
+    use rng;
+    use std::sync::Arc;
+
+    // In a white furnace, a volume that absorbs nothing is invisible,
+    // however its light scatters
+    let mut scene = Scene::new();
+    scene.environment = Environment::Uniform { radiance: Color::white() };
+    let cloud = Volume::new(&Color::white(), 2.0, Arc::new(HenyeyGreenstein::new(0.7)));
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(cloud)));
+    scene.build_bvh();
+    let r = Ray::new(&Vector::new(0.0, 0.0, 3.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    for i in 0..100 {
+        rng::reseed(i);
+        assert_eq!(trace(&r, &scene, 0, 4, None), Color::white());
+    }
+
+    // One that absorbs everything that it scatters lets through as much light
+    // as a shadow ray through it does
+    scene.materials[0] = Arc::new(Volume::new(&Color::black(), 0.5, Arc::new(Isotropic)));
+    let count = 4000;
+    let radiance = (0..count)
+        .map(|i| {
+            rng::reseed(i);
+            trace(&r, &scene, 0, 4, None).g
+        })
+        .sum::<Float>() / count as Float;
+    let transmittance = scene.transmittance(&r).g;
+    assert!((transmittance - (-1.0 as Float).exp()).abs() < 1e-3, "{}", transmittance);
+    assert!((radiance - transmittance).abs() < 0.03, "{} {}", radiance, transmittance);
+
+    // One without any density lets everything through
+    scene.materials[0] = Arc::new(Volume::new(&Color::black(), 0.0, Arc::new(Isotropic)));
+    for i in 0..100 {
+        rng::reseed(i);
+        assert_eq!(trace(&r, &scene, 0, 4, None), Color::white());
+    }
+    assert_eq!(scene.transmittance(&r), Color::white());
+}
+
+#[test]
+fn test_direct_lighting() {
+    use vector::Vector;
+    use shape::Plane;
+    use shape::Quad;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use material::Emissive;
+    use environment::Environment;
+    use rng;
+    use std::sync::Arc;
+
+    // A floor lit only by a square lamp, whose corner is right above the point
+    // that's traced: the light reflected is the albedo times the lamp's
+    // radiance times the lamp's form factor, and light from the lamp that a
+    // path finds after it was sampled isn't counted twice
+    let mut scene = Scene::new();
+    scene.environment = Environment::Uniform { radiance: Color::black() };
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)),
+                             Arc::new(Lambertian::new(&Color::gray(0.5)))));
+    scene.add(Primitive::new(Quad::new(&Vector::new(0.0, 2.0, 0.0), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 0.0, 1.0)),
+                             Arc::new(Emissive::new(&Color::gray(4.0)))));
+    scene.build_bvh();
+    assert_eq!(scene.lights.len(), 2);
+    let x = 0.5 / Float::sqrt(1.25);
+    let expected = 0.5 * 4.0 * x * x.atan() / consts::PI;
+    let r = Ray::new(&Vector::new(0.0, 0.5, 0.0), &Vector::new(0.0, -1.0, 0.0), 0.0, Float::MAX);
+    let radiance = (0..10000)
+        .map(|i| {
+            rng::reseed(i);
+            trace(&r, &scene, 0, 4, None).r
+        })
+        .sum::<Float>() / 10000.0;
+    assert!((radiance - expected).abs() < 0.02 * expected, "{} {}", radiance, expected);
+}
+
+#[test]
+fn test_portals() {
+    use vector::Vector;
+    use shape::Plane;
+    use shape::Quad;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use environment::Environment;
+    use rng;
+    use std::sync::Arc;
+
+    // A floor under a black ceiling, with a square window in it whose corner
+    // is right above the point that's traced: sampled as a portal, the light
+    // reflected is the albedo times the environment's radiance times the
+    // window's form factor, and it's far less noisy than when paths have to
+    // scatter through the window
+    let mut scene = Scene::new();
+    scene.environment = Environment::Uniform { radiance: Color::white() };
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)),
+                             Arc::new(Lambertian::new(&Color::gray(0.5)))));
+    let black = Arc::new(Lambertian::new(&Color::black()));
+    for &(corner, u, v) in &[([-1000.0, -1000.0], 1000.0, 2000.0),
+                             ([1.0, -1000.0], 999.0, 2000.0),
+                             ([0.0, -1000.0], 1.0, 1000.0),
+                             ([0.0, 1.0], 1.0, 999.0)] {
+        scene.add(Primitive::new(Quad::new(&Vector::new(corner[0], 2.0, corner[1]),
+                                           &Vector::new(u, 0.0, 0.0),
+                                           &Vector::new(0.0, 0.0, v)),
+                                 black.clone()));
+    }
+    scene.build_bvh();
+    let x = 0.5 / Float::sqrt(1.25);
+    let expected = 0.5 * x * x.atan() / consts::PI;
+    let r = Ray::new(&Vector::new(0.0, 0.5, 0.0), &Vector::new(0.0, -1.0, 0.0), 0.0, Float::MAX);
+    let estimate = |scene: &Scene| {
+        let samples: Vec<Float> = (0..10000)
+            .map(|i| {
+                rng::reseed(i);
+                trace(&r, scene, 0, 4, None).r
+            })
+            .collect();
+        let mean = samples.iter().sum::<Float>() / samples.len() as Float;
+        let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<Float>() / samples.len() as Float;
+        (mean, variance)
+    };
+    let (_, found_variance) = estimate(&scene);
+    scene.portals.push(Quad::new(&Vector::new(0.0, 2.0, 0.0), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 0.0, 1.0)));
+    let (sampled, sampled_variance) = estimate(&scene);
+    assert!((sampled - expected).abs() < 0.02 * expected, "{} {}", sampled, expected);
+    assert!(sampled_variance < 0.1 * found_variance, "{} {}", sampled_variance, found_variance);
+}
+
+#[test]
+fn test_shadow_catcher() {
+    use vector::Vector;
+    use shape::Plane;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use material::ShadowCatcher;
+    use environment::Environment;
+    use rng;
+    use std::sync::Arc;
+
+    // Without anything to cast shadows on it, a shadow catcher can't be seen
+    let mut scene = Scene::new();
+    scene.environment = Environment::Uniform { radiance: Color::white() };
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)),
+                             Arc::new(ShadowCatcher::new(&Color::gray(0.5)))));
+    scene.build_bvh();
+    let r = Ray::new(&Vector::new(0.0, 1.0, 1.0), &Vector::new(0.0, -1.0, -1.0), 0.0, Float::MAX);
+    let mut aovs = AovSample::new();
+    rng::reseed(0);
+    assert_eq!(trace(&r, &scene, 0, 4, Some(&mut aovs)), Color::white());
+    assert_eq!(aovs.alpha, 0.0);
+
+    // A black sphere above it casts a shadow, whose alpha is however much
+    // light it takes away from what's behind the catcher
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 1.5, 0.0), 1.0), Arc::new(Lambertian::new(&Color::black()))));
+    scene.build_bvh();
+    let mut alpha = 0.0;
+    for i in 0..1000 {
+        rng::reseed(i);
+        let radiance = trace(&r, &scene, 0, 4, Some(&mut aovs)).r;
+        assert!((radiance + aovs.alpha - 1.0).abs() < 1e-6);
+        alpha += aovs.alpha / 1000.0;
+    }
+    assert!(alpha > 0.3 && alpha < 0.9, "{}", alpha);
+
+    // The sphere itself is opaque, and the environment isn't
+    trace(&Ray::new(&Vector::new(0.0, 1.5, 3.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX), &scene, 0, 4, Some(&mut aovs));
+    assert_eq!(aovs.alpha, 1.0);
+    trace(&Ray::new(&Vector::new(0.0, 1.5, 3.0), &Vector::new(0.0, 1.0, 0.0), 0.0, Float::MAX), &scene, 0, 4, Some(&mut aovs));
+    assert_eq!(aovs.alpha, 0.0);
+
+    // Over a transparent background (or a backplate), the camera sees only
+    // the light that the catcher adds over what's behind it, while the
+    // environment still lights the scene
+    let sky = Ray::new(&Vector::new(0.0, 1.5, 3.0), &Vector::new(0.0, 1.0, 0.0), 0.0, Float::MAX);
+    assert_eq!(trace_camera(&sky, &scene, 4, Some(Color::new(0.0, 1.0, 0.0)), None), Color::new(0.0, 1.0, 0.0));
+    for i in 0..100 {
+        rng::reseed(i);
+        assert_eq!(trace_camera(&r, &scene, 4, Some(Color::black()), Some(&mut aovs)), Color::black());
+    }
+    let wall = Ray::new(&Vector::new(2.0, 0.5, 3.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, 0.0, -1.0), &Vector::new(0.0, 0.0, 1.0)),
+                             Arc::new(Lambertian::new(&Color::gray(0.5)))));
+    scene.build_bvh();
+    rng::reseed(0);
+    assert!(trace_camera(&wall, &scene, 4, Some(Color::black()), Some(&mut aovs)).r > 0.0);
+    assert_eq!(aovs.alpha, 1.0);
+}
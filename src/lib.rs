@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+// Casts between `Float` and a concrete float type are only unnecessary at
+// one of the two precisions
+#![allow(clippy::unnecessary_cast)]
+
+// External crates
+extern crate image;
+extern crate exr;
+extern crate png;
+extern crate rayon;
+extern crate serde;
+extern crate toml;
+extern crate roxmltree;
+#[cfg(feature = "oidn")]
+extern crate oidn;
+#[cfg(feature = "preview")]
+extern crate minifb;
+#[cfg(feature = "simd")]
+extern crate wide;
+#[cfg(feature = "gpu")]
+extern crate wgpu;
+#[cfg(feature = "gpu")]
+extern crate pollster;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "glam")]
+extern crate glam;
+#[cfg(feature = "nalgebra")]
+extern crate nalgebra;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "python")]
+extern crate numpy;
+// (PyO3's macros name `::core`, which this edition resolves from the crate's
+// root)
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(all(target_arch = "wasm32", feature = "wasm-threads"))]
+extern crate wasm_bindgen_rayon;
+
+// The renderer's modules, shared by the `raytracer` binary (in `main.rs`)
+// and the benchmarks
+pub mod vector;
+pub mod color;
+pub mod ray;
+pub mod shape;
+pub mod material;
+pub mod medium;
+pub mod phase;
+pub mod material_graph;
+pub mod primitive;
+pub mod scene;
+pub mod environment;
+pub mod camera;
+pub mod stereo;
+pub mod sampler;
+pub mod blue_noise;
+pub mod film;
+pub mod rng;
+pub mod sampling;
+pub mod filter;
+pub mod temporal;
+pub mod framebuffer;
+pub mod output;
+pub mod metadata;
+pub mod tonemap;
+pub mod denoise;
+pub mod bloom;
+pub mod checkpoint;
+pub mod distributed;
+pub mod preview;
+pub mod server;
+pub mod sequence;
+pub mod scheduler;
+pub mod animation;
+pub mod transform;
+pub mod matrix;
+pub mod aabb;
+pub mod bvh;
+pub mod sphere_list;
+pub mod mesh;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+pub mod pbrt;
+pub mod mitsuba;
+pub mod scene_file;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod builder;
+pub mod scenes;
+pub mod reload;
+pub mod stats;
+pub mod memory;
+pub mod light;
+pub mod integrator;
+pub mod bake;
+pub mod probe;
+pub mod texture;
+pub mod backplate;
+pub mod renderer;
+pub mod settings;
+pub mod error;
+pub mod gpu;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
+// (Its functions take pointers from C, which must each be null or valid, as
+// the header says, rather than in Rust's doc comments)
+#[cfg(feature = "capi")]
+#[allow(clippy::missing_safety_doc)]
+pub mod capi;
@@ -0,0 +1,239 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Geometry;
+use shape::Quad;
+use shape::Shape;
+use ray::Ray;
+use scene::Scene;
+use sampling;
+use rng::Rng;
+
+// The scene's area lights: every triangle of its meshes (as well as its
+// triangles and quads) whose material emits light (see: `Emissive`), in world
+// space, which the integrator samples directly from the diffuse surfaces that
+// paths hit (see: `integrator::trace`), rather than waiting for paths to find
+// them. A triangle is picked with a probability proportional to its power (its
+// area times the luminance of its radiance), and then a point uniformly on it,
+// so that a mesh's triangles are picked by their areas, and brighter meshes
+// more often than dimmer ones
+#[derive(Clone, Debug, Default)]
+pub struct Lights {
+    triangles: Vec<EmissiveTriangle>,
+    // The running sum of the triangles' powers
+    cdf: Vec<Float>,
+    // Whether each primitive (by object ID) has any emissive triangles
+    objects: Vec<bool>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct EmissiveTriangle {
+    vertices: [Vector; 3],
+    normal: Vector,
+    radiance: Color,
+}
+
+// A point on one of the lights
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightSample {
+    pub position: Vector,
+    pub normal: Vector,
+    pub radiance: Color,
+    // The probability density of picking the point, with respect to area
+    pub pdf: Float,
+}
+
+impl Lights {
+    // Gather the scene's lights (where its primitives are now)
+    pub fn new(scene: &Scene) -> Lights {
+        let mut lights = Lights {
+            objects: vec![false; scene.items.len()],
+            ..Lights::default()
+        };
+        let mut total = 0.0;
+        for item in &scene.items {
+            let emission = |material_id: Option<u32>| scene.material(material_id.unwrap_or(item.material_id)).emission();
+            let triangles: Vec<([Vector; 3], Color)> = match item.shape {
+                Geometry::Triangle(ref triangle) => vec![(triangle.vertices, emission(None))],
+                Geometry::Quad(ref quad) => {
+                    let (a, b, c, d) = (quad.corner, quad.corner + quad.u, quad.corner + quad.u + quad.v, quad.corner + quad.v);
+                    vec![([a, b, c], emission(None)), ([a, c, d], emission(None))]
+                }
+                Geometry::Mesh(ref mesh) => {
+                    mesh.triangles
+                        .iter()
+                        .enumerate()
+                        .map(|(i, t)| (t.vertices, emission(mesh.material_ids.as_ref().map(|ids| ids[i]))))
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+            for (vertices, radiance) in triangles {
+                if radiance.luminance() <= 0.0 {
+                    continue;
+                }
+                let vertices = if item.transform.is_identity() {
+                    vertices
+                } else {
+                    [item.transform.point_to_world(&vertices[0]),
+                     item.transform.point_to_world(&vertices[1]),
+                     item.transform.point_to_world(&vertices[2])]
+                };
+                let cross = (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0]));
+                let area = 0.5 * cross.length();
+                // (Skipping degenerate triangles, which can't be picked)
+                if !area.is_normal() {
+                    continue;
+                }
+                total += area * radiance.luminance();
+                lights.triangles.push(EmissiveTriangle {
+                    vertices,
+                    normal: cross.normalize(),
+                    radiance,
+                });
+                lights.cdf.push(total);
+                lights.objects[item.object_id as usize] = true;
+            }
+        }
+        lights
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    // Is the object (by its ID) one of the lights? Light that paths find on
+    // a light by scattering off of a diffuse surface was already counted when
+    // the surface sampled the lights, so it isn't counted again
+    pub fn contains(&self, object_id: u32) -> bool {
+        self.objects.get(object_id as usize).cloned().unwrap_or(false)
+    }
+
+    // The total power of the lights, i.e. the sum of their areas times the
+    // luminance of their radiance
+    pub fn power(&self) -> Float {
+        self.cdf.last().cloned().unwrap_or(0.0)
+    }
+
+    // Pick a point on the lights (see: `Lights`)
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<LightSample> {
+        if self.is_empty() {
+            return None;
+        }
+        let total = self.power();
+        let u = rng.next_float() * total;
+        let i = self.cdf.partition_point(|&c| c <= u).min(self.triangles.len() - 1);
+        let triangle = &self.triangles[i];
+        let (b0, b1) = sampling::triangle(rng);
+        let [a, b, c] = triangle.vertices;
+        Some(LightSample {
+            position: a * b0 + b * b1 + c * (1.0 - b0 - b1),
+            normal: triangle.normal,
+            radiance: triangle.radiance,
+            // (The triangle's probability, over its area)
+            pdf: triangle.radiance.luminance() / total,
+        })
+    }
+}
+
+// Pick a point uniformly on the portals (see: `Scene::portals`), by their
+// areas, towards which the environment is sampled from a point inside
+pub fn sample_portals(portals: &[Quad], rng: &mut impl Rng) -> Option<Vector> {
+    let total: Float = portals.iter().map(portal_area).sum();
+    if !total.is_normal() {
+        return None;
+    }
+    let mut u = rng.next_float() * total;
+    let portal = portals.iter().find(|p| {
+            u -= portal_area(p);
+            u < 0.0
+        })
+        .unwrap_or(&portals[portals.len() - 1]);
+    Some(portal.corner + portal.u * rng.next_float() + portal.v * rng.next_float())
+}
+
+// The probability density (with respect to solid angle) of picking the
+// direction from `origin` by picking a point on the portals, counting every
+// portal that the direction passes through, or zero if it passes through none
+// (in which case the environment can only be found by scattering into it)
+pub fn portal_pdf(portals: &[Quad], origin: &Vector, direction: &Vector) -> Float {
+    if portals.is_empty() {
+        return 0.0;
+    }
+    let total: Float = portals.iter().map(portal_area).sum();
+    let r = Ray::new(origin, direction, 0.0, Float::MAX);
+    portals.iter()
+        .filter_map(|portal| {
+            let dg = portal.intersect(&r)?;
+            let cos = portal.u.cross(&portal.v).normalize().dot(&r.direction).abs();
+            Some(dg.t * dg.t / (cos * total))
+        })
+        .sum()
+}
+
+fn portal_area(portal: &Quad) -> Float {
+    portal.u.cross(&portal.v).length()
+}
+
+#[test]
+fn test_lights() {
+    use mesh::Mesh;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use material::Emissive;
+    use transform::Transform;
+    use rng::seeded_rng;
+    use vector::TEST_EPSILON;
+    use vector::consts;
+    use std::sync::Arc;
+
+    // A glowing cube (moved from the origin) beside a sphere that isn't a
+    // light: the points picked lie on the cube's surface, each as likely as
+    // any other
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(Lambertian::new(&Color::white()))));
+    let mut cube = Primitive::new(Mesh::cube(), Arc::new(Emissive::new(&Color::new(2.0, 1.0, 1.0))));
+    cube.transform = Transform::translate(&Vector::new(0.0, 5.0, 0.0));
+    scene.add(cube);
+    scene.build_bvh();
+    let lights = &scene.lights;
+    assert_eq!(lights.len(), 12);
+    assert!(!lights.contains(0) && lights.contains(1));
+    let mut rng = seeded_rng(1);
+    for _ in 0..100 {
+        let sample = lights.sample(&mut rng).unwrap();
+        let local = sample.position - Vector::new(0.0, 5.0, 0.0);
+        let extent = local.x.abs().max(local.y.abs()).max(local.z.abs());
+        assert!((extent - 1.0).abs() < TEST_EPSILON && local.dot(&sample.normal) > 0.0);
+        assert!((sample.pdf - 1.0 / 24.0).abs() < TEST_EPSILON);
+        assert_eq!(sample.radiance, Color::new(2.0, 1.0, 1.0));
+    }
+    assert!(Lights::default().sample(&mut rng).is_none());
+
+    // The points picked on a window lie on it, and the directions towards
+    // them have a density that integrates to one over the window
+    let portals = [Quad::new(&Vector::new(-1.0, 0.0, -2.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0))];
+    let point = sample_portals(&portals, &mut rng).unwrap();
+    assert!(point.z == -2.0 && point.x.abs() <= 1.0 && (0.0..=1.0).contains(&point.y));
+    assert_eq!(portal_pdf(&portals, &Vector::zero(), &Vector::new(0.0, 0.0, 1.0)), 0.0);
+    let samples = 40000;
+    let integral = (0..samples)
+        .map(|_| {
+            let point = sample_portals(&portals, &mut rng).unwrap();
+            let pdf = portal_pdf(&portals, &Vector::zero(), &point);
+            // (The solid angle that the window subtends, by sampling the
+            // sphere of directions uniformly)
+            let d = sampling::unit_sphere(&mut rng).normalize();
+            (1.0 / pdf, if portal_pdf(&portals, &Vector::zero(), &d) > 0.0 { 4.0 * consts::PI } else { 0.0 })
+        })
+        .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+    let estimate = integral.0 / samples as Float;
+    let solid_angle = integral.1 / samples as Float;
+    assert!((estimate - solid_angle).abs() < 0.1 * solid_angle, "{} {}", estimate, solid_angle);
+    assert!(sample_portals(&[], &mut rng).is_none());
+}
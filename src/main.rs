@@ -1,192 +1,683 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+// Casts between `Float` and a concrete float type are only unnecessary at
+// one of the two precisions
+#![allow(clippy::unnecessary_cast)]
+// The render functions below take the render settings one by one
+#![allow(clippy::too_many_arguments)]
 
 // External crates
-extern crate rand;
-use rand::Rng;
+extern crate raytracer;
+extern crate rayon;
 
 // Standard library
-use std::error::Error;
+use std::io;
 use std::io::prelude::*;
-use std::fs::File;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::time::Instant;
-use std::thread;
 use std::sync::Arc;
 
-// Bring custom modules into global scope
-mod vector;
-mod ray;
-mod shape;
-mod material;
-mod primitive;
-mod scene;
-mod camera;
-
 // Custom modules
-use vector::Vector;
-use ray::Ray;
-use shape::Shape;
-use shape::DifferentialGeometry;
-use shape::Sphere;
-use shape::Plane;
-use material::Material;
-use material::Lambertian;
-use material::Metallic;
-use material::Dielectric;
-use primitive::Primitive;
-use scene::Scene;
-use camera::Camera;
+use raytracer::output;
+use raytracer::checkpoint;
+use raytracer::sequence;
+use raytracer::scheduler;
+use raytracer::distributed;
+use raytracer::vector::Vector;
+use raytracer::vector::Float;
+use raytracer::scene::Scene;
+use raytracer::scene_file;
+use raytracer::scene_file::SettingsDescription;
+use raytracer::reload::Change;
+use raytracer::reload::SceneReloader;
+use raytracer::camera::Camera;
+use raytracer::camera::Orbit;
+use raytracer::sampler::SamplerType;
+use raytracer::sampler::hash_combine;
+use raytracer::blue_noise::BlueNoiseMask;
+use raytracer::film::Film;
+use raytracer::film::Aov;
+use raytracer::filter::FilterType;
+use raytracer::output::OutputFormat;
+use raytracer::metadata::Metadata;
+use raytracer::denoise::Denoiser;
+use raytracer::checkpoint::RenderState;
+use raytracer::preview::Preview;
+use raytracer::server::PreviewServer;
+use raytracer::server::Progress;
+use raytracer::sequence::Sequence;
+use raytracer::scheduler::TileProgress;
+use raytracer::distributed::Coordinator;
+use raytracer::distributed::Job;
+use raytracer::distributed::Role;
+use raytracer::animation::Keyframe;
+use raytracer::animation::ObjectAnimation;
+use raytracer::animation::Track;
+use raytracer::temporal::TemporalAccumulator;
+use raytracer::tonemap::DisplayTransform;
+use raytracer::tonemap::ToneMapOperator;
+use raytracer::tonemap::TransferFunction;
+use raytracer::bloom::Bloom;
+use raytracer::gpu::GpuRenderer;
+use raytracer::texture::TextureCache;
+use raytracer::renderer::Renderer;
+use raytracer::settings::RenderSettings;
+use raytracer::stats::RenderStats;
+use raytracer::memory::MemoryUsage;
 
+// Load the render settings from this TOML file instead (see:
+// `RenderSettings`, which can start from the "preview" or "production"
+// preset), in place of the resolution, sampling, filter, tone mapping, seed,
+// and tile size constants below
+const SETTINGS: Option<&str> = None;
 // Output resolution
 const RES_X: u32 = 800;
 const RES_Y: u32 = 800;
+// Only render the pixels within this rectangle, (x0, y0, x1, y1) where x1
+// and y1 are exclusive, leaving the rest of the image black (the film keeps
+// its full dimensions, so the crop lines up with the full render)
+const CROP: Option<(u32, u32, u32, u32)> = None;
+// The maximum number of samples taken per pixel
 const SAMPLES: u32 = 1;
+// Adaptive sampling: every pixel takes at least MIN_SAMPLES samples, after
+// which sampling stops once the pixel's relative error drops below the
+// noise threshold
+const MIN_SAMPLES: u32 = 8;
+const NOISE_THRESHOLD: Float = 0.01;
 const MAX_DEPTH: u32 = 5;
-const NUMBER_OF_THREADS: u32 = 10;
-const GAMMA: f64 = 1.0 / 2.2;
-
-fn trace(r: &Ray, scene: &Scene, depth: u32) -> Vector {
-    let surface_interaction = scene.intersect(&r);
-    match surface_interaction {
-        // Hit
-        Some((dg, mtl)) => {
-            let mut attenuation = Vector::one();
-            if depth < MAX_DEPTH {
-                let bounce_ray = mtl.scatter(&r, &dg, &mut attenuation);
-                return attenuation * trace(&bounce_ray, &scene, depth + 1);
-            } else {
-                Vector::zero()
+// The number of threads that render in parallel (0 uses one per core)
+const NUMBER_OF_THREADS: usize = 0;
+// Threads take square tiles of this many pixels across from a shared queue
+const TILE_SIZE: u32 = 32;
+// Show how far along each pass is
+const PROGRESS_BAR: bool = false;
+// Print what the scene is made of once it's built (see: `SceneStats`), and
+// what the render did (rays traced, BVH node visits, intersection tests, and
+// the time spent in each stage) and how much memory it took (see:
+// `MemoryUsage`) once it finishes
+const PRINT_STATS: bool = false;
+// Save the image after every SAVE_INTERVAL passes
+const SAVE_INTERVAL: u32 = 16;
+// Stop rendering (after the current pass) once this much time has elapsed
+const TIME_LIMIT: Option<Duration> = None;
+// Save a checkpoint alongside the image (see: SAVE_INTERVAL), from which the
+// render resumes if it is restarted
+const CHECKPOINT: Option<&str> = None;
+// Render the animation built by `build_sequence` instead of a single image,
+// writing one numbered image per frame
+const ANIMATE: bool = false;
+// When animating, blend each frame with the (reprojected) previous frame to
+// reduce flickering, giving the current frame this weight
+const TEMPORAL_ALPHA: Option<Float> = None;
+// Render the scene from each of the named cameras that it defines instead,
+// one after another, writing one image per camera (e.g.
+// "output/render_side.png"): `Some(&[])` renders every camera
+const CAMERAS: Option<&[&str]> = None;
+// Serve the in-progress render over HTTP at this address, e.g.
+// Some("0.0.0.0:8080"), to monitor it from a browser
+const HTTP_PREVIEW: Option<&str> = None;
+// Show the film in a window after every pass (requires the `preview`
+// feature), in which the mouse moves the camera: closing the window stops
+// the render
+const PREVIEW: bool = false;
+// While the preview window is open, bring the scene up to date whenever its
+// (TOML) scene file is saved, and restart the render (only with the preview
+// window built in, see: `PREVIEW`)
+const HOT_RELOAD: bool = false;
+// Path trace on the GPU instead (requires the `gpu` feature, and a scene of
+// spheres and planes that are at most translated), falling back to the CPU
+// if the scene can't be rendered there
+const GPU: bool = false;
+// Render across machines: one process coordinates, e.g.
+// Some(Role::Coordinator("0.0.0.0:7878")), handing out tiles of
+// JOB_TILE_SIZE pixels across (of the image, or of every frame when ANIMATE
+// is enabled) to any number of workers, e.g.
+// Some(Role::Worker("coordinator:7878")), which render them in parallel
+const DISTRIBUTED: Option<Role> = None;
+const JOB_TILE_SIZE: u32 = 128;
+// The scene file rendered (see: `scene_file` for its format, or a PBRT file
+// ending in ".pbrt", or a Mitsuba file ending in ".xml"), whose render
+// settings override those below, with its textures cached within
+// TEXTURE_BUDGET bytes
+const SCENE: &str = "scenes/spheres.toml";
+const TEXTURE_BUDGET: usize = 256 << 20;
+// How display outputs encode linear values (`Linear` for data passes)
+const TRANSFER_FUNCTION: TransferFunction = TransferFunction::Srgb;
+// Exposure adjustment (in stops) and tone mapping applied to the output
+const EXPOSURE: Float = 0.0;
+const TONE_MAP: ToneMapOperator = ToneMapOperator::Linear;
+// Add glare around the brightest parts of the image before it's tone mapped,
+// e.g. Some(Bloom { intensity: 0.04, radius: 0.1, threshold: 0.0 })
+const BLOOM: Option<Bloom> = None;
+// Render over a transparent background, saving the image with its alpha
+// (for compositing, which needs an OUTPUT_FORMAT of PNG or EXR), or over the
+// photograph at BACKPLATE, e.g. Some("footage/plate.jpg"), which the camera
+// sees in place of the environment
+const TRANSPARENT: bool = false;
+const BACKPLATE: Option<&str> = None;
+const OUTPUT_FORMAT: OutputFormat = OutputFormat::Png8;
+const SAMPLER: SamplerType = SamplerType::Sobol;
+// The filter used to reconstruct the image from its samples
+const FILTER: FilterType = FilterType::Mitchell;
+// All randomness in the render is derived from this seed, so rendering the
+// same scene with the same seed reproduces the same image
+const SEED: u32 = 0;
+// Offset each pixel's samples with a blue-noise mask (best for previews)
+const BLUE_NOISE: bool = false;
+const BLUE_NOISE_MASK_SIZE: usize = 64;
+// Also record normal, depth, albedo, and ID AOVs, each saved to its own file
+const WRITE_AOVS: bool = false;
+// Denoise the beauty image before it is saved, as a post-process (which
+// also records the AOVs that guide the denoiser)
+const DENOISER: Option<Denoiser> = None;
+const RECORD_AOVS: bool = WRITE_AOVS || DENOISER.is_some();
+
+// The render settings, from SETTINGS or the constants above
+fn render_settings() -> RenderSettings {
+    match SETTINGS {
+        Some(path) => match RenderSettings::load(Path::new(path)) {
+            Ok(settings) => settings,
+            Err(why) => panic!("couldn't load {}: {}", path, why),
+        },
+        None => RenderSettings {
+            width: RES_X,
+            height: RES_Y,
+            samples: SAMPLES,
+            min_samples: MIN_SAMPLES,
+            noise_threshold: NOISE_THRESHOLD,
+            max_depth: MAX_DEPTH,
+            sampler: SAMPLER,
+            filter: FILTER,
+            seed: SEED,
+            tile_size: TILE_SIZE,
+            display: DisplayTransform::new(EXPOSURE, TONE_MAP, TRANSFER_FUNCTION),
+            bloom: BLOOM,
+            transparent: TRANSPARENT,
+            backplate: BACKPLATE.map(PathBuf::from),
+        },
+    }
+}
+
+// The renderer for the settings, along with the crop, blue-noise mask, and
+// AOVs set above
+fn build_renderer(settings: &RenderSettings) -> Renderer {
+    let mask = if BLUE_NOISE {
+        Some(Arc::new(BlueNoiseMask::new(BLUE_NOISE_MASK_SIZE, hash_combine(settings.seed, 1))))
+    } else {
+        None
+    };
+    let backplate = match settings.load_backplate() {
+        Ok(backplate) => backplate,
+        Err(why) => panic!("couldn't load the backplate: {}", why),
+    };
+    Renderer {
+        crop: CROP,
+        mask,
+        record_aovs: RECORD_AOVS,
+        backplate,
+        ..settings.renderer()
+    }
+}
+
+// The animation rendered when ANIMATE is enabled: the camera dollies
+// towards the spheres while one of them bobs up and down (blurred by the
+// shutter, which stays open for half of each frame)
+fn build_sequence() -> Sequence {
+    let mut sequence = Sequence::new(0, 23);
+    sequence.camera.position = Track::linear(vec![Keyframe::new(0.0, Vector::new(0.0, 0.0, 0.0)),
+                                                  Keyframe::new(23.0, Vector::new(0.1, 0.05, -0.3))]);
+    sequence.camera.target = Track::linear(vec![Keyframe::new(0.0, Vector::new(0.0, 0.0, -1.0))]);
+    sequence.shutter = 0.5;
+    // One of the spheres (which are added after the four walls)
+    let mut bobbing_sphere = ObjectAnimation::new(7);
+    bobbing_sphere.position = Track::cubic(vec![Keyframe::new(0.0, Vector::zero()),
+                                                Keyframe::new(12.0, Vector::new(0.0, 0.2, 0.0)),
+                                                Keyframe::new(23.0, Vector::zero())]);
+    sequence.objects = vec![bobbing_sphere];
+    sequence
+}
+
+// Render every sample of one frame of a sequence (within `bounds`), updating
+// the scene in place for each pass (with each frame seeded differently, so
+// that its noise differs)
+fn render_frame(film: &mut Film,
+                bounds: (u32, u32, u32, u32),
+                scene: &mut Scene,
+                sequence: &Sequence,
+                frame: u32,
+                renderer: &Renderer,
+                settings: &RenderSettings,
+                fov: Float) {
+    let seed = hash_combine(settings.seed, frame + 2);
+    for pass in 0..renderer.samples {
+        let time = sequence.shutter_time(frame, pass, renderer.samples);
+        sequence.update_scene(scene, time);
+        let camera = sequence.camera(time, fov, settings.aspect_ratio());
+        scene.select_lods(&camera, settings.height);
+        renderer.render_pass(film, bounds, &camera, scene, seed, &|_| {});
+    }
+}
+
+// Save a rendered frame of a sequence, blending it with the previous frame
+// first if temporal accumulation is enabled (so frames must be saved in
+// order)
+fn save_frame(film: &Film,
+              scene: &mut Scene,
+              sequence: &Sequence,
+              frame: u32,
+              temporal: &mut Option<TemporalAccumulator>,
+              settings: &RenderSettings,
+              fov: Float)
+              -> String {
+    // Reproject with the scene and camera as they were mid-shutter
+    sequence.update_scene(scene, frame as Float);
+    let camera = sequence.camera(frame as Float, fov, settings.aspect_ratio());
+
+    let image = match *temporal {
+        Some(ref mut temporal) => temporal.accumulate(film, &camera, scene),
+        None => film.to_framebuffer(),
+    };
+    let image = settings.post_process(image);
+    let frame_path = sequence::frame_path("output/render", frame, OUTPUT_FORMAT.extension());
+    if let Err(why) = output::write_image(&image, Path::new(&frame_path), OUTPUT_FORMAT, &settings.display) {
+        panic!("couldn't write to {}: {}", frame_path, why);
+    }
+    frame_path
+}
+
+// Render every frame of a sequence
+fn render_sequence(scene: &mut Scene,
+                   sequence: &Sequence,
+                   renderer: &Renderer,
+                   settings: &RenderSettings,
+                   fov: Float) {
+    let mut temporal = TEMPORAL_ALPHA.map(|alpha| TemporalAccumulator::new(alpha, 0.01));
+    for frame in sequence.first_frame..sequence.last_frame + 1 {
+        let start = Instant::now();
+        let mut film = Film::new(settings.width, settings.height);
+        let bounds = renderer.bounds(settings.width, settings.height);
+        render_frame(&mut film, bounds, scene, sequence, frame, renderer, settings, fov);
+        let frame_path = save_frame(&film, scene, sequence, frame, &mut temporal, settings, fov);
+        println!("frame {}: saved {} after {:?} seconds", frame, frame_path, start.elapsed().as_secs());
+    }
+}
+
+// Render the image (or, when ANIMATE is enabled, the animation) across
+// machines: the coordinator hands out tiles of each frame to the workers
+// that connect to it and saves the merged images, while each worker (running
+// this program, with the same scene and settings) renders the tiles it is
+// given
+fn render_distributed(role: Role,
+                      scene: &mut Scene,
+                      camera: &Camera,
+                      renderer: &Renderer,
+                      settings: &RenderSettings,
+                      fov: Float) {
+    let sequence = build_sequence();
+    match role {
+        Role::Coordinator(address) => {
+            let coordinator = match Coordinator::bind(address) {
+                Ok(coordinator) => coordinator,
+                Err(why) => panic!("couldn't listen on {}: {}", address, why),
+            };
+            println!("waiting for workers at {}", coordinator.address);
+            let frames = if ANIMATE { sequence.first_frame..sequence.last_frame + 1 } else { 0..1 };
+            let (width, height) = (settings.width, settings.height);
+            let tiles = scheduler::tiles(renderer.bounds(width, height), JOB_TILE_SIZE);
+            let jobs = frames.flat_map(|frame| tiles.iter().map(move |&tile| Job { frame, tile })).collect();
+
+            let start = Instant::now();
+            let mut temporal = TEMPORAL_ALPHA.map(|alpha| TemporalAccumulator::new(alpha, 0.01));
+            let frame_done = |frame: u32, film: Film| {
+                if ANIMATE {
+                    let frame_path = save_frame(&film, scene, &sequence, frame, &mut temporal, settings, fov);
+                    println!("frame {}: saved {} after {:?} seconds", frame, frame_path, start.elapsed().as_secs());
+                } else {
+                    save_outputs(&film, renderer, settings, renderer.samples, "output/render", false);
+                    println!("saved output/render.{} after {:?} seconds",
+                             OUTPUT_FORMAT.extension(),
+                             start.elapsed().as_secs());
+                }
+            };
+            if let Err(why) = coordinator.render(width, height, jobs, frame_done) {
+                panic!("couldn't coordinate the render: {}", why);
             }
         }
-        // Miss
-        None => {
-            let unit_direction = r.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            let white = Vector::one();
-            let blue = Vector::new(0.5, 0.7, 1.0);
-            white.lerp(&blue, t)
+        Role::Worker(address) => {
+            let render_job = |job: &Job, film: &mut Film| {
+                let bounds = (job.tile.x0, job.tile.y0, job.tile.x1, job.tile.y1);
+                if ANIMATE {
+                    render_frame(film, bounds, scene, &sequence, job.frame, renderer, settings, fov);
+                } else {
+                    for _ in 0..renderer.samples {
+                        renderer.render_pass(film, bounds, camera, scene, settings.seed, &|_| {});
+                    }
+                }
+            };
+            match distributed::work(address, settings.width, settings.height, &*renderer.filter, render_job) {
+                Ok(jobs) => println!("rendered {} jobs for {}", jobs, address),
+                Err(why) => panic!("couldn't render for {}: {}", address, why),
+            }
         }
     }
 }
 
-struct Color(u32, u32, u32);
-
-fn threaded_color(start: (u32, u32),
-                  end: (u32, u32),
-                  camera: Arc<Camera>,
-                  scene: Arc<Scene>)
-                  -> Vec<Color> {
-    let mut colors = Vec::new();
-    let mut rng = rand::thread_rng();
-
-    for y in start.1..end.1 {
-        // Each row
-        for x in start.0..end.0 {
-            // Each col
-            let mut col = Vector::zero();
-            // Perform anti-aliasing
-            for s in 0..SAMPLES {
-                // The uv-coordinates of the current pixel with random offsets
-                // (note that we flip the y-axis)
-                let u = (x as f64 + rng.next_f64()) / RES_X as f64;
-                let v = ((RES_Y - y) as f64 + rng.next_f64()) / RES_Y as f64;
-                let r = camera.generate_ray(u, v);
-                col += trace(&r, &scene, 0);
-            }
-
-            col /= SAMPLES as f64;
-            let gamma_corrected = col.powf(GAMMA);
+// Render the scene from each of the named cameras in turn: everything built
+// for the scene is shared between the views
+fn render_cameras(scene: &Scene,
+                  names: &[&str],
+                  renderer: &Renderer,
+                  settings: &RenderSettings) {
+    let names: Vec<&str> = if names.is_empty() {
+        scene.cameras.iter().map(|c| c.0.as_str()).collect()
+    } else {
+        names.to_vec()
+    };
+    for name in names {
+        let camera = match scene.camera(name) {
+            Some(camera) => camera,
+            None => panic!("the scene has no camera named {}", name),
+        };
+        let start = Instant::now();
+        let mut film = Film::new(settings.width, settings.height);
+        let bounds = renderer.bounds(settings.width, settings.height);
+        for _ in 0..renderer.samples {
+            renderer.render_pass(&mut film, bounds, camera, scene, settings.seed, &|_| {});
+        }
+        let stem = format!("output/render_{}", name);
+        save_outputs(&film, renderer, settings, renderer.samples, &stem, false);
+        println!("camera {}: saved {}.{} after {:?} seconds",
+                 name,
+                 stem,
+                 OUTPUT_FORMAT.extension(),
+                 start.elapsed().as_secs());
+    }
+}
 
-            // Convert colors to 0..255
-            let ir = (255.99 * gamma_corrected.x) as u32;
-            let ig = (255.99 * gamma_corrected.y) as u32;
-            let ib = (255.99 * gamma_corrected.z) as u32;
+// Render the scene on the GPU and save the image, or explain why it couldn't
+// be rendered there
+fn render_gpu(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Result<(), String> {
+    let start = Instant::now();
+    let mut renderer = GpuRenderer::new(scene, settings.width, settings.height, settings.max_depth)?;
+    for _ in 0..settings.samples {
+        renderer.render_pass(camera, settings.seed);
+    }
+    let framebuffer = settings.post_process(renderer.framebuffer()?);
+    let path_name = format!("output/render.{}", OUTPUT_FORMAT.extension());
+    if let Err(why) = output::write_image(&framebuffer, Path::new(&path_name), OUTPUT_FORMAT, &settings.display) {
+        panic!("couldn't write to {}: {}", path_name, why);
+    }
+    println!("saved {} after {} passes on the GPU ({:?} seconds)",
+             path_name,
+             settings.samples,
+             start.elapsed().as_secs());
+    Ok(())
+}
 
-            colors.push(Color(ir, ig, ib));
+// Save the (optionally denoised) image, along with the AOVs and a checkpoint
+// if they are enabled, after `pass` passes (to files named after `stem`)
+fn save_outputs(film: &Film, renderer: &Renderer, settings: &RenderSettings, pass: u32, stem: &str, checkpoint: bool) {
+    let path_name = format!("{}.{}", stem, OUTPUT_FORMAT.extension());
+    let path = Path::new(&path_name);
+    let beauty = match DENOISER {
+        Some(denoiser) => denoiser.denoise(film),
+        None => film.to_framebuffer(),
+    };
+    let beauty = settings.post_process(beauty);
+    let mut metadata = Metadata::render(settings, renderer.debug);
+    metadata.add_scene(Path::new(SCENE));
+    let written = if settings.writes_alpha() {
+        let alpha: Vec<Float> = film.aov_framebuffer(Aov::Alpha).pixels.iter().map(|p| p.r).collect();
+        output::write_image_with_alpha(&beauty, &alpha, path, OUTPUT_FORMAT, &settings.display, &metadata)
+    } else {
+        output::write_image_with_metadata(&beauty, path, OUTPUT_FORMAT, &settings.display, &metadata)
+    };
+    if let Err(why) = written {
+        panic!("couldn't write to {}: {}", path.display(), why);
+    }
+    if let (Some(checkpoint_path), true) = (CHECKPOINT, checkpoint) {
+        let state = RenderState {
+            pass,
+            seed: settings.seed,
+            sampler: renderer.sampler,
+        };
+        if let Err(why) = checkpoint::save(Path::new(checkpoint_path), &state, film) {
+            panic!("couldn't write to {}: {}", checkpoint_path, why);
+        }
+    }
+    if WRITE_AOVS {
+        for &aov in &[Aov::Normal, Aov::Depth, Aov::Position, Aov::Albedo, Aov::ObjectId, Aov::MaterialId, Aov::Alpha] {
+            let aov_path = format!("{}_{}.{}", stem, aov.name(), OUTPUT_FORMAT.extension());
+            if let Err(why) = output::write_aov(film, aov, Path::new(&aov_path), OUTPUT_FORMAT) {
+                panic!("couldn't write to {}: {}", aov_path, why);
+            }
         }
     }
-    colors
 }
 
-fn map(v: f64, fmin: f64, fmax: f64, tmin: f64, tmax: f64) -> f64 {
+// Load and build a scene file (by its extension), along with the render
+// settings that it overrides, and (for TOML scene files, which are the only
+// ones that can be reloaded) a reloader if it's wanted, which only the preview
+// window's loop ever checks
+fn load_scene(path: &Path,
+              aspect_ratio: Float,
+              textures: &Arc<TextureCache>)
+              -> io::Result<(Scene, SettingsDescription, Option<SceneReloader>)> {
+    let extension = path.extension().and_then(|e| e.to_str());
+    if cfg!(feature = "preview") && PREVIEW && HOT_RELOAD && extension != Some("pbrt") && extension != Some("xml") {
+        let (reloader, scene) = SceneReloader::load(path, aspect_ratio, textures)?;
+        return Ok((scene, reloader.settings().clone(), Some(reloader)));
+    }
+    let imported = scene_file::load(path, aspect_ratio, textures)?;
+    for warning in &imported.warnings {
+        println!("{}: {}", path.display(), warning);
+    }
+    Ok((imported.scene, imported.settings, None))
+}
+
+fn map(v: Float, fmin: Float, fmax: Float, tmin: Float, tmax: Float) -> Float {
     (v - fmin) / (tmin - fmin) * (tmax - fmax) + fmax
 }
 
 fn main() {
-    let path = Path::new("output/render.ppm");
+    let path_name = format!("output/render.{}", OUTPUT_FORMAT.extension());
+    let path = Path::new(&path_name);
     let display = path.display();
-    let mut file = File::create(&path).expect("couldn't create file");
 
     // Use the time module to record how long it takes to render the entire scene
     let start = Instant::now();
-    println!("starting render: {} x {} px", RES_X, RES_Y);
-
-    // Build a scene
-    let mut scene = Scene::new();
-    let mtl_diff_red = Arc::new(Lambertian::new(&Vector::new(1.0, 0.0, 0.0)));
-    let mtl_diff_green = Arc::new(Lambertian::new(&Vector::new(0.0, 1.0, 0.0)));
-    let mtl_diff_white = Arc::new(Lambertian::new(&Vector::one()));
-    let mtl_glass = Arc::new(Dielectric::new(1.5));
-
-    // Walls
-    let floor = Arc::new(Plane::new(&Vector::new(0.0, -0.6, 0.0), &Vector::new(0.0, 1.0, 0.0)));
-    let left = Arc::new(Plane::new(&Vector::new(1.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0)));
-    let right = Arc::new(Plane::new(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(-1.0, 0.0, 0.0)));
-    let back = Arc::new(Plane::new(&Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 0.0, -1.0)));
-    scene.items.push(Primitive::new(floor, mtl_diff_white.clone()));
-    scene.items.push(Primitive::new(left, mtl_diff_red.clone()));
-    scene.items.push(Primitive::new(right, mtl_diff_green.clone()));
-    scene.items.push(Primitive::new(back, mtl_diff_white.clone()));
-
-    // Spheres
-    const NUMBER_OF_SPHERES: u32 = 7;
-    const MINIMUM_RADIUS: f64 = 0.1;
-    for i in 0..NUMBER_OF_SPHERES {
-        let pct = (i as f64) / (NUMBER_OF_SPHERES as f64);
-        let x = pct * 2.0 - 1.0;
-        let mtl = Arc::new(Metallic::new(&Vector::one(), x));
-        let sph = Arc::new(Sphere::new(&Vector::new(x + 0.05, 0.0, -1.0),
-                                       (pct * 0.5 + MINIMUM_RADIUS) * 0.25));
-        scene.items.push(Primitive::new(sph, mtl));
-    }
-
-    // Set up camera and scene atomic reference counted pointers
-    let shared_camera = Arc::new(Camera::new(60.0, RES_X as f64 / RES_Y as f64));
-    let shared_scene = Arc::new(scene);
-
-    // Launch threads
-    let mut file_contents: String = format!("P3\n{} {}\n255\n", RES_X, RES_Y);
-    let mut child_threads = vec![];
-    for i in 0..NUMBER_OF_THREADS {
-        let start: (u32, u32) = (0, i * (RES_X / NUMBER_OF_THREADS));
-        let end: (u32, u32) = (RES_Y, (i + 1) * (RES_X / NUMBER_OF_THREADS));
-        let cloned_scene = shared_scene.clone();
-        let cloned_camera = shared_camera.clone();
-        child_threads.push(thread::spawn(move || {
-            threaded_color(start, end, cloned_camera, cloned_scene)
-        }));
-    }
-
-    // Re-join threads and write ppm pixel data
-    for child in child_threads {
-        let res = child.join().unwrap();
-        for item in res {
-            let pixel = format!("{} {} {}\n", item.0, item.1, item.2);
-            file_contents.push_str(&pixel);
+    let mut settings = render_settings();
+    let (width, height) = (settings.width, settings.height);
+    println!("starting render: {} x {} px", width, height);
+    if let Err(why) = rayon::ThreadPoolBuilder::new().num_threads(NUMBER_OF_THREADS).build_global() {
+        panic!("couldn't start the render threads: {}", why);
+    }
+
+    // Load the scene, along with its cameras and any render settings that it
+    // overrides
+    let mut stats = RenderStats::new();
+    let build_start = Instant::now();
+    let fov = 60.0;
+    let aspect_ratio = settings.aspect_ratio();
+    let textures = Arc::new(TextureCache::new(TEXTURE_BUDGET));
+    let (mut scene, overrides, mut reloader) = match load_scene(Path::new(SCENE), aspect_ratio, &textures) {
+        Ok(scene) => scene,
+        Err(why) => panic!("couldn't load {}: {}", SCENE, why),
+    };
+    for problem in scene.validate() {
+        println!("{}: {}", SCENE, problem);
+    }
+    stats.add_time("build", build_start.elapsed());
+    if PRINT_STATS {
+        println!("{}", scene.stats());
+    }
+    let mut camera = scene.cameras.first().map_or_else(|| Camera::new(fov, aspect_ratio), |c| c.1);
+
+    // Render progressively, one sample per pixel per pass, periodically
+    // saving the partially converged image so that the render can be
+    // stopped at any time
+    overrides.apply(&mut settings);
+    let mut renderer = build_renderer(&settings);
+    if let Some(role) = DISTRIBUTED {
+        render_distributed(role, &mut scene, &camera, &renderer, &settings, fov);
+        return;
+    }
+    if ANIMATE {
+        render_sequence(&mut scene, &build_sequence(), &renderer, &settings, fov);
+        return;
+    }
+    if let Some(names) = CAMERAS {
+        render_cameras(&scene, names, &renderer, &settings);
+        return;
+    }
+    if GPU {
+        match render_gpu(&scene, &camera, &settings) {
+            Ok(()) => return,
+            Err(why) => println!("rendering on the CPU instead: {}", why),
+        }
+    }
+
+    let mut film = Film::new(width, height);
+    let mut first_pass = 0;
+    if let Some(checkpoint_path) = CHECKPOINT {
+        if Path::new(checkpoint_path).exists() {
+            let (state, resumed) = match checkpoint::load(Path::new(checkpoint_path)) {
+                Ok(checkpoint) => checkpoint,
+                Err(why) => panic!("couldn't resume from {}: {}", checkpoint_path, why),
+            };
+            if state.seed != settings.seed || state.sampler != renderer.sampler || resumed.width != width ||
+               resumed.height != height {
+                panic!("{} was saved with different render settings", checkpoint_path);
+            }
+            println!("resuming from {} after {} passes", checkpoint_path, state.pass);
+            film = resumed;
+            first_pass = state.pass;
         }
     }
 
-    // Calculate the render time
-    let elapsed = start.elapsed();
+    // Moving the camera in the preview window (or editing the scene file)
+    // restarts the render from scratch (the checkpoint, which doesn't record
+    // the camera or the scene, is no longer saved once the render restarts)
+    let mut preview = if PREVIEW {
+        match Preview::new(width, height) {
+            Ok(preview) => Some(preview),
+            Err(why) => {
+                println!("couldn't open a preview window: {}", why);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let server = HTTP_PREVIEW.map(|address| match PreviewServer::start(address) {
+        Ok(server) => {
+            println!("serving the render at http://{}/", server.address);
+            server
+        }
+        Err(why) => panic!("couldn't serve on {}: {}", address, why),
+    });
+    let mut orbit = Orbit::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0));
+    let mut restarted = false;
+    let mut pass = first_pass;
+    let mut finished = pass >= renderer.samples;
+    loop {
+        if let Some(ref mut preview) = preview {
+            if preview.control(&mut orbit) {
+                camera = orbit.camera(fov, aspect_ratio);
+                restarted = true;
+                film.clear();
+                pass = 0;
+                finished = false;
+            }
+            if let Some(ref mut reloader) = reloader {
+                match reloader.poll(&mut scene) {
+                    Ok(Change::Nothing) => {}
+                    Ok(change) => {
+                        println!("reloaded {} ({:?} changed)", SCENE, change);
+                        for problem in scene.validate() {
+                            println!("{}: {}", SCENE, problem);
+                        }
+                        // (The film keeps its size, whatever the settings say)
+                        let mut reloaded = render_settings();
+                        reloader.settings().apply(&mut reloaded);
+                        renderer = build_renderer(&reloaded);
+                        settings = reloaded;
+                        if change >= Change::Cameras {
+                            camera = scene.cameras.first().map_or(camera, |c| c.1);
+                        }
+                        restarted = true;
+                        film.clear();
+                        pass = 0;
+                        finished = false;
+                    }
+                    Err(why) => println!("couldn't reload {}: {}", SCENE, why),
+                }
+            }
+            if !preview.is_open() {
+                if !finished {
+                    save_outputs(&film, &renderer, &settings, pass, "output/render", !restarted);
+                }
+                break;
+            }
+            if finished {
+                preview.refresh();
+                continue;
+            }
+        } else if finished {
+            break;
+        }
 
-    // Write to the file
-    match file.write_all(file_contents.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why.description()),
-        Ok(_) => {
-            println!("successfully wrote to {}, finished in {:?} seconds",
+        let progress_bar = |progress: &TileProgress| {
+            if PROGRESS_BAR {
+                print!("\rpass {}: {:3}%", pass + 1, 100 * progress.completed / progress.total);
+                let _ = io::stdout().flush();
+            }
+        };
+        let bounds = renderer.bounds(width, height);
+        scene.select_lods(&camera, height);
+        stats.add(&renderer.render_pass(&mut film, bounds, &camera, &scene, settings.seed, &progress_bar));
+        if PROGRESS_BAR {
+            println!();
+        }
+        pass += 1;
+        if let Some(ref mut preview) = preview {
+            if let Err(why) = preview.update(&film, &settings.display) {
+                println!("couldn't update the preview window: {}", why);
+            }
+        }
+
+        finished = pass >= renderer.samples || TIME_LIMIT.is_some_and(|limit| start.elapsed() >= limit);
+        if let Some(ref server) = server {
+            let progress = Progress {
+                pass,
+                samples_per_pixel: renderer.samples_per_pixel(&film) as f64,
+                elapsed_seconds: start.elapsed().as_secs_f64(),
+                finished,
+            };
+            server.publish(&film, &settings.display, progress);
+        }
+        if finished || pass % SAVE_INTERVAL == 0 {
+            let output_start = Instant::now();
+            save_outputs(&film, &renderer, &settings, pass, "output/render", !restarted);
+            stats.add_time("output", output_start.elapsed());
+            println!("pass {}: saved {} after {:?} seconds",
+                     pass,
                      display,
-                     elapsed.as_secs())
+                     start.elapsed().as_secs());
         }
     }
+
+    for (path, why) in textures.failures() {
+        println!("couldn't load the texture {}: {}", path.display(), why);
+    }
+    println!("successfully wrote to {}, finished in {:?} seconds ({:.2} samples per pixel)",
+             display,
+             start.elapsed().as_secs(),
+             renderer.samples_per_pixel(&film));
+    if PRINT_STATS {
+        println!("{}", stats);
+        println!("{}", MemoryUsage::new(&scene, &textures, Some(&film)));
+    }
 }
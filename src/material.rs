@@ -1,148 +1,645 @@
 use vector::Vector;
+use vector::Float;
+use color::Color;
 use ray::Ray;
 use shape::DifferentialGeometry;
+use texture::ImageTexture;
+use texture::TextureCache;
+use material_graph::GraphDescription;
+use material_graph::MaterialGraph;
+use phase::PhaseData;
+use phase::PhaseFunction;
+use rng;
+use rng::ThreadRng;
+use sampling;
+use error::Result;
 
-extern crate rand;
-use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// A plain description of a material, for backends that can't call back into
+// the material itself (see: `gpu`), and for saving it along with a scene
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialData {
+    Lambertian { albedo: Color },
+    // (By the path of its texture)
+    TexturedLambertian { texture: PathBuf },
+    Metallic { albedo: Color, glossiness: Float },
+    Dielectric {
+        ior: Float,
+        #[serde(default)]
+        priority: Option<u32>,
+        #[serde(default)]
+        dispersion: Option<Dispersion>,
+    },
+    Emissive { radiance: Color },
+    ThinDielectric { ior: Float, tint: Color },
+    Volume {
+        albedo: Color,
+        density: Float,
+        #[serde(default)]
+        phase: PhaseData,
+    },
+    ShadowCatcher { albedo: Color },
+    Graph(GraphDescription),
+}
+
+impl MaterialData {
+    // Construct the material, loading any textures through `textures` (which
+    // only fails for graphs that aren't valid)
+    pub fn create(&self, textures: &Arc<TextureCache>) -> Result<Arc<dyn Material>> {
+        Ok(match *self {
+            MaterialData::Lambertian { albedo } => Arc::new(Lambertian::new(&albedo)),
+            MaterialData::TexturedLambertian { ref texture } => {
+                Arc::new(TexturedLambertian::new(ImageTexture::new(textures, texture)))
+            }
+            MaterialData::Metallic { albedo, glossiness } => Arc::new(Metallic::new(&albedo, glossiness)),
+            MaterialData::Dielectric { ior, priority, dispersion } => Arc::new(Dielectric { ior, priority, dispersion }),
+            MaterialData::Emissive { radiance } => Arc::new(Emissive::new(&radiance)),
+            MaterialData::ThinDielectric { ior, tint } => Arc::new(ThinDielectric::new(ior, &tint)),
+            MaterialData::Volume { albedo, density, phase } => Arc::new(Volume::new(&albedo, density, phase.create())),
+            MaterialData::ShadowCatcher { albedo } => Arc::new(ShadowCatcher::new(&albedo)),
+            MaterialData::Graph(ref graph) => Arc::new(MaterialGraph::new(graph, textures)?),
+        })
+    }
+}
 
 pub trait Material: Sync + Send {
     // Produce a scattered ray
     fn scatter(&self,
                incident: &Ray,
                intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
+               attenuation: &mut Color)
                -> Ray;
+
+    // The material's base color, as recorded in the albedo AOV
+    fn albedo(&self) -> Color {
+        Color::white()
+    }
+
+    // The radiance that the surface emits, the same everywhere on it and in
+    // every direction (see: `Emissive`)
+    fn emission(&self) -> Color {
+        Color::black()
+    }
+
+    // The reflectance at the point, for materials that scatter diffusely
+    // (i.e. whose BSDF is this over pi): the integrator samples the scene's
+    // lights directly from diffuse surfaces (see: `light`), while other
+    // materials only find lights by scattering into them
+    fn diffuse(&self, _intersection: &DifferentialGeometry) -> Option<Color> {
+        None
+    }
+
+    // The index of refraction and priority of a dielectric that nests inside
+    // others (see: `MediumStack`)
+    fn medium(&self) -> Option<(Float, u32)> {
+        None
+    }
+
+    // How a dielectric's index of refraction varies with the wavelength of
+    // light, if it does (see: `Dispersion`)
+    fn dispersion(&self) -> Option<&Dispersion> {
+        None
+    }
+
+    // The fraction of the light arriving at the given cosine to the normal
+    // that passes straight through the surface, for surfaces that let light
+    // through without bending it (see: `ThinDielectric`), through which the
+    // rays towards the lights pass as well
+    fn transmittance(&self, _cos_theta: Float) -> Option<Color> {
+        None
+    }
+
+    // The volume that the (closed) surface bounds, for surfaces that are only
+    // the boundary of one (see: `Volume`)
+    fn volume(&self) -> Option<&Volume> {
+        None
+    }
+
+    // Whether the surface stands in for one in a photograph that the render
+    // is composited over (see: `ShadowCatcher`)
+    fn catches_shadows(&self) -> bool {
+        false
+    }
+
+    // Materials that can't be described by `MaterialData` return `None`
+    fn data(&self) -> Option<MaterialData> {
+        None
+    }
+}
+
+// A random direction of diffuse scattering from a surface with the normal `n`
+fn diffuse_direction(n: &Vector) -> Vector {
+    let d = *n + sampling::unit_sphere(&mut ThreadRng);
+    // (The point in the sphere can land right opposite the normal, and a
+    // direction of (almost) zero can't be normalized)
+    if d.squared_length() > 1e-12 {
+        d
+    } else {
+        *n
+    }
 }
 
 pub struct Lambertian {
-    pub albedo: Vector,
+    pub albedo: Color,
 }
 
 impl Material for Lambertian {
     fn scatter(&self,
                incident: &Ray,
                intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
+               attenuation: &mut Color)
                -> Ray {
 
-        let target = intersection.position + intersection.normal + Vector::random_in_unit_sphere();
-        let scattered = Ray::new(&intersection.position,
-                                 &mut (target - intersection.position),
-                                 incident.t_min,
-                                 incident.t_max);
+        let direction = diffuse_direction(&intersection.normal);
+        let scattered = Ray::spawn(&intersection.position, &intersection.normal, &direction, incident.t_max);
 
         *attenuation = self.albedo;
         scattered
     }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn diffuse(&self, _intersection: &DifferentialGeometry) -> Option<Color> {
+        Some(self.albedo)
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Lambertian { albedo: self.albedo })
+    }
 }
 
 impl Lambertian {
-    pub fn new(a: &Vector) -> Lambertian {
+    pub fn new(a: &Color) -> Lambertian {
         Lambertian { albedo: *a }
     }
 }
 
+// A diffuse material whose color is looked up in a texture, at the texture
+// coordinates of the point hit
+pub struct TexturedLambertian {
+    pub texture: ImageTexture,
+}
+
+impl Material for TexturedLambertian {
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
+               -> Ray {
+
+        let direction = diffuse_direction(&intersection.normal);
+        let scattered = Ray::spawn(&intersection.position, &intersection.normal, &direction, incident.t_max);
+
+        *attenuation = self.texture.lookup(intersection.uv.0, intersection.uv.1);
+        scattered
+    }
+
+    fn diffuse(&self, intersection: &DifferentialGeometry) -> Option<Color> {
+        Some(self.texture.lookup(intersection.uv.0, intersection.uv.1))
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::TexturedLambertian { texture: self.texture.path() })
+    }
+}
+
+impl TexturedLambertian {
+    pub fn new(texture: ImageTexture) -> TexturedLambertian {
+        TexturedLambertian { texture }
+    }
+}
+
 pub struct Metallic {
-    pub albedo: Vector,
-    pub glossiness: f64,
+    pub albedo: Color,
+    pub glossiness: Float,
 }
 
 impl Material for Metallic {
     fn scatter(&self,
                incident: &Ray,
                intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
+               attenuation: &mut Color)
                -> Ray {
 
         let reflected = incident.direction.normalize().reflect(&intersection.normal);
-        let scattered = Ray::new(&intersection.position,
-                                 &(reflected + Vector::random_in_unit_sphere() * self.glossiness),
-                                 incident.t_min,
-                                 incident.t_max);
+        let scattered = Ray::spawn(&intersection.position,
+                                  &intersection.normal,
+                                  &(reflected + sampling::unit_sphere(&mut ThreadRng) * self.glossiness),
+                                  incident.t_max);
 
         *attenuation = self.albedo;
         scattered
     }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Metallic {
+            albedo: self.albedo,
+            glossiness: self.glossiness,
+        })
+    }
 }
 
 impl Metallic {
-    pub fn new(a: &Vector, g: f64) -> Metallic {
+    pub fn new(a: &Color, g: Float) -> Metallic {
         Metallic {
             albedo: *a,
-            glossiness: g.min(1.0).max(0.0),
+            glossiness: g.clamp(0.0, 1.0),
         }
     }
 }
 
+// A conductor's reflectance at normal incidence (e.g. for a `Metallic`'s
+// albedo), from the real and imaginary parts of its index of refraction
+pub fn conductor_reflectance(eta: &Color, k: &Color) -> Color {
+    let reflectance = |eta: Float, k: Float| ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+    Color::new(reflectance(eta.r, k.r), reflectance(eta.g, k.g), reflectance(eta.b, k.b))
+}
+
 pub struct Dielectric {
-    pub ior: f64,
+    pub ior: Float,
+    // Where the dielectric overlaps others, it's inside them if theirs is
+    // higher (see: `MediumStack`), or surrounded by air if it has none
+    pub priority: Option<u32>,
+    // (Its index of refraction is `ior` for paths that don't take part in
+    // dispersion, which should be the model's at the middle of the spectrum)
+    pub dispersion: Option<Dispersion>,
 }
 
 impl Material for Dielectric {
     fn scatter(&self,
                incident: &Ray,
                intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
+               attenuation: &mut Color)
+               -> Ray {
+        // Air in a vacuum has an IOR of 1.0, which is on the other side of the
+        // surface (unless the integrator knows better, see: `refract`)
+        let entering = incident.direction.dot(&intersection.normal) < 0.0;
+        let (eta_i, eta_t) = if entering { (1.0, self.ior) } else { (self.ior, 1.0) };
+        *attenuation = Color::white();
+        refract(incident, intersection, eta_i, eta_t)
+    }
+
+    fn medium(&self) -> Option<(Float, u32)> {
+        self.priority.map(|priority| (self.ior, priority))
+    }
+
+    fn dispersion(&self) -> Option<&Dispersion> {
+        self.dispersion.as_ref()
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Dielectric {
+            ior: self.ior,
+            priority: self.priority,
+            dispersion: self.dispersion,
+        })
+    }
+}
+
+impl Dielectric {
+    pub fn new(i: Float) -> Dielectric {
+        Dielectric {
+            ior: i,
+            priority: None,
+            dispersion: None,
+        }
+    }
+
+    // A dispersive dielectric, whose `ior` is the model's at the Fraunhofer d
+    // line (587.56 nanometers), where glasses' indices are usually quoted
+    pub fn dispersive(dispersion: Dispersion) -> Dielectric {
+        Dielectric {
+            ior: dispersion.ior(D_LINE),
+            priority: None,
+            dispersion: Some(dispersion),
+        }
+    }
+}
+
+pub const D_LINE: Float = 587.56;
+
+// How a dielectric's index of refraction varies with the wavelength of light
+// (in nanometers), which bends each wavelength by a different angle and so
+// splits white light into its colors (as a prism does, or a gem's fire): a
+// path that meets a dispersive dielectric picks a single wavelength to carry
+// from then on (see: `Color::wavelength`), and refracts with its index
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Dispersion {
+    // Cauchy's equation, n = a + b / l^2 + c / l^4, with the wavelength l in
+    // micrometers (e.g. a = 1.5046 and b = 0.0042 for BK7 glass)
+    Cauchy {
+        a: Float,
+        b: Float,
+        #[serde(default)]
+        c: Float,
+    },
+    // The Sellmeier equation, n^2 = 1 + sum(b l^2 / (l^2 - c)), with the
+    // wavelength l in micrometers (and each c in square micrometers), as
+    // optical glasses' data sheets give it
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Dispersion {
+    pub fn ior(&self, nm: Float) -> Float {
+        let l2 = (nm / 1000.0) * (nm / 1000.0);
+        match *self {
+            Dispersion::Cauchy { a, b, c } => a + b / l2 + c / (l2 * l2),
+            Dispersion::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<Float>()).sqrt(),
+        }
+    }
+}
+
+// Refract a ray through (or reflect it off of, with the probability of
+// Fresnel reflection) the smooth interface between media whose indices of
+// refraction are `eta_i`, on the side that the ray comes from, and `eta_t`
+pub fn refract(incident: &Ray, intersection: &DifferentialGeometry, eta_i: Float, eta_t: Float) -> Ray {
+    // (Between media of the same IOR there's no interface at all, which
+    // Schlick's approximation would still reflect off of at grazing angles)
+    if eta_i == eta_t {
+        return Ray::spawn(&intersection.position, &intersection.normal, &incident.direction, incident.t_max);
+    }
+
+    // The index of refraction (IOR) of a particular medium is defined
+    // as the speed of light in a vacuum divided by the speed of light
+    // in the medium:
+    //              n = c / v
+    //
+    // Snell's law states:
+    //              n_i * sin(theta_i) = n_t * sin(theta_t)
+    //
+    // So, sin(theta_t) = (n_i / n_t) * sin(theta_i)
+    let eta = eta_i / eta_t;
+
+    // R0 is the probability of reflection at normal incidence, which
+    // is given by the equation:
+    //              r0 = ((n1 - n2) / (n1 + n2))^2
+    let mut r0 = (eta_i - eta_t) / (eta_i + eta_t);
+    r0 = r0 * r0;
+
+    // Check if the incident ray is inside of the medium, in which case
+    // flip the normal
+    let mut outward_normal = intersection.normal;
+    if incident.direction.dot(&outward_normal) > 0.0 {
+        outward_normal *= -1.0;
+    }
+
+    // Calculate angles
+    let cos_theta_i = -incident.direction.dot(&outward_normal);
+    let cos_theta_t = 1.0 - eta * eta * (1.0 - cos_theta_i * cos_theta_i);
+
+    let probability_of_reflection = schlick(r0, cos_theta_i);
+
+    // Check for total internal reflection (when cos_theta_t is negative)
+    let scattered = if cos_theta_t > 0.0 && rng::next_float() > probability_of_reflection {
+        // Refract
+        (incident.direction * eta) +
+        (outward_normal * (eta * cos_theta_i - cos_theta_t.sqrt()))
+    } else {
+        // Reflect
+        incident.direction.reflect(&outward_normal)
+    };
+
+    Ray::spawn(&intersection.position,
+              &intersection.normal,
+              &scattered,
+              incident.t_max)
+}
+
+// Schlick's approximation of the Fresnel reflectance, from the reflectance at
+// normal incidence
+fn schlick(r0: Float, cos_theta: Float) -> Float {
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powf(5.0)
+}
+
+// A dielectric too thin for its refraction to matter (e.g. a window pane, a
+// bubble, or a soap film), whose two surfaces are one: light either reflects
+// off of it (as it would off of both surfaces together, bouncing between them
+// any number of times) or passes straight through, tinted by what the glass
+// absorbs. Since it bends nothing, the rays towards the lights pass through it
+// too (see: `Material::transmittance`), so what's behind a window is lit
+// directly through it, rather than only by the paths that happen to find the
+// lights, as it would be behind a full `Dielectric`
+pub struct ThinDielectric {
+    pub ior: Float,
+    // The color of the light that passes through, e.g. white for clear glass
+    pub tint: Color,
+}
+
+impl Material for ThinDielectric {
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
+               -> Ray {
+
+        let cos_theta = incident.direction.dot(&intersection.normal).abs();
+        let scattered = if rng::next_float() < self.reflectance(cos_theta) {
+            *attenuation = Color::white();
+            incident.direction.reflect(&intersection.normal)
+        } else {
+            *attenuation = self.tint;
+            incident.direction
+        };
+        Ray::spawn(&intersection.position, &intersection.normal, &scattered, incident.t_max)
+    }
+
+    fn albedo(&self) -> Color {
+        self.tint
+    }
+
+    fn transmittance(&self, cos_theta: Float) -> Option<Color> {
+        Some(self.tint * (1.0 - self.reflectance(cos_theta)))
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::ThinDielectric { ior: self.ior, tint: self.tint })
+    }
+}
+
+impl ThinDielectric {
+    pub fn new(ior: Float, tint: &Color) -> ThinDielectric {
+        ThinDielectric { ior, tint: *tint }
+    }
+
+    // The light that's reflected by either surface, and by the second after
+    // bouncing between them, r + (1 - r)^2 r / (1 - r^2) for the reflectance
+    // r of each surface
+    pub fn reflectance(&self, cos_theta: Float) -> Float {
+        let r0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        let r = schlick(r0, cos_theta);
+        2.0 * r / (1.0 + r)
+    }
+}
+
+// A homogeneous participating medium (e.g. a cloud, smoke, or fog) filling a
+// closed shape, whose surface is only its boundary: light passes into it
+// unbent, and travels an exponentially distributed distance through it
+// (whose average is one over its density) before it scatters by the phase
+// function, keeping the albedo of it each time, or leaves it again (see:
+// `integrator::scatter_volume`). Nothing else should be inside it, since the
+// paths within it only look for its boundary
+pub struct Volume {
+    // The fraction of the light that each scattering keeps (the rest of it is
+    // absorbed), e.g. nearly white for clouds, and darker for smoke
+    pub albedo: Color,
+    // How much of the light is scattered or absorbed per unit length
+    pub density: Float,
+    pub phase: Arc<dyn PhaseFunction>,
+}
+
+impl Material for Volume {
+    // (Seen by integrators that don't follow paths into volumes, it's
+    // transparent)
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
                -> Ray {
 
-        // The index of refraction (IOR) of a particular medium is defined
-        // as the speed of light in a vacuum divided by the speed of light
-        // in the medium:
-        //              n = c / v
-        //
-        // Snell's law states:
-        //              n_i * sin(theta_i) = n_t * sin(theta_t)
-        //
-        // So, sin(theta_t) = (n_i / n_t) * sin(theta_i)
-        let mut ior = self.ior;
-
-        // R0 is the probability of reflection at normal incidence, which
-        // is given by the equation:
-        //              r0 = ((n1 - n2) / (n1 + n2))^2
-        // Air in a vacuum has an IOR of 1.0, which is n1 in the equation
-        // above
-        let mut r0 = (1.0 - ior) / (1.0 + ior);
-        r0 = r0 * r0;
-
-        // Check if the incident ray is inside of the medium, in which case
-        // flip the normal
-        let mut outward_normal = intersection.normal;
-        if incident.direction.dot(&outward_normal) > 0.0 {
-            outward_normal *= -1.0;
-            ior = 1.0 / ior;
+        *attenuation = Color::white();
+        Ray::spawn(&intersection.position, &intersection.normal, &incident.direction, incident.t_max)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn volume(&self) -> Option<&Volume> {
+        Some(self)
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Volume {
+            albedo: self.albedo,
+            density: self.density,
+            phase: self.phase.data(),
+        })
+    }
+}
+
+impl Volume {
+    pub fn new(albedo: &Color, density: Float, phase: Arc<dyn PhaseFunction>) -> Volume {
+        Volume {
+            albedo: *albedo,
+            density,
+            phase,
         }
-        ior = 1.0 / ior;
-
-        // Calculate angles
-        let cos_theta_i = incident.direction.dot(&outward_normal) * -1.0;
-        let cos_theta_t = 1.0 - ior * ior * (1.0 - cos_theta_i * cos_theta_i);
-
-        // Schlick's approximation
-        let probability_of_reflection = r0 + (1.0 - r0) * (1.0 - cos_theta_i).powf(5.0);
-        let mut rng = rand::thread_rng();
-        let mut scattered: Vector;
-
-        // Check for total internal reflection (when cos_theta_t is negative)
-        if cos_theta_t > 0.0 && rng.next_f64() > probability_of_reflection {
-            // Refract
-            scattered = (incident.direction * ior) +
-                        (outward_normal * (ior * cos_theta_i - cos_theta_t.sqrt()));
+    }
+
+    // How far light travels through the volume before it next scatters (or
+    // is absorbed), from a uniformly distributed `u` in [0, 1): a volume
+    // without any density (which `Scene::validate` reports) never scatters,
+    // and so lets light straight through
+    pub fn free_path(&self, u: Float) -> Float {
+        if self.density > 0.0 {
+            -(1.0 - u).ln() / self.density
         } else {
-            // Reflect
-            scattered = incident.direction.reflect(&outward_normal);
+            Float::INFINITY
         }
+    }
 
-        *attenuation = Vector::one();
-        let refracted = incident.direction.refract(&intersection.normal);
-        Ray::new(&intersection.position,
-                 &scattered,
-                 incident.t_min,
-                 incident.t_max)
+    // The fraction of the light that travels `length` through the volume
+    // without scattering
+    pub fn unscattered(&self, length: Float) -> Float {
+        if self.density > 0.0 {
+            (-self.density * length).exp()
+        } else {
+            1.0
+        }
     }
 }
 
-impl Dielectric {
-    pub fn new(i: f64) -> Dielectric {
-        Dielectric { ior: i }
+// A surface that glows (with the same radiance everywhere on it, in every
+// direction, and from both of its sides) and reflects nothing: meshes,
+// triangles, and quads made of it light the scene as area lights (see:
+// `light`), while other shapes only light what scatters into them
+pub struct Emissive {
+    pub radiance: Color,
+}
+
+impl Material for Emissive {
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
+               -> Ray {
+
+        *attenuation = Color::black();
+        Ray::spawn(&intersection.position, &intersection.normal, &intersection.normal, incident.t_max)
+    }
+
+    fn albedo(&self) -> Color {
+        Color::black()
+    }
+
+    fn emission(&self) -> Color {
+        self.radiance
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Emissive { radiance: self.radiance })
+    }
+}
+
+impl Emissive {
+    pub fn new(radiance: &Color) -> Emissive {
+        Emissive { radiance: *radiance }
+    }
+}
+
+// A stand-in for a surface in a photograph (e.g. the ground under a CG
+// object), diffuse with the photographed surface's albedo: paths that bounce
+// off of it see it as any other diffuse surface, but the camera sees only the
+// shadows and reflections that the rest of the scene casts onto it, over what
+// is behind it (see: `integrator::trace`), with an alpha of as much light as
+// the shadows take away, so that it can be composited over the photograph
+pub struct ShadowCatcher {
+    pub albedo: Color,
+}
+
+impl Material for ShadowCatcher {
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
+               -> Ray {
+
+        let direction = diffuse_direction(&intersection.normal);
+        *attenuation = self.albedo;
+        Ray::spawn(&intersection.position, &intersection.normal, &direction, incident.t_max)
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn diffuse(&self, _intersection: &DifferentialGeometry) -> Option<Color> {
+        Some(self.albedo)
+    }
+
+    fn catches_shadows(&self) -> bool {
+        true
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::ShadowCatcher { albedo: self.albedo })
+    }
+}
+
+impl ShadowCatcher {
+    pub fn new(albedo: &Color) -> ShadowCatcher {
+        ShadowCatcher { albedo: *albedo }
     }
 }
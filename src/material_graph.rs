@@ -0,0 +1,399 @@
+use vector::Float;
+use color::Color;
+use ray::Ray;
+use shape::DifferentialGeometry;
+use material::Material;
+use material::MaterialData;
+use material::Lambertian;
+use material::Metallic;
+use material::Dielectric;
+use texture::ImageTexture;
+use texture::TextureCache;
+use rng;
+use error::Result;
+use error::TracerError;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// A material described by a graph of nodes, so that new looks can be written
+// in a scene file rather than as new `Material`s, e.g.
+//
+//     [[materials]]
+//     name = "lacquered_wood"
+//     type = "graph"
+//     output = "lacquer"
+//
+//     [[materials.nodes]]
+//     name = "wood"
+//     type = "texture"
+//     path = "textures/wood.png"
+//
+//     [[materials.nodes]]
+//     name = "base"
+//     type = "diffuse"
+//     color = "wood"
+//
+//     [[materials.nodes]]
+//     name = "coat"
+//     type = "metal"
+//     color = [1.0, 1.0, 1.0]
+//     roughness = 0.05
+//
+//     [[materials.nodes]]
+//     name = "reflectance"
+//     type = "fresnel"
+//     ior = 1.5
+//
+//     [[materials.nodes]]
+//     name = "lacquer"
+//     type = "mix_bsdf"
+//     a = "base"
+//     b = "coat"
+//     factor = "reflectance"
+//
+// Nodes produce either a value (a color, of which inputs that take a number
+// use the luminance) or a BSDF, and each input names an earlier node (so that
+// graphs can't have cycles) or is given as a color or a number: the graph is
+// evaluated at each hit, from the BSDF node named as its output
+
+// An input to a node: another node, or a constant
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InputDescription {
+    Node(String),
+    Color(Color),
+    Number(Float),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeKind {
+    // Values: a texture (at the hit's texture coordinates), the texture
+    // coordinates themselves (as red and green), and the world-space normal
+    // (mapped to [0, 1])
+    Texture { path: PathBuf },
+    Uv,
+    Normal,
+    // Math, channel by channel
+    Add { a: InputDescription, b: InputDescription },
+    Subtract { a: InputDescription, b: InputDescription },
+    Multiply { a: InputDescription, b: InputDescription },
+    // 1 - input
+    Invert { input: InputDescription },
+    // a where the factor is 0, and b where it's 1
+    Mix {
+        a: InputDescription,
+        b: InputDescription,
+        factor: InputDescription,
+    },
+    // The fraction of light that a dielectric of this index of refraction
+    // reflects at the angle that it's seen from (by Schlick's approximation)
+    Fresnel { ior: Float },
+    // BSDFs (see: `Lambertian`, `Metallic`, and `Dielectric`)
+    Diffuse { color: InputDescription },
+    Metal {
+        color: InputDescription,
+        #[serde(default = "default_roughness")]
+        roughness: InputDescription,
+    },
+    Glass { ior: Float },
+    // Either BSDF, chosen at random with the probability of b being the
+    // factor
+    MixBsdf {
+        a: String,
+        b: String,
+        factor: InputDescription,
+    },
+}
+
+fn default_roughness() -> InputDescription {
+    InputDescription::Number(0.0)
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeDescription {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: NodeKind,
+}
+
+// A graph, as written in a scene file (and saved with a scene, see:
+// `MaterialData`)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GraphDescription {
+    pub nodes: Vec<NodeDescription>,
+    // The name of the BSDF node that the material is
+    pub output: String,
+}
+
+impl GraphDescription {
+    // The paths of the graph's textures
+    pub fn textures(&self) -> impl Iterator<Item = &PathBuf> {
+        self.nodes.iter().filter_map(|node| match node.kind {
+            NodeKind::Texture { ref path } => Some(path),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Input {
+    Node(usize),
+    Constant(Color),
+}
+
+enum Node {
+    Texture(ImageTexture),
+    Uv,
+    Normal,
+    Add(Input, Input),
+    Subtract(Input, Input),
+    Multiply(Input, Input),
+    Invert(Input),
+    Mix(Input, Input, Input),
+    Fresnel(Float),
+    Diffuse(Input),
+    Metal(Input, Input),
+    Glass(Dielectric),
+    MixBsdf(usize, usize, Input),
+}
+
+impl Node {
+    fn is_bsdf(&self) -> bool {
+        matches!(*self, Node::Diffuse(_) | Node::Metal(..) | Node::Glass(_) | Node::MixBsdf(..))
+    }
+}
+
+pub struct MaterialGraph {
+    nodes: Vec<Node>,
+    output: usize,
+    description: GraphDescription,
+}
+
+impl MaterialGraph {
+    // Check and compile the graph, loading its textures through `textures`:
+    // every input must name an earlier node of the right kind (a value, or a
+    // BSDF), as must the output (a BSDF)
+    pub fn new(description: &GraphDescription, textures: &Arc<TextureCache>) -> Result<MaterialGraph> {
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        let mut nodes: Vec<Node> = Vec::with_capacity(description.nodes.len());
+        for node in &description.nodes {
+            let invalid = |why: String| TracerError::InvalidParameter(format!("node {:?} {}", node.name, why));
+            let find = |name: &str, bsdf: bool| -> Result<usize> {
+                match names.get(name) {
+                    Some(&index) if nodes[index].is_bsdf() == bsdf => Ok(index),
+                    Some(_) if bsdf => Err(invalid(format!("needs a BSDF, but {:?} is a value", name))),
+                    Some(_) => Err(invalid(format!("needs a value, but {:?} is a BSDF", name))),
+                    None => Err(invalid(format!("comes before any node named {:?}", name))),
+                }
+            };
+            let input = |input: &InputDescription| -> Result<Input> {
+                match *input {
+                    InputDescription::Node(ref name) => Ok(Input::Node(find(name, false)?)),
+                    InputDescription::Color(color) => Ok(Input::Constant(color)),
+                    InputDescription::Number(v) => Ok(Input::Constant(Color::gray(v))),
+                }
+            };
+            let compiled = match node.kind {
+                NodeKind::Texture { ref path } => Node::Texture(ImageTexture::new(textures, path)),
+                NodeKind::Uv => Node::Uv,
+                NodeKind::Normal => Node::Normal,
+                NodeKind::Add { ref a, ref b } => Node::Add(input(a)?, input(b)?),
+                NodeKind::Subtract { ref a, ref b } => Node::Subtract(input(a)?, input(b)?),
+                NodeKind::Multiply { ref a, ref b } => Node::Multiply(input(a)?, input(b)?),
+                NodeKind::Invert { input: ref i } => Node::Invert(input(i)?),
+                NodeKind::Mix { ref a, ref b, ref factor } => Node::Mix(input(a)?, input(b)?, input(factor)?),
+                NodeKind::Fresnel { ior } if ior > 0.0 => Node::Fresnel(ior),
+                NodeKind::Diffuse { ref color } => Node::Diffuse(input(color)?),
+                NodeKind::Metal { ref color, ref roughness } => Node::Metal(input(color)?, input(roughness)?),
+                NodeKind::Glass { ior } if ior > 0.0 => Node::Glass(Dielectric::new(ior)),
+                NodeKind::Fresnel { ior } | NodeKind::Glass { ior } => {
+                    return Err(invalid(format!("has an index of refraction of {}", ior)))
+                }
+                NodeKind::MixBsdf { ref a, ref b, ref factor } => {
+                    Node::MixBsdf(find(a, true)?, find(b, true)?, input(factor)?)
+                }
+            };
+            if names.insert(&node.name, nodes.len()).is_some() {
+                return Err(invalid("is named twice".to_string()));
+            }
+            nodes.push(compiled);
+        }
+        let output = match names.get(description.output.as_str()) {
+            Some(&index) if nodes[index].is_bsdf() => index,
+            Some(_) => return Err(TracerError::InvalidParameter(format!("the output {:?} isn't a BSDF", description.output))),
+            None => return Err(TracerError::InvalidParameter(format!("no node named {:?}", description.output))),
+        };
+        Ok(MaterialGraph {
+            nodes,
+            output,
+            description: description.clone(),
+        })
+    }
+
+    fn input(&self, input: &Input, incident: &Ray, dg: &DifferentialGeometry) -> Color {
+        match *input {
+            Input::Node(index) => self.value(index, incident, dg),
+            Input::Constant(value) => value,
+        }
+    }
+
+    // Evaluate a value node (where nodes that feed several others are
+    // evaluated once for each)
+    fn value(&self, index: usize, incident: &Ray, dg: &DifferentialGeometry) -> Color {
+        let input = |input: &Input| self.input(input, incident, dg);
+        match self.nodes[index] {
+            Node::Texture(ref texture) => texture.lookup(dg.uv.0, dg.uv.1),
+            Node::Uv => Color::new(dg.uv.0, dg.uv.1, 0.0),
+            Node::Normal => Color::from_vector(&(dg.normal * 0.5)) + Color::gray(0.5),
+            Node::Add(ref a, ref b) => input(a) + input(b),
+            Node::Subtract(ref a, ref b) => input(a) - input(b),
+            Node::Multiply(ref a, ref b) => input(a) * input(b),
+            Node::Invert(ref i) => Color::white() - input(i),
+            Node::Mix(ref a, ref b, ref factor) => {
+                let factor = input(factor);
+                input(a) * (Color::white() - factor) + input(b) * factor
+            }
+            Node::Fresnel(ior) => {
+                let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+                let cos_theta = incident.direction.normalize().dot(&dg.normal).abs().min(1.0);
+                Color::gray(r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5))
+            }
+            _ => unreachable!("BSDFs aren't values"),
+        }
+    }
+
+    fn scatter_bsdf(&self, index: usize, incident: &Ray, dg: &DifferentialGeometry, attenuation: &mut Color) -> Ray {
+        let input = |input: &Input| self.input(input, incident, dg);
+        match self.nodes[index] {
+            Node::Diffuse(ref color) => Lambertian::new(&input(color)).scatter(incident, dg, attenuation),
+            Node::Metal(ref color, ref roughness) => {
+                Metallic::new(&input(color), input(roughness).luminance()).scatter(incident, dg, attenuation)
+            }
+            Node::Glass(ref glass) => glass.scatter(incident, dg, attenuation),
+            Node::MixBsdf(a, b, ref factor) => {
+                // (Choosing each in proportion to its weight leaves the
+                // attenuation of the one chosen unweighted)
+                let chosen = if rng::next_float() < input(factor).luminance() { b } else { a };
+                self.scatter_bsdf(chosen, incident, dg, attenuation)
+            }
+            _ => unreachable!("values aren't BSDFs"),
+        }
+    }
+}
+
+impl Material for MaterialGraph {
+    fn scatter(&self,
+               incident: &Ray,
+               intersection: &DifferentialGeometry,
+               attenuation: &mut Color)
+               -> Ray {
+        self.scatter_bsdf(self.output, incident, intersection, attenuation)
+    }
+
+    fn data(&self) -> Option<MaterialData> {
+        Some(MaterialData::Graph(self.description.clone()))
+    }
+}
+
+#[test]
+fn test_material_graph() {
+    use vector::Vector;
+    use shape::Sphere;
+    use shape::Shape;
+    use scenes;
+    use scenes::FURNACE_RADIANCE;
+    use renderer::Renderer;
+    use film::Film;
+
+    let textures = Arc::new(TextureCache::new(1 << 20));
+    let graph: GraphDescription = toml::from_str(r#"
+        output = "out"
+
+        [[nodes]]
+        name = "half"
+        type = "mix"
+        a = [1.0, 1.0, 1.0]
+        b = 0.0
+        factor = 0.5
+
+        [[nodes]]
+        name = "base"
+        type = "diffuse"
+        color = "half"
+
+        [[nodes]]
+        name = "coat"
+        type = "glass"
+        ior = 1.5
+
+        [[nodes]]
+        name = "reflectance"
+        type = "fresnel"
+        ior = 1.5
+
+        [[nodes]]
+        name = "out"
+        type = "mix_bsdf"
+        a = "base"
+        b = "coat"
+        factor = "reflectance"
+    "#)
+        .unwrap();
+    let material = MaterialGraph::new(&graph, &textures).unwrap();
+    assert_eq!(material.data(), Some(MaterialData::Graph(graph.clone())));
+
+    // Values evaluate at the hit
+    let sphere = Sphere::new(&Vector::zero(), 1.0);
+    let incident = Ray::new(&Vector::new(0.0, 0.0, 2.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    let dg = sphere.intersect(&incident).unwrap();
+    assert_eq!(material.value(0, &incident, &dg), Color::gray(0.5));
+    // (Head on, glass reflects 4%)
+    assert!((material.value(3, &incident, &dg).r - 0.04).abs() < 1e-6);
+
+    // A graph of white BSDFs disappears in the furnace, like each BSDF
+    let mut white = graph.clone();
+    white.nodes[0].kind = NodeKind::Invert { input: InputDescription::Number(0.0) };
+    let scene = scenes::furnace(MaterialGraph::new(&white, &textures).unwrap(), 1.0);
+    let mut renderer = Renderer::new();
+    renderer.samples = 2;
+    renderer.min_samples = 2;
+    renderer.max_depth = 64;
+    renderer.filter = Arc::from(::filter::FilterType::Box.create());
+    let mut film = Film::new(8, 8);
+    renderer.render(&mut film, (0, 0, 8, 8), scene.camera("main").unwrap(), &scene, 0, &|_: &_| {});
+    assert!(film.to_framebuffer().pixels.iter().all(|p| (p.max_channel() - FURNACE_RADIANCE.r).abs() < 1e-6));
+
+    // Graphs can be written in scene files, and saved with scenes
+    let description = ::scene_file::SceneDescription::parse(r#"
+        [[materials]]
+        name = "gold"
+        type = "graph"
+        output = "metal"
+
+        [[materials.nodes]]
+        name = "metal"
+        type = "metal"
+        color = [1.0, 0.8, 0.3]
+        roughness = 0
+    "#)
+        .unwrap();
+    let mut scene = description.build(1.0, &textures).unwrap();
+    scene.add(::primitive::Primitive::new(sphere, description.build_materials(&textures).unwrap()["gold"].clone()));
+    let saved = toml::to_string(&scene).unwrap();
+    let loaded: ::scene::Scene = toml::from_str(&saved).unwrap();
+    assert_eq!(loaded.materials[0].data(), scene.materials[0].data());
+
+    // Mistakes are caught when the graph is built
+    let mut broken = graph.clone();
+    broken.output = "half".to_string();
+    assert!(MaterialGraph::new(&broken, &textures).is_err());
+    let mut broken = graph;
+    broken.nodes.swap(0, 1);
+    let why = MaterialGraph::new(&broken, &textures).err().unwrap().to_string();
+    assert!(why.contains("before any node named \"half\""), "{}", why);
+}
@@ -0,0 +1,321 @@
+use vector::Vector;
+use vector::Float;
+use vector::consts;
+
+// A 4x4 (row major) matrix, for the arbitrary transformations of imported
+// scenes (see: `pbrt`, and `mitsuba`), which (unlike a `Transform`) are
+// baked into their geometry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix(pub [[Float; 4]; 4]);
+
+impl Matrix {
+    pub fn identity() -> Matrix {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Matrix(m)
+    }
+
+    pub fn translate(t: &Vector) -> Matrix {
+        let mut m = Matrix::identity();
+        m.0[0][3] = t.x;
+        m.0[1][3] = t.y;
+        m.0[2][3] = t.z;
+        m
+    }
+
+    pub fn scale(s: &Vector) -> Matrix {
+        let mut m = Matrix::identity();
+        m.0[0][0] = s.x;
+        m.0[1][1] = s.y;
+        m.0[2][2] = s.z;
+        m
+    }
+
+    // Scale, then rotate, and then translate (as glTF's nodes do)
+    pub fn from_trs(translation: &Vector, rotation: &Quaternion, scale: &Vector) -> Matrix {
+        Matrix::translate(translation).mul(&rotation.to_matrix()).mul(&Matrix::scale(scale))
+    }
+
+    // A rotation by `angle` degrees about `axis`
+    pub fn rotate(angle: Float, axis: &Vector) -> Matrix {
+        let a = axis.normalize();
+        let (s, c) = (angle * (consts::PI / 180.0)).sin_cos();
+        let mut m = Matrix::identity();
+        m.0[0][0] = a.x * a.x + (1.0 - a.x * a.x) * c;
+        m.0[0][1] = a.x * a.y * (1.0 - c) - a.z * s;
+        m.0[0][2] = a.x * a.z * (1.0 - c) + a.y * s;
+        m.0[1][0] = a.x * a.y * (1.0 - c) + a.z * s;
+        m.0[1][1] = a.y * a.y + (1.0 - a.y * a.y) * c;
+        m.0[1][2] = a.y * a.z * (1.0 - c) - a.x * s;
+        m.0[2][0] = a.x * a.z * (1.0 - c) - a.y * s;
+        m.0[2][1] = a.y * a.z * (1.0 - c) + a.x * s;
+        m.0[2][2] = a.z * a.z + (1.0 - a.z * a.z) * c;
+        m
+    }
+
+    // The frame at `from` whose z axis points towards `to`, and whose x axis
+    // is `up` crossed with it (i.e. a left-handed camera's camera-to-world
+    // matrix)
+    pub fn look_at(from: &Vector, to: &Vector, up: &Vector) -> Option<Matrix> {
+        let direction = (*to - *from).normalize();
+        let right = up.normalize().cross(&direction);
+        if right.length() == 0.0 {
+            return None;
+        }
+        let right = right.normalize();
+        let up = direction.cross(&right);
+        let mut m = Matrix::identity();
+        for (i, column) in [right, up, direction, *from].iter().enumerate() {
+            m.0[0][i] = column.x;
+            m.0[1][i] = column.y;
+            m.0[2][i] = column.z;
+        }
+        Some(m)
+    }
+
+    // From 16 numbers in row major order
+    pub fn from_rows(numbers: &[Float]) -> Matrix {
+        let mut m = Matrix::identity();
+        for (i, &x) in numbers.iter().enumerate() {
+            m.0[i / 4][i % 4] = x;
+        }
+        m
+    }
+
+    pub fn mul(&self, rhs: &Matrix) -> Matrix {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.0[i][k] * rhs.0[k][j]).sum();
+            }
+        }
+        Matrix(m)
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.0[j][i];
+            }
+        }
+        Matrix(m)
+    }
+
+    // Gauss-Jordan elimination, with partial pivoting
+    pub fn inverse(&self) -> Option<Matrix> {
+        let mut a = self.0;
+        let mut inverse = Matrix::identity().0;
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+            if a[pivot][column] == 0.0 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for i in 0..4 {
+                if i != column {
+                    let factor = a[i][column];
+                    for j in 0..4 {
+                        a[i][j] -= factor * a[column][j];
+                        inverse[i][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix(inverse))
+    }
+
+    // The determinant of the linear (upper-left 3x3) part, which is negative
+    // for transformations that swap handedness
+    pub fn determinant(&self) -> Float {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0]) +
+        m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // Normals are transformed by the inverse transpose
+    pub fn normal_matrix(&self) -> Matrix {
+        self.inverse().unwrap_or_else(Matrix::identity).transpose()
+    }
+
+    pub fn point(&self, p: &Vector) -> Vector {
+        let m = &self.0;
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        (self.vector(p) + Vector::new(m[0][3], m[1][3], m[2][3])) / w
+    }
+
+    pub fn vector(&self, v: &Vector) -> Vector {
+        let m = &self.0;
+        Vector::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                    m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                    m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+}
+
+// A rotation, as a unit quaternion (x, y, z) * sin(angle / 2) + cos(angle / 2)
+// about a unit axis: unlike Euler angles, rotations can be composed and
+// interpolated (see: `slerp`) without gimbal lock, and they're how glTF
+// files store them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+    pub w: Float,
+}
+
+impl Quaternion {
+    pub fn new(x: Float, y: Float, z: Float, w: Float) -> Quaternion {
+        Quaternion { x, y, z, w }
+    }
+
+    pub fn identity() -> Quaternion {
+        Quaternion::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    // A rotation by `angle` degrees about `axis` (as `Matrix::rotate`)
+    pub fn from_axis_angle(axis: &Vector, angle: Float) -> Quaternion {
+        let a = axis.normalize();
+        let (s, c) = (angle * (consts::PI / 360.0)).sin_cos();
+        Quaternion::new(a.x * s, a.y * s, a.z * s, c)
+    }
+
+    // The rotation of a matrix's linear part, which must be a rotation
+    // (without any scale or shear)
+    pub fn from_matrix(m: &Matrix) -> Quaternion {
+        let m = &m.0;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        // (Taking the square root of the largest of the four candidates, so
+        // that it isn't close to zero)
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quaternion::new((m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s, 0.25 * s)
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            Quaternion::new(0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s, (m[2][1] - m[1][2]) / s)
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            Quaternion::new((m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s, (m[0][2] - m[2][0]) / s)
+        } else {
+            let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+            Quaternion::new((m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s, (m[1][0] - m[0][1]) / s)
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, rhs: &Quaternion) -> Float {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let length = self.dot(self).sqrt();
+        Quaternion::new(self.x / length, self.y / length, self.z / length, self.w / length)
+    }
+
+    // The opposite rotation (of a unit quaternion)
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    // The rotation by `rhs` and then by this quaternion
+    pub fn mul(&self, rhs: &Quaternion) -> Quaternion {
+        Quaternion::new(self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+                        self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+                        self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+                        self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z)
+    }
+
+    pub fn rotate(&self, v: &Vector) -> Vector {
+        // v + 2 * cross(q, cross(q, v) + w * v), where q is the vector part
+        let q = Vector::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        *v + t * self.w + q.cross(&t)
+    }
+
+    // Spherical linear interpolation, which rotates at a constant rate from
+    // this rotation (at `t` = 0) to `rhs` (at `t` = 1), the shorter way around
+    pub fn slerp(&self, rhs: &Quaternion, t: Float) -> Quaternion {
+        let mut cos_theta = self.dot(rhs);
+        let mut rhs = *rhs;
+        if cos_theta < 0.0 {
+            rhs = Quaternion::new(-rhs.x, -rhs.y, -rhs.z, -rhs.w);
+            cos_theta = -cos_theta;
+        }
+        // (Close enough to parallel that the sine below is too small to divide
+        // by, where a linear interpolation is just as good)
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (((1.0 - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+        };
+        Quaternion::new(self.x * a + rhs.x * b, self.y * a + rhs.y * b, self.z * a + rhs.z * b, self.w * a + rhs.w * b)
+            .normalize()
+    }
+
+    pub fn to_matrix(&self) -> Matrix {
+        let Quaternion { x, y, z, w } = *self;
+        let mut m = Matrix::identity();
+        m.0[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        m.0[0][1] = 2.0 * (x * y - z * w);
+        m.0[0][2] = 2.0 * (x * z + y * w);
+        m.0[1][0] = 2.0 * (x * y + z * w);
+        m.0[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        m.0[1][2] = 2.0 * (y * z - x * w);
+        m.0[2][0] = 2.0 * (x * z - y * w);
+        m.0[2][1] = 2.0 * (y * z + x * w);
+        m.0[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        m
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Quaternion {
+        Quaternion::identity()
+    }
+}
+
+#[test]
+fn test_matrix_and_quaternion() {
+    use vector::TEST_EPSILON;
+
+    let close = |a: &Vector, b: &Vector| (*a - *b).length() < TEST_EPSILON * 10.0;
+    let p = Vector::new(1.0, 2.0, 3.0);
+    let axis = Vector::new(1.0, 1.0, 0.0);
+    let m = Matrix::translate(&Vector::new(1.0, 0.0, -2.0))
+        .mul(&Matrix::rotate(30.0, &axis))
+        .mul(&Matrix::scale(&Vector::new(2.0, 3.0, 4.0)));
+    let inverse = m.inverse().unwrap();
+    assert!(close(&inverse.point(&m.point(&p)), &p));
+    let trs = Matrix::from_trs(&Vector::new(1.0, 0.0, -2.0),
+                               &Quaternion::from_axis_angle(&axis, 30.0),
+                               &Vector::new(2.0, 3.0, 4.0));
+    assert!(close(&trs.point(&p), &m.point(&p)));
+    assert!(Matrix::scale(&Vector::new(1.0, 0.0, 1.0)).inverse().is_none());
+    // Normals stay perpendicular to the (transformed) surface
+    let (tangent, normal) = (Vector::new(1.0, -1.0, 0.0), Vector::new(1.0, 1.0, 0.0));
+    assert!(m.vector(&tangent).dot(&m.normal_matrix().vector(&normal)).abs() < TEST_EPSILON * 10.0);
+
+    // Quaternions rotate as matrices do, and convert back and forth
+    let q = Quaternion::from_axis_angle(&axis, 30.0);
+    assert!(close(&q.rotate(&p), &Matrix::rotate(30.0, &axis).vector(&p)));
+    assert!(close(&q.to_matrix().vector(&p), &q.rotate(&p)));
+    for quaternion in &[q, Quaternion::from_axis_angle(&Vector::new(0.0, 0.0, 1.0), 180.0)] {
+        let back = Quaternion::from_matrix(&quaternion.to_matrix());
+        assert!((back.dot(quaternion).abs() - 1.0).abs() < TEST_EPSILON * 10.0);
+    }
+    assert!(close(&q.mul(&q.conjugate()).rotate(&p), &p));
+    assert!(close(&q.mul(&q).rotate(&p), &Quaternion::from_axis_angle(&axis, 60.0).rotate(&p)));
+    // Halfway between no rotation and 60 degrees is 30 degrees
+    let halfway = Quaternion::identity().slerp(&Quaternion::from_axis_angle(&axis, 60.0), 0.5);
+    assert!(close(&halfway.rotate(&p), &q.rotate(&p)));
+}
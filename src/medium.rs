@@ -0,0 +1,101 @@
+use vector::Float;
+
+// Nested dielectrics, e.g. a liquid in a glass, or ice in water: modelled as
+// overlapping closed shapes (so that the liquid's surface reaches a little
+// into the glass, where their interface is), whose overlap is taken to be
+// inside the one with the higher priority (see: "Simple Nested Dielectrics in
+// Ray Traced Images", Schmidt and Budge). A path keeps a stack of the
+// dielectrics that it's inside, so that the surfaces of the others within
+// them are passed through, and so that the indices of refraction on either
+// side of each interface are those of the media that meet there, rather than
+// the dielectric's against air. Only dielectrics with a priority take part
+// (see: `Material::medium`): the rest are surrounded by air wherever they are,
+// as hollow dielectrics (e.g. a sphere inside out within another) expect
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Medium {
+    // The object (and so the closed shape) that the medium fills
+    pub object_id: u32,
+    pub ior: Float,
+    pub priority: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediumStack {
+    // In the order that they were entered
+    media: Vec<Medium>,
+}
+
+impl MediumStack {
+    // In air, inside nothing
+    pub fn new() -> MediumStack {
+        MediumStack { media: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.media.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.media.is_empty()
+    }
+
+    // The medium that the path is in: whichever of those that it's inside has
+    // the highest priority (the last entered, of those with the same)
+    pub fn current(&self) -> Option<&Medium> {
+        self.media.iter().max_by_key(|m| m.priority)
+    }
+
+    pub fn ior(&self) -> Float {
+        self.current().map_or(1.0, |m| m.ior)
+    }
+
+    // Whether the surface of the medium is inside one of a higher priority
+    // (other than itself), where it isn't an interface at all
+    pub fn passes_through(&self, medium: &Medium) -> bool {
+        self.media.iter().any(|m| m.object_id != medium.object_id && m.priority > medium.priority)
+    }
+
+    // The indices of refraction on the side of the medium's surface that the
+    // path arrives from and on the other, as it enters the medium or leaves
+    pub fn interface(&self, medium: &Medium, entering: bool) -> (Float, Float) {
+        if entering {
+            (self.ior(), medium.ior)
+        } else {
+            (medium.ior, self.crossed(medium, false).ior())
+        }
+    }
+
+    // The media that the path is inside once it crosses the medium's surface
+    // (leaving a medium that it was never inside changes nothing)
+    pub fn crossed(&self, medium: &Medium, entering: bool) -> MediumStack {
+        let mut media = self.clone();
+        if entering {
+            media.media.push(*medium);
+        } else if let Some(i) = media.media.iter().rposition(|m| m.object_id == medium.object_id) {
+            media.media.remove(i);
+        }
+        media
+    }
+}
+
+#[test]
+fn test_medium_stack() {
+    // Water (at a lower priority) filling a glass: the path enters the
+    // glass's wall, and then the water where it overlaps the wall
+    let glass = Medium { object_id: 0, ior: 1.5, priority: 2 };
+    let water = Medium { object_id: 1, ior: 1.33, priority: 1 };
+    let air = MediumStack::new();
+    assert_eq!(air.interface(&glass, true), (1.0, 1.5));
+    let in_glass = air.crossed(&glass, true);
+    assert!(in_glass.passes_through(&water) && !in_glass.passes_through(&glass));
+    let in_both = in_glass.crossed(&water, true);
+    assert_eq!(in_both.current(), Some(&glass));
+
+    // Leaving the glass's wall into the water, and then the water into air
+    assert_eq!(in_both.interface(&glass, false), (1.5, 1.33));
+    let in_water = in_both.crossed(&glass, false);
+    assert_eq!((in_water.len(), in_water.ior()), (1, 1.33));
+    assert_eq!(in_water.interface(&water, false), (1.33, 1.0));
+    assert!(in_water.crossed(&water, false).is_empty());
+    assert_eq!(air.crossed(&water, false), air);
+}
@@ -0,0 +1,84 @@
+use scene::Scene;
+use texture::TextureCache;
+use film::Film;
+
+use std::fmt;
+
+// Roughly how much memory each part of a render takes, so that it's clear
+// what to trim when a scene doesn't fit: counted from the sizes of what each
+// part holds (rather than from the allocator), so it's an estimate
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    // The primitives, and their meshes' triangles and attributes (counting
+    // each mesh once, however many instances share it)
+    pub geometry: usize,
+    // The scene's BVH and its meshes'
+    pub acceleration: usize,
+    // The texture tiles resident in the cache, which never holds more than
+    // its budget
+    pub textures: usize,
+    pub texture_budget: usize,
+    // The film's pixels, along with their AOVs and statistics
+    pub film: usize,
+}
+
+impl MemoryUsage {
+    pub fn new(scene: &Scene, textures: &TextureCache, film: Option<&Film>) -> MemoryUsage {
+        let stats = scene.stats();
+        MemoryUsage {
+            geometry: stats.geometry_memory,
+            acceleration: stats.bvh.as_ref().map_or(0, |b| b.memory) + stats.mesh_bvhs.memory,
+            textures: textures.stats().resident_bytes,
+            texture_budget: textures.budget,
+            film: film.map_or(0, |f| f.memory()),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.geometry + self.acceleration + self.textures + self.film
+    }
+}
+
+pub fn megabytes(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "memory: {:.2} MB", megabytes(self.total()))?;
+        writeln!(f, "    geometry: {:.2} MB", megabytes(self.geometry))?;
+        writeln!(f, "    acceleration structures: {:.2} MB", megabytes(self.acceleration))?;
+        writeln!(f,
+                 "    textures: {:.2} MB (of a {:.2} MB budget)",
+                 megabytes(self.textures),
+                 megabytes(self.texture_budget))?;
+        write!(f, "    film: {:.2} MB", megabytes(self.film))
+    }
+}
+
+#[test]
+fn test_memory_usage() {
+    use mesh::Mesh;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use color::Color;
+    use texture::DEFAULT_BUDGET;
+    use film::Pixel;
+    use std::mem;
+    use std::sync::Arc;
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Mesh::cube(), Arc::new(Lambertian::new(&Color::white()))));
+    let textures = TextureCache::new(DEFAULT_BUDGET);
+    let film = Film::new(4, 4);
+    let before = MemoryUsage::new(&scene, &textures, Some(&film));
+    scene.build_bvh();
+    let usage = MemoryUsage::new(&scene, &textures, Some(&film));
+
+    // Building the scene's BVH only adds to its acceleration structures
+    assert!(usage.acceleration > before.acceleration && usage.geometry == before.geometry);
+    assert_eq!(usage.film, 16 * mem::size_of::<Pixel>());
+    assert_eq!((usage.textures, usage.texture_budget), (0, DEFAULT_BUDGET));
+    assert_eq!(usage.total(), usage.geometry + usage.acceleration + usage.film);
+    assert!(usage.to_string().starts_with("memory: "));
+}
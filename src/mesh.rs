@@ -0,0 +1,323 @@
+use vector::Vector;
+use vector::Float;
+use ray::Ray;
+use aabb::Aabb;
+use bvh::Bvh;
+use bvh::BvhStats;
+use camera::Camera;
+use matrix::Matrix;
+use shape::Shape;
+use shape::Triangle;
+use shape::DifferentialGeometry;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::mem;
+use std::sync::Arc;
+
+// A triangle mesh, with a BVH over its triangles (so that meshes with many
+// triangles don't each cost the scene's BVH a leaf per triangle)
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    // The normals at each triangle's vertices, which are interpolated across
+    // it (otherwise, the triangles are flat)
+    pub normals: Option<Vec<[Vector; 3]>>,
+    // The texture coordinates at each triangle's vertices, which are
+    // interpolated across it (otherwise, each triangle's are its barycentric
+    // coordinates)
+    pub uvs: Option<Vec<[(Float, Float); 3]>>,
+    // Each triangle's index into the scene's material table (see:
+    // `Scene::add_material`), for meshes whose triangles don't all share
+    // the primitive's material
+    pub material_ids: Option<Vec<u32>>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>) -> Mesh {
+        let bounds: Vec<_> = triangles.iter().map(|t| t.bounds()).collect();
+        Mesh {
+            triangles,
+            normals: None,
+            uvs: None,
+            material_ids: None,
+            bvh: Bvh::new(&bounds),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    pub fn bvh_stats(&self) -> BvhStats {
+        self.bvh.stats()
+    }
+
+    // The (approximate) number of bytes that the mesh's triangles and their
+    // attributes take (not counting its BVH)
+    pub fn memory(&self) -> usize {
+        self.triangles.capacity() * mem::size_of::<Triangle>() +
+        self.normals.as_ref().map_or(0, |n| n.capacity() * mem::size_of::<[Vector; 3]>()) +
+        self.uvs.as_ref().map_or(0, |uvs| uvs.capacity() * mem::size_of::<[(Float, Float); 3]>()) +
+        self.material_ids.as_ref().map_or(0, |ids| ids.capacity() * mem::size_of::<u32>())
+    }
+
+    // A cube spanning [-1, 1] along every axis, whose faces face outward
+    pub fn cube() -> Mesh {
+        let axes = [Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, 1.0)];
+        let mut triangles = Vec::new();
+        for axis in 0..3 {
+            for &sign in &[-1.0, 1.0] {
+                // (The face's edges, whose cross product faces outward)
+                let (mut u, mut v) = (axes[(axis + 1) % 3], axes[(axis + 2) % 3]);
+                if sign < 0.0 {
+                    ::std::mem::swap(&mut u, &mut v);
+                }
+                let center = axes[axis] * sign;
+                let corners = [center - u - v, center + u - v, center + u + v, center - u + v];
+                triangles.push(Triangle::new(&corners[0], &corners[1], &corners[2]));
+                triangles.push(Triangle::new(&corners[0], &corners[2], &corners[3]));
+            }
+        }
+        Mesh::new(triangles)
+    }
+
+    // The mesh, with `matrix` baked into it: its triangles keep facing the
+    // same way (even if the matrix swaps handedness), unless `flip`
+    pub fn transformed(&self, matrix: &Matrix, flip: bool) -> Mesh {
+        let flip = flip != (matrix.determinant() < 0.0);
+        let order = if flip { [0, 2, 1] } else { [0, 1, 2] };
+        let triangles = self.triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] = order.map(|i| matrix.point(&t.vertices[i]));
+                Triangle::new(&a, &b, &c)
+            })
+            .collect();
+        let mut mesh = Mesh::new(triangles);
+        let normal_matrix = matrix.normal_matrix();
+        mesh.normals = self.normals.as_ref().map(|normals| {
+            normals.iter().map(|n| order.map(|i| normal_matrix.vector(&n[i]).normalize())).collect()
+        });
+        mesh.uvs = self.uvs.as_ref().map(|uvs| uvs.iter().map(|uv| order.map(|i| uv[i])).collect());
+        mesh.material_ids = self.material_ids.clone();
+        mesh
+    }
+}
+
+// Meshes are saved without their BVH, which is rebuilt when they're loaded
+#[derive(Serialize)]
+struct MeshRef<'a> {
+    triangles: &'a [Triangle],
+    normals: &'a Option<Vec<[Vector; 3]>>,
+    uvs: &'a Option<Vec<[(Float, Float); 3]>>,
+    material_ids: &'a Option<Vec<u32>>,
+}
+
+#[derive(Deserialize)]
+struct MeshData {
+    triangles: Vec<Triangle>,
+    normals: Option<Vec<[Vector; 3]>>,
+    uvs: Option<Vec<[(Float, Float); 3]>>,
+    material_ids: Option<Vec<u32>>,
+}
+
+impl Serialize for Mesh {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MeshRef {
+                triangles: &self.triangles,
+                normals: &self.normals,
+                uvs: &self.uvs,
+                material_ids: &self.material_ids,
+            }
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Mesh {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Mesh, D::Error> {
+        let data = MeshData::deserialize(deserializer)?;
+        let mut mesh = Mesh::new(data.triangles);
+        mesh.normals = data.normals;
+        mesh.uvs = data.uvs;
+        mesh.material_ids = data.material_ids;
+        Ok(mesh)
+    }
+}
+
+impl Shape for Mesh {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let mut closest: Option<(DifferentialGeometry<'_>, usize)> = None;
+        self.bvh.traverse(r, |i| {
+            if let Some(dg) = self.triangles[i].intersect(r) {
+                if dg.t < closest.as_ref().map_or(r.t_max, |c| c.0.t) {
+                    closest = Some((dg, i));
+                }
+            }
+            closest.as_ref().map_or(r.t_max, |c| c.0.t)
+        });
+
+        closest.map(|(mut dg, i)| {
+            // (A triangle's uvs are the barycentric coordinates of its
+            // second and third vertices)
+            let (beta, gamma) = dg.uv;
+            let alpha = 1.0 - beta - gamma;
+            if let Some(ref normals) = self.normals {
+                let [a, b, c] = normals[i];
+                dg.normal = (a * alpha + b * beta + c * gamma).normalize();
+            }
+            if let Some(ref uvs) = self.uvs {
+                let [a, b, c] = uvs[i];
+                dg.uv = (a.0 * alpha + b.0 * beta + c.0 * gamma, a.1 * alpha + b.1 * beta + c.1 * gamma);
+            }
+            if let Some(ref material_ids) = self.material_ids {
+                dg.material_id = Some(material_ids[i]);
+            }
+            dg
+        })
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.bvh.any(r, |i| self.triangles[i].hit_any(r))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.bvh.bounds())
+        }
+    }
+}
+
+// How an instance of a mesh with levels of detail picks its level, given
+// one threshold per level (from the most to the least detailed): instances
+// that pass none of the thresholds use the least detailed level
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LodSelection {
+    // Level i is used while the instance is within `distances[i]` of the
+    // camera
+    Distance(Vec<Float>),
+    // Level i is used while the instance's bounds are at least `sizes[i]`
+    // pixels tall on screen
+    ProjectedSize(Vec<Float>),
+}
+
+// A mesh at several levels of detail (from the most to the least detailed),
+// of which one is rendered at a time: instances share the levels, but each
+// picks its own (see: `Scene::select_lods`), so that far away instances of
+// a detailed mesh cost little more than a few triangles
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LodMesh {
+    pub levels: Vec<Arc<Mesh>>,
+    pub selection: LodSelection,
+    // The level that is rendered
+    pub level: usize,
+}
+
+impl LodMesh {
+    pub fn new(levels: Vec<Arc<Mesh>>, selection: LodSelection) -> LodMesh {
+        assert!(!levels.is_empty(), "a mesh needs at least one level of detail");
+        LodMesh {
+            levels,
+            selection,
+            level: 0,
+        }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.levels[self.level]
+    }
+
+    // The level to render for an instance with the given (world space)
+    // bounds, seen by a camera rendering an image `height` pixels tall
+    pub fn select(&self, bounds: &Aabb, camera: &Camera, height: u32) -> usize {
+        let distance = (bounds.centroid() - camera.origin()).length();
+        let level = match self.selection {
+            LodSelection::Distance(ref distances) => distances.iter().position(|&d| distance <= d),
+            LodSelection::ProjectedSize(ref sizes) => {
+                // The height on screen of the sphere around the bounds (and,
+                // from inside it, the whole screen)
+                let radius = bounds.diagonal().length() * 0.5;
+                let half_height = (camera.fov.to_radians() * 0.5).tan();
+                let size = if distance > radius {
+                    height as Float * radius / (distance * half_height)
+                } else {
+                    Float::MAX
+                };
+                sizes.iter().position(|&s| size >= s)
+            }
+        };
+        level.unwrap_or(self.levels.len() - 1).min(self.levels.len() - 1)
+    }
+}
+
+impl Shape for LodMesh {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        self.mesh().intersect(r)
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.mesh().hit_any(r)
+    }
+
+    // The bounds of every level together, so that they don't change with
+    // the level (and the scene's BVH needn't be rebuilt when it does)
+    fn bounds(&self) -> Option<Aabb> {
+        self.levels.iter().filter_map(|level| level.bounds()).fold(None, |bounds, b| match bounds {
+            Some(bounds) => Some(b.union(&bounds)),
+            None => Some(b),
+        })
+    }
+}
+
+#[test]
+fn test_lod_selection() {
+    use shape::Geometry;
+    use scene::Scene;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use color::Color;
+    use transform::Transform;
+
+    // A quad of two triangles, and a single (coarser) triangle
+    let (a, b, c, d) = (Vector::new(-1.0, -1.0, 0.0),
+                        Vector::new(1.0, -1.0, 0.0),
+                        Vector::new(1.0, 1.0, 0.0),
+                        Vector::new(-1.0, 1.0, 0.0));
+    let fine = Arc::new(Mesh::new(vec![Triangle::new(&a, &b, &c), Triangle::new(&a, &c, &d)]));
+    let coarse = Arc::new(Mesh::new(vec![Triangle::new(&a, &b, &c)]));
+    let r = Ray::new(&Vector::new(-0.5, 0.5, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    assert!(fine.intersect(&r).is_some());
+    assert!(coarse.intersect(&r).is_none());
+
+    let camera = Camera::new(90.0, 1.0);
+    let material = Arc::new(Lambertian::new(&Color::white()));
+    let mut scene = Scene::new();
+    for &(z, selection) in &[(-5.0, 0), (-50.0, 0), (-5.0, 1), (-50.0, 1)] {
+        let selection = if selection == 0 {
+            LodSelection::Distance(vec![10.0])
+        } else {
+            // With a 90 degree field of view, the sphere around the quad is
+            // about 28 pixels (of 100) tall at 5 units away, and 3 at 50
+            LodSelection::ProjectedSize(vec![20.0])
+        };
+        let mut item = Primitive::new(LodMesh::new(vec![fine.clone(), coarse.clone()], selection), material.clone());
+        item.transform = Transform::translate(&Vector::new(0.0, 0.0, z));
+        scene.add(item);
+    }
+    scene.build_bvh();
+    scene.select_lods(&camera, 100);
+    let levels: Vec<usize> = scene.items
+        .iter()
+        .map(|item| match item.shape {
+            Geometry::Lod(ref lod) => lod.level,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(levels, vec![0, 1, 0, 1]);
+}
@@ -0,0 +1,87 @@
+use vector::Float;
+use settings::RenderSettings;
+use integrator::DebugMode;
+
+use std::fs;
+use std::path::Path;
+
+// What an image was rendered from, written into the files that can hold it
+// (as EXR header attributes, PNG text chunks, and Radiance header lines, see:
+// `output::write_image_with_metadata`), so that any image can be rendered
+// again: each entry is a key (e.g. "seed") and its value, in the order added,
+// with all of the render settings as TOML under "settings" (which `tracer
+// --settings` loads as it is)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    // Just the name and version of the renderer
+    pub fn new() -> Metadata {
+        let mut metadata = Metadata { entries: Vec::new() };
+        metadata.add("software", format!("tracer-rs {}", env!("CARGO_PKG_VERSION")));
+        metadata
+    }
+
+    // The settings an image was rendered with, and which integrator it used
+    // (the path tracer, or one of its debug views)
+    pub fn render(settings: &RenderSettings, debug: Option<DebugMode>) -> Metadata {
+        let mut metadata = Metadata::new();
+        metadata.add("resolution", format!("{}x{}", settings.width, settings.height));
+        metadata.add("samples", settings.samples);
+        metadata.add("seed", settings.seed);
+        metadata.add("max_depth", settings.max_depth);
+        metadata.add("integrator", debug.map_or_else(|| "path".to_string(), |m| format!("debug {}", m.name())));
+        if let Ok(settings) = toml::to_string(settings) {
+            metadata.add("settings", settings);
+        }
+        metadata
+    }
+
+    pub fn add<V: ToString>(&mut self, key: &str, value: V) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|e| e.0 == key).map(|e| e.1.as_str())
+    }
+
+    // The scene file, along with a hash of its contents (to tell whether it has
+    // changed since, which doesn't cover the models and textures that it loads)
+    pub fn add_scene(&mut self, path: &Path) {
+        self.add("scene", path.display());
+        if let Ok(bytes) = fs::read(path) {
+            self.add("scene_hash", format!("{:016x}", fnv1a(&bytes)));
+        }
+    }
+
+    // How long the render took, and how many samples each pixel took (on
+    // average, with adaptive sampling)
+    pub fn add_render_time(&mut self, seconds: f64, samples_per_pixel: Float) {
+        self.add("render_time", format!("{:.3}s", seconds));
+        self.add("samples_per_pixel", format!("{:.2}", samples_per_pixel));
+    }
+}
+
+// The 64-bit FNV-1a hash
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[test]
+fn test_metadata() {
+    use std::env;
+
+    let path = env::temp_dir().join("tracer_test_metadata.toml");
+    fs::write(&path, "[[objects]]\n").unwrap();
+    let settings = RenderSettings::preview();
+    let mut metadata = Metadata::render(&settings, None);
+    metadata.add_scene(&path);
+    assert_eq!(metadata.get("integrator"), Some("path"));
+    assert_eq!(metadata.get("seed"), Some("0"));
+    assert_eq!(metadata.get("scene_hash").map(|h| h.len()), Some(16));
+
+    // The settings that were written can be loaded again
+    assert_eq!(RenderSettings::parse(metadata.get("settings").unwrap()).unwrap(), settings);
+}
@@ -0,0 +1,652 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use vector::consts;
+use matrix::Matrix;
+use shape::Sphere;
+use shape::Triangle;
+use mesh::Mesh;
+use material::Material;
+use material::Lambertian;
+use material::TexturedLambertian;
+use material::Metallic;
+use material::Dielectric;
+use material::ThinDielectric;
+use material::conductor_reflectance;
+use primitive::Primitive;
+use scene::Scene;
+use camera::Camera;
+use sampler::SamplerType;
+use filter::FilterType;
+use texture::ImageTexture;
+use texture::TextureCache;
+use scene_file::SettingsDescription;
+use scene_file::ImportedScene;
+use obj;
+use ply;
+use error::Result;
+use error::TracerError;
+
+use roxmltree::Document;
+use roxmltree::Node;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Load a Mitsuba (0.6, 2, or 3) XML scene file's shapes, BSDFs, and sensor
+// (with any files that it refers to, which are relative to it), for images
+// of the given aspect ratio
+pub fn load(path: &Path, aspect_ratio: Float, textures: &Arc<TextureCache>) -> Result<ImportedScene> {
+    let directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    parse(&fs::read_to_string(path)?, &directory, aspect_ratio, textures)
+}
+
+pub fn parse(source: &str,
+             directory: &Path,
+             aspect_ratio: Float,
+             textures: &Arc<TextureCache>)
+             -> Result<ImportedScene> {
+    let document = Document::parse(source).map_err(|why| invalid(why.to_string()))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "scene" {
+        return Err(invalid("not a Mitsuba scene".to_string()));
+    }
+    let mut importer = Importer {
+        directory: directory.to_path_buf(),
+        aspect_ratio,
+        textures,
+        defaults: HashMap::new(),
+        bsdfs: HashMap::new(),
+        images: HashMap::new(),
+        result: ImportedScene {
+            scene: Scene::new(),
+            settings: SettingsDescription::default(),
+            warnings: Vec::new(),
+        },
+    };
+    importer.import(root)?;
+    importer.result.scene.build_bvh();
+    Ok(importer.result)
+}
+
+fn invalid(why: String) -> TracerError {
+    TracerError::Parse(why)
+}
+
+// Mitsuba 0.6 names properties in camel case (e.g. "toWorld"), while later
+// versions use snake case (e.g. "to_world"), so they're compared without
+// either
+fn key(name: &str) -> String {
+    name.chars().filter(|&c| c != '_').flat_map(|c| c.to_lowercase()).collect()
+}
+
+fn numbers(text: &str) -> Result<Vec<Float>> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| invalid(format!("{:?} isn't a number", s))))
+        .collect()
+}
+
+fn elements<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|n| n.is_element())
+}
+
+// The indices of refraction of the materials that Mitsuba knows by name
+const IORS: [(&str, Float); 22] = [("vacuum", 1.0),
+                                   ("helium", 1.000036),
+                                   ("hydrogen", 1.000132),
+                                   ("air", 1.000277),
+                                   ("carbon dioxide", 1.00045),
+                                   ("water", 1.333),
+                                   ("acetone", 1.36),
+                                   ("ethanol", 1.361),
+                                   ("carbon tetrachloride", 1.461),
+                                   ("glycerol", 1.4729),
+                                   ("benzene", 1.501),
+                                   ("silicone oil", 1.52045),
+                                   ("bromine", 1.661),
+                                   ("water ice", 1.31),
+                                   ("fused quartz", 1.458),
+                                   ("pyrex", 1.47),
+                                   ("acrylic glass", 1.49),
+                                   ("polypropylene", 1.49),
+                                   ("bk7", 1.5046),
+                                   ("sodium chloride", 1.544),
+                                   ("amber", 1.55),
+                                   ("diamond", 2.419)];
+
+// The reflectances (at normal incidence) of the conductors that Mitsuba
+// knows by name, where "none" is a perfect mirror
+const CONDUCTORS: [(&str, [Float; 3]); 8] = [("none", [1.0, 1.0, 1.0]),
+                                             ("Ag", [0.972, 0.960, 0.915]),
+                                             ("Al", [0.913, 0.922, 0.924]),
+                                             ("Au", [1.0, 0.782, 0.344]),
+                                             ("Cr", [0.549, 0.556, 0.554]),
+                                             ("Cu", [0.955, 0.638, 0.538]),
+                                             ("Fe", [0.562, 0.565, 0.578]),
+                                             ("Ti", [0.542, 0.497, 0.449])];
+
+struct Importer<'a> {
+    directory: PathBuf,
+    aspect_ratio: Float,
+    textures: &'a Arc<TextureCache>,
+    // The values of the scene's parameters (e.g. "$spp")
+    defaults: HashMap<String, String>,
+    // BSDFs and (bitmap) textures declared at the top level, by their IDs
+    bsdfs: HashMap<String, Arc<dyn Material>>,
+    images: HashMap<String, PathBuf>,
+    result: ImportedScene,
+}
+
+impl<'a> Importer<'a> {
+    fn warn(&mut self, warning: String) {
+        if !self.result.warnings.contains(&warning) {
+            self.result.warnings.push(warning);
+        }
+    }
+
+    // An attribute, with any of the scene's parameters substituted into it
+    fn attribute(&self, node: Node, name: &str) -> Option<String> {
+        node.attribute(name).map(|value| {
+            let mut value = value.to_string();
+            for (name, default) in &self.defaults {
+                value = value.replace(&format!("${}", name), default);
+            }
+            value
+        })
+    }
+
+    // One of an element's properties, e.g. `<float name="radius" .../>`
+    fn property<'b, 'input>(&self, node: Node<'b, 'input>, name: &str) -> Option<Node<'b, 'input>> {
+        let name = key(name);
+        elements(node).find(|n| n.attribute("name").is_some_and(|n| key(n) == name))
+    }
+
+    fn value(&self, node: Node, name: &str) -> Option<String> {
+        self.property(node, name).and_then(|p| self.attribute(p, "value"))
+    }
+
+    fn float(&self, node: Node, name: &str, default: Float) -> Result<Float> {
+        match self.value(node, name) {
+            Some(value) => value.trim().parse().map_err(|_| invalid(format!("{:?} isn't a number", value))),
+            None => Ok(default),
+        }
+    }
+
+    fn boolean(&self, node: Node, name: &str) -> bool {
+        self.value(node, name).is_some_and(|v| v.trim() == "true")
+    }
+
+    // A point or vector, as either "x, y, z" or separate attributes (which
+    // are `default` where left out)
+    fn vector(&self, node: Node, default: Float) -> Result<Vector> {
+        if let Some(value) = self.attribute(node, "value") {
+            let v = numbers(&value)?;
+            return match v.as_slice() {
+                [x] => Ok(Vector::new(*x, *x, *x)),
+                [x, y, z] => Ok(Vector::new(*x, *y, *z)),
+                _ => Err(invalid(format!("{:?} isn't a vector", value))),
+            };
+        }
+        let component = |name: &str| -> Result<Float> {
+            match self.attribute(node, name) {
+                Some(v) => v.trim().parse().map_err(|_| invalid(format!("{:?} isn't a number", v))),
+                None => Ok(default),
+            }
+        };
+        Ok(Vector::new(component("x")?, component("y")?, component("z")?))
+    }
+
+    fn color(&mut self, node: Node, name: &str, default: Float) -> Result<Color> {
+        let property = match self.property(node, name) {
+            Some(property) => property,
+            None => return Ok(Color::gray(default)),
+        };
+        let value = self.attribute(property, "value").unwrap_or_default();
+        match (property.tag_name().name(), numbers(&value)) {
+            ("rgb", Ok(ref v)) | ("spectrum", Ok(ref v)) | ("float", Ok(ref v)) if v.len() == 1 => {
+                Ok(Color::gray(v[0]))
+            }
+            ("rgb", Ok(ref v)) | ("color", Ok(ref v)) if v.len() == 3 => Ok(Color::new(v[0], v[1], v[2])),
+            (tag, _) => {
+                self.warn(format!("using a default color instead of a {} color", tag));
+                Ok(Color::gray(default))
+            }
+        }
+    }
+
+    // The arbitrary transformation of an element (e.g. a shape's "to_world"),
+    // whose steps each apply after the last
+    fn transform(&self, node: Node, name: &str) -> Result<Matrix> {
+        let mut matrix = Matrix::identity();
+        let transform = match self.property(node, name).filter(|p| p.tag_name().name() == "transform") {
+            Some(transform) => transform,
+            None => return Ok(matrix),
+        };
+        for step in elements(transform) {
+            let m = match step.tag_name().name() {
+                "translate" => Matrix::translate(&self.vector(step, 0.0)?),
+                "scale" => Matrix::scale(&self.vector(step, 1.0)?),
+                "rotate" => {
+                    let angle = self.attribute(step, "angle").unwrap_or_default();
+                    let angle = angle.trim().parse().map_err(|_| invalid(format!("{:?} isn't an angle", angle)))?;
+                    Matrix::rotate(angle, &self.vector(step, 0.0)?)
+                }
+                "matrix" => {
+                    let value = self.attribute(step, "value").unwrap_or_default();
+                    let v = numbers(&value)?;
+                    if v.len() != 16 {
+                        return Err(invalid(format!("{:?} isn't a 4x4 matrix", value)));
+                    }
+                    Matrix::from_rows(&v)
+                }
+                "lookat" => {
+                    let vector = |name: &str| -> Result<Vector> {
+                        let value = self.attribute(step, name).unwrap_or_else(|| "0, 1, 0".to_string());
+                        let v = numbers(&value)?;
+                        match v.as_slice() {
+                            [x, y, z] => Ok(Vector::new(*x, *y, *z)),
+                            _ => Err(invalid(format!("{:?} isn't a vector", value))),
+                        }
+                    };
+                    Matrix::look_at(&vector("origin")?, &vector("target")?, &vector("up")?)
+                        .ok_or_else(|| invalid("lookat's up vector is along its view direction".to_string()))?
+                }
+                tag => return Err(invalid(format!("unknown transformation {:?}", tag))),
+            };
+            matrix = m.mul(&matrix);
+        }
+        Ok(matrix)
+    }
+
+    fn import(&mut self, root: Node) -> Result<()> {
+        for node in elements(root).filter(|n| n.tag_name().name() == "default") {
+            if let (Some(name), Some(value)) = (node.attribute("name"), node.attribute("value")) {
+                self.defaults.insert(name.to_string(), value.to_string());
+            }
+        }
+        for node in elements(root) {
+            match node.tag_name().name() {
+                "default" => {}
+                "integrator" => {
+                    // (Integrators may be nested, e.g. within an AOV integrator)
+                    let depth = node.descendants().find(|n| n.attribute("name").is_some_and(|n| key(n) == "maxdepth"));
+                    if let Some(depth) = depth {
+                        let depth = self.attribute(depth, "value").unwrap_or_default();
+                        let depth: i64 = depth.trim()
+                            .parse()
+                            .map_err(|_| invalid(format!("{:?} isn't a depth", depth)))?;
+                        // (Where -1 is unbounded)
+                        if depth >= 0 {
+                            self.result.settings.max_depth = Some(depth as u32);
+                        }
+                    }
+                }
+                "sensor" => self.sensor(node)?,
+                "bsdf" => {
+                    let material = self.bsdf(node)?;
+                    if let Some(id) = node.attribute("id") {
+                        self.bsdfs.insert(id.to_string(), material);
+                    }
+                }
+                "texture" => {
+                    match (node.attribute("id"), self.image(node)) {
+                        (Some(id), Some(path)) => {
+                            self.images.insert(id.to_string(), path);
+                        }
+                        _ => self.warn(format!("ignoring {:?} textures", node.attribute("type").unwrap_or(""))),
+                    }
+                }
+                "shape" => self.shape(node)?,
+                "emitter" => self.warn("ignoring emitters (scenes are lit by the sky)".to_string()),
+                tag => self.warn(format!("ignoring <{}> elements", tag)),
+            }
+        }
+        Ok(())
+    }
+
+    fn sensor(&mut self, node: Node) -> Result<()> {
+        let kind = node.attribute("type").unwrap_or("");
+        if kind != "perspective" && kind != "thinlens" {
+            self.warn(format!("using a perspective camera instead of the {:?} sensor", kind));
+        }
+        let to_world = self.transform(node, "to_world")?;
+        let from = to_world.point(&Vector::zero());
+        let direction = to_world.vector(&Vector::new(0.0, 0.0, 1.0)).normalize();
+        let up = to_world.vector(&Vector::new(0.0, 1.0, 0.0));
+        let left = to_world.vector(&Vector::new(1.0, 0.0, 0.0));
+
+        // The field of view is along the image's width by default (and a
+        // focal length is that of a 35mm camera)
+        let fov = match self.value(node, "focal_length") {
+            Some(length) if self.value(node, "fov").is_none() => {
+                let length: Float = length.trim_end_matches("mm").trim().parse().unwrap_or(50.0);
+                2.0 * (18.0 / length).atan() * (180.0 / consts::PI)
+            }
+            _ => self.float(node, "fov", 40.0)?,
+        };
+        let axis = self.value(node, "fov_axis").unwrap_or_else(|| "x".to_string());
+        let aspect_ratio = self.aspect_ratio;
+        let tan_half = (fov * (consts::PI / 360.0)).tan();
+        let tan_half_vertical = match axis.as_str() {
+            "y" => tan_half,
+            "diagonal" => tan_half / (1.0 + aspect_ratio * aspect_ratio).sqrt(),
+            "smaller" if aspect_ratio >= 1.0 => tan_half,
+            "larger" if aspect_ratio < 1.0 => tan_half,
+            // ("x")
+            _ => tan_half / aspect_ratio,
+        };
+        let fov = 2.0 * tan_half_vertical.atan() * (180.0 / consts::PI);
+        let camera = Camera::look_at(&from, &(from + direction), &up, fov, aspect_ratio);
+
+        // Mitsuba's images run from camera space's +x (on the left) to its -x,
+        // unless the transformation swaps handedness
+        let camera = if up.cross(&-direction).dot(&left) > 0.0 {
+            camera.mirrored()
+        } else {
+            camera
+        };
+        let name = node.attribute("id").unwrap_or("camera");
+        self.result.scene.add_camera(name, camera);
+
+        for child in elements(node) {
+            match (child.tag_name().name(), child.attribute("type").unwrap_or("")) {
+                ("sampler", kind) => {
+                    let samples = self.float(child, "sample_count", 4.0)?;
+                    self.result.settings.samples = Some(samples as u32);
+                    self.result.settings.sampler = Some(match kind {
+                        "independent" => SamplerType::Random,
+                        "halton" => SamplerType::Halton,
+                        _ => SamplerType::Sobol,
+                    });
+                }
+                ("film", _) => {
+                    if let Some(filter) = elements(child).find(|n| n.tag_name().name() == "rfilter") {
+                        let kind = filter.attribute("type").unwrap_or("");
+                        let filter = match kind {
+                            "box" => FilterType::Box,
+                            "tent" => FilterType::Tent,
+                            "gaussian" => FilterType::Gaussian,
+                            "mitchell" | "catmullrom" => FilterType::Mitchell,
+                            _ => {
+                                self.warn(format!("using a Mitchell filter instead of the {:?} filter", kind));
+                                FilterType::Mitchell
+                            }
+                        };
+                        self.result.settings.filter = Some(filter);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // The path of a bitmap texture
+    fn image(&self, node: Node) -> Option<PathBuf> {
+        if node.attribute("type") != Some("bitmap") {
+            return None;
+        }
+        self.value(node, "filename").map(|filename| self.directory.join(filename))
+    }
+
+    // A diffuse material, whose reflectance may be a texture
+    fn diffuse(&mut self, node: Node, name: &str, default: Float) -> Result<Arc<dyn Material>> {
+        if let Some(property) = self.property(node, name) {
+            let path = match property.tag_name().name() {
+                "texture" => self.image(property),
+                "ref" => property.attribute("id").and_then(|id| self.images.get(id)).cloned(),
+                _ => None,
+            };
+            match path {
+                Some(path) => return Ok(Arc::new(TexturedLambertian::new(ImageTexture::new(self.textures, &path)))),
+                None if property.tag_name().name() == "texture" || property.tag_name().name() == "ref" => {
+                    self.warn(format!("using a default color instead of the texture {:?}",
+                                      property.attribute("id").or_else(|| property.attribute("type")).unwrap_or("")));
+                    return Ok(Arc::new(Lambertian::new(&Color::gray(default))));
+                }
+                None => {}
+            }
+        }
+        Ok(Arc::new(Lambertian::new(&self.color(node, name, default)?)))
+    }
+
+    // An index of refraction, by value or by name
+    fn ior(&self, node: Node, name: &str, default: Float) -> Result<Float> {
+        match self.value(node, name) {
+            Some(value) => {
+                if let Some(&(_, ior)) = IORS.iter().find(|&&(n, _)| n.eq_ignore_ascii_case(value.trim())) {
+                    return Ok(ior);
+                }
+                value.trim().parse().map_err(|_| invalid(format!("unknown index of refraction {:?}", value)))
+            }
+            None => Ok(default),
+        }
+    }
+
+    // The closest of the renderer's materials to one of Mitsuba's BSDFs
+    fn bsdf(&mut self, node: Node) -> Result<Arc<dyn Material>> {
+        let kind = node.attribute("type").unwrap_or("");
+        Ok(match kind {
+            "diffuse" | "roughdiffuse" => self.diffuse(node, "reflectance", 0.5)?,
+            // (Adapters around another BSDF)
+            "twosided" | "mask" | "bumpmap" | "normalmap" | "blendbsdf" => {
+                if kind != "twosided" {
+                    self.warn(format!("ignoring {:?} BSDFs, in favor of the BSDFs within them", kind));
+                }
+                match elements(node).find(|n| n.tag_name().name() == "bsdf" || n.tag_name().name() == "ref") {
+                    Some(inner) => self.material(inner)?,
+                    None => Arc::new(Lambertian::new(&Color::gray(0.5))),
+                }
+            }
+            "plastic" | "roughplastic" => {
+                self.warn(format!("approximating {:?} BSDFs as diffuse", kind));
+                self.diffuse(node, "diffuse_reflectance", 0.5)?
+            }
+            "principled" => {
+                self.warn(format!("approximating {:?} BSDFs as diffuse", kind));
+                self.diffuse(node, "base_color", 0.5)?
+            }
+            "dielectric" | "roughdielectric" => {
+                let ior = self.ior(node, "int_ior", 1.5046)? / self.ior(node, "ext_ior", 1.000277)?;
+                Arc::new(Dielectric::new(ior))
+            }
+            "thindielectric" => {
+                let ior = self.ior(node, "int_ior", 1.5046)? / self.ior(node, "ext_ior", 1.000277)?;
+                Arc::new(ThinDielectric::new(ior, &self.color(node, "specular_transmittance", 1.0)?))
+            }
+            "conductor" | "roughconductor" => {
+                let albedo = if self.property(node, "specular_reflectance").is_some() {
+                    self.color(node, "specular_reflectance", 1.0)?
+                } else if self.property(node, "eta").is_some() {
+                    conductor_reflectance(&self.color(node, "eta", 0.0)?, &self.color(node, "k", 1.0)?)
+                } else {
+                    let name = self.value(node, "material").unwrap_or_else(|| "none".to_string());
+                    match CONDUCTORS.iter().find(|&&(n, _)| n == name.trim()) {
+                        Some(&(_, albedo)) => Color::from(albedo),
+                        None => {
+                            self.warn(format!("using a default reflectance for the conductor {:?}", name));
+                            Color::gray(0.9)
+                        }
+                    }
+                };
+                let alpha = if kind == "roughconductor" { 0.1 } else { 0.0 };
+                Arc::new(Metallic::new(&albedo, self.float(node, "alpha", alpha)?))
+            }
+            _ => {
+                self.warn(format!("using a diffuse material instead of {:?} BSDFs", kind));
+                Arc::new(Lambertian::new(&Color::gray(0.5)))
+            }
+        })
+    }
+
+    // A BSDF, or a reference to one
+    fn material(&mut self, node: Node) -> Result<Arc<dyn Material>> {
+        if node.tag_name().name() == "ref" {
+            let id = node.attribute("id").unwrap_or("");
+            return self.bsdfs.get(id).cloned().ok_or_else(|| invalid(format!("no BSDF with the ID {:?}", id)));
+        }
+        self.bsdf(node)
+    }
+
+    fn shape(&mut self, node: Node) -> Result<()> {
+        let kind = node.attribute("type").unwrap_or("");
+        let material = match elements(node).find(|n| n.tag_name().name() == "bsdf" || n.tag_name().name() == "ref") {
+            Some(bsdf) => self.material(bsdf)?,
+            None => Arc::new(Lambertian::new(&Color::gray(0.5))),
+        };
+        if elements(node).any(|n| n.tag_name().name() == "emitter") {
+            self.warn("ignoring emitters (scenes are lit by the sky)".to_string());
+        }
+        let to_world = self.transform(node, "to_world")?;
+        let flip = self.boolean(node, "flip_normals");
+
+        let mesh = match kind {
+            "sphere" => {
+                // (Spheres may only be scaled uniformly)
+                let center = match self.property(node, "center") {
+                    Some(center) => self.vector(center, 0.0)?,
+                    None => Vector::zero(),
+                };
+                let radius = self.float(node, "radius", 1.0)? * to_world.determinant().abs().cbrt();
+                self.result.scene.add(Primitive::new(Sphere::new(&to_world.point(&center), radius), material));
+                return Ok(());
+            }
+            "rectangle" => rectangle(),
+            "cube" => Mesh::cube(),
+            "disk" => disk(),
+            "obj" | "ply" => {
+                let filename = self.value(node, "filename")
+                    .ok_or_else(|| invalid(format!("a {} shape without a filename", kind)))?;
+                let path = self.directory.join(filename);
+                let mut meshes = if kind == "obj" {
+                    obj::load(&path, self.textures)?.groups.into_iter().map(|g| g.mesh).collect()
+                } else {
+                    vec![ply::load(&path)?]
+                };
+                for mesh in &mut meshes {
+                    mesh.material_ids = None;
+                    if self.boolean(node, "face_normals") {
+                        mesh.normals = None;
+                    }
+                    self.result.scene.add(Primitive::new(mesh.transformed(&to_world, flip), material.clone()));
+                }
+                return Ok(());
+            }
+            _ => {
+                self.warn(format!("ignoring {:?} shapes", kind));
+                return Ok(());
+            }
+        };
+        self.result.scene.add(Primitive::new(mesh.transformed(&to_world, flip), material));
+        Ok(())
+    }
+}
+
+// Mitsuba's built-in shapes (besides `cube`, see: `Mesh::cube`), which face
+// their +z axis: `rectangle` spans [-1, 1] along x and y
+fn rectangle() -> Mesh {
+    let corners = [Vector::new(-1.0, -1.0, 0.0),
+                   Vector::new(1.0, -1.0, 0.0),
+                   Vector::new(1.0, 1.0, 0.0),
+                   Vector::new(-1.0, 1.0, 0.0)];
+    let mut mesh = Mesh::new(vec![Triangle::new(&corners[0], &corners[1], &corners[2]),
+                                  Triangle::new(&corners[0], &corners[2], &corners[3])]);
+    mesh.uvs = Some(vec![[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], [(0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]]);
+    mesh
+}
+
+// `disk` is the unit disk in the xy plane (as a fan of triangles)
+fn disk() -> Mesh {
+    const SEGMENTS: usize = 64;
+    let rim = |i: usize| {
+        let (s, c) = (i as Float * (2.0 * consts::PI / SEGMENTS as Float)).sin_cos();
+        Vector::new(c, s, 0.0)
+    };
+    Mesh::new((0..SEGMENTS).map(|i| Triangle::new(&Vector::zero(), &rim(i), &rim(i + 1))).collect())
+}
+
+#[test]
+fn test_load_mitsuba() {
+    use ray::Ray;
+    use vector::TEST_EPSILON;
+
+    let source = r#"
+        <scene version="3.0.0">
+            <default name="spp" value="8"/>
+            <integrator type="path">
+                <integer name="max_depth" value="3"/>
+            </integrator>
+            <sensor type="perspective">
+                <float name="fov" value="45"/>
+                <transform name="to_world">
+                    <lookat origin="0, 0, 0" target="0, 0, -1" up="0, 1, 0"/>
+                </transform>
+                <sampler type="independent">
+                    <integer name="sample_count" value="$spp"/>
+                </sampler>
+                <film type="hdrfilm">
+                    <rfilter type="tent"/>
+                </film>
+            </sensor>
+            <bsdf type="twosided" id="red">
+                <bsdf type="diffuse">
+                    <rgb name="reflectance" value="1, 0, 0"/>
+                </bsdf>
+            </bsdf>
+            <emitter type="constant"/>
+            <shape type="sphere">
+                <point name="center" x="0" y="0" z="-3"/>
+                <float name="radius" value="0.5"/>
+                <ref id="red"/>
+            </shape>
+            <shape type="rectangle">
+                <transform name="to_world">
+                    <rotate x="1" angle="-90"/>
+                    <scale x="10" z="10"/>
+                    <translate y="-1"/>
+                </transform>
+                <bsdf type="dielectric">
+                    <string name="int_ior" value="water"/>
+                </bsdf>
+            </shape>
+            <shape type="cylinder"/>
+        </scene>
+    "#;
+    let textures = Arc::new(TextureCache::new(0));
+    let mitsuba = parse(source, Path::new("."), 1.0, &textures).unwrap();
+    assert_eq!(mitsuba.settings.samples, Some(8));
+    assert_eq!(mitsuba.settings.max_depth, Some(3));
+    assert_eq!(mitsuba.settings.sampler, Some(SamplerType::Random));
+    assert_eq!(mitsuba.settings.filter, Some(FilterType::Tent));
+    assert_eq!(mitsuba.warnings.len(), 2);
+
+    let scene = &mitsuba.scene;
+    assert_eq!(scene.items.len(), 2);
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 2.5).abs() < TEST_EPSILON);
+    assert_eq!(material.albedo(), Color::new(1.0, 0.0, 0.0));
+
+    // The floor is rotated to face up, scaled, and then moved down
+    let r = Ray::new(&Vector::new(8.0, 0.0, 8.0), &Vector::new(0.0, -1.0, 0.0), 0.001, Float::MAX);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 1.0).abs() < TEST_EPSILON);
+    assert!(dg.normal.y > 0.0);
+    assert_eq!(material.data(),
+               Some(::material::MaterialData::Dielectric { ior: 1.333 / 1.000277, priority: None, dispersion: None }));
+
+    // Mitsuba's cameras match the renderer's (unlike PBRT's)
+    let camera = scene.camera("camera").unwrap();
+    assert!(camera.generate_ray(1.0, 0.5).direction.x > 0.0);
+
+    assert!(parse("<scene><shape type=\"sphere\"><ref id=\"missing\"/></shape></scene>",
+                  Path::new("."),
+                  1.0,
+                  &textures)
+        .is_err());
+    assert!(parse("<scene>", Path::new("."), 1.0, &textures).is_err());
+}
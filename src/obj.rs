@@ -0,0 +1,601 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Triangle;
+use shape::Geometry;
+use mesh::Mesh;
+use material::Material;
+use material::Lambertian;
+use material::TexturedLambertian;
+use material::Metallic;
+use material::Dielectric;
+use material::Emissive;
+use primitive::Primitive;
+use scene::Scene;
+use transform::Transform;
+use aabb::Aabb;
+use texture::ImageTexture;
+use texture::TextureCache;
+use error::Result;
+use error::TracerError;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+// The material of faces that don't name one (or that name one that no
+// material library defines)
+const DEFAULT_MATERIAL: &str = "default";
+
+// A group of faces (see: `g` and `o`), as one mesh whose material IDs index
+// the model's materials
+pub struct ObjGroup {
+    pub name: String,
+    pub mesh: Mesh,
+}
+
+// A Wavefront OBJ model, with the materials of its material libraries
+pub struct Obj {
+    pub groups: Vec<ObjGroup>,
+    pub materials: Vec<(String, Arc<dyn Material>)>,
+}
+
+impl Obj {
+    // Add each of the model's groups to the scene as a primitive (with its
+    // materials added to the scene's material table), returning their
+    // object IDs
+    pub fn add_to(self, scene: &mut Scene) -> Vec<u32> {
+        let material_ids: Vec<u32> = self.materials.iter().map(|m| scene.add_material(&m.1)).collect();
+        let mut object_ids = Vec::new();
+        for group in self.groups {
+            let mut mesh = group.mesh;
+            let first = mesh.material_ids.as_ref().and_then(|ids| ids.first().cloned()).unwrap_or(0);
+            if let Some(ref mut ids) = mesh.material_ids {
+                for id in ids.iter_mut() {
+                    *id = material_ids[*id as usize];
+                }
+            }
+            object_ids.push(scene.items.len() as u32);
+            scene.add(Primitive::new(mesh, self.materials[first as usize].1.clone()));
+        }
+        object_ids
+    }
+}
+
+fn invalid(line: usize, why: &str) -> TracerError {
+    TracerError::Parse(format!("line {}: {}", line, why))
+}
+
+fn parse_floats(line: usize, words: &[&str], count: usize) -> Result<Vec<Float>> {
+    if words.len() < count {
+        return Err(invalid(line, "too few coordinates"));
+    }
+    words.iter()
+        .take(count)
+        .map(|w| w.parse::<Float>().map_err(|_| invalid(line, "expected a number")))
+        .collect()
+}
+
+fn parse_vector(line: usize, words: &[&str]) -> Result<Vector> {
+    let v = parse_floats(line, words, 3)?;
+    Ok(Vector::new(v[0], v[1], v[2]))
+}
+
+fn parse_color(line: usize, words: &[&str]) -> Result<Color> {
+    let v = parse_floats(line, words, 3)?;
+    Ok(Color::new(v[0], v[1], v[2]))
+}
+
+// An index into one of a model's lists (starting from 1, or counting back
+// from the most recent entry if negative), as an index starting from 0
+fn parse_index(line: usize, word: &str, len: usize) -> Result<usize> {
+    let i: i64 = word.parse().map_err(|_| invalid(line, "expected an index"))?;
+    let index = if i < 0 { len as i64 + i } else { i - 1 };
+    if index < 0 || index >= len as i64 {
+        return Err(invalid(line, "index out of range"));
+    }
+    Ok(index as usize)
+}
+
+// The faces of a group as it's being read
+#[derive(Default)]
+struct GroupBuilder {
+    name: String,
+    triangles: Vec<Triangle>,
+    normals: Vec<Option<[Vector; 3]>>,
+    uvs: Vec<Option<[(Float, Float); 3]>>,
+    material_ids: Vec<u32>,
+}
+
+impl GroupBuilder {
+    // Each triangle's normals and uvs are stored if any triangle has them
+    // (triangles without normals are flat, and those without uvs are given
+    // their barycentric coordinates)
+    fn build(self) -> ObjGroup {
+        let with_normals = self.normals.iter().any(|n| n.is_some());
+        let with_uvs = self.uvs.iter().any(|uv| uv.is_some());
+        let normals = if with_normals {
+            Some(self.triangles
+                .iter()
+                .zip(&self.normals)
+                .map(|(t, n)| {
+                    n.unwrap_or_else(|| {
+                        let [a, b, c] = t.vertices;
+                        let normal = (b - a).cross(&(c - a)).normalize();
+                        [normal, normal, normal]
+                    })
+                })
+                .collect())
+        } else {
+            None
+        };
+        let uvs = if with_uvs {
+            Some(self.uvs.iter().map(|uv| uv.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)])).collect())
+        } else {
+            None
+        };
+        let mut mesh = Mesh::new(self.triangles);
+        mesh.normals = normals;
+        mesh.uvs = uvs;
+        mesh.material_ids = Some(self.material_ids);
+        ObjGroup {
+            name: self.name,
+            mesh,
+        }
+    }
+}
+
+// Load a Wavefront OBJ model: polygons are split into fans of triangles,
+// and the materials of any material libraries it uses (see: `load_mtl`) are
+// loaded along with it (with their textures through `textures`)
+pub fn load(path: &Path, textures: &Arc<TextureCache>) -> Result<Obj> {
+    let source = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut library = HashMap::new();
+    let mut materials: Vec<(String, Arc<dyn Material>)> = Vec::new();
+    let mut material_id: Option<u32> = None;
+    let mut groups = Vec::new();
+    let mut group = GroupBuilder::default();
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue,
+        };
+        match keyword {
+            "v" => positions.push(parse_vector(number, args)?),
+            "vn" => normals.push(parse_vector(number, args)?),
+            "vt" => {
+                let uv = parse_floats(number, args, 2)?;
+                uvs.push((uv[0], uv[1]));
+            }
+            "g" | "o" => {
+                let name = args.join(" ");
+                let previous = ::std::mem::take(&mut group);
+                if !previous.triangles.is_empty() {
+                    groups.push(previous.build());
+                }
+                group.name = name;
+            }
+            "mtllib" => {
+                for file in args {
+                    library.extend(load_mtl(&directory.join(file), textures)?);
+                }
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                material_id = Some(material_index(&mut materials, &library, &name));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(invalid(number, "a face needs at least three vertices"));
+                }
+                // Each vertex is `v`, `v/vt`, `v//vn`, or `v/vt/vn`
+                let mut vertices = Vec::with_capacity(args.len());
+                for arg in args {
+                    let mut indices = arg.split('/');
+                    let p = parse_index(number, indices.next().unwrap_or(""), positions.len())?;
+                    let uv = match indices.next() {
+                        Some(i) if !i.is_empty() => Some(uvs[parse_index(number, i, uvs.len())?]),
+                        _ => None,
+                    };
+                    let n = match indices.next() {
+                        Some(i) if !i.is_empty() => Some(normals[parse_index(number, i, normals.len())?]),
+                        _ => None,
+                    };
+                    vertices.push((positions[p], uv, n));
+                }
+                let id = match material_id {
+                    Some(id) => id,
+                    None => {
+                        let id = material_index(&mut materials, &library, DEFAULT_MATERIAL);
+                        material_id = Some(id);
+                        id
+                    }
+                };
+                let (a, uv_a, n_a) = vertices[0];
+                for pair in vertices[1..].windows(2) {
+                    let ((b, uv_b, n_b), (c, uv_c, n_c)) = (pair[0], pair[1]);
+                    group.triangles.push(Triangle::new(&a, &b, &c));
+                    group.normals.push(match (n_a, n_b, n_c) {
+                        (Some(n_a), Some(n_b), Some(n_c)) => Some([n_a, n_b, n_c]),
+                        _ => None,
+                    });
+                    group.uvs.push(match (uv_a, uv_b, uv_c) {
+                        (Some(uv_a), Some(uv_b), Some(uv_c)) => Some([uv_a, uv_b, uv_c]),
+                        _ => None,
+                    });
+                    group.material_ids.push(id);
+                }
+            }
+            // (Smoothing groups, lines, points, and curves are ignored)
+            _ => {}
+        }
+    }
+    if !group.triangles.is_empty() {
+        groups.push(group.build());
+    }
+    Ok(Obj { groups, materials })
+}
+
+// The index of the named material among those used so far, adding it (from
+// the material library, or as the default material if it isn't there) if
+// it hasn't been used yet
+fn material_index(materials: &mut Vec<(String, Arc<dyn Material>)>,
+                  library: &HashMap<String, Arc<dyn Material>>,
+                  name: &str)
+                  -> u32 {
+    if let Some(i) = materials.iter().position(|m| m.0 == name) {
+        return i as u32;
+    }
+    let material = match library.get(name) {
+        Some(material) => material.clone(),
+        None => Arc::new(Lambertian::new(&Color::gray(0.8))),
+    };
+    materials.push((name.to_string(), material));
+    materials.len() as u32 - 1
+}
+
+// The parts of an MTL material that map onto the crate's materials
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    shininess: Float,
+    ior: Float,
+    dissolve: Float,
+    illum: u32,
+    emission: Color,
+    diffuse_map: Option<String>,
+}
+
+impl MtlMaterial {
+    // Materials that emit light are lights, transparent materials are
+    // dielectrics, reflective materials (or materials that are mostly
+    // specular) are metals, and the rest are diffuse
+    fn create(&self, directory: &Path, textures: &Arc<TextureCache>) -> Arc<dyn Material> {
+        if self.emission.max_channel() > 0.0 {
+            return Arc::new(Emissive::new(&self.emission));
+        }
+        if self.dissolve < 1.0 || [4, 6, 7, 9].contains(&self.illum) {
+            return Arc::new(Dielectric::new(self.ior));
+        }
+        if self.illum == 3 || self.illum == 5 || self.specular.max_channel() > self.diffuse.max_channel() {
+            // Shininess (the exponent of a Phong lobe) runs from 0 to 1000
+            let glossiness = 1.0 - (self.shininess / 1000.0).clamp(0.0, 1.0).sqrt();
+            return Arc::new(Metallic::new(&self.specular, glossiness));
+        }
+        match self.diffuse_map {
+            Some(ref map) => Arc::new(TexturedLambertian::new(ImageTexture::new(textures, &directory.join(map)))),
+            None => Arc::new(Lambertian::new(&self.diffuse)),
+        }
+    }
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            diffuse: Color::gray(0.8),
+            specular: Color::black(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+            emission: Color::black(),
+            diffuse_map: None,
+        }
+    }
+}
+
+// Load the materials of an MTL material library, by name
+pub fn load_mtl(path: &Path, textures: &Arc<TextureCache>) -> Result<HashMap<String, Arc<dyn Material>>> {
+    let source = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let (keyword, args) = match words.split_first() {
+            Some((keyword, args)) => (*keyword, args),
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material.create(directory, textures));
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let material = match current {
+            Some((_, ref mut material)) => material,
+            None => return Err(invalid(number, "expected `newmtl` first")),
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_color(number, args)?,
+            "Ks" => material.specular = parse_color(number, args)?,
+            "Ke" => material.emission = parse_color(number, args)?,
+            "Ns" => material.shininess = parse_floats(number, args, 1)?[0],
+            "Ni" => material.ior = parse_floats(number, args, 1)?[0],
+            "d" => material.dissolve = parse_floats(number, args, 1)?[0],
+            "Tr" => material.dissolve = 1.0 - parse_floats(number, args, 1)?[0],
+            "illum" => material.illum = parse_floats(number, args, 1)?[0] as u32,
+            // (Options before the file name, e.g. `-bm 1.0`, are skipped)
+            "map_Kd" => material.diffuse_map = args.last().map(|s| s.to_string()),
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material.create(directory, textures));
+    }
+    Ok(materials)
+}
+
+// Spheres are exported with this many segments around them (and half as
+// many rings from pole to pole)
+const SPHERE_SEGMENTS: usize = 32;
+
+// A face's vertex: its position, and (if it has them) normal and uv
+type Vertex = (Vector, Option<Vector>, Option<(Float, Float)>);
+
+// Writes each face with its own vertices, transformed to world space, and
+// numbers them across the whole file
+struct ObjWriter<W: Write> {
+    out: W,
+    positions: usize,
+    normals: usize,
+    uvs: usize,
+}
+
+impl<W: Write> ObjWriter<W> {
+    fn face(&mut self, transform: &Transform, vertices: &[Vertex]) -> io::Result<()> {
+        let mut face = String::from("f");
+        for &(p, n, uv) in vertices {
+            let p = transform.point_to_world(&p);
+            writeln!(self.out, "v {} {} {}", p.x, p.y, p.z)?;
+            self.positions += 1;
+            face += &format!(" {}", self.positions);
+            if let Some((u, v)) = uv {
+                writeln!(self.out, "vt {} {}", u, v)?;
+                self.uvs += 1;
+                face += &format!("/{}", self.uvs);
+            }
+            if let Some(n) = n {
+                let n = transform.normal_to_world(&n);
+                writeln!(self.out, "vn {} {} {}", n.x, n.y, n.z)?;
+                self.normals += 1;
+                face += &format!("{}/{}", if uv.is_some() { "" } else { "/" }, self.normals);
+            }
+        }
+        writeln!(self.out, "{}", face)
+    }
+
+    fn mesh(&mut self, transform: &Transform, mesh: &Mesh) -> io::Result<()> {
+        let mut material_id = None;
+        for (i, triangle) in mesh.triangles.iter().enumerate() {
+            if let Some(ref ids) = mesh.material_ids {
+                if material_id != Some(ids[i]) {
+                    writeln!(self.out, "usemtl material_{}", ids[i])?;
+                    material_id = Some(ids[i]);
+                }
+            }
+            let normals = mesh.normals.as_ref().map(|normals| normals[i]);
+            let uvs = mesh.uvs.as_ref().map(|uvs| uvs[i]);
+            let vertices: Vec<Vertex> = (0..3)
+                .map(|j| (triangle.vertices[j], normals.map(|n| n[j]), uvs.map(|uv| uv[j])))
+                .collect();
+            self.face(transform, &vertices)?;
+        }
+        Ok(())
+    }
+
+    // A UV sphere, whose faces at the poles are triangles
+    fn sphere(&mut self, transform: &Transform, center: &Vector, radius: Float) -> io::Result<()> {
+        const RINGS: usize = SPHERE_SEGMENTS / 2;
+        let vertex = |ring: usize, segment: usize| {
+            let theta = ring as Float / RINGS as Float * ::std::f64::consts::PI as Float;
+            let phi = segment as Float / SPHERE_SEGMENTS as Float * 2.0 * ::std::f64::consts::PI as Float;
+            let n = Vector::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+            (*center + n * radius, Some(n), None)
+        };
+        for ring in 0..RINGS {
+            for segment in 0..SPHERE_SEGMENTS {
+                let mut vertices = vec![vertex(ring, segment)];
+                if ring > 0 {
+                    vertices.push(vertex(ring, segment + 1));
+                }
+                if ring + 1 < RINGS {
+                    vertices.push(vertex(ring + 1, segment + 1));
+                }
+                vertices.push(vertex(ring + 1, segment));
+                self.face(transform, &vertices)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Write the scene's geometry (but not its materials) to an OBJ file, as one
+// object per primitive, e.g. to check how a scene is put together in another
+// viewer: transforms are baked into the vertices, spheres are tessellated,
+// and planes are cut down to squares large enough to cover the rest of the
+// scene (custom shapes, which can't be looked into, are left out)
+pub fn save(scene: &Scene, path: &Path) -> Result<()> {
+    let bounds = scene.items.iter().filter_map(|item| item.bounds()).fold(Aabb::empty(), |a, b| a.union(&b));
+    let (centroid, size) = if bounds.is_empty() {
+        (Vector::zero(), 1.0)
+    } else {
+        (bounds.centroid(), bounds.diagonal().length().max(1.0))
+    };
+
+    let mut writer = ObjWriter {
+        out: BufWriter::new(fs::File::create(path)?),
+        positions: 0,
+        normals: 0,
+        uvs: 0,
+    };
+    for item in &scene.items {
+        writeln!(writer.out, "o object_{}", item.object_id)?;
+        writeln!(writer.out, "usemtl material_{}", item.material_id)?;
+        let transform = &item.transform;
+        match item.shape {
+            Geometry::Sphere(ref s) => writer.sphere(transform, &s.center, s.radius)?,
+            Geometry::Plane(ref p) => {
+                // (The square's edges, whose cross product is the normal)
+                let other = if p.normal.x.abs() < 0.9 { Vector::new(1.0, 0.0, 0.0) } else { Vector::new(0.0, 1.0, 0.0) };
+                let u = p.normal.cross(&other).normalize();
+                let v = p.normal.cross(&u);
+                let half = size + (transform.point_to_world(&p.center) - centroid).length();
+                let (u, v) = (u * half, v * half);
+                let corners = [p.center - u - v, p.center + u - v, p.center + u + v, p.center - u + v];
+                let vertices: Vec<Vertex> = corners.iter().map(|&c| (c, Some(p.normal), None)).collect();
+                writer.face(transform, &vertices)?;
+            }
+            Geometry::Triangle(ref t) => {
+                let vertices: Vec<Vertex> = t.vertices.iter().map(|&p| (p, None, None)).collect();
+                writer.face(transform, &vertices)?;
+            }
+            Geometry::Quad(ref q) => {
+                let corners = [q.corner, q.corner + q.u, q.corner + q.u + q.v, q.corner + q.v];
+                let vertices: Vec<Vertex> = corners.iter().map(|&c| (c, None, None)).collect();
+                writer.face(transform, &vertices)?;
+            }
+            Geometry::Spheres(ref spheres) => {
+                let mut material_id = None;
+                for i in 0..spheres.len() {
+                    if material_id != Some(spheres.material_id(i)) {
+                        writeln!(writer.out, "usemtl material_{}", spheres.material_id(i))?;
+                        material_id = Some(spheres.material_id(i));
+                    }
+                    writer.sphere(transform, &spheres.center(i), spheres.radius(i))?;
+                }
+            }
+            Geometry::Mesh(ref mesh) => writer.mesh(transform, mesh)?,
+            // (The level of detail that was last selected)
+            Geometry::Lod(ref lod) => writer.mesh(transform, lod.mesh())?,
+            Geometry::Custom(_) => writeln!(writer.out, "# (a custom shape, which can't be exported)")?,
+        }
+    }
+    Ok(writer.out.flush()?)
+}
+
+#[test]
+fn test_load_obj() {
+    use shape::Shape;
+    use ray::Ray;
+    use vector::TEST_EPSILON;
+
+    let directory = ::std::env::temp_dir().join("tracer_test_load_obj");
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("model.mtl"),
+              "newmtl red\nKd 1 0 0\n\nnewmtl glass\nNi 1.33\nd 0.2\n\nnewmtl neon\nKe 4 1 0\n")
+        .unwrap();
+    // A quad (with normals and uvs) in one group, and a triangle (without
+    // either) with a material that no library defines in another
+    fs::write(directory.join("model.obj"),
+              "mtllib model.mtl\n\
+               v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\n\
+               vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+               vn 0 0 1\nvn 1 0 1\n\
+               g front\nusemtl red\nf 1/1/1 2/2/2 3/3/2 4/4/1\n\
+               o back # comment\nusemtl missing\nf -4 -2 -3\n")
+        .unwrap();
+    let textures = Arc::new(TextureCache::new(1 << 20));
+    let obj = load(&directory.join("model.obj"), &textures).unwrap();
+
+    assert_eq!(obj.groups.len(), 2);
+    assert_eq!(obj.groups[0].name, "front");
+    assert_eq!(obj.groups[0].mesh.len(), 2);
+    assert_eq!(obj.groups[1].name, "back");
+    assert_eq!(obj.groups[1].mesh.len(), 1);
+    let names: Vec<&str> = obj.materials.iter().map(|m| m.0.as_str()).collect();
+    assert_eq!(names, vec!["red", "missing"]);
+    assert_eq!(obj.materials[0].1.albedo(), Color::new(1.0, 0.0, 0.0));
+    let library = load_mtl(&directory.join("model.mtl"), &textures).unwrap();
+    assert_eq!(library["neon"].emission(), Color::new(4.0, 1.0, 0.0));
+
+    // The normals and uvs are interpolated across the quad
+    let r = Ray::new(&Vector::new(0.5, -0.5, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    let dg = obj.groups[0].mesh.intersect(&r).unwrap();
+    assert!((dg.uv.0 - 0.75).abs() < TEST_EPSILON && (dg.uv.1 - 0.25).abs() < TEST_EPSILON);
+    assert!(dg.normal.x > 0.0 && (dg.normal.length() - 1.0).abs() < TEST_EPSILON);
+    assert_eq!(dg.material_id, Some(0));
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Triangle::new(&Vector::zero(), &Vector::one(), &Vector::new(1.0, 0.0, 0.0)),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    assert_eq!(obj.add_to(&mut scene), vec![1, 2]);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert_eq!(material.albedo(), Color::new(1.0, 0.0, 0.0));
+    assert_eq!(dg.material_id, Some(1));
+
+    assert!(load(&directory.join("no_such_model.obj"), &textures).is_err());
+    fs::write(directory.join("broken.obj"), "v 0 0 0\nf 1 2 3\n").unwrap();
+    assert!(load(&directory.join("broken.obj"), &textures).is_err());
+}
+
+#[test]
+fn test_save_obj() {
+    use shape::Shape;
+    use shape::Sphere;
+    use shape::Quad;
+    use ray::Ray;
+
+    let material: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -4.0), 1.0), material.clone()));
+    scene.add(Primitive::new(Quad::new(&Vector::new(-1.0, -1.0, -8.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 2.0, 0.0)),
+                             material.clone()));
+    let mut cube = Primitive::new(Mesh::cube(), material.clone());
+    cube.transform = Transform::new(&Vector::new(4.0, 0.0, -4.0), &Vector::new(0.0, 45.0, 0.0), &Vector::one(), &Vector::zero());
+    scene.add(cube);
+
+    let path = ::std::env::temp_dir().join("tracer_test_save_obj.obj");
+    save(&scene, &path).unwrap();
+    let textures = Arc::new(TextureCache::new(0));
+    let obj = load(&path, &textures).unwrap();
+    let names: Vec<&str> = obj.groups.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(names, vec!["object_0", "object_1", "object_2"]);
+    assert_eq!(obj.groups[1].mesh.len(), 2);
+    assert_eq!(obj.groups[2].mesh.len(), 12);
+
+    // The exported shapes are where the scene's are (with the sphere's faces
+    // just inside it)
+    let rays = [Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX),
+                Ray::new(&Vector::new(0.5, 0.5, 0.0), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX),
+                Ray::new(&Vector::new(4.0, 0.0, 0.0), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX)];
+    for ((group, item), r) in obj.groups.iter().zip(&scene.items).zip(&rays) {
+        let expected = item.intersect_shape(r).unwrap().t;
+        let dg = group.mesh.intersect(r).unwrap();
+        assert!((dg.t - expected).abs() < 0.01, "{} != {}", dg.t, expected);
+    }
+    let _ = fs::remove_file(&path);
+}
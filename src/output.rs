@@ -0,0 +1,578 @@
+use vector::Float;
+use color::Color;
+use framebuffer::Framebuffer;
+use film::Aov;
+use film::Film;
+use film::ID_RANKS;
+use tonemap::DisplayTransform;
+use metadata::Metadata;
+
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Layer, LayerAttributes, SmallVec, WritableImage};
+use exr::prelude::{AttributeValue, Text};
+use exr::prelude::f16;
+use exr::prelude::Image as ExrImage;
+use image;
+use image::codecs::jpeg::JpegEncoder;
+use image::ExtendedColorType;
+use png;
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::path::Path;
+
+// The image formats that a framebuffer can be written to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    Ppm,
+    // PNG with 8 or 16 bits per channel
+    Png8,
+    Png16,
+    // JPEG with a quality between 1 and 100
+    Jpeg(u8),
+    // OpenEXR, which stores linear (i.e. not gamma corrected) radiance
+    Exr(ExrPrecision),
+    // Radiance RGBE, which also stores linear radiance
+    Hdr,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExrPrecision {
+    // 16-bit floats
+    Half,
+    // 32-bit floats
+    Full,
+}
+
+impl OutputFormat {
+    // Guess the format from a file's extension (PNGs default to 8 bits)
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(OutputFormat::Ppm),
+            "png" => Some(OutputFormat::Png8),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg(90)),
+            "exr" => Some(OutputFormat::Exr(ExrPrecision::Half)),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Png8 | OutputFormat::Png16 => "png",
+            OutputFormat::Jpeg(_) => "jpg",
+            OutputFormat::Exr(_) => "exr",
+            OutputFormat::Hdr => "hdr",
+        }
+    }
+}
+
+fn to_io_error(e: image::ImageError) -> io::Error {
+    io::Error::other(e)
+}
+
+// Write a framebuffer to disk in the given format: formats that store linear
+// radiance only have the exposure adjustment of the display transform applied
+pub fn write_image(framebuffer: &Framebuffer,
+                   path: &Path,
+                   format: OutputFormat,
+                   display: &DisplayTransform)
+                   -> io::Result<()> {
+    write_image_with_metadata(framebuffer, path, format, display, &Metadata::new())
+}
+
+// As `write_image`, also recording how the image was rendered in the formats
+// that have room for it (EXR, PNG, and HDR, but not PPM or JPEG)
+pub fn write_image_with_metadata(framebuffer: &Framebuffer,
+                                 path: &Path,
+                                 format: OutputFormat,
+                                 display: &DisplayTransform,
+                                 metadata: &Metadata)
+                                 -> io::Result<()> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    match format {
+        OutputFormat::Ppm => framebuffer.write_ppm(path, display),
+        OutputFormat::Png8 => {
+            let bytes = framebuffer.to_rgb8(display);
+            write_png(path, width, height, png::ColorType::Rgb, png::BitDepth::Eight, &bytes, metadata)
+        }
+        OutputFormat::Png16 => {
+            // (PNG stores 16-bit values big-endian)
+            let bytes: Vec<u8> = framebuffer.to_rgb16(display).iter().flat_map(|v| v.to_be_bytes()).collect();
+            write_png(path, width, height, png::ColorType::Rgb, png::BitDepth::Sixteen, &bytes, metadata)
+        }
+        OutputFormat::Jpeg(quality) => {
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = JpegEncoder::new_with_quality(file, quality.clamp(1, 100));
+            encoder.encode(&framebuffer.to_rgb8(display), width, height, ExtendedColorType::Rgb8)
+                .map_err(to_io_error)
+        }
+        OutputFormat::Exr(precision) => {
+            let channels = framebuffer_channels(&framebuffer.exposed(display), "");
+            write_exr(path, width, height, &channels, precision, metadata)
+        }
+        OutputFormat::Hdr => write_hdr(&framebuffer.exposed(display), path, metadata),
+    }
+}
+
+// As `write_image_with_metadata`, for an image with an alpha channel (one
+// value per pixel) that it's premultiplied by, as a transparent render is
+// (see: `Renderer::transparent`): EXRs store the premultiplied radiance along
+// with an A channel, as compositors expect, while PNGs store the colors
+// divided by their alpha (so that the display transform sees the colors
+// themselves), which is lossy where light is added over a transparent
+// background (e.g. by glare). The other formats have no alpha channel
+pub fn write_image_with_alpha(framebuffer: &Framebuffer,
+                              alpha: &[Float],
+                              path: &Path,
+                              format: OutputFormat,
+                              display: &DisplayTransform,
+                              metadata: &Metadata)
+                              -> io::Result<()> {
+    if alpha.len() != framebuffer.pixels.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("there are {} alpha values for {} pixels",
+                                          alpha.len(),
+                                          framebuffer.pixels.len())));
+    }
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let straight = || {
+        Framebuffer {
+            width,
+            height,
+            pixels: framebuffer.pixels
+                .iter()
+                .zip(alpha)
+                .map(|(c, &a)| if a > 0.0 { *c / a } else { Color::black() })
+                .collect(),
+        }
+    };
+    match format {
+        OutputFormat::Png8 => {
+            let bytes: Vec<u8> = straight().to_rgb8(display)
+                .chunks(3)
+                .zip(alpha)
+                .flat_map(|(rgb, &a)| [rgb[0], rgb[1], rgb[2], (255.99 * a.clamp(0.0, 1.0)) as u8])
+                .collect();
+            write_png(path, width, height, png::ColorType::Rgba, png::BitDepth::Eight, &bytes, metadata)
+        }
+        OutputFormat::Png16 => {
+            let bytes: Vec<u8> = straight().to_rgb16(display)
+                .chunks(3)
+                .zip(alpha)
+                .flat_map(|(rgb, &a)| [rgb[0], rgb[1], rgb[2], (65535.99 * a.clamp(0.0, 1.0)) as u16])
+                .flat_map(|v| v.to_be_bytes())
+                .collect();
+            write_png(path, width, height, png::ColorType::Rgba, png::BitDepth::Sixteen, &bytes, metadata)
+        }
+        OutputFormat::Exr(precision) => {
+            let mut channels = framebuffer_channels(&framebuffer.exposed(display), "");
+            channels.push(ExrChannel {
+                name: "A".to_string(),
+                values: alpha.to_vec(),
+            });
+            write_exr(path, width, height, &channels, precision, metadata)
+        }
+        OutputFormat::Ppm | OutputFormat::Jpeg(_) | OutputFormat::Hdr => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput,
+                               format!("{} images have no alpha channel (write a PNG or EXR instead)",
+                                       format.extension().to_uppercase())))
+        }
+    }
+}
+
+// Write RGB (or RGBA) values of the given depth as a PNG, with the metadata
+// as (UTF-8) text chunks
+fn write_png(path: &Path,
+             width: u32,
+             height: u32,
+             color: png::ColorType,
+             depth: png::BitDepth,
+             bytes: &[u8],
+             metadata: &Metadata)
+             -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    for (key, value) in &metadata.entries {
+        encoder.add_itxt_chunk(key.clone(), value.clone()).map_err(io::Error::other)?;
+    }
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(bytes).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+// Write one of a film's AOVs to its own file: data formats (EXR and HDR)
+// store the raw values, while display formats remap normals from [-1, 1] to
+// [0, 1], depths to [0, 1] relative to the farthest surface, and positions to
+// [0, 1] across the image's bounds. EXR depths and positions are always 32-bit
+// (half floats can't tell apart surfaces far from the origin), with depths in
+// a single Z channel, as compositors expect
+pub fn write_aov(film: &Film, aov: Aov, path: &Path, format: OutputFormat) -> io::Result<()> {
+    let mut framebuffer = film.aov_framebuffer(aov);
+    match format {
+        OutputFormat::Exr(_) if aov == Aov::ObjectId || aov == Aov::MaterialId => {
+            return write_exr(path, film.width, film.height, &id_channels(film, aov), ExrPrecision::Full, &Metadata::new());
+        }
+        OutputFormat::Exr(_) if aov == Aov::Depth => {
+            let depth = ExrChannel {
+                name: "Z".to_string(),
+                values: framebuffer.pixels.iter().map(|p| p.r).collect(),
+            };
+            return write_exr(path, film.width, film.height, &[depth], ExrPrecision::Full, &Metadata::new());
+        }
+        OutputFormat::Exr(_) if aov == Aov::Position => {
+            let channels = framebuffer_channels(&framebuffer, "");
+            return write_exr(path, film.width, film.height, &channels, ExrPrecision::Full, &Metadata::new());
+        }
+        OutputFormat::Exr(_) | OutputFormat::Hdr => {
+            return write_image(&framebuffer, path, format, &DisplayTransform::raw());
+        }
+        _ => (),
+    }
+
+    let display = match aov {
+        Aov::Normal => {
+            for p in &mut framebuffer.pixels {
+                *p = *p * 0.5 + Color::gray(0.5);
+            }
+            DisplayTransform::raw()
+        }
+        Aov::Depth => {
+            let far = framebuffer.pixels.iter().fold(0.0, |far: Float, p| far.max(p.r));
+            if far > 0.0 {
+                for p in &mut framebuffer.pixels {
+                    *p /= far;
+                }
+            }
+            DisplayTransform::raw()
+        }
+        Aov::Position => {
+            let (mut low, mut high) = (Color::gray(Float::MAX), Color::gray(-Float::MAX));
+            for p in &framebuffer.pixels {
+                low = Color::new(low.r.min(p.r), low.g.min(p.g), low.b.min(p.b));
+                high = Color::new(high.r.max(p.r), high.g.max(p.g), high.b.max(p.b));
+            }
+            let extent = high - low;
+            let scale = Color::new(1.0 / extent.r.max(1e-6), 1.0 / extent.g.max(1e-6), 1.0 / extent.b.max(1e-6));
+            for p in &mut framebuffer.pixels {
+                *p = (*p - low) * scale;
+            }
+            DisplayTransform::raw()
+        }
+        // Albedos are colors, so they're displayed like the beauty image
+        Aov::Albedo => DisplayTransform::default(),
+        Aov::ObjectId | Aov::MaterialId | Aov::Alpha => DisplayTransform::raw(),
+    };
+    write_image(&framebuffer, path, format, &display)
+}
+
+// The channels of an EXR ID pass: for each rank, the ID seen by the pixel's
+// samples (stored exactly, as 32-bit floats) and the fraction of samples
+// that saw it, from most to least common
+fn id_channels(film: &Film, aov: Aov) -> Vec<ExrChannel> {
+    let mut channels = Vec::new();
+    for rank in 0..ID_RANKS {
+        let ranks: Vec<(Float, Float)> = film.pixels
+            .iter()
+            .map(|p| {
+                let ids = if aov == Aov::ObjectId { &p.object_ids } else { &p.material_ids };
+                let (id, count) = ids.ranks[rank];
+                (id as Float, count as Float / p.aov_count.max(1) as Float)
+            })
+            .collect();
+        channels.push(ExrChannel {
+            name: format!("{}.id{}", aov.name(), rank),
+            values: ranks.iter().map(|r| r.0).collect(),
+        });
+        channels.push(ExrChannel {
+            name: format!("{}.coverage{}", aov.name(), rank),
+            values: ranks.iter().map(|r| r.1).collect(),
+        });
+    }
+    channels
+}
+
+// Encode a color with a shared exponent: each channel stores an 8-bit
+// mantissa, while the fourth byte stores the exponent of the largest channel
+fn to_rgbe(c: &Color) -> [u8; 4] {
+    let v = c.max_channel();
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // Find the exponent e such that v = m * 2^e, with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / Float::powi(2.0, e) >= 1.0 {
+        e += 1;
+    } else if v / Float::powi(2.0, e) < 0.5 {
+        e -= 1;
+    }
+    let e = e.clamp(-128, 127);
+    let scale = 256.0 / Float::powi(2.0, e);
+    [(c.r.max(0.0) * scale).min(255.0) as u8,
+     (c.g.max(0.0) * scale).min(255.0) as u8,
+     (c.b.max(0.0) * scale).min(255.0) as u8,
+     (e + 128) as u8]
+}
+
+// Run-length encode one channel of a scanline
+fn write_rle_channel(values: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 3;
+    let mut i = 0;
+    while i < values.len() {
+        // Find the next run of identical values
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = 1;
+            while run_start + run_length < values.len() && run_length < 127 &&
+                  values[run_start + run_length] == values[run_start] {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        // Write everything before the run literally (in chunks of 128)
+        while i < run_start {
+            let count = (run_start - i).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&values[i..i + count]);
+            i += count;
+        }
+
+        if run_start < values.len() && run_length >= MIN_RUN {
+            out.push(128 + run_length as u8);
+            out.push(values[run_start]);
+            i = run_start + run_length;
+        }
+    }
+}
+
+// Write a framebuffer as a run-length encoded Radiance RGBE (.hdr) file, with
+// the metadata as comments in its header (the first line of each value
+// following its key, and the rest indented beneath it)
+pub fn write_hdr(framebuffer: &Framebuffer, path: &Path, metadata: &Metadata) -> io::Result<()> {
+    let (width, height) = (framebuffer.width as usize, framebuffer.height as usize);
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(b"#?RADIANCE\n");
+    for (key, value) in &metadata.entries {
+        let mut lines = value.trim_end().lines();
+        bytes.extend_from_slice(format!("# {}: {}\n", key, lines.next().unwrap_or("")).as_bytes());
+        for line in lines {
+            bytes.extend_from_slice(format!("#     {}\n", line).trim_end().as_bytes());
+            bytes.push(b'\n');
+        }
+    }
+    bytes.extend_from_slice(b"FORMAT=32-bit_rle_rgbe\n\n");
+    bytes.extend_from_slice(format!("-Y {} +X {}\n", height, width).as_bytes());
+
+    for row in framebuffer.pixels.chunks(width) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(to_rgbe).collect();
+
+        // Run-length encoding is only defined for "reasonable" widths
+        if !(8..32768).contains(&width) {
+            for pixel in &rgbe {
+                bytes.extend_from_slice(pixel);
+            }
+            continue;
+        }
+
+        bytes.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+        for channel in 0..4 {
+            let values: Vec<u8> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+            write_rle_channel(&values, &mut bytes);
+        }
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&bytes)
+}
+
+// A single channel of floating point data, e.g. the red channel of an image
+pub struct ExrChannel {
+    pub name: String,
+    pub values: Vec<Float>,
+}
+
+// Split a framebuffer into its R, G, and B channels, with the channel names
+// optionally qualified by a layer name (e.g. "normal.R")
+pub fn framebuffer_channels(framebuffer: &Framebuffer, layer: &str) -> Vec<ExrChannel> {
+    let prefix = if layer.is_empty() { String::new() } else { format!("{}.", layer) };
+    vec![
+        ExrChannel {
+            name: format!("{}R", prefix),
+            values: framebuffer.pixels.iter().map(|p| p.r).collect(),
+        },
+        ExrChannel {
+            name: format!("{}G", prefix),
+            values: framebuffer.pixels.iter().map(|p| p.g).collect(),
+        },
+        ExrChannel {
+            name: format!("{}B", prefix),
+            values: framebuffer.pixels.iter().map(|p| p.b).collect(),
+        },
+    ]
+}
+
+// Write any number of named channels (each with one value per pixel, in
+// row-major order) to a single-layer EXR file, with the metadata as text
+// attributes of its header
+pub fn write_exr(path: &Path,
+                 width: u32,
+                 height: u32,
+                 channels: &[ExrChannel],
+                 precision: ExrPrecision,
+                 metadata: &Metadata)
+                 -> io::Result<()> {
+    let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = channels.iter()
+        .map(|channel| {
+            let samples = match precision {
+                ExrPrecision::Half => {
+                    FlatSamples::F16(channel.values.iter().map(|&v| f16::from_f64(v as f64)).collect())
+                }
+                ExrPrecision::Full => {
+                    FlatSamples::F32(channel.values.iter().map(|&v| v as f32).collect())
+                }
+            };
+            AnyChannel::new(channel.name.as_str(), samples)
+        })
+        .collect();
+
+    let mut attributes = LayerAttributes::default();
+    for (key, value) in &metadata.entries {
+        // (EXR has a standard attribute for the software)
+        match key.as_str() {
+            "software" => attributes.software_name = Some(Text::from(value.as_str())),
+            _ => {
+                attributes.other.insert(Text::from(key.as_str()), AttributeValue::Text(Text::from(value.as_str())));
+            }
+        }
+    }
+    let layer = Layer::new((width as usize, height as usize),
+                           attributes,
+                           Encoding::SMALL_LOSSLESS,
+                           AnyChannels::sort(channels));
+    ExrImage::from_layer(layer).write().to_file(path).map_err(io::Error::other)
+}
+
+#[test]
+fn test_exr_round_trip() {
+    use exr::prelude::read_first_flat_layer_from_file;
+
+    let mut framebuffer = Framebuffer::new(2, 2);
+    framebuffer.set(1, 0, &Color::new(4.0, 0.5, 0.25));
+    let path = ::std::env::temp_dir().join("tracer_test_exr_round_trip.exr");
+    write_image(&framebuffer, &path, OutputFormat::Exr(ExrPrecision::Full), &DisplayTransform::default())
+        .unwrap();
+
+    let image = read_first_flat_layer_from_file(&path).unwrap();
+    let red = image.layer_data.channel_data.list.iter().find(|c| c.name == *"R").unwrap();
+    assert_eq!(red.sample_data.value_by_flat_index(1).to_f32(), 4.0);
+}
+
+#[test]
+fn test_write_image_with_alpha() {
+    use exr::prelude::read_first_flat_layer_from_file;
+    use std::env;
+
+    // A half-covered pixel beside an empty one: the PNG stores the pixel's
+    // own color, and the EXR its premultiplied radiance
+    let mut framebuffer = Framebuffer::new(2, 1);
+    framebuffer.set(0, 0, &Color::new(0.5, 0.25, 0.0));
+    let alpha = [0.5, 0.0];
+    let path = env::temp_dir().join("tracer_test_alpha.png");
+    write_image_with_alpha(&framebuffer, &alpha, &path, OutputFormat::Png8, &DisplayTransform::raw(), &Metadata::new())
+        .unwrap();
+    let png = image::open(&path).unwrap().to_rgba8();
+    assert_eq!((png.get_pixel(0, 0).0, png.get_pixel(1, 0).0), ([255, 127, 0, 127], [0, 0, 0, 0]));
+
+    let path = env::temp_dir().join("tracer_test_alpha.exr");
+    let format = OutputFormat::Exr(ExrPrecision::Full);
+    write_image_with_alpha(&framebuffer, &alpha, &path, format, &DisplayTransform::raw(), &Metadata::new()).unwrap();
+    let image = read_first_flat_layer_from_file(&path).unwrap();
+    let channel = |name: &str| image.layer_data.channel_data.list.iter().find(|c| c.name == *name).unwrap();
+    assert_eq!(channel("R").sample_data.value_by_flat_index(0).to_f32(), 0.5);
+    assert_eq!(channel("A").sample_data.value_by_flat_index(0).to_f32(), 0.5);
+
+    let path = env::temp_dir().join("tracer_test_alpha.jpg");
+    let format = OutputFormat::Jpeg(90);
+    assert!(write_image_with_alpha(&framebuffer, &alpha, &path, format, &DisplayTransform::raw(), &Metadata::new()).is_err());
+
+    let path = env::temp_dir().join("tracer_test_alpha_mismatch.png");
+    let format = OutputFormat::Png8;
+    let error = write_image_with_alpha(&framebuffer, &alpha[..1], &path, format, &DisplayTransform::raw(), &Metadata::new())
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_depth_and_position_aovs() {
+    use exr::prelude::read_first_flat_layer_from_file;
+    use film::AovSample;
+    use vector::Vector;
+
+    // Depths and positions far from the origin survive at full precision
+    let mut film = Film::new(2, 1);
+    let mut aovs = AovSample::new();
+    aovs.depth = 1000.125;
+    aovs.position = Vector::new(4096.5, -0.25, 3.0);
+    film.pixel_mut(1, 0).add_aov_sample(&aovs);
+    let path = ::std::env::temp_dir().join("tracer_test_depth_and_position_aovs.exr");
+    let value = |channel: &str, i: usize| {
+        let image = read_first_flat_layer_from_file(&path).unwrap();
+        let names: Vec<_> = image.layer_data.channel_data.list.iter().map(|c| c.name.to_string()).collect();
+        let channel = image.layer_data.channel_data.list.iter().find(|c| c.name == *channel);
+        (channel.map(|c| c.sample_data.value_by_flat_index(i).to_f32()), names)
+    };
+
+    write_aov(&film, Aov::Depth, &path, OutputFormat::Exr(ExrPrecision::Half)).unwrap();
+    assert_eq!(value("Z", 1), (Some(1000.125), vec!["Z".to_string()]));
+    write_aov(&film, Aov::Position, &path, OutputFormat::Exr(ExrPrecision::Half)).unwrap();
+    assert_eq!(value("R", 1).0, Some(4096.5));
+    assert_eq!(value("G", 1).0, Some(-0.25));
+    assert_eq!(value("B", 0).0, Some(0.0));
+}
+
+#[test]
+fn test_metadata() {
+    use exr::meta::MetaData;
+    use std::io::BufReader;
+    use std::env;
+
+    // The metadata can be read back from each format that holds it
+    let mut metadata = Metadata::new();
+    metadata.add("seed", 7);
+    metadata.add("scene", "scènes/spheres.toml");
+    let framebuffer = Framebuffer::new(4, 2);
+    let path = env::temp_dir().join("tracer_test_metadata.png");
+    for &format in &[OutputFormat::Png8, OutputFormat::Png16] {
+        write_image_with_metadata(&framebuffer, &path, format, &DisplayTransform::default(), &metadata).unwrap();
+        let reader = png::Decoder::new(BufReader::new(File::open(&path).unwrap())).read_info().unwrap();
+        let text: Vec<_> =
+            reader.info().utf8_text.iter().map(|t| (t.keyword.clone(), t.get_text().unwrap())).collect();
+        assert_eq!(text, metadata.entries);
+    }
+
+    let path = env::temp_dir().join("tracer_test_metadata.exr");
+    write_image_with_metadata(&framebuffer, &path, OutputFormat::Exr(ExrPrecision::Half), &DisplayTransform::default(), &metadata)
+        .unwrap();
+    let header = MetaData::read_from_file(&path, false).unwrap().headers.remove(0);
+    let attribute = |key: &str| match header.own_attributes.other.get(&Text::from(key)) {
+        Some(AttributeValue::Text(text)) => Some(text.to_string()),
+        _ => None,
+    };
+    assert_eq!(attribute("seed"), Some("7".to_string()));
+    assert!(header.own_attributes.software_name.as_ref().unwrap().to_string().starts_with("tracer-rs"));
+
+    let path = env::temp_dir().join("tracer_test_metadata.hdr");
+    write_image_with_metadata(&framebuffer, &path, OutputFormat::Hdr, &DisplayTransform::default(), &metadata).unwrap();
+    let source = String::from_utf8_lossy(&::std::fs::read(&path).unwrap()).into_owned();
+    assert!(source.starts_with("#?RADIANCE\n# software: ") && source.contains("\n# seed: 7\n# scene: scènes/spheres.toml\nFORMAT="));
+}
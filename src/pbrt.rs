@@ -0,0 +1,832 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use vector::consts;
+use matrix::Matrix;
+use shape::Sphere;
+use shape::Triangle;
+use mesh::Mesh;
+use material::Material;
+use material::Lambertian;
+use material::TexturedLambertian;
+use material::Metallic;
+use material::Dielectric;
+use material::ThinDielectric;
+use material::conductor_reflectance;
+use primitive::Primitive;
+use scene::Scene;
+use camera::Camera;
+use sampler::SamplerType;
+use filter::FilterType;
+use texture::ImageTexture;
+use texture::TextureCache;
+use scene_file::SettingsDescription;
+use scene_file::ImportedScene;
+use ply;
+use error::Result;
+use error::TracerError;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Load a PBRT (v3, or the similar parts of v4) scene file (with any files that it includes, which are
+// relative to it), for images of the given aspect ratio
+pub fn load(path: &Path, aspect_ratio: Float, textures: &Arc<TextureCache>) -> Result<ImportedScene> {
+    let directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    parse(&fs::read_to_string(path)?, &directory, aspect_ratio, textures)
+}
+
+pub fn parse(source: &str,
+             directory: &Path,
+             aspect_ratio: Float,
+             textures: &Arc<TextureCache>)
+             -> Result<ImportedScene> {
+    let mut parser = Parser::new(tokenize(source)?, directory, aspect_ratio, textures);
+    parser.parse()?;
+    parser.result.scene.build_bvh();
+    Ok(parser.result)
+}
+
+fn invalid(why: String) -> TracerError {
+    TracerError::Parse(why)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    // A directive (or a bare `true` or `false`)
+    Word(String),
+    Str(String),
+    Number(Float),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '[' | ']' => {
+                chars.next();
+                tokens.push(if c == '[' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => s.extend(chars.next()),
+                        Some(c) => s.push(c),
+                        None => return Err(invalid("a string never ends".to_string())),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if word.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+                    let number = word.parse().map_err(|_| invalid(format!("{:?} isn't a number", word)))?;
+                    tokens.push(Token::Number(number));
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Numbers(Vec<Float>),
+    Strings(Vec<String>),
+    Bools(Vec<bool>),
+}
+
+// The parameters that follow a directive, e.g. `"float radius" [ 2 ]`, by
+// name (along with their types)
+#[derive(Clone, Debug, Default)]
+struct Parameters(HashMap<String, (String, Value)>);
+
+impl Parameters {
+    fn numbers(&self, name: &str) -> Option<&[Float]> {
+        match self.0.get(name) {
+            Some(&(_, Value::Numbers(ref numbers))) => Some(numbers),
+            _ => None,
+        }
+    }
+
+    fn float(&self, name: &str, default: Float) -> Float {
+        self.numbers(name).and_then(|n| n.first().cloned()).unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.0.get(name) {
+            Some(&(_, Value::Strings(ref strings))) => strings.first().map(|s| s.as_str()),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> bool {
+        match self.0.get(name) {
+            Some(&(_, Value::Bools(ref bools))) => bools.first().cloned().unwrap_or(default),
+            _ => default,
+        }
+    }
+
+    fn kind(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|p| p.0.as_str())
+    }
+}
+
+// A shape as it was given, in its own space (so that objects can be
+// instanced with further transformations)
+#[derive(Clone)]
+enum LocalShape {
+    Sphere(Float),
+    Mesh(Arc<Mesh>),
+}
+
+#[derive(Clone)]
+struct ShapeRecord {
+    shape: LocalShape,
+    matrix: Matrix,
+    material: Arc<dyn Material>,
+    reverse_orientation: bool,
+}
+
+impl ShapeRecord {
+    // A primitive of the shape, moved into the world by `instance`
+    fn primitive(&self, instance: &Matrix) -> Primitive {
+        let matrix = instance.mul(&self.matrix);
+        match self.shape {
+            LocalShape::Sphere(radius) => {
+                // (Spheres may only be scaled uniformly)
+                let scale = matrix.determinant().abs().cbrt();
+                Primitive::new(Sphere::new(&matrix.point(&Vector::zero()), radius * scale),
+                               self.material.clone())
+            }
+            LocalShape::Mesh(ref mesh) => {
+                Primitive::new(mesh.transformed(&matrix, self.reverse_orientation), self.material.clone())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum TextureValue {
+    Image(PathBuf),
+    Constant(Color),
+}
+
+// The state restored by `AttributeEnd`
+#[derive(Clone)]
+struct Attributes {
+    ctm: Matrix,
+    material: Option<Arc<dyn Material>>,
+    reverse_orientation: bool,
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    directory: PathBuf,
+    aspect_ratio: Float,
+    textures: &'a Arc<TextureCache>,
+    // The current transformation matrix, and the shapes' current material
+    // (where `None` is PBRT's "interface", which hides the shapes)
+    attributes: Attributes,
+    attribute_stack: Vec<Attributes>,
+    transform_stack: Vec<Matrix>,
+    // Whether transformations only apply at the end of the shutter interval
+    // (which is ignored, since there is no motion blur)
+    end_time_only: bool,
+    coordinate_systems: HashMap<String, Matrix>,
+    named_materials: HashMap<String, Option<Arc<dyn Material>>>,
+    named_textures: HashMap<String, TextureValue>,
+    objects: HashMap<String, Vec<ShapeRecord>>,
+    // The object being defined (between `ObjectBegin` and `ObjectEnd`)
+    object: Option<(String, Vec<ShapeRecord>)>,
+    result: ImportedScene,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: Vec<Token>, directory: &Path, aspect_ratio: Float, textures: &'a Arc<TextureCache>) -> Parser<'a> {
+        Parser {
+            tokens,
+            position: 0,
+            directory: directory.to_path_buf(),
+            aspect_ratio,
+            textures,
+            attributes: Attributes {
+                ctm: Matrix::identity(),
+                material: Some(Arc::new(Lambertian::new(&Color::gray(0.5)))),
+                reverse_orientation: false,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            end_time_only: false,
+            coordinate_systems: HashMap::new(),
+            named_materials: HashMap::new(),
+            named_textures: HashMap::new(),
+            objects: HashMap::new(),
+            object: None,
+            result: ImportedScene {
+                scene: Scene::new(),
+                settings: SettingsDescription::default(),
+                warnings: Vec::new(),
+            },
+        }
+    }
+
+    fn warn(&mut self, warning: String) {
+        if !self.result.warnings.contains(&warning) {
+            self.result.warnings.push(warning);
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn string(&mut self, directive: &str) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            _ => Err(invalid(format!("{} expects a string", directive))),
+        }
+    }
+
+    // `count` numbers, which may be in brackets
+    fn numbers(&mut self, directive: &str, count: usize) -> Result<Vec<Float>> {
+        let bracketed = self.peek() == Some(&Token::Open);
+        if bracketed {
+            self.next();
+        }
+        let mut numbers = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.next() {
+                Some(Token::Number(x)) => numbers.push(x),
+                _ => return Err(invalid(format!("{} expects {} numbers", directive, count))),
+            }
+        }
+        if bracketed && self.next() != Some(Token::Close) {
+            return Err(invalid(format!("{} expects {} numbers", directive, count)));
+        }
+        Ok(numbers)
+    }
+
+    fn vector(&mut self, directive: &str) -> Result<Vector> {
+        let v = self.numbers(directive, 3)?;
+        Ok(Vector::new(v[0], v[1], v[2]))
+    }
+
+    fn parameters(&mut self) -> Result<Parameters> {
+        let mut parameters = Parameters::default();
+        while let Some(Token::Str(declaration)) = self.peek() {
+            let declaration = declaration.clone();
+            let words: Vec<&str> = declaration.split_whitespace().collect();
+            let (kind, name) = match words.as_slice() {
+                [kind, name] => (kind.to_string(), name.to_string()),
+                _ => return Err(invalid(format!("{:?} isn't a parameter", declaration))),
+            };
+            self.next();
+
+            let mut tokens = Vec::new();
+            match self.next() {
+                Some(Token::Open) => {
+                    loop {
+                        match self.next() {
+                            Some(Token::Close) => break,
+                            Some(token) => tokens.push(token),
+                            None => return Err(invalid(format!("the values of {:?} never end", name))),
+                        }
+                    }
+                }
+                Some(token) => tokens.push(token),
+                None => return Err(invalid(format!("{:?} has no value", name))),
+            }
+            let value = match tokens.first() {
+                Some(&Token::Str(_)) => {
+                    Value::Strings(tokens.into_iter()
+                        .filter_map(|t| match t {
+                            Token::Str(s) => Some(s),
+                            _ => None,
+                        })
+                        .collect())
+                }
+                Some(&Token::Word(_)) => Value::Bools(tokens.iter().map(|t| *t == Token::Word("true".to_string())).collect()),
+                _ => {
+                    Value::Numbers(tokens.into_iter()
+                        .filter_map(|t| match t {
+                            Token::Number(x) => Some(x),
+                            _ => None,
+                        })
+                        .collect())
+                }
+            };
+            parameters.0.insert(name, (kind, value));
+        }
+        Ok(parameters)
+    }
+
+    // Skip an unsupported directive's arguments, up to the next directive
+    fn skip(&mut self) {
+        while let Some(token) = self.peek() {
+            if let Token::Word(ref w) = *token {
+                if w.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    break;
+                }
+            }
+            self.position += 1;
+        }
+    }
+
+    fn transform(&mut self, m: Matrix) {
+        if !self.end_time_only {
+            self.attributes.ctm = self.attributes.ctm.mul(&m);
+        }
+    }
+
+    fn parse(&mut self) -> Result<()> {
+        while let Some(token) = self.next() {
+            let directive = match token {
+                Token::Word(directive) => directive,
+                token => return Err(invalid(format!("expected a directive, but found {:?}", token))),
+            };
+            match directive.as_str() {
+                "Identity" => {
+                    if !self.end_time_only {
+                        self.attributes.ctm = Matrix::identity();
+                    }
+                }
+                "Translate" => {
+                    let t = self.vector(&directive)?;
+                    self.transform(Matrix::translate(&t));
+                }
+                "Scale" => {
+                    let s = self.vector(&directive)?;
+                    self.transform(Matrix::scale(&s));
+                }
+                "Rotate" => {
+                    let v = self.numbers(&directive, 4)?;
+                    self.transform(Matrix::rotate(v[0], &Vector::new(v[1], v[2], v[3])));
+                }
+                "LookAt" => {
+                    let v = self.numbers(&directive, 9)?;
+                    let m = Matrix::look_at(&Vector::new(v[0], v[1], v[2]),
+                                            &Vector::new(v[3], v[4], v[5]),
+                                            &Vector::new(v[6], v[7], v[8]))
+                        .and_then(|m| m.inverse())
+                        .ok_or_else(|| invalid("LookAt's up vector is along its view direction".to_string()))?;
+                    self.transform(m);
+                }
+                "Transform" | "ConcatTransform" => {
+                    let m = Matrix::from_rows(&self.numbers(&directive, 16)?).transpose();
+                    if directive == "Transform" && !self.end_time_only {
+                        self.attributes.ctm = m;
+                    } else {
+                        self.transform(m);
+                    }
+                }
+                "CoordinateSystem" => {
+                    let name = self.string(&directive)?;
+                    self.coordinate_systems.insert(name, self.attributes.ctm);
+                }
+                "CoordSysTransform" => {
+                    let name = self.string(&directive)?;
+                    match self.coordinate_systems.get(&name) {
+                        Some(&m) => self.attributes.ctm = m,
+                        None => self.warn(format!("ignoring the unknown coordinate system {:?}", name)),
+                    }
+                }
+                "ActiveTransform" => {
+                    match self.next() {
+                        Some(Token::Word(ref time)) if time == "EndTime" => {
+                            self.warn("ignoring motion blur".to_string());
+                            self.end_time_only = true;
+                        }
+                        Some(Token::Word(_)) => self.end_time_only = false,
+                        _ => return Err(invalid("ActiveTransform expects a time".to_string())),
+                    }
+                }
+                "ReverseOrientation" => self.attributes.reverse_orientation = !self.attributes.reverse_orientation,
+                "AttributeBegin" => self.attribute_stack.push(self.attributes.clone()),
+                "AttributeEnd" => {
+                    self.attributes = self.attribute_stack
+                        .pop()
+                        .ok_or_else(|| invalid("AttributeEnd without AttributeBegin".to_string()))?;
+                }
+                "TransformBegin" => self.transform_stack.push(self.attributes.ctm),
+                "TransformEnd" => {
+                    self.attributes.ctm = self.transform_stack
+                        .pop()
+                        .ok_or_else(|| invalid("TransformEnd without TransformBegin".to_string()))?;
+                }
+                "Camera" => {
+                    let kind = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    self.camera(&kind, &parameters)?;
+                }
+                "Sampler" => {
+                    let kind = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    let samples = match parameters.numbers("xsamples") {
+                        Some(_) => parameters.float("xsamples", 4.0) * parameters.float("ysamples", 4.0),
+                        None => parameters.float("pixelsamples", 16.0),
+                    };
+                    self.result.settings.samples = Some(samples as u32);
+                    self.result.settings.sampler = Some(match kind.as_str() {
+                        "random" => SamplerType::Random,
+                        "halton" => SamplerType::Halton,
+                        _ => SamplerType::Sobol,
+                    });
+                }
+                "Integrator" => {
+                    self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    self.result.settings.max_depth = Some(parameters.float("maxdepth", 5.0) as u32);
+                }
+                "PixelFilter" => {
+                    let kind = self.string(&directive)?;
+                    self.parameters()?;
+                    let filter = match kind.as_str() {
+                        "box" => FilterType::Box,
+                        "triangle" => FilterType::Tent,
+                        "gaussian" => FilterType::Gaussian,
+                        "mitchell" => FilterType::Mitchell,
+                        _ => {
+                            self.warn(format!("using a Mitchell filter instead of the {:?} filter", kind));
+                            FilterType::Mitchell
+                        }
+                    };
+                    self.result.settings.filter = Some(filter);
+                }
+                "WorldBegin" => {
+                    self.attributes.ctm = Matrix::identity();
+                    self.coordinate_systems.insert("world".to_string(), Matrix::identity());
+                }
+                "Texture" => {
+                    let name = self.string(&directive)?;
+                    let kind = self.string(&directive)?;
+                    let class = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    if kind == "float" {
+                        continue;
+                    }
+                    let texture = match class.as_str() {
+                        "imagemap" => {
+                            parameters.string("filename").map(|filename| TextureValue::Image(self.directory.join(filename)))
+                        }
+                        "constant" => Some(TextureValue::Constant(self.color(&parameters, "value", 1.0))),
+                        _ => None,
+                    };
+                    match texture {
+                        Some(texture) => {
+                            self.named_textures.insert(name, texture);
+                        }
+                        None => self.warn(format!("ignoring {:?} textures", class)),
+                    }
+                }
+                "Material" => {
+                    let kind = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    self.attributes.material = self.material(&kind, &parameters);
+                }
+                "MakeNamedMaterial" => {
+                    let name = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    let kind = parameters.string("type").unwrap_or("").to_string();
+                    let material = self.material(&kind, &parameters);
+                    self.named_materials.insert(name, material);
+                }
+                "NamedMaterial" => {
+                    let name = self.string(&directive)?;
+                    self.attributes.material = self.named_materials
+                        .get(&name)
+                        .cloned()
+                        .ok_or_else(|| invalid(format!("no material named {:?}", name)))?;
+                }
+                "Shape" => {
+                    let kind = self.string(&directive)?;
+                    let parameters = self.parameters()?;
+                    self.shape(&kind, &parameters)?;
+                }
+                "ObjectBegin" => {
+                    let name = self.string(&directive)?;
+                    self.attribute_stack.push(self.attributes.clone());
+                    self.object = Some((name, Vec::new()));
+                }
+                "ObjectEnd" => {
+                    let (name, shapes) = self.object
+                        .take()
+                        .ok_or_else(|| invalid("ObjectEnd without ObjectBegin".to_string()))?;
+                    self.objects.insert(name, shapes);
+                    self.attributes = self.attribute_stack
+                        .pop()
+                        .ok_or_else(|| invalid("ObjectEnd without ObjectBegin".to_string()))?;
+                }
+                "ObjectInstance" => {
+                    let name = self.string(&directive)?;
+                    let shapes = self.objects.get(&name).ok_or_else(|| invalid(format!("no object named {:?}", name)))?;
+                    for shape in shapes {
+                        self.result.scene.add(shape.primitive(&self.attributes.ctm));
+                    }
+                }
+                "Include" | "Import" => {
+                    let filename = self.string(&directive)?;
+                    let path = self.directory.join(filename);
+                    let source = fs::read_to_string(&path)
+                        .map_err(|why| invalid(format!("couldn't include {}: {}", path.display(), why)))?;
+                    let tokens = tokenize(&source)?;
+                    self.tokens.splice(self.position..self.position, tokens);
+                }
+                "LightSource" | "AreaLightSource" => {
+                    self.skip();
+                    self.warn("ignoring lights (scenes are lit by the sky)".to_string());
+                }
+                // (Settings that don't apply to this renderer)
+                "Film" | "Accelerator" | "ColorSpace" | "Option" | "TransformTimes" | "WorldEnd" => self.skip(),
+                _ => {
+                    self.skip();
+                    self.warn(format!("ignoring the unsupported directive {}", directive));
+                }
+            }
+        }
+        if self.object.is_some() {
+            return Err(invalid("ObjectBegin without ObjectEnd".to_string()));
+        }
+        Ok(())
+    }
+
+    // PBRT's (perspective) camera looks down the z axis of camera space,
+    // which the current transformation matrix maps the world into
+    fn camera(&mut self, kind: &str, parameters: &Parameters) -> Result<()> {
+        if kind != "perspective" {
+            self.warn(format!("using a perspective camera instead of the {:?} camera", kind));
+        }
+        let camera_to_world = self.attributes
+            .ctm
+            .inverse()
+            .ok_or_else(|| invalid("the camera's transformation can't be inverted".to_string()))?;
+        self.coordinate_systems.insert("camera".to_string(), camera_to_world);
+
+        let from = camera_to_world.point(&Vector::zero());
+        let direction = camera_to_world.vector(&Vector::new(0.0, 0.0, 1.0)).normalize();
+        let up = camera_to_world.vector(&Vector::new(0.0, 1.0, 0.0));
+        let right = camera_to_world.vector(&Vector::new(1.0, 0.0, 0.0));
+
+        // The field of view is that of the image's shorter edge
+        let fov = parameters.float("fov", 90.0);
+        let fov = if self.aspect_ratio >= 1.0 {
+            fov
+        } else {
+            2.0 * ((fov * (consts::PI / 360.0)).tan() / self.aspect_ratio).atan() * (180.0 / consts::PI)
+        };
+        let camera = Camera::look_at(&from, &(from + direction), &up, fov, self.aspect_ratio);
+
+        // PBRT's camera space is left-handed, so that its images are mirrored
+        // (unless the transformation swaps handedness, as many scenes do)
+        let camera = if up.cross(&-direction).dot(&right) < 0.0 {
+            camera.mirrored()
+        } else {
+            camera
+        };
+        self.result.scene.add_camera("camera", camera);
+        Ok(())
+    }
+
+    fn color(&mut self, parameters: &Parameters, name: &str, default: Float) -> Color {
+        match (parameters.kind(name), parameters.numbers(name)) {
+            (Some("rgb"), Some(&[r, g, b])) | (Some("color"), Some(&[r, g, b])) => Color::new(r, g, b),
+            (Some("float"), Some(&[x])) => Color::gray(x),
+            (Some(kind), _) => {
+                self.warn(format!("using a default color instead of {:?} colors", kind));
+                Color::gray(default)
+            }
+            (None, _) => Color::gray(default),
+        }
+    }
+
+    // A diffuse material, whose color may be a texture
+    fn diffuse(&mut self, parameters: &Parameters, name: &str, default: Float) -> Arc<dyn Material> {
+        if parameters.kind(name) == Some("texture") {
+            let texture = parameters.string(name).and_then(|t| self.named_textures.get(t)).cloned();
+            match texture {
+                Some(TextureValue::Image(path)) => {
+                    return Arc::new(TexturedLambertian::new(ImageTexture::new(self.textures, &path)));
+                }
+                Some(TextureValue::Constant(color)) => return Arc::new(Lambertian::new(&color)),
+                None => {
+                    self.warn(format!("using a default color for the missing texture {:?}",
+                                      parameters.string(name).unwrap_or("")));
+                    return Arc::new(Lambertian::new(&Color::gray(default)));
+                }
+            }
+        }
+        Arc::new(Lambertian::new(&self.color(parameters, name, default)))
+    }
+
+    // The closest of the renderer's materials to one of PBRT's (v3 or v4)
+    fn material(&mut self, kind: &str, parameters: &Parameters) -> Option<Arc<dyn Material>> {
+        let roughness = |parameters: &Parameters| {
+            let r = parameters.float("roughness",
+                                     0.5 * (parameters.float("uroughness", 0.0) + parameters.float("vroughness", 0.0)));
+            if parameters.bool("remaproughness", true) { r.sqrt() } else { r }
+        };
+        Some(match kind {
+            "" | "none" | "interface" => return None,
+            "matte" => self.diffuse(parameters, "Kd", 0.5),
+            "diffuse" => self.diffuse(parameters, "reflectance", 0.5),
+            "plastic" | "substrate" | "uber" | "coateddiffuse" | "translucent" | "disney" => {
+                self.warn(format!("approximating {:?} materials as diffuse", kind));
+                let name = if kind == "coateddiffuse" { "reflectance" } else { "Kd" };
+                self.diffuse(parameters, name, if kind == "plastic" { 0.25 } else { 0.5 })
+            }
+            "mirror" => Arc::new(Metallic::new(&self.color(parameters, "Kr", 0.9), 0.0)),
+            "metal" | "conductor" => {
+                // The reflectance at normal incidence, given the complex index
+                // of refraction (which defaults to copper's)
+                let eta = match parameters.numbers("eta") {
+                    Some(&[r, g, b]) => Color::new(r, g, b),
+                    _ => Color::new(0.200438, 0.924033, 1.10221),
+                };
+                let k = match parameters.numbers("k") {
+                    Some(&[r, g, b]) => Color::new(r, g, b),
+                    _ => Color::new(3.91295, 2.45285, 2.14219),
+                };
+                let albedo = match parameters.numbers("reflectance") {
+                    Some(_) => self.color(parameters, "reflectance", 1.0),
+                    None => conductor_reflectance(&eta, &k),
+                };
+                Arc::new(Metallic::new(&albedo, roughness(parameters)))
+            }
+            "glass" | "dielectric" => {
+                let ior = parameters.numbers("eta").or_else(|| parameters.numbers("index")).map_or(1.5, |n| n[0]);
+                Arc::new(Dielectric::new(ior))
+            }
+            "thindielectric" => {
+                let ior = parameters.numbers("eta").map_or(1.5, |n| n[0]);
+                Arc::new(ThinDielectric::new(ior, &Color::white()))
+            }
+            _ => {
+                self.warn(format!("using a diffuse material instead of {:?} materials", kind));
+                Arc::new(Lambertian::new(&Color::gray(0.5)))
+            }
+        })
+    }
+
+    fn shape(&mut self, kind: &str, parameters: &Parameters) -> Result<()> {
+        let shape = match kind {
+            "sphere" => LocalShape::Sphere(parameters.float("radius", 1.0)),
+            // (Subdivision surfaces are rendered as their control meshes)
+            "trianglemesh" | "loopsubdiv" => LocalShape::Mesh(Arc::new(triangle_mesh(parameters)?)),
+            "plymesh" => {
+                let filename = parameters.string("filename")
+                    .ok_or_else(|| invalid("a plymesh without a filename".to_string()))?;
+                LocalShape::Mesh(Arc::new(ply::load(&self.directory.join(filename))?))
+            }
+            _ => {
+                self.warn(format!("ignoring {:?} shapes", kind));
+                return Ok(());
+            }
+        };
+        let material = match self.attributes.material {
+            Some(ref material) => material.clone(),
+            None => return Ok(()),
+        };
+        let record = ShapeRecord {
+            shape,
+            matrix: self.attributes.ctm,
+            material,
+            reverse_orientation: self.attributes.reverse_orientation,
+        };
+        match self.object {
+            Some((_, ref mut shapes)) => shapes.push(record),
+            None => self.result.scene.add(record.primitive(&Matrix::identity())),
+        }
+        Ok(())
+    }
+}
+
+// A "trianglemesh" shape's triangles, with their normals and texture
+// coordinates if it has them
+fn triangle_mesh(parameters: &Parameters) -> Result<Mesh> {
+    let positions: Vec<Vector> = parameters.numbers("P")
+        .ok_or_else(|| invalid("a triangle mesh without positions".to_string()))?
+        .chunks_exact(3)
+        .map(|p| Vector::new(p[0], p[1], p[2]))
+        .collect();
+    let indices: Vec<usize> = match parameters.numbers("indices") {
+        Some(indices) => indices.iter().map(|&i| i as usize).collect(),
+        None if positions.len() == 3 => vec![0, 1, 2],
+        None => return Err(invalid("a triangle mesh without indices".to_string())),
+    };
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&i| i >= positions.len()) {
+        return Err(invalid("a triangle mesh's indices don't match its positions".to_string()));
+    }
+    let normals: Option<Vec<Vector>> = parameters.numbers("N")
+        .map(|n| n.chunks_exact(3).map(|n| Vector::new(n[0], n[1], n[2])).collect());
+    let uvs: Option<Vec<(Float, Float)>> = parameters.numbers("uv")
+        .or_else(|| parameters.numbers("st"))
+        .map(|uv| uv.chunks_exact(2).map(|uv| (uv[0], uv[1])).collect());
+
+    let triangles = indices.chunks(3)
+        .map(|i| Triangle::new(&positions[i[0]], &positions[i[1]], &positions[i[2]]))
+        .collect();
+    let mut mesh = Mesh::new(triangles);
+    if let Some(normals) = normals.filter(|n| n.len() == positions.len()) {
+        mesh.normals = Some(indices.chunks(3).map(|i| [normals[i[0]], normals[i[1]], normals[i[2]]]).collect());
+    }
+    if let Some(uvs) = uvs.filter(|uv| uv.len() == positions.len()) {
+        mesh.uvs = Some(indices.chunks(3).map(|i| [uvs[i[0]], uvs[i[1]], uvs[i[2]]]).collect());
+    }
+    Ok(mesh)
+}
+
+#[test]
+fn test_load_pbrt() {
+    use ray::Ray;
+    use vector::TEST_EPSILON;
+
+    let source = r#"
+        LookAt 0 0 0  0 0 -1  0 1 0  # A camera at the origin
+        Camera "perspective" "float fov" [ 45 ]
+        Sampler "halton" "integer pixelsamples" 8
+        Integrator "path" "integer maxdepth" [ 3 ]
+        PixelFilter "triangle"
+        WorldBegin
+        LightSource "infinite" "rgb L" [ 1 1 1 ]
+        MakeNamedMaterial "red" "string type" "matte" "rgb Kd" [ 1 0 0 ]
+        AttributeBegin
+            Translate 0 0 -3
+            NamedMaterial "red"
+            Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        ObjectBegin "floor"
+            Material "mirror"
+            Shape "trianglemesh" "integer indices" [ 0 1 2  0 2 3 ]
+                "point P" [ -1 0 -1  -1 0 1  1 0 1  1 0 -1 ]
+        ObjectEnd
+        AttributeBegin
+            Translate 0 -1 0
+            Scale 10 1 10
+            ObjectInstance "floor"
+        AttributeEnd
+        Shape "disk"
+    "#;
+    let textures = Arc::new(TextureCache::new(0));
+    let pbrt = parse(source, Path::new("."), 1.0, &textures).unwrap();
+    assert_eq!(pbrt.settings.samples, Some(8));
+    assert_eq!(pbrt.settings.max_depth, Some(3));
+    assert_eq!(pbrt.settings.sampler, Some(SamplerType::Halton));
+    assert_eq!(pbrt.settings.filter, Some(FilterType::Tent));
+    assert_eq!(pbrt.warnings.len(), 2);
+
+    let scene = &pbrt.scene;
+    assert_eq!(scene.items.len(), 2);
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 2.5).abs() < TEST_EPSILON);
+    assert_eq!(material.albedo(), Color::new(1.0, 0.0, 0.0));
+
+    // The instanced floor is scaled to reach far from the origin, and faces up
+    let r = Ray::new(&Vector::new(8.0, 0.0, 8.0), &Vector::new(0.0, -1.0, 0.0), 0.001, Float::MAX);
+    let (dg, _) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 1.0).abs() < TEST_EPSILON);
+    assert!(dg.normal.y > 0.0);
+
+    // PBRT's camera is left-handed, so the image's right edge is towards -x
+    let camera = scene.camera("camera").unwrap();
+    assert!(camera.generate_ray(1.0, 0.5).direction.x < 0.0);
+    assert!((camera.generate_ray(0.5, 0.5).direction.normalize() - Vector::new(0.0, 0.0, -1.0)).length() < TEST_EPSILON);
+
+    assert!(parse("AttributeEnd", Path::new("."), 1.0, &textures).is_err());
+    assert!(parse("Shape \"sphere", Path::new("."), 1.0, &textures).is_err());
+}
@@ -0,0 +1,202 @@
+use vector::Vector;
+use vector::Float;
+use vector::consts;
+use rng::Rng;
+use sampling;
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+// A plain description of a phase function, for saving it along with the
+// volume that scatters by it (see: `Volume`)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PhaseData {
+    #[default]
+    Isotropic,
+    HenyeyGreenstein { g: Float },
+    // (`weight` is the fraction of the light that scatters by the first lobe)
+    TwoLobe { g1: Float, g2: Float, weight: Float },
+}
+
+impl PhaseData {
+    pub fn create(&self) -> Arc<dyn PhaseFunction> {
+        match *self {
+            PhaseData::Isotropic => Arc::new(Isotropic),
+            PhaseData::HenyeyGreenstein { g } => Arc::new(HenyeyGreenstein::new(g)),
+            PhaseData::TwoLobe { g1, g2, weight } => {
+                Arc::new(TwoLobe::new(HenyeyGreenstein::new(g1), HenyeyGreenstein::new(g2), weight))
+            }
+        }
+    }
+
+    // What's wrong with the parameters, if anything: asymmetries must lie
+    // strictly between -1 (scattering straight back) and 1 (straight ahead),
+    // where the Henyey-Greenstein function is a spike
+    pub fn problem(&self) -> Option<String> {
+        let asymmetric = |g: Float| g > -1.0 && g < 1.0;
+        match *self {
+            PhaseData::HenyeyGreenstein { g } if !asymmetric(g) => Some(format!("its asymmetry is {}", g)),
+            PhaseData::TwoLobe { g1, g2, .. } if !(asymmetric(g1) && asymmetric(g2)) => {
+                Some(format!("its asymmetries are {} and {}", g1, g2))
+            }
+            PhaseData::TwoLobe { weight, .. } if !(0.0..=1.0).contains(&weight) => {
+                Some(format!("its first lobe's weight is {}", weight))
+            }
+            _ => None,
+        }
+    }
+}
+
+// How the light that a volume scatters is spread over the directions around
+// the point where it scatters, by the angle between the direction that the
+// light was going in and the one that it goes in afterwards: the density of
+// each direction (over the whole sphere of them, which it integrates to one
+// over, since a phase function only redistributes light), and a way to pick
+// directions with that density, so that scattering by it carries no weight
+// of its own (see: `sample`)
+pub trait PhaseFunction: Sync + Send {
+    // The density of scattering by the angle whose cosine is `cos_theta`
+    fn p(&self, cos_theta: Float) -> Float;
+
+    // The cosine of the angle to scatter by, picked with the density `p`,
+    // from a uniformly distributed `u` in [0, 1)
+    fn sample_cos_theta(&self, u: Float) -> Float;
+
+    fn data(&self) -> PhaseData;
+}
+
+// Pick the direction that light going in `direction` scatters into
+pub fn sample(phase: &dyn PhaseFunction, direction: &Vector, rng: &mut impl Rng) -> Vector {
+    let cos_theta = phase.sample_cos_theta(rng.next_float()).clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * rng.next_float();
+    let (s, t) = sampling::basis(direction);
+    (s * (sin_theta * phi.cos()) + t * (sin_theta * phi.sin()) + *direction * cos_theta).normalize()
+}
+
+// Scattering equally in every direction
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn p(&self, _cos_theta: Float) -> Float {
+        1.0 / (4.0 * consts::PI)
+    }
+
+    fn sample_cos_theta(&self, u: Float) -> Float {
+        1.0 - 2.0 * u
+    }
+
+    fn data(&self) -> PhaseData {
+        PhaseData::Isotropic
+    }
+}
+
+// The Henyey-Greenstein phase function, whose asymmetry `g` is the average
+// cosine of the angle that light scatters by: positive for scattering mostly
+// forwards (as the water droplets in clouds do, at about 0.85), negative for
+// scattering mostly backwards, and zero for scattering in every direction
+pub struct HenyeyGreenstein {
+    pub g: Float,
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, cos_theta: Float) -> Float {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * consts::PI * denominator * denominator.sqrt())
+    }
+
+    // (By inverting its cumulative distribution, which divides by g, so that
+    // nearly isotropic lobes are sampled as isotropic ones)
+    fn sample_cos_theta(&self, u: Float) -> Float {
+        let g = self.g;
+        if g.abs() < 1e-3 {
+            return Isotropic.sample_cos_theta(u);
+        }
+        let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    }
+
+    fn data(&self) -> PhaseData {
+        PhaseData::HenyeyGreenstein { g: self.g }
+    }
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: Float) -> HenyeyGreenstein {
+        HenyeyGreenstein { g }
+    }
+}
+
+// A mixture of two Henyey-Greenstein lobes, e.g. a strong forward one and a
+// weaker backward one, which no single lobe can match: clouds scatter most
+// light nearly straight ahead (the silver lining at their edges against the
+// sun), but still a little back towards it
+pub struct TwoLobe {
+    pub first: HenyeyGreenstein,
+    pub second: HenyeyGreenstein,
+    // The fraction of the light that scatters by the first lobe
+    pub weight: Float,
+}
+
+impl PhaseFunction for TwoLobe {
+    fn p(&self, cos_theta: Float) -> Float {
+        self.weight * self.first.p(cos_theta) + (1.0 - self.weight) * self.second.p(cos_theta)
+    }
+
+    // (Picking a lobe by its weight, and reusing the rest of `u` to sample it)
+    fn sample_cos_theta(&self, u: Float) -> Float {
+        if u < self.weight {
+            self.first.sample_cos_theta(u / self.weight)
+        } else {
+            self.second.sample_cos_theta((u - self.weight) / (1.0 - self.weight))
+        }
+    }
+
+    fn data(&self) -> PhaseData {
+        PhaseData::TwoLobe {
+            g1: self.first.g,
+            g2: self.second.g,
+            weight: self.weight,
+        }
+    }
+}
+
+impl TwoLobe {
+    pub fn new(first: HenyeyGreenstein, second: HenyeyGreenstein, weight: Float) -> TwoLobe {
+        TwoLobe { first, second, weight }
+    }
+}
+
+#[test]
+fn test_phase_functions() {
+    use rng::seeded_rng;
+
+    // Each phase function integrates to one over the sphere (by sampling it
+    // uniformly), and the directions it picks have its average cosine
+    let mut rng = seeded_rng(3);
+    let phases = [(PhaseData::Isotropic, 0.0),
+                  (PhaseData::HenyeyGreenstein { g: 0.8 }, 0.8),
+                  (PhaseData::HenyeyGreenstein { g: -0.3 }, -0.3),
+                  (PhaseData::TwoLobe { g1: 0.9, g2: -0.4, weight: 0.75 }, 0.75 * 0.9 - 0.25 * 0.4)];
+    let direction = Vector::new(1.0, -2.0, 0.5).normalize();
+    let count = 40000;
+    for &(data, mean_cos) in &phases {
+        let phase = data.create();
+        assert_eq!(phase.data(), data);
+        let (mut integral, mut sampled_cos) = (0.0, 0.0);
+        for _ in 0..count {
+            integral += phase.p(Isotropic.sample_cos_theta(rng.next_float())) * 4.0 * consts::PI / count as Float;
+            let scattered = sample(&*phase, &direction, &mut rng);
+            assert!((scattered.length() - 1.0).abs() < 1e-3);
+            sampled_cos += scattered.dot(&direction) / count as Float;
+        }
+        assert!((integral - 1.0).abs() < 0.05, "{:?} integrates to {}", data, integral);
+        assert!((sampled_cos - mean_cos).abs() < 0.02, "{:?} has a mean cosine of {}", data, sampled_cos);
+    }
+    assert!(PhaseData::HenyeyGreenstein { g: 1.0 }.problem().is_some());
+    assert!(PhaseData::TwoLobe { g1: 0.5, g2: 0.0, weight: 2.0 }.problem().is_some());
+    assert!(PhaseData::default().problem().is_none());
+}
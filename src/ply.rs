@@ -0,0 +1,290 @@
+use vector::Vector;
+use vector::Float;
+use shape::Triangle;
+use mesh::Mesh;
+use error::Result;
+use error::TracerError;
+
+use std::fs;
+use std::path::Path;
+
+fn invalid(why: &str) -> TracerError {
+    TracerError::Parse(why.to_string())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+// The scalar types of properties, by their size in bytes (and whether they
+// are signed, or floating point)
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Scalar {
+    Int(usize),
+    Uint(usize),
+    Float(usize),
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::Int(1),
+            "uchar" | "uint8" => Scalar::Uint(1),
+            "short" | "int16" => Scalar::Int(2),
+            "ushort" | "uint16" => Scalar::Uint(2),
+            "int" | "int32" => Scalar::Int(4),
+            "uint" | "uint32" => Scalar::Uint(4),
+            "float" | "float32" => Scalar::Float(4),
+            "double" | "float64" => Scalar::Float(8),
+            _ => return Err(invalid("unknown property type")),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    scalar: Scalar,
+    // The type of a list property's length, which comes before its items
+    count: Option<Scalar>,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Reads the values of the body, in whatever format it's in
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        if self.format == Format::Ascii {
+            while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+            let start = self.position;
+            while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+                self.position += 1;
+            }
+            let word = ::std::str::from_utf8(&self.bytes[start..self.position]).map_err(|_| invalid("not ASCII"))?;
+            return word.parse().map_err(|_| invalid("expected a number"));
+        }
+
+        let size = match scalar {
+            Scalar::Int(size) | Scalar::Uint(size) | Scalar::Float(size) => size,
+        };
+        if self.position + size > self.bytes.len() {
+            return Err(invalid("the file ends early"));
+        }
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(&self.bytes[self.position..self.position + size]);
+        self.position += size;
+        if self.format == Format::BinaryBigEndian {
+            b[..size].reverse();
+        }
+        Ok(match scalar {
+            Scalar::Int(1) => b[0] as i8 as f64,
+            Scalar::Int(2) => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::Int(_) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::Uint(1) => b[0] as f64,
+            Scalar::Uint(2) => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::Uint(_) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::Float(4) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::Float(_) => f64::from_le_bytes(b),
+        })
+    }
+}
+
+// Load a (binary or ASCII) PLY mesh: vertices' positions, along with their
+// normals and texture coordinates if they have them, and faces (split into
+// fans of triangles), while any other elements are skipped
+pub fn load(path: &Path) -> Result<Mesh> {
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(b"ply") {
+        return Err(invalid("not a PLY file"));
+    }
+
+    // The header is ASCII, up to the line "end_header"
+    let end = bytes.windows(11).position(|w| w == b"end_header\n" || w == b"end_header\r").ok_or_else(|| {
+        invalid("the header never ends")
+    })?;
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut body_start = end + 11;
+    if bytes.get(body_start - 1) == Some(&b'\r') && bytes.get(body_start) == Some(&b'\n') {
+        body_start += 1;
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in header.lines().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", ..] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", ..] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", ..] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => {
+                elements.push(Element {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| invalid("expected an element count"))?,
+                    properties: Vec::new(),
+                })
+            }
+            ["property", "list", count, scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("a property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(scalar)?,
+                    count: Some(Scalar::parse(count)?),
+                });
+            }
+            ["property", scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("a property before any element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(scalar)?,
+                    count: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    let mut body = Body {
+        format: format.ok_or_else(|| invalid("the header has no format"))?,
+        bytes: &bytes[body_start..],
+        position: 0,
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+    for element in &elements {
+        let vertex = element.name == "vertex";
+        let face = element.name == "face";
+        for _ in 0..element.count {
+            let mut scalars = [0.0; 8];
+            let mut indices = Vec::new();
+            for property in &element.properties {
+                if let Some(count) = property.count {
+                    let len = body.read(count)? as usize;
+                    for _ in 0..len {
+                        let index = body.read(property.scalar)?;
+                        if face && (property.name == "vertex_indices" || property.name == "vertex_index") {
+                            indices.push(index as usize);
+                        }
+                    }
+                    continue;
+                }
+                let v = body.read(property.scalar)?;
+                let slot = match property.name.as_str() {
+                    "x" => 0,
+                    "y" => 1,
+                    "z" => 2,
+                    "nx" => 3,
+                    "ny" => 4,
+                    "nz" => 5,
+                    "u" | "s" | "texture_u" | "texture_s" => 6,
+                    "v" | "t" | "texture_v" | "texture_t" => 7,
+                    _ => continue,
+                };
+                scalars[slot] = v as Float;
+            }
+            if vertex {
+                positions.push(Vector::new(scalars[0], scalars[1], scalars[2]));
+                normals.push(Vector::new(scalars[3], scalars[4], scalars[5]));
+                uvs.push((scalars[6], scalars[7]));
+            } else if face {
+                faces.push(indices);
+            }
+        }
+    }
+
+    let property = |name: &str| {
+        elements.iter().any(|e| e.name == "vertex" && e.properties.iter().any(|p| p.name == name))
+    };
+    let with_normals = property("nx");
+    let with_uvs = ["u", "s", "texture_u", "texture_s"].iter().any(|name| property(name));
+    let mut triangles = Vec::new();
+    let mut triangle_normals = Vec::new();
+    let mut triangle_uvs = Vec::new();
+    for face in &faces {
+        if face.iter().any(|&i| i >= positions.len()) {
+            return Err(invalid("a face refers to a missing vertex"));
+        }
+        for pair in face.windows(2).skip(1) {
+            let (a, b, c) = (face[0], pair[0], pair[1]);
+            triangles.push(Triangle::new(&positions[a], &positions[b], &positions[c]));
+            triangle_normals.push([normals[a], normals[b], normals[c]]);
+            triangle_uvs.push([uvs[a], uvs[b], uvs[c]]);
+        }
+    }
+    let mut mesh = Mesh::new(triangles);
+    if with_normals {
+        mesh.normals = Some(triangle_normals);
+    }
+    if with_uvs {
+        mesh.uvs = Some(triangle_uvs);
+    }
+    Ok(mesh)
+}
+
+#[test]
+fn test_load_ply() {
+    use shape::Shape;
+    use ray::Ray;
+
+    // The same quad (with an extra element that is skipped), in ASCII and
+    // in big-endian binary
+    let header = |format: &str| {
+        format!("ply\nformat {} 1.0\ncomment a quad\nelement vertex 4\nproperty float x\nproperty float y\n\
+                 property float z\nproperty uchar red\nproperty float u\nproperty float v\nelement face 1\n\
+                 property list uchar int vertex_indices\nelement edge 1\nproperty int vertex1\nproperty int vertex2\n\
+                 end_header\n",
+                format)
+    };
+    let vertices = [(-1.0f32, -1.0f32, 0u8), (1.0, -1.0, 1), (1.0, 1.0, 2), (-1.0, 1.0, 3)];
+    let mut ascii = header("ascii");
+    let mut binary = header("binary_big_endian").into_bytes();
+    for &(x, y, red) in &vertices {
+        let (u, v) = ((x + 1.0) * 0.5, (y + 1.0) * 0.5);
+        ascii += &format!("{} {} 0 {} {} {}\n", x, y, red, u, v);
+        for f in &[x, y, 0.0] {
+            binary.extend_from_slice(&f.to_be_bytes());
+        }
+        binary.push(red);
+        binary.extend_from_slice(&u.to_be_bytes());
+        binary.extend_from_slice(&v.to_be_bytes());
+    }
+    ascii += "4 0 1 2 3\n0 1\n";
+    binary.push(4);
+    for i in 0..4i32 {
+        binary.extend_from_slice(&i.to_be_bytes());
+    }
+    binary.extend_from_slice(&0i32.to_be_bytes());
+    binary.extend_from_slice(&1i32.to_be_bytes());
+
+    let directory = ::std::env::temp_dir();
+    let r = Ray::new(&Vector::new(0.5, -0.5, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    for (name, contents) in [("ascii", ascii.into_bytes()), ("binary", binary)] {
+        let path = directory.join(format!("tracer_test_load_ply_{}.ply", name));
+        fs::write(&path, contents).unwrap();
+        let mesh = load(&path).unwrap();
+        assert_eq!(mesh.len(), 2);
+        assert!(mesh.normals.is_none());
+        let dg = mesh.intersect(&r).unwrap();
+        assert!((dg.uv.0 - 0.75).abs() < 1e-6 && (dg.uv.1 - 0.25).abs() < 1e-6);
+    }
+
+    let path = directory.join("tracer_test_load_ply_broken.ply");
+    fs::write(&path, "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n").unwrap();
+    assert!(load(&path).is_err());
+}
@@ -0,0 +1,120 @@
+use camera::Orbit;
+use film::Film;
+use tonemap::DisplayTransform;
+use error::Result;
+#[cfg(not(feature = "preview"))]
+use error::TracerError;
+
+#[cfg(feature = "preview")]
+use vector::Float;
+#[cfg(feature = "preview")]
+use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
+
+#[cfg(feature = "preview")]
+use std::io;
+
+// A window that shows the film as it converges, so that bad renders can be
+// stopped early, and in which the camera can be moved with the mouse: this
+// requires the `preview` feature, without which no window is ever opened
+#[cfg(feature = "preview")]
+pub struct Preview {
+    window: Window,
+    buffer: Vec<u32>,
+    // The mouse position when the window was last polled
+    mouse: Option<(f32, f32)>,
+}
+
+#[cfg(feature = "preview")]
+impl Preview {
+    // Open a window of the given size (which fails, e.g., on a machine
+    // without a display)
+    pub fn new(width: u32, height: u32) -> Result<Preview> {
+        let mut window = Window::new("tracer", width as usize, height as usize, WindowOptions::default())
+            .map_err(io::Error::other)?;
+        window.set_target_fps(60);
+        Ok(Preview {
+            window,
+            buffer: vec![0; (width * height) as usize],
+            mouse: None,
+        })
+    }
+
+    // Whether the user wants to keep rendering (closing the window or
+    // pressing escape stops the render)
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    pub fn update(&mut self, film: &Film, display: &DisplayTransform) -> Result<()> {
+        let rgb = film.to_framebuffer().to_rgb8(display);
+        for (pixel, c) in self.buffer.iter_mut().zip(rgb.chunks(3)) {
+            *pixel = (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32;
+        }
+        self.window
+            .update_with_buffer(&self.buffer, film.width as usize, film.height as usize)
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    // Keep the window responsive while there's nothing new to show
+    pub fn refresh(&mut self) {
+        self.window.update();
+    }
+
+    // Move the camera according to the mouse: dragging with the left button
+    // orbits, dragging with the right (or middle) button pans, and
+    // scrolling zooms (returns whether the camera moved)
+    pub fn control(&mut self, orbit: &mut Orbit) -> bool {
+        let mouse = self.window.get_mouse_pos(MouseMode::Pass);
+        let (_, height) = self.window.get_size();
+        let mut moved = false;
+
+        if let (Some((x, y)), Some((last_x, last_y))) = (mouse, self.mouse) {
+            let dx = (x - last_x) as Float / height.max(1) as Float;
+            let dy = (y - last_y) as Float / height.max(1) as Float;
+            if dx != 0.0 || dy != 0.0 {
+                if self.window.get_mouse_down(MouseButton::Left) {
+                    orbit.rotate(-dx * 4.0, dy * 4.0);
+                    moved = true;
+                } else if self.window.get_mouse_down(MouseButton::Right) ||
+                          self.window.get_mouse_down(MouseButton::Middle) {
+                    orbit.pan(-dx, dy);
+                    moved = true;
+                }
+            }
+        }
+        self.mouse = mouse;
+
+        if let Some((_, scroll)) = self.window.get_scroll_wheel() {
+            if scroll != 0.0 {
+                orbit.zoom(scroll as Float * 0.1);
+                moved = true;
+            }
+        }
+        moved
+    }
+}
+
+#[cfg(not(feature = "preview"))]
+pub struct Preview;
+
+#[cfg(not(feature = "preview"))]
+impl Preview {
+    pub fn new(width: u32, height: u32) -> Result<Preview> {
+        Err(TracerError::InvalidParameter("the preview window needs the `preview` feature".to_string()))
+    }
+
+    pub fn is_open(&self) -> bool {
+        true
+    }
+
+    pub fn update(&mut self, film: &Film, display: &DisplayTransform) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn refresh(&mut self) {}
+
+    pub fn control(&mut self, orbit: &mut Orbit) -> bool {
+        false
+    }
+}
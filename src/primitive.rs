@@ -1,28 +1,146 @@
 use shape::Shape;
+use shape::Geometry;
 use shape::DifferentialGeometry;
 use ray::Ray;
 use material::Material;
+use transform::Transform;
+use aabb::Aabb;
+use vector::Float;
+use stats;
+use stats::Counter;
+
+use serde::{Deserialize, Serialize};
 
 use std::sync::Arc;
 
+// The kinds of rays that the integrator traces: those from the camera, those
+// that test whether a light is in shadow, and those that paths bounce along
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Indirect,
+}
+
+// Which kinds of rays see a primitive (every kind, by default), e.g. for a
+// wall that blocks the light without hiding the room from the camera,
+// or an object that casts no shadows: rays that don't see a primitive go
+// straight through it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub indirect: bool,
+}
+
+impl Visibility {
+    pub fn new() -> Visibility {
+        Visibility {
+            camera: true,
+            shadow: true,
+            indirect: true,
+        }
+    }
+
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Indirect => self.indirect,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility::new()
+    }
+}
+
 // Primitives are instances of renderable geometry
 pub struct Primitive {
-    pub shape: Arc<Shape>,
-    pub material: Arc<Material>,
+    pub shape: Geometry,
+    pub material: Arc<dyn Material>,
+    // Identify the primitive and its material in ID passes: these are
+    // assigned when the primitive is added to a scene (see: `Scene::add`)
+    pub object_id: u32,
+    pub material_id: u32,
+    // Moves the shape without rebuilding it (e.g. between animation frames)
+    pub transform: Transform,
+    pub visibility: Visibility,
 }
 
 impl Primitive {
-    pub fn new(s: Arc<Shape>, m: Arc<Material>) -> Primitive {
+    pub fn new<G: Into<Geometry>>(s: G, m: Arc<dyn Material>) -> Primitive {
         Primitive {
-            shape: s,
+            shape: s.into(),
             material: m,
+            object_id: 0,
+            material_id: 0,
+            transform: Transform::identity(),
+            visibility: Visibility::new(),
         }
     }
 
-    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry, Arc<Material>)> {
-        if let Some(dg) = self.shape.intersect(incident) {
-            return Some((dg, self.material.clone()));
-        };
-        None
+    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry<'_>, &dyn Material)> {
+        self.intersect_shape(incident).map(|dg| (dg, &*self.material))
+    }
+
+    // The world space bounds of the (transformed) shape
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.shape.bounds().map(|bounds| bounds.transformed(&self.transform))
+    }
+
+    // Intersect the (transformed) shape: rather than transforming the shape,
+    // the ray is transformed into the shape's space
+    pub fn intersect_shape(&self, incident: &Ray) -> Option<DifferentialGeometry<'_>> {
+        stats::count(Counter::PrimitiveTests);
+        if self.transform.is_identity() {
+            return self.shape.intersect(incident);
+        }
+        let (local, scale) = self.local_ray(incident);
+        if self.transform.is_translation() {
+            let translation = self.transform.translation();
+            return self.shape.intersect(&local).map(|mut dg| {
+                dg.position += translation;
+                dg
+            });
+        }
+        self.shape.intersect(&local).map(|mut dg| {
+            dg.t /= scale;
+            dg.position = self.transform.point_to_world(&dg.position);
+            dg.normal = self.transform.normal_to_world(&dg.normal);
+            dg
+        })
+    }
+
+    // Does the ray hit the (transformed) shape closer than its `t_max`?
+    pub fn hit_any(&self, incident: &Ray) -> bool {
+        stats::count(Counter::PrimitiveTests);
+        if self.transform.is_identity() {
+            return self.shape.hit_any(incident);
+        }
+        self.shape.hit_any(&self.local_ray(incident).0)
+    }
+
+    // The ray in the shape's space, along with how much longer distances
+    // along it are than those along the incident ray: the local ray's
+    // direction is normalized, so this is the scale along the ray
+    fn local_ray(&self, incident: &Ray) -> (Ray, Float) {
+        if self.transform.is_translation() {
+            let local = Ray::new(&(incident.origin - self.transform.translation()),
+                                 &incident.direction,
+                                 incident.t_min,
+                                 incident.t_max);
+            return (local, 1.0);
+        }
+        let direction = self.transform.vector_to_local(&incident.direction);
+        let scale = direction.length();
+        let local = Ray::new(&self.transform.point_to_local(&incident.origin),
+                             &direction,
+                             incident.t_min * scale,
+                             incident.t_max * scale);
+        (local, scale)
     }
 }
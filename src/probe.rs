@@ -0,0 +1,262 @@
+use vector::Vector;
+use vector::Float;
+use vector::consts;
+use color::Color;
+use ray::Ray;
+use scene::Scene;
+use framebuffer::Framebuffer;
+use integrator::trace;
+use output;
+use output::OutputFormat;
+use tonemap::DisplayTransform;
+use sampler::hash_combine;
+use rng;
+use rng::ThreadRng;
+use rng::Rng;
+use error::Result;
+use error::TracerError;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+// Light probes capture the light arriving at a point from every direction, to
+// light dynamic objects in real-time engines with the scene's global
+// illumination: as a cubemap (for reflections), or projected onto the first
+// nine spherical harmonics (for diffuse lighting, see: `ShProbe`)
+
+// The cubemap's faces, in the order (and with the orientation) of OpenGL's
+// and Direct3D's cube textures
+pub const FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+#[derive(Copy, Clone, Debug)]
+pub struct ProbeSettings {
+    // The width and height of each of the cubemap's faces
+    pub resolution: u32,
+    // The number of rays traced through each texel
+    pub samples: u32,
+    pub max_depth: u32,
+    pub seed: u32,
+}
+
+impl ProbeSettings {
+    pub fn new() -> ProbeSettings {
+        ProbeSettings {
+            resolution: 64,
+            samples: 64,
+            max_depth: 8,
+            seed: 0,
+        }
+    }
+}
+
+impl Default for ProbeSettings {
+    fn default() -> ProbeSettings {
+        ProbeSettings::new()
+    }
+}
+
+// The direction through the point (s, t) of the face, each in [-1, 1] with t
+// increasing down the face
+fn face_direction(face: usize, s: Float, t: Float) -> Vector {
+    match face {
+        0 => Vector::new(1.0, -t, -s),
+        1 => Vector::new(-1.0, -t, s),
+        2 => Vector::new(s, 1.0, t),
+        3 => Vector::new(s, -1.0, -t),
+        4 => Vector::new(s, -t, 1.0),
+        _ => Vector::new(-s, -t, -1.0),
+    }
+}
+
+// Render the radiance arriving at `position` onto the six faces of a cube
+// (see: `FACES`)
+pub fn render_cubemap(scene: &Scene, position: &Vector, settings: &ProbeSettings) -> Vec<Framebuffer> {
+    let size = settings.resolution;
+    (0..6)
+        .map(|face| {
+            let mut image = Framebuffer::new(size, size);
+            image.pixels = (0..size * size)
+                .into_par_iter()
+                .map(|index| {
+                    let texel_hash = hash_combine(hash_combine(settings.seed, face as u32), index);
+                    let mut sum = Color::black();
+                    for sample in 0..settings.samples {
+                        rng::reseed(hash_combine(texel_hash, sample));
+                        let s = ((index % size) as Float + ThreadRng.next_float()) / size as Float * 2.0 - 1.0;
+                        let t = ((index / size) as Float + ThreadRng.next_float()) / size as Float * 2.0 - 1.0;
+                        let ray = Ray::new(position, &face_direction(face, s, t).normalize(), 0.0, Float::MAX);
+                        let radiance = trace(&ray, scene, 0, settings.max_depth, None);
+                        // (As in the renderer, samples that aren't finite are
+                        // taken as black)
+                        if radiance.r.is_finite() && radiance.g.is_finite() && radiance.b.is_finite() {
+                            sum += radiance;
+                        }
+                    }
+                    sum / settings.samples.max(1) as Float
+                })
+                .collect();
+            image
+        })
+        .collect()
+}
+
+// The first nine real spherical harmonics (bands 0 to 2) along `d`
+fn sh_basis(d: &Vector) -> [Float; 9] {
+    [0.282095,
+     0.488603 * d.y,
+     0.488603 * d.z,
+     0.488603 * d.x,
+     1.092548 * d.x * d.y,
+     1.092548 * d.y * d.z,
+     0.315392 * (3.0 * d.z * d.z - 1.0),
+     1.092548 * d.x * d.z,
+     0.546274 * (d.x * d.x - d.y * d.y)]
+}
+
+// A probe's radiance, projected onto the first nine spherical harmonics:
+// smooth enough to light diffuse surfaces with (which blur away the rest, see:
+// "An Efficient Representation for Irradiance Environment Maps" by Ramamoorthi
+// and Hanrahan), and small enough to store one at every point of a grid
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShProbe {
+    pub position: Vector,
+    pub coefficients: [Color; 9],
+}
+
+impl ShProbe {
+    // Project a cubemap (see: `render_cubemap`), weighting each texel by the
+    // solid angle that it covers
+    pub fn from_cubemap(position: &Vector, faces: &[Framebuffer]) -> ShProbe {
+        let mut coefficients = [Color::black(); 9];
+        let mut total = 0.0;
+        for (face, image) in faces.iter().enumerate() {
+            let size = image.width as Float;
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    let s = (x as Float + 0.5) / size * 2.0 - 1.0;
+                    let t = (y as Float + 0.5) / size * 2.0 - 1.0;
+                    let weight = 1.0 / (1.0 + s * s + t * t).powf(1.5);
+                    let radiance = image.get(x, y);
+                    for (c, basis) in coefficients.iter_mut().zip(&sh_basis(&face_direction(face, s, t).normalize())) {
+                        *c += radiance * (basis * weight);
+                    }
+                    total += weight;
+                }
+            }
+        }
+        // (The weights are normalized so that they sum to the sphere's solid
+        // angle exactly)
+        for c in &mut coefficients {
+            *c *= 4.0 * consts::PI / total;
+        }
+        ShProbe {
+            position: *position,
+            coefficients,
+        }
+    }
+
+    // The irradiance at the probe of a surface facing `normal`
+    pub fn irradiance(&self, normal: &Vector) -> Color {
+        // (The cosine lobe's convolution of each band)
+        let bands = [consts::PI, 2.0 * consts::PI / 3.0, consts::PI / 4.0];
+        let basis = sh_basis(&normal.normalize());
+        let mut irradiance = Color::black();
+        for (i, (c, b)) in self.coefficients.iter().zip(&basis).enumerate() {
+            let band = match i {
+                0 => bands[0],
+                1..=3 => bands[1],
+                _ => bands[2],
+            };
+            irradiance += *c * (band * b);
+        }
+        irradiance
+    }
+}
+
+// Bake a probe at each of the positions
+pub fn bake_probes(scene: &Scene, positions: &[Vector], settings: &ProbeSettings) -> Vec<(Vec<Framebuffer>, ShProbe)> {
+    positions.iter()
+        .map(|position| {
+            let faces = render_cubemap(scene, position, settings);
+            let probe = ShProbe::from_cubemap(position, &faces);
+            (faces, probe)
+        })
+        .collect()
+}
+
+// Write the cubemap's faces beside `path`, each named for its face (e.g.
+// "probe.exr" as "probe_px.exr", "probe_nx.exr", and so on), returning where
+pub fn write_cubemap(faces: &[Framebuffer],
+                     path: &Path,
+                     format: OutputFormat,
+                     display: &DisplayTransform)
+                     -> Result<Vec<PathBuf>> {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("probe");
+    let mut paths = Vec::new();
+    for (image, name) in faces.iter().zip(&FACES) {
+        let face_path = path.with_file_name(format!("{}_{}.{}", stem, name, format.extension()));
+        output::write_image(image, &face_path, format, display)?;
+        paths.push(face_path);
+    }
+    Ok(paths)
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProbeFile {
+    probes: Vec<ShProbe>,
+}
+
+// Write the probes' spherical harmonics to a TOML file, as a `probes` array
+pub fn write_sh_probes(probes: &[ShProbe], path: &Path) -> Result<()> {
+    let file = ProbeFile { probes: probes.to_vec() };
+    let source = toml::to_string(&file).map_err(|why| TracerError::Parse(why.to_string()))?;
+    fs::write(path, source)?;
+    Ok(())
+}
+
+pub fn load_sh_probes(path: &Path) -> Result<Vec<ShProbe>> {
+    let file: ProbeFile = toml::from_str(&fs::read_to_string(path)?).map_err(|why| TracerError::Parse(why.to_string()))?;
+    Ok(file.probes)
+}
+
+#[test]
+fn test_probes() {
+    use environment::Environment;
+    use primitive::Primitive;
+    use shape::Plane;
+    use material::Lambertian;
+    use std::env;
+    use std::sync::Arc;
+
+    // In a uniform environment, every texel sees its radiance, and the
+    // irradiance is pi times it, whichever way a surface faces
+    let mut scene = Scene::new();
+    scene.environment = Environment::Uniform { radiance: Color::new(0.5, 0.25, 1.0) };
+    scene.build_bvh();
+    let settings = ProbeSettings { resolution: 8, samples: 1, ..ProbeSettings::new() };
+    let faces = render_cubemap(&scene, &Vector::zero(), &settings);
+    assert!(faces.iter().all(|f| f.pixels.iter().all(|p| *p == Color::new(0.5, 0.25, 1.0))));
+    let probe = ShProbe::from_cubemap(&Vector::zero(), &faces);
+    for normal in &[Vector::new(0.0, 1.0, 0.0), Vector::new(1.0, -1.0, 0.5)] {
+        let e = probe.irradiance(normal);
+        assert!((e.r - 0.5 * consts::PI).abs() < 1e-3 && (e.b - consts::PI).abs() < 1e-3, "{:?}", e);
+    }
+
+    // Under the sky, a probe above black ground sees more light from above
+    scene.environment = Environment::Sky;
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)),
+                             Arc::new(Lambertian::new(&Color::black()))));
+    scene.build_bvh();
+    let (faces, probe) = bake_probes(&scene, &[Vector::new(0.0, 1.0, 0.0)], &settings).pop().unwrap();
+    assert!(faces[3].pixels.iter().all(|p| *p == Color::black()));
+    assert!(probe.irradiance(&Vector::new(0.0, -1.0, 0.0)).g < 0.1 * probe.irradiance(&Vector::new(0.0, 1.0, 0.0)).g);
+
+    // Probes round trip through their files
+    let path = env::temp_dir().join("tracer_test_probes.toml");
+    write_sh_probes(&[probe], &path).unwrap();
+    assert_eq!(load_sh_probes(&path).unwrap(), vec![probe]);
+}
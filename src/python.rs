@@ -0,0 +1,248 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use material::Lambertian;
+use material::Metallic;
+use material::Dielectric;
+use scene::Scene;
+use camera::Camera;
+use film::Film;
+use builder::SceneBuilder;
+use texture::TextureCache;
+use texture::DEFAULT_BUDGET;
+use settings::RenderSettings;
+use scene_file;
+use scenes;
+use error::TracerError;
+
+use numpy::ndarray::Array3;
+use numpy::IntoPyArray;
+use numpy::PyArray3;
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use std::path::Path;
+use std::sync::Arc;
+
+// A Python module, `tracer`, that builds scenes and renders them to numpy
+// arrays, to drive the renderer from a notebook, e.g.
+//
+//     import tracer
+//
+//     scene = (tracer.SceneBuilder()
+//              .sphere([0.0, 0.0, -1.0], 0.5).metallic([1.0, 1.0, 1.0], 0.2)
+//              .plane([0.0, -0.5, 0.0], [0.0, 1.0, 0.0]).lambertian([0.8, 0.8, 0.8])
+//              .camera("main", [0.0, 0.0, 1.0], [0.0, 0.0, -1.0], 60.0)
+//              .build())
+//     image = tracer.render(scene, 320, 240, samples=64)
+//
+// where `image` is the film's (linear) radiance, of shape (height, width, 3)
+// with the top row first. Like the web demo (see: `wasm`), it's built with
+// the `python` feature as a dynamic library, named for the module:
+//
+//     cargo rustc --lib --release --features python,pyo3/extension-module --crate-type cdylib
+//     cp target/release/libraytracer.so tracer.so
+
+impl From<TracerError> for PyErr {
+    fn from(why: TracerError) -> PyErr {
+        match why {
+            TracerError::Io(_) | TracerError::Image(_) => PyIOError::new_err(why.to_string()),
+            TracerError::Parse(_) | TracerError::InvalidParameter(_) => PyValueError::new_err(why.to_string()),
+            TracerError::Render(_) => PyRuntimeError::new_err(why.to_string()),
+        }
+    }
+}
+
+fn vector(v: [Float; 3]) -> Vector {
+    Vector::new(v[0], v[1], v[2])
+}
+
+#[pyclass(name = "Scene", module = "tracer")]
+pub struct PyScene {
+    scene: Scene,
+}
+
+#[pymethods]
+impl PyScene {
+    // One of the built-in scenes (see: `scenes::NAMES`)
+    #[staticmethod]
+    #[pyo3(signature = (name, aspect_ratio = 1.0))]
+    fn named(name: &str, aspect_ratio: Float) -> PyResult<PyScene> {
+        match scenes::named(name, aspect_ratio) {
+            Some(scene) => Ok(PyScene { scene }),
+            None => Err(PyValueError::new_err(format!("no scene named {:?} (there are {:?})", name, scenes::NAMES))),
+        }
+    }
+
+    // A scene file of any format that the command-line renderer reads (see:
+    // `scene_file::load`), whose render settings are ignored
+    #[staticmethod]
+    #[pyo3(signature = (path, aspect_ratio = 1.0))]
+    fn load(path: &str, aspect_ratio: Float) -> PyResult<PyScene> {
+        let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+        let imported = scene_file::load(Path::new(path), aspect_ratio, &textures)?;
+        Ok(PyScene { scene: imported.scene })
+    }
+
+    // The names of the scene's cameras
+    #[getter]
+    fn cameras(&self) -> Vec<String> {
+        self.scene.cameras.iter().map(|c| c.0.clone()).collect()
+    }
+
+    // Problems with the scene that don't stop it from rendering (see:
+    // `Scene::validate`)
+    fn validate(&self) -> Vec<String> {
+        self.scene.validate()
+    }
+
+    fn __len__(&self) -> usize {
+        self.scene.items.len()
+    }
+}
+
+// `SceneBuilder`, whose calls each return the builder so that they can be
+// chained in the same way
+#[pyclass(name = "SceneBuilder", module = "tracer")]
+pub struct PySceneBuilder {
+    // (Taken by `build`)
+    builder: Option<SceneBuilder>,
+}
+
+impl PySceneBuilder {
+    fn chain<F>(mut slf: PyRefMut<'_, Self>, f: F) -> PyResult<PyRefMut<'_, Self>>
+        where F: FnOnce(SceneBuilder) -> SceneBuilder
+    {
+        let builder = slf.builder.take().ok_or_else(|| PyRuntimeError::new_err("the scene was already built"))?;
+        slf.builder = Some(f(builder));
+        Ok(slf)
+    }
+}
+
+#[pymethods]
+impl PySceneBuilder {
+    #[new]
+    fn new() -> PySceneBuilder {
+        PySceneBuilder { builder: Some(SceneBuilder::new()) }
+    }
+
+    fn aspect_ratio(slf: PyRefMut<'_, Self>, aspect_ratio: Float) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.aspect_ratio(aspect_ratio))
+    }
+
+    #[pyo3(signature = (name, from, to, fov = 60.0))]
+    fn camera<'p>(slf: PyRefMut<'p, Self>, name: &str, from: [Float; 3], to: [Float; 3], fov: Float)
+                  -> PyResult<PyRefMut<'p, Self>> {
+        PySceneBuilder::chain(slf, |b| b.camera(name, &vector(from), &vector(to), fov))
+    }
+
+    fn sphere(slf: PyRefMut<'_, Self>, center: [Float; 3], radius: Float) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.sphere(&vector(center), radius))
+    }
+
+    fn plane(slf: PyRefMut<'_, Self>, center: [Float; 3], normal: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.plane(&vector(center), &vector(normal)))
+    }
+
+    fn triangle(slf: PyRefMut<'_, Self>, a: [Float; 3], b: [Float; 3], c: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |builder| builder.triangle(&vector(a), &vector(b), &vector(c)))
+    }
+
+    fn quad(slf: PyRefMut<'_, Self>, corner: [Float; 3], u: [Float; 3], v: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.quad(&vector(corner), &vector(u), &vector(v)))
+    }
+
+    fn mesh_from_obj<'p>(slf: PyRefMut<'p, Self>, path: &str) -> PyResult<PyRefMut<'p, Self>> {
+        PySceneBuilder::chain(slf, |b| b.mesh_from_obj(Path::new(path)))
+    }
+
+    // The most recent shape's material, as one of the three kinds
+    fn lambertian(slf: PyRefMut<'_, Self>, albedo: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.material(Lambertian::new(&Color::from(albedo))))
+    }
+
+    #[pyo3(signature = (albedo, glossiness = 0.0))]
+    fn metallic(slf: PyRefMut<'_, Self>, albedo: [Float; 3], glossiness: Float) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.material(Metallic::new(&Color::from(albedo), glossiness)))
+    }
+
+    fn dielectric(slf: PyRefMut<'_, Self>, ior: Float) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.material(Dielectric::new(ior)))
+    }
+
+    fn at(slf: PyRefMut<'_, Self>, translation: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.at(&vector(translation)))
+    }
+
+    fn rotated(slf: PyRefMut<'_, Self>, rotation: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.rotated(&vector(rotation)))
+    }
+
+    fn scaled(slf: PyRefMut<'_, Self>, scale: [Float; 3]) -> PyResult<PyRefMut<'_, Self>> {
+        PySceneBuilder::chain(slf, |b| b.scaled(&vector(scale)))
+    }
+
+    fn build(&mut self) -> PyResult<PyScene> {
+        let builder = self.builder.take().ok_or_else(|| PyRuntimeError::new_err("the scene was already built"))?;
+        Ok(PyScene { scene: builder.build()? })
+    }
+}
+
+// Render the scene from the named camera (or its first, or otherwise a
+// default one at the origin), starting from the named preset's settings
+// (see: `RenderSettings::preset`), and return the film's radiance: the
+// interpreter is released while the render threads run
+#[pyfunction]
+#[pyo3(signature = (scene, width, height, samples = None, max_depth = None, seed = 0, camera = None, preset = None))]
+#[allow(clippy::too_many_arguments)]
+fn render<'py>(py: Python<'py>,
+               mut scene: PyRefMut<'py, PyScene>,
+               width: u32,
+               height: u32,
+               samples: Option<u32>,
+               max_depth: Option<u32>,
+               seed: u32,
+               camera: Option<&str>,
+               preset: Option<&str>)
+               -> PyResult<Bound<'py, PyArray3<Float>>> {
+    let mut settings = match preset {
+        Some(name) => {
+            RenderSettings::preset(name).ok_or_else(|| PyValueError::new_err(format!("unknown preset {:?}", name)))?
+        }
+        None => RenderSettings::new(),
+    };
+    if width == 0 || height == 0 {
+        return Err(PyValueError::new_err("the image must be at least a pixel across"));
+    }
+    settings.samples = samples.unwrap_or(settings.samples);
+    settings.max_depth = max_depth.unwrap_or(settings.max_depth);
+    let scene = &mut scene.scene;
+    let camera = match camera {
+        Some(name) => *scene.camera(name).ok_or_else(|| PyValueError::new_err(format!("no camera named {:?}", name)))?,
+        None => scene.cameras.first().map_or_else(|| Camera::new(60.0, width as Float / height as Float), |c| c.1),
+    };
+
+    let renderer = settings.renderer();
+    let mut film = Film::new(width, height);
+    let bounds = renderer.bounds(width, height);
+    scene.select_lods(&camera, height);
+    py.allow_threads(|| renderer.render(&mut film, bounds, &camera, scene, seed, &|_: &_| {}));
+
+    let pixels = film.to_framebuffer().pixels;
+    let mut values = Vec::with_capacity(pixels.len() * 3);
+    for pixel in &pixels {
+        values.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    let image = Array3::from_shape_vec((height as usize, width as usize, 3), values)
+        .expect("the film has a pixel for each index");
+    Ok(image.into_pyarray(py))
+}
+
+#[pymodule]
+fn tracer(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyScene>()?;
+    m.add_class::<PySceneBuilder>()?;
+    m.add_function(wrap_pyfunction!(python::render, m)?)?;
+    m.add("SCENES", scenes::NAMES.to_vec())?;
+    Ok(())
+}
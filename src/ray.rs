@@ -1,23 +1,67 @@
 use vector::Vector;
+use vector::Float;
+
+// Rays leaving a surface start off it by this much, relative to the size of
+// the coordinates of the point they leave
+const OFFSET_SCALE: Float = 64.0 * Float::EPSILON;
 
 pub struct Ray {
     pub origin: Vector,
     pub direction: Vector,
-    pub t_min: f64,
-    pub t_max: f64,
+    pub t_min: Float,
+    pub t_max: Float,
 }
 
 impl Ray {
-    pub fn new(o: &Vector, d: &Vector, t_min: f64, t_max: f64) -> Ray {
+    pub fn new(o: &Vector, d: &Vector, t_min: Float, t_max: Float) -> Ray {
+        let direction = d.normalize();
+        if cfg!(feature = "validate") {
+            assert!(o.is_finite() && direction.is_finite() && !t_min.is_nan() && !t_max.is_nan(),
+                    "a ray from {:?} along {:?} (between {} and {})",
+                    o,
+                    d,
+                    t_min,
+                    t_max);
+        }
         Ray {
             origin: *o,
-            direction: d.normalize(),
-            t_min: t_min,
-            t_max: t_max,
+            direction,
+            t_min,
+            t_max,
         }
     }
 
-    pub fn point_at(&self, t: f64) -> Vector {
+    // A ray leaving the surface at `p` (with normal `n`) in the direction `d`,
+    // from just off the surface (see: `offset_origin`)
+    pub fn spawn(p: &Vector, n: &Vector, d: &Vector, t_max: Float) -> Ray {
+        Ray::new(&offset_origin(p, n, d), d, 0.0, t_max)
+    }
+
+    // A ray from the surface at `p` toward `target` (e.g. to test whether a
+    // light at `target` is in shadow), which stops just short of it
+    pub fn spawn_to(p: &Vector, n: &Vector, target: &Vector) -> Ray {
+        let origin = offset_origin(p, n, &(*target - *p));
+        let d = *target - origin;
+        Ray::new(&origin, &d, 0.0, d.length() * (1.0 - OFFSET_SCALE))
+    }
+
+    pub fn point_at(&self, t: Float) -> Vector {
         self.origin + self.direction * t
     }
 }
+
+// The point `p` on a surface with normal `n`, moved along the normal to the
+// side that a ray in direction `d` leaves toward, so that the ray can't hit
+// the surface again right where it starts (as "shadow acne" does): the error
+// in a point of intersection grows with its coordinates, so the distance is
+// relative to their size (which a fixed minimum distance along the ray isn't:
+// it's too small far from the origin, and too large close to it)
+pub fn offset_origin(p: &Vector, n: &Vector, d: &Vector) -> Vector {
+    let size = p.x.abs().max(p.y.abs()).max(p.z.abs());
+    let offset = *n * ((size + 1.0) * OFFSET_SCALE);
+    if d.dot(n) < 0.0 {
+        *p - offset
+    } else {
+        *p + offset
+    }
+}
@@ -0,0 +1,192 @@
+use vector::Float;
+use material::Material;
+use scene::Scene;
+use scene_file::SceneDescription;
+use scene_file::SettingsDescription;
+use texture::TextureCache;
+use error::Result;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// Notices when a file is written to, by polling its modification time (which
+// is cheap enough to do every frame)
+pub struct FileWatcher {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> FileWatcher {
+        FileWatcher {
+            path: path.to_path_buf(),
+            modified: FileWatcher::modified(path),
+        }
+    }
+
+    // Has the file changed since it was last checked (or since the watcher
+    // was made)?
+    pub fn changed(&mut self) -> bool {
+        let modified = FileWatcher::modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+}
+
+// What changed between two versions of a scene file, from the least to the
+// most work to bring the scene up to date (where each change includes those
+// before it)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Nothing,
+    // Only the render settings, which don't touch the scene
+    Settings,
+    Cameras,
+    // Materials (or their textures), which are swapped in place
+    Materials,
+    // Objects, which means building the whole scene again
+    Geometry,
+}
+
+pub fn changes(old: &SceneDescription, new: &SceneDescription) -> Change {
+    if old.objects != new.objects || old.directory != new.directory {
+        Change::Geometry
+    } else if old.materials != new.materials || old.textures != new.textures {
+        Change::Materials
+    } else if old.cameras != new.cameras {
+        Change::Cameras
+    } else if old.settings != new.settings {
+        Change::Settings
+    } else {
+        Change::Nothing
+    }
+}
+
+// Keeps a scene up to date with its scene file as the file is edited (e.g.
+// while looking at it in the preview window), rebuilding only what changed:
+// only the scene file itself is watched, so edits to the models and textures
+// that it refers to aren't noticed
+pub struct SceneReloader {
+    watcher: FileWatcher,
+    description: SceneDescription,
+    // The scene's named materials, as they were built
+    materials: HashMap<String, Arc<dyn Material>>,
+    aspect_ratio: Float,
+    textures: Arc<TextureCache>,
+}
+
+impl SceneReloader {
+    // Load and build a scene file (see: `SceneDescription::build`)
+    pub fn load(path: &Path, aspect_ratio: Float, textures: &Arc<TextureCache>) -> Result<(SceneReloader, Scene)> {
+        // (The file is watched before it's read, so that no edit is missed)
+        let watcher = FileWatcher::new(path);
+        let description = SceneDescription::load(path)?;
+        let materials = description.build_materials(textures)?;
+        let scene = description.build_with(&materials, aspect_ratio, textures)?;
+        let reloader = SceneReloader {
+            watcher,
+            description,
+            materials,
+            aspect_ratio,
+            textures: textures.clone(),
+        };
+        Ok((reloader, scene))
+    }
+
+    pub fn settings(&self) -> &SettingsDescription {
+        &self.description.settings
+    }
+
+    // If the scene file has changed, bring the scene up to date with it: a
+    // file that can't be loaded (e.g. one that's only half written) leaves
+    // the scene as it was
+    pub fn poll(&mut self, scene: &mut Scene) -> Result<Change> {
+        if !self.watcher.changed() {
+            return Ok(Change::Nothing);
+        }
+        let description = SceneDescription::load(&self.watcher.path)?;
+        let mut change = changes(&self.description, &description);
+        if change == Change::Materials {
+            let materials = description.build_materials(&self.textures)?;
+            // (The objects can only keep their materials if none were removed)
+            if self.materials.keys().all(|name| materials.contains_key(name)) {
+                for (name, old) in &self.materials {
+                    scene.replace_material(old, &materials[name]);
+                }
+                self.materials = materials;
+            } else {
+                change = Change::Geometry;
+            }
+        }
+        if change == Change::Geometry {
+            let materials = description.build_materials(&self.textures)?;
+            *scene = description.build_with(&materials, self.aspect_ratio, &self.textures)?;
+            self.materials = materials;
+        } else if change >= Change::Cameras {
+            scene.cameras = description.build_cameras(self.aspect_ratio);
+        }
+        self.description = description;
+        Ok(change)
+    }
+}
+
+#[test]
+fn test_scene_reloader() {
+    use ray::Ray;
+    use vector::Vector;
+    use color::Color;
+    use std::time::Duration;
+
+    let path = ::std::env::temp_dir().join("tracer_test_scene_reloader.toml");
+    let source = |albedo: &str, radius: &str, samples: u32| {
+        format!("[settings]\nsamples = {}\n\n[[materials]]\nname = \"red\"\ntype = \"lambertian\"\nalbedo = {}\n\n\
+                 [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = {}\nmaterial = \"red\"\n",
+                samples,
+                albedo,
+                radius)
+    };
+    // (Each write is given a later modification time, in case the file
+    // system's are coarse)
+    let mut time = SystemTime::now();
+    let mut write = |source: String| {
+        fs::write(&path, source).unwrap();
+        time += Duration::from_secs(1);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(time).unwrap();
+    };
+    write(source("[1.0, 0.0, 0.0]", "0.5", 4));
+
+    let textures = Arc::new(TextureCache::new(0));
+    let (mut reloader, mut scene) = SceneReloader::load(&path, 1.0, &textures).unwrap();
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let hit = |scene: &Scene| scene.intersect(&r).map(|(dg, material)| (dg.t, material.albedo()));
+    assert_eq!(reloader.poll(&mut scene).unwrap(), Change::Nothing);
+
+    write(source("[1.0, 0.0, 0.0]", "0.5", 8));
+    assert_eq!(reloader.poll(&mut scene).unwrap(), Change::Settings);
+    assert_eq!(reloader.settings().samples, Some(8));
+
+    write(source("[0.0, 0.0, 1.0]", "0.5", 8));
+    assert_eq!(reloader.poll(&mut scene).unwrap(), Change::Materials);
+    assert_eq!(hit(&scene), Some((1.5, Color::new(0.0, 0.0, 1.0))));
+    assert_eq!(scene.materials.len(), 1);
+
+    write(source("[0.0, 0.0, 1.0]", "1.0", 8));
+    assert_eq!(reloader.poll(&mut scene).unwrap(), Change::Geometry);
+    assert_eq!(hit(&scene), Some((1.0, Color::new(0.0, 0.0, 1.0))));
+
+    // A broken file leaves the scene as it was
+    write("[[objects]\n".to_string());
+    assert!(reloader.poll(&mut scene).is_err());
+    assert_eq!(hit(&scene), Some((1.0, Color::new(0.0, 0.0, 1.0))));
+}
@@ -0,0 +1,533 @@
+use vector::Float;
+use color::Color;
+use scene::Scene;
+use ray::Ray;
+use primitive::RayKind;
+use bvh::PACKET_SIZE;
+use camera::Camera;
+use sampler::Sampler;
+use sampler::SamplerType;
+use sampler::hash_combine;
+use blue_noise::BlueNoiseMask;
+use blue_noise::BlueNoiseSampler;
+use film::Film;
+use film::FilmTile;
+use film::Pixel;
+use film::AovSample;
+use backplate::Backplate;
+use filter::Filter;
+use filter::FilterType;
+use filter::FilterData;
+use integrator::trace_camera_hit;
+use integrator::trace_debug;
+use integrator::DebugMode;
+use integrator::trace_path;
+use integrator::PathRecord;
+use scheduler;
+use scheduler::Tile;
+use scheduler::TileProgress;
+use server::Progress;
+use stats;
+use stats::Counter;
+use stats::Counts;
+use stats::RenderStats;
+use stats::Stopwatch;
+use rng;
+use error::Result;
+use error::TracerError;
+
+use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How far along a render is (see: `Renderer::render`)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderProgress {
+    // The pass underway (counting from 0), out of the passes to render
+    pub pass: u32,
+    pub passes: u32,
+    // The tiles of the pass that are finished, out of its tiles
+    pub tiles_completed: usize,
+    pub tiles: usize,
+    // The average number of samples taken per pixel so far
+    pub samples_per_pixel: Float,
+    pub elapsed: Duration,
+    // How much longer the render should take, if the passes to come take as
+    // long as those so far (which overestimates it once adaptive sampling
+    // stops pixels early), or `None` before anything has finished
+    pub remaining: Option<Duration>,
+    pub rays_per_second: Float,
+}
+
+// Told how a render is going whenever a tile finishes, e.g. to draw a
+// progress bar: any `Fn(&RenderProgress)` is one
+pub trait ProgressSink: Sync {
+    fn report(&self, progress: &RenderProgress);
+}
+
+impl<F: Fn(&RenderProgress) + Sync> ProgressSink for F {
+    fn report(&self, progress: &RenderProgress) {
+        self(progress)
+    }
+}
+
+// How the film is sampled: see the constants in `main` for what each setting
+// does
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Renderer {
+    // The pixels rendered, (x0, y0, x1, y1) where x1 and y1 are exclusive,
+    // or the whole film
+    pub crop: Option<(u32, u32, u32, u32)>,
+    // The maximum number of samples (passes) taken per pixel
+    pub samples: u32,
+    pub min_samples: u32,
+    pub noise_threshold: Float,
+    pub max_depth: u32,
+    pub tile_size: u32,
+    pub sampler: SamplerType,
+    // (Saved by its description, so only the built-in filters can be saved)
+    #[serde(serialize_with = "serialize_filter", deserialize_with = "deserialize_filter")]
+    pub filter: Arc<dyn Filter>,
+    pub mask: Option<Arc<BlueNoiseMask>>,
+    pub record_aovs: bool,
+    // Leave the background out of the image, for compositing over another:
+    // the camera rays that miss the scene are black, and the film records
+    // the alpha of what they hit (see: `Aov::Alpha`), which the image is
+    // premultiplied by
+    #[serde(default)]
+    pub transparent: bool,
+    // Or fill it in with a photograph (see: `Backplate`)
+    #[serde(default)]
+    pub backplate: Option<Arc<Backplate>>,
+    // Render one of the false-color debug views instead of the lighting
+    #[serde(default)]
+    pub debug: Option<DebugMode>,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer {
+            crop: None,
+            samples: 1,
+            min_samples: 8,
+            noise_threshold: 0.01,
+            max_depth: 5,
+            tile_size: 32,
+            sampler: SamplerType::Sobol,
+            filter: Arc::from(FilterType::Mitchell.create()),
+            mask: None,
+            record_aovs: false,
+            transparent: false,
+            backplate: None,
+            debug: None,
+        }
+    }
+
+    // The sampler that each render thread draws from: every thread shares the
+    // same sampler seed, since samplers decorrelate pixels by themselves (and
+    // blue-noise masking relies on each pixel seeing the same underlying
+    // sequence)
+    fn pixel_sampler(&self, seed: u32) -> Box<dyn Sampler> {
+        let sampler = self.sampler.create(hash_combine(seed, 0));
+        match self.mask {
+            Some(ref mask) => Box::new(BlueNoiseSampler::new(sampler, mask.clone())),
+            None => sampler,
+        }
+    }
+
+    // Start the `index`th sample of the pixel (x, y), seeding the calling
+    // thread's generator for its path, and return where in the image it is,
+    // with an offset within the pixel drawn from the sampler
+    fn start_sample(sampler: &mut dyn Sampler, x: u32, y: u32, index: u32, seed: u32) -> (Float, Float) {
+        sampler.start_sample(x, y, index);
+        Renderer::seed_path(x, y, index, seed);
+        let (du, dv) = sampler.next_2d();
+        (x as Float + du, y as Float + dv)
+    }
+
+    // Seed the calling thread's generator for the path of the `index`th
+    // sample of the pixel (x, y), so that the path doesn't depend on which
+    // other samples were started alongside it
+    fn seed_path(x: u32, y: u32, index: u32, seed: u32) {
+        let pixel_hash = hash_combine(hash_combine(seed, x), y);
+        rng::reseed(hash_combine(pixel_hash, index));
+    }
+
+    // Trace the `index`th sample of the pixel (x, y) again, with the same
+    // camera ray and random numbers as when the film was rendered (with the
+    // same seed), recording every bounce of its path (e.g. to find out where a
+    // firefly comes from)
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace_sample(&self,
+                        camera: &Camera,
+                        scene: &Scene,
+                        width: u32,
+                        height: u32,
+                        x: u32,
+                        y: u32,
+                        index: u32,
+                        seed: u32)
+                        -> PathRecord {
+        let mut sampler = self.pixel_sampler(seed);
+        let (px, py) = Renderer::start_sample(&mut *sampler, x, y, index, seed);
+        trace_path(&camera.pixel_ray(px, py, width, height), scene, self.max_depth)
+    }
+
+    // The pixels of a film of the given size that are rendered (see: `crop`)
+    pub fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        match self.crop {
+            Some((x0, y0, x1, y1)) => (x0.min(width), y0.min(height), x1.min(width), y1.min(height)),
+            None => (0, 0, width, height),
+        }
+    }
+
+    // The average number of samples taken by each pixel that is rendered
+    pub fn samples_per_pixel(&self, film: &Film) -> Float {
+        let (x0, y0, x1, y1) = self.bounds(film.width, film.height);
+        film.total_samples() as Float / (x1.saturating_sub(x0) * y1.saturating_sub(y0)).max(1) as Float
+    }
+
+    // Take one more sample in every pixel of the film within `bounds` that
+    // hasn't yet converged, returning what the pass did
+    pub fn render_pass(&self,
+                       film: &mut Film,
+                       bounds: (u32, u32, u32, u32),
+                       camera: &Camera,
+                       scene: &Scene,
+                       seed: u32,
+                       progress: &(dyn Fn(&TileProgress) + Sync))
+                       -> RenderStats {
+        self.render_pass_until(film, bounds, camera, scene, seed, &AtomicBool::new(false), progress)
+    }
+
+    // Render every pass into the film within `bounds`, reporting to `sink`
+    // as each tile finishes
+    pub fn render(&self,
+                  film: &mut Film,
+                  bounds: (u32, u32, u32, u32),
+                  camera: &Camera,
+                  scene: &Scene,
+                  seed: u32,
+                  sink: &dyn ProgressSink)
+                  -> RenderStats {
+        let start = Stopwatch::start();
+        let mut stats = RenderStats::new();
+        for pass in 0..self.samples {
+            let samples_per_pixel = self.samples_per_pixel(film);
+            let rays = stats.rays;
+            let report = |tiles: &TileProgress| {
+                let elapsed = start.elapsed();
+                let seconds = elapsed.as_secs_f64();
+                let done = (pass as f64 + tiles.completed as f64 / tiles.total as f64) / self.samples as f64;
+                let pass_samples = tiles.completed as Float / tiles.total as Float;
+                sink.report(&RenderProgress {
+                    pass,
+                    passes: self.samples,
+                    tiles_completed: tiles.completed,
+                    tiles: tiles.total,
+                    samples_per_pixel: samples_per_pixel + pass_samples,
+                    elapsed,
+                    remaining: if seconds > 0.0 {
+                        Some(Duration::from_secs_f64(seconds * (1.0 - done) / done))
+                    } else {
+                        None
+                    },
+                    rays_per_second: if seconds > 0.0 {
+                        ((rays + tiles.rays) as f64 / seconds) as Float
+                    } else {
+                        0.0
+                    },
+                });
+            };
+            stats.add(&self.render_pass(film, bounds, camera, scene, seed, &report));
+        }
+        stats
+    }
+
+    // As above, but skipping the pixels that remain once `cancelled` is set
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "render_pass", skip_all, fields(bounds = ?bounds)))]
+    fn render_pass_until(&self,
+                         film: &mut Film,
+                         bounds: (u32, u32, u32, u32),
+                         camera: &Camera,
+                         scene: &Scene,
+                         seed: u32,
+                         cancelled: &AtomicBool,
+                         progress: &(dyn Fn(&TileProgress) + Sync))
+                         -> RenderStats {
+        let (width, height) = (film.width, film.height);
+        let (x0, y0, x1, y1) = bounds;
+        if x0 >= x1 || y0 >= y1 {
+            return RenderStats::new();
+        }
+        let start = Stopwatch::start();
+        let filter = &*self.filter;
+        // The tiles are rendered in parallel, each splatting its samples into
+        // its own tile of the film (see: `scheduler`)
+        let tiles = scheduler::tiles((x0, y0, x1, y1), self.tile_size);
+        let new_sampler = || self.pixel_sampler(seed);
+        // The pixels of each row of a tile are sampled in packets of
+        // neighbors, whose camera rays are coherent enough that their first
+        // hits are best found together (see: `Scene::intersect_packet`)
+        let render_tile = |sampler: &mut Box<dyn Sampler>, area: &Tile, pixels: &mut [Pixel], tile: &mut FilmTile| {
+            let tile_width = (area.x1 - area.x0) as usize;
+            for (row, pixels) in pixels.chunks_mut(tile_width).enumerate() {
+                for (packet, pixels) in pixels.chunks_mut(PACKET_SIZE).enumerate() {
+                    let (x0, y) = (area.x0 + (packet * PACKET_SIZE) as u32, area.y0 + row as u32);
+                    let samples: Vec<(usize, Float, Float)> = pixels.iter()
+                        .enumerate()
+                        .filter(|&(_, pixel)| {
+                            !(pixel.count >= self.min_samples && pixel.is_converged(self.noise_threshold) ||
+                              cancelled.load(Ordering::Relaxed))
+                        })
+                        .map(|(lane, pixel)| {
+                            let (px, py) = Renderer::start_sample(&mut **sampler, x0 + lane as u32, y, pixel.count, seed);
+                            (lane, px, py)
+                        })
+                        .collect();
+                    let rays: Vec<Ray> = samples.iter().map(|&(_, px, py)| camera.pixel_ray(px, py, width, height)).collect();
+                    let mut hits = match self.debug {
+                        Some(_) => Vec::new(),
+                        None => scene.intersect_packet(&rays, RayKind::Camera),
+                    }
+                    .into_iter();
+                    for (&(lane, px, py), r) in samples.iter().zip(&rays) {
+                        let (x, pixel) = (x0 + lane as u32, &mut pixels[lane]);
+                        Renderer::seed_path(x, y, pixel.count, seed);
+                        let behind = match self.backplate {
+                            Some(ref plate) => Some(plate.lookup(px / width as Float, py / height as Float)),
+                            None if self.transparent => Some(Color::black()),
+                            None => None,
+                        };
+                        let radiance = if let Some(mode) = self.debug {
+                            trace_debug(mode, r, scene, self.max_depth)
+                        } else if self.record_aovs || self.transparent {
+                            let mut aovs = AovSample::new();
+                            let radiance = trace_camera_hit(r, hits.next().flatten(), scene, self.max_depth, behind, Some(&mut aovs));
+                            pixel.add_aov_sample(&aovs);
+                            radiance
+                        } else {
+                            trace_camera_hit(r, hits.next().flatten(), scene, self.max_depth, behind, None)
+                        };
+                        // A sample that isn't finite would stay in the pixel's
+                        // sum for good, so it's counted and taken as black
+                        // instead
+                        let radiance = if radiance.is_finite() {
+                            radiance
+                        } else {
+                            if cfg!(feature = "validate") {
+                                panic!("sample {} of pixel ({}, {}) is {:?}", pixel.count, x, y, radiance);
+                            }
+                            stats::count(Counter::InvalidSamples);
+                            Color::black()
+                        };
+                        pixel.add_sample(&radiance);
+                        tile.add_sample(px, py, &radiance, filter);
+                    }
+                }
+            }
+        };
+        let counts = Counts::new();
+        scheduler::render_tiles(film, &tiles, filter, &counts, new_sampler, render_tile, progress);
+
+        let mut stats = RenderStats::collect(&counts);
+        stats.add_time("render", start.elapsed());
+        #[cfg(feature = "tracing")]
+        tracing::info!(rays = stats.rays,
+                       invalid_samples = stats.invalid_samples,
+                       rays_per_second = stats.rays_per_second(),
+                       "finished the pass");
+        stats
+    }
+
+    // Render every pass into the film on a background thread, so that the
+    // host (e.g. a GUI, or a server) stays responsive and can stop the render
+    // whenever it likes
+    pub fn render_async(&self, scene: Arc<Scene>, camera: Camera, seed: u32, film: Film) -> RenderHandle {
+        let renderer = self.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(Mutex::new(Progress::default()));
+        let (thread_cancelled, thread_progress) = (cancelled.clone(), progress.clone());
+        let thread = thread::spawn(move || {
+            let start = Stopwatch::start();
+            let mut film = film;
+            let mut pass = 0;
+            let bounds = renderer.bounds(film.width, film.height);
+            while pass < renderer.samples && !thread_cancelled.load(Ordering::Relaxed) {
+                renderer.render_pass_until(&mut film, bounds, &camera, &scene, seed, &thread_cancelled, &|_| {});
+                pass += 1;
+                *thread_progress.lock().unwrap() = Progress {
+                    pass,
+                    samples_per_pixel: renderer.samples_per_pixel(&film) as f64,
+                    elapsed_seconds: start.elapsed().as_secs_f64(),
+                    finished: false,
+                };
+            }
+            thread_progress.lock().unwrap().finished = true;
+            film
+        });
+        RenderHandle {
+            thread,
+            cancelled,
+            progress,
+        }
+    }
+}
+
+fn serialize_filter<S: Serializer>(filter: &Arc<dyn Filter>, serializer: S) -> Result<S::Ok, S::Error> {
+    match filter.data() {
+        Some(data) => data.serialize(serializer),
+        None => Err(ser::Error::custom("the filter can't be saved")),
+    }
+}
+
+fn deserialize_filter<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<dyn Filter>, D::Error> {
+    Ok(Arc::from(FilterData::deserialize(deserializer)?.create()))
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new()
+    }
+}
+
+// A render running in the background (see: `Renderer::render_async`)
+pub struct RenderHandle {
+    thread: JoinHandle<Film>,
+    cancelled: Arc<AtomicBool>,
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl RenderHandle {
+    // How far along the render was after its last finished pass
+    pub fn progress(&self) -> Progress {
+        *self.progress.lock().unwrap()
+    }
+
+    // Stop rendering as soon as possible: the pass underway is cut short,
+    // leaving its remaining pixels with one sample fewer
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // Wait for the render to finish (or, once cancelled, to stop), and take
+    // the film (unless the render thread panicked)
+    pub fn join(self) -> Result<Film> {
+        self.thread.join().map_err(TracerError::from_panic)
+    }
+}
+
+#[test]
+fn test_render_async() {
+    use shape::Sphere;
+    use material::Lambertian;
+    use primitive::Primitive;
+    use vector::Vector;
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -1.0), 0.5),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    let scene = Arc::new(scene);
+    let camera = Camera::new(60.0, 1.0);
+    let mut renderer = Renderer::new();
+    renderer.samples = 3;
+    renderer.min_samples = 3;
+
+    let handle = renderer.render_async(scene.clone(), camera, 0, Film::new(8, 8));
+    let film = handle.join().unwrap();
+    assert_eq!(film.total_samples(), 3 * 64);
+
+    // Cancelling before the render gets going leaves the film (nearly) empty
+    renderer.samples = 1000;
+    let handle = renderer.render_async(scene, camera, 0, Film::new(8, 8));
+    handle.cancel();
+    let progress = handle.progress();
+    let film = handle.join().unwrap();
+    assert!(film.total_samples() <= 64);
+    assert!(progress.pass <= 1);
+}
+
+#[test]
+fn test_render_progress() {
+    use shape::Sphere;
+    use material::Lambertian;
+    use primitive::Primitive;
+    use vector::Vector;
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -1.0), 0.5),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    let mut renderer = Renderer::new();
+    renderer.samples = 2;
+    renderer.min_samples = 2;
+    renderer.tile_size = 4;
+    let mut film = Film::new(8, 8);
+    let reports = Mutex::new(Vec::new());
+    let sink = |progress: &RenderProgress| reports.lock().unwrap().push(*progress);
+    renderer.render(&mut film, (0, 0, 8, 8), &Camera::new(60.0, 1.0), &scene, 0, &sink);
+    assert_eq!(film.total_samples(), 2 * 64);
+
+    // Every tile of every pass reports, and the last report is of the finished
+    // render
+    let reports = reports.into_inner().unwrap();
+    assert_eq!(reports.len(), 2 * 4);
+    let last = reports.last().unwrap();
+    assert_eq!((last.pass, last.passes, last.tiles_completed, last.tiles), (1, 2, 4, 4));
+    assert_eq!(last.samples_per_pixel, 2.0);
+    assert!(last.remaining.is_none_or(|remaining| remaining == Duration::from_secs(0)));
+}
+
+#[test]
+fn test_renderer_round_trip() {
+    let mut renderer = Renderer::new();
+    renderer.samples = 64;
+    renderer.crop = Some((1, 2, 3, 4));
+    renderer.filter = Arc::from(FilterType::Gaussian.create());
+    let source = toml::to_string(&renderer).unwrap();
+    let loaded: Renderer = toml::from_str(&source).unwrap();
+    assert_eq!(loaded.samples, 64);
+    assert_eq!(loaded.crop, Some((1, 2, 3, 4)));
+    assert_eq!(loaded.sampler, renderer.sampler);
+    assert_eq!(loaded.filter.data(), renderer.filter.data());
+}
+
+// (With the `validate` feature, bad samples panic instead)
+#[cfg(not(feature = "validate"))]
+#[test]
+fn test_invalid_samples() {
+    use vector::Vector;
+    use ray::Ray;
+    use shape::Sphere;
+    use shape::DifferentialGeometry;
+    use material::Material;
+    use primitive::Primitive;
+
+    // A material whose every bounce is NaN
+    struct Broken;
+    impl Material for Broken {
+        fn scatter(&self, incident: &Ray, intersection: &DifferentialGeometry, attenuation: &mut Color) -> Ray {
+            *attenuation = Color::new(Float::NAN, 0.0, 0.0);
+            Ray::spawn(&intersection.position, &intersection.normal, &intersection.normal, incident.t_max)
+        }
+    }
+
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -1.0), 0.5), Arc::new(Broken)));
+    let mut renderer = Renderer::new();
+    renderer.samples = 2;
+    renderer.min_samples = 2;
+    let mut film = Film::new(8, 8);
+    renderer.render_pass(&mut film, (0, 0, 8, 8), &Camera::new(60.0, 1.0), &scene, 0, &|_| {});
+    // The pixels that saw the sphere are black, rather than NaN
+    let framebuffer = film.to_framebuffer();
+    assert!(framebuffer.get(4, 4).is_finite());
+    assert_eq!(framebuffer.get(4, 4), Color::black());
+}
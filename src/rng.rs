@@ -0,0 +1,128 @@
+use vector::Float;
+use sampler::hash;
+
+use std::cell::RefCell;
+
+// Every thread owns a random number generator, which the renderer reseeds
+// (from the render seed, pixel, and sample index) before each camera sample:
+// the random decisions made along a path are then independent of which
+// thread traced it, so a given seed always reproduces the same image
+thread_local! {
+    static RNG: RefCell<XorShiftRng> = RefCell::new(seeded_rng(0));
+}
+
+// A source of uniformly distributed random bits, from which everything else
+// is drawn (see: `sampling`)
+pub trait Rng {
+    fn next_u32(&mut self) -> u32;
+
+    fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | (self.next_u32() as u64)
+    }
+
+    // A uniformly distributed number in [0, 1), from 52 random bits placed in
+    // the mantissa of a number in [1, 2)
+    fn next_f64(&mut self) -> f64 {
+        f64::from_bits(0x3ff0000000000000 | (self.next_u64() & 0xfffffffffffff)) - 1.0
+    }
+
+    // (In the precision that the renderer was built with)
+    #[cfg(not(feature = "f32"))]
+    fn next_float(&mut self) -> Float {
+        self.next_f64()
+    }
+
+    // (From 24 random bits, since rounding 52 of them to single precision
+    // could round up to one)
+    #[cfg(feature = "f32")]
+    fn next_float(&mut self) -> Float {
+        (self.next_u32() >> 8) as Float * (1.0 / (1u32 << 24) as Float)
+    }
+
+    // A uniformly distributed integer in [low, high), without modulo bias
+    fn gen_range(&mut self, low: usize, high: usize) -> usize {
+        let range = (high - low) as u64;
+        let zone = u64::MAX - u64::MAX % range;
+        loop {
+            let v = self.next_u64();
+            if v < zone {
+                return low + (v % range) as usize;
+            }
+        }
+    }
+
+    // Fisher-Yates
+    fn shuffle<T>(&mut self, values: &mut [T]) {
+        for i in (1..values.len()).rev() {
+            let j = self.gen_range(0, i + 1);
+            values.swap(i, j);
+        }
+    }
+}
+
+// Marsaglia's xorshift128 generator ("Xorshift RNGs", 2003), which is small
+// and fast, and doesn't depend on the platform (so that renders match
+// everywhere, including the web)
+#[derive(Clone, Debug)]
+pub struct XorShiftRng {
+    state: [u32; 4],
+}
+
+impl Rng for XorShiftRng {
+    fn next_u32(&mut self) -> u32 {
+        let [x, y, z, w] = self.state;
+        let t = x ^ (x << 11);
+        self.state = [y, z, w, w ^ (w >> 19) ^ (t ^ (t >> 8))];
+        self.state[3]
+    }
+}
+
+// A handle to the calling thread's generator, for code that's handed no
+// other (e.g. materials, as they scatter)
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ThreadRng;
+
+impl Rng for ThreadRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+}
+
+// Returns a small, fast RNG whose state is derived from `seed`
+pub fn seeded_rng(seed: u32) -> XorShiftRng {
+    // The xorshift generator must not be seeded with all zeros
+    let mut state = [0u32; 4];
+    let mut h = seed;
+    for word in &mut state {
+        h = hash(h);
+        *word = h | 1;
+    }
+    XorShiftRng { state }
+}
+
+// Reseed the calling thread's generator
+pub fn reseed(seed: u32) {
+    RNG.with(|rng| *rng.borrow_mut() = seeded_rng(seed));
+}
+
+// A uniformly distributed random number in [0, 1), drawn from the calling
+// thread's generator
+pub fn next_float() -> Float {
+    ThreadRng.next_float()
+}
+
+#[test]
+fn test_next_float() {
+    // The most random bits still come out below one
+    struct Ones;
+    impl Rng for Ones {
+        fn next_u32(&mut self) -> u32 {
+            u32::MAX
+        }
+    }
+    assert!(Ones.next_float() < 1.0 && Ones.next_f64() < 1.0);
+
+    let mut rng = seeded_rng(7);
+    let mean = (0..10000).map(|_| rng.next_float()).sum::<Float>() / 10000.0;
+    assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+}
@@ -0,0 +1,271 @@
+use vector::Float;
+use rng::seeded_rng;
+use rng::XorShiftRng;
+use rng::Rng;
+
+use serde::{Deserialize, Serialize};
+
+// The largest Float that is strictly less than 1.0
+const ONE_MINUS_EPSILON: Float = 1.0 - Float::EPSILON * 0.5;
+
+// Samplers produce the (ideally well-distributed) sample points used to
+// place rays within a pixel
+pub trait Sampler: Send {
+    // Prepare the sampler to generate sample `index` of the pixel at (x, y)
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    // Return the next dimension of the current sample, in [0, 1)
+    fn next_1d(&mut self) -> Float;
+
+    // Return the next two dimensions of the current sample, in [0, 1)^2
+    fn next_2d(&mut self) -> (Float, Float) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerType {
+    Random,
+    Halton,
+    Sobol,
+}
+
+impl SamplerType {
+    // Construct a new sampler of this type, using `seed` for any
+    // randomization (scrambling) that it performs
+    pub fn create(&self, seed: u32) -> Box<dyn Sampler> {
+        match *self {
+            SamplerType::Random => Box::new(RandomSampler::new(seed)),
+            SamplerType::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// A small, fast integer hash (see: "Hash Functions for GPU Rendering",
+// Jarzynski and Olano)
+pub fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub fn hash_combine(seed: u32, v: u32) -> u32 {
+    hash(seed ^ v.wrapping_add(0x9e3779b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+fn to_unit_float(x: u32) -> Float {
+    (x as Float * (1.0 / 4294967296.0)).min(ONE_MINUS_EPSILON)
+}
+
+// Uniform random samples with no stratification whatsoever
+pub struct RandomSampler {
+    seed: u32,
+    rng: XorShiftRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u32) -> RandomSampler {
+        RandomSampler {
+            seed,
+            rng: seeded_rng(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        // Derive the stream from the sample's identity rather than the order
+        // in which samples are taken
+        let pixel_hash = hash_combine(hash_combine(self.seed, x), y);
+        self.rng = seeded_rng(hash_combine(pixel_hash, index));
+    }
+
+    fn next_1d(&mut self) -> Float {
+        self.rng.next_float()
+    }
+}
+
+// The first few prime numbers, one per Halton dimension
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+// The Halton sequence uses the radical inverse of the sample index in a
+// different prime base for each dimension
+pub struct HaltonSampler {
+    // Random digit permutations (one per dimension) used for scrambling
+    permutations: Vec<Vec<u32>>,
+    seed: u32,
+    pixel_hash: u32,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u32) -> HaltonSampler {
+        let mut rng = seeded_rng(seed);
+        let permutations = PRIMES.iter()
+            .map(|&base| {
+                let mut perm: Vec<u32> = (0..base).collect();
+                rng.shuffle(&mut perm);
+                perm
+            })
+            .collect();
+
+        HaltonSampler {
+            permutations,
+            seed,
+            pixel_hash: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn scrambled_radical_inverse(&self, dimension: usize, mut a: u64) -> Float {
+        let base = PRIMES[dimension] as u64;
+        let perm = &self.permutations[dimension];
+        let inv_base = 1.0 / base as Float;
+        let mut reversed: u64 = 0;
+        let mut inv_base_n = 1.0;
+        while a > 0 {
+            let next = a / base;
+            let digit = a - next * base;
+            reversed = reversed * base + perm[digit as usize] as u64;
+            inv_base_n *= inv_base;
+            a = next;
+        }
+        // The infinite tail of (permuted) zero digits forms a geometric series
+        let tail = inv_base * perm[0] as Float / (1.0 - inv_base);
+        ((reversed as Float + tail) * inv_base_n).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_hash = hash_combine(hash_combine(self.seed, x), y);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let dimension = self.dimension % PRIMES.len();
+        self.dimension += 1;
+
+        // Decorrelate neighboring pixels with a per-pixel toroidal shift
+        // (Cranley-Patterson rotation), which preserves the sequence's
+        // stratification within the pixel
+        let shift = to_unit_float(hash_combine(self.pixel_hash, dimension as u32));
+        let u = self.scrambled_radical_inverse(dimension, self.index) + shift;
+        if u >= 1.0 { u - 1.0 } else { u }
+    }
+}
+
+// Generator matrix for the second dimension of the Sobol sequence (the first
+// dimension is the van der Corput sequence, i.e. a bit reversal)
+const SOBOL_MATRIX_1: [u32; 32] = sobol_matrix_1();
+
+const fn sobol_matrix_1() -> [u32; 32] {
+    // Primitive polynomial x + 1, with m_1 = 1
+    let mut m = [0u32; 32];
+    m[0] = 1 << 31;
+    let mut i = 1;
+    while i < 32 {
+        m[i] = m[i - 1] ^ (m[i - 1] >> 1);
+        i += 1;
+    }
+    m
+}
+
+fn sobol(index: u32, dimension: usize) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut v = 0;
+    let mut i = index;
+    let mut k = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            v ^= SOBOL_MATRIX_1[k];
+        }
+        i >>= 1;
+        k += 1;
+    }
+    v
+}
+
+// Owen scrambling of a base-2 value via hashing (see: "Practical Hash-based
+// Owen Scrambling", Burley)
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// The 2D Sobol sequence with Owen scrambling, where successive pairs of
+// dimensions are decorrelated by shuffling the sequence with different seeds
+pub struct SobolSampler {
+    seed: u32,
+    pixel_hash: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel_hash: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample(&self, dimension: usize) -> Float {
+        let seed = hash_combine(self.pixel_hash, self.dimension);
+        let shuffled = nested_uniform_scramble(self.index, seed);
+        let v = sobol(shuffled, dimension);
+        to_unit_float(nested_uniform_scramble(v, hash_combine(seed, dimension as u32)))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_hash = hash_combine(hash_combine(self.seed, x), y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let u = self.sample(0);
+        self.dimension += 1;
+        u
+    }
+
+    fn next_2d(&mut self) -> (Float, Float) {
+        let u = (self.sample(0), self.sample(1));
+        self.dimension += 1;
+        u
+    }
+}
+
+#[test]
+fn test_sobol_stratification() {
+    // Any aligned block of 4 samples from a (0, 2)-sequence should place
+    // exactly one sample in each quadrant of the unit square
+    let mut sampler = SobolSampler::new(7);
+    let mut quadrants = [0; 4];
+    for i in 0..4 {
+        sampler.start_sample(3, 5, i);
+        let (u, v) = sampler.next_2d();
+        assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+        quadrants[(u * 2.0) as usize + 2 * (v * 2.0) as usize] += 1;
+    }
+    assert_eq!(quadrants, [1, 1, 1, 1]);
+}
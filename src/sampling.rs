@@ -0,0 +1,108 @@
+use vector::Vector;
+use vector::Float;
+use vector::consts;
+use rng::Rng;
+
+// Warps uniform random numbers into the distributions that the renderer
+// samples from: each routine draws from the generator it's given, so that
+// samples can be reproduced by seeding it (see: `rng`)
+
+// A uniformly distributed point inside the unit sphere, by rejection: pick
+// points inside the cube around it until one is also inside the sphere
+pub fn unit_sphere(rng: &mut impl Rng) -> Vector {
+    loop {
+        let p = Vector::new(rng.next_float(), rng.next_float(), rng.next_float()) * 2.0 - Vector::one();
+        if p.squared_length() <= 1.0 {
+            return p;
+        }
+    }
+}
+
+// A uniformly distributed point inside the unit disk, by Shirley and Chiu's
+// concentric mapping (which maps square strata onto disk strata with little
+// distortion)
+pub fn unit_disk(rng: &mut impl Rng) -> (Float, Float) {
+    let (u, v) = (rng.next_float() * 2.0 - 1.0, rng.next_float() * 2.0 - 1.0);
+    if u == 0.0 && v == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if u.abs() > v.abs() {
+        (u, consts::FRAC_PI_4 * (v / u))
+    } else {
+        (v, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (u / v))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// A unit direction about the (unit) normal n, with a density proportional to
+// the cosine of its angle to n (cos(theta) / pi), by Malley's method: a point
+// on the disk projected up onto the hemisphere
+pub fn cosine_hemisphere(rng: &mut impl Rng, n: &Vector) -> Vector {
+    let (x, y) = unit_disk(rng);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    let (s, t) = basis(n);
+    (s * x + t * y + *n * z).normalize()
+}
+
+// A microfacet normal about the (unit) normal n, distributed by the GGX (or
+// Trowbridge-Reitz) distribution of roughness `alpha`, with a density of
+// D(m) cos(theta_m)
+pub fn ggx(rng: &mut impl Rng, n: &Vector, alpha: Float) -> Vector {
+    let u = rng.next_float();
+    let phi = 2.0 * consts::PI * rng.next_float();
+    let tan_squared = alpha * alpha * u / (1.0 - u);
+    let cos_theta = 1.0 / (1.0 + tan_squared).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (s, t) = basis(n);
+    (s * (sin_theta * phi.cos()) + t * (sin_theta * phi.sin()) + *n * cos_theta).normalize()
+}
+
+// The barycentric coordinates (b0, b1) of a uniformly distributed point on a
+// triangle, where the third is 1 - b0 - b1
+pub fn triangle(rng: &mut impl Rng) -> (Float, Float) {
+    let s = rng.next_float().sqrt();
+    let v = rng.next_float();
+    (1.0 - s, v * s)
+}
+
+// Two unit vectors that are perpendicular to each other and to the (unit)
+// vector n ("Building an Orthonormal Basis, Revisited", Duff et al.)
+pub fn basis(n: &Vector) -> (Vector, Vector) {
+    let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (Vector::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+     Vector::new(b, sign + n.y * n.y * a, -n.y))
+}
+
+#[test]
+fn test_sampling() {
+    use rng::seeded_rng;
+    use vector::TEST_EPSILON;
+
+    let n = Vector::new(1.0, 2.0, -2.0).normalize();
+    let (s, t) = basis(&n);
+    assert!(s.dot(&n).abs() < TEST_EPSILON && t.dot(&n).abs() < TEST_EPSILON && s.dot(&t).abs() < TEST_EPSILON);
+
+    let mut rng = seeded_rng(7);
+    let count = 20000;
+    let mut mean_cos = 0.0;
+    for _ in 0..count {
+        assert!(unit_sphere(&mut rng).length() <= 1.0);
+        let (x, y) = unit_disk(&mut rng);
+        assert!(x * x + y * y <= 1.0 + TEST_EPSILON);
+        let (b0, b1) = triangle(&mut rng);
+        assert!(b0 >= 0.0 && b1 >= 0.0 && b0 + b1 <= 1.0 + TEST_EPSILON);
+        let m = ggx(&mut rng, &n, 0.3);
+        assert!(m.dot(&n) > 0.0 && (m.length() - 1.0).abs() < 1e-4);
+
+        let d = cosine_hemisphere(&mut rng, &n);
+        assert!(d.dot(&n) >= 0.0);
+        mean_cos += d.dot(&n) / count as Float;
+    }
+    // (The mean cosine of cosine-distributed directions is 2 / 3)
+    assert!((mean_cos - 2.0 / 3.0).abs() < 0.01);
+
+    // The same seed draws the same samples
+    assert_eq!(cosine_hemisphere(&mut seeded_rng(3), &n), cosine_hemisphere(&mut seeded_rng(3), &n));
+}
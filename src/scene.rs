@@ -1,33 +1,1169 @@
 use shape::DifferentialGeometry;
+use shape::Geometry;
+use shape::Shape;
+use shape::Triangle;
+use shape::Quad;
+use mesh::Mesh;
 use ray::Ray;
 use material::Material;
+use material::MaterialData;
+use color::Color;
+use color::WAVELENGTHS;
 use primitive::Primitive;
+use primitive::RayKind;
+use primitive::Visibility;
+use camera::Camera;
+use environment::Environment;
+use transform::Transform;
+use texture::TextureCache;
+use texture::DEFAULT_BUDGET;
+use vector::Vector;
+use vector::Float;
+use bvh::Bvh;
+use bvh::BvhStats;
+use light::Lights;
+use memory::megabytes;
+use stats;
+use stats::Counter;
+use error::Result;
+use error::TracerError;
 
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::Path;
 use std::sync::Arc;
 
-// Scenes contain a list of primitives
+// The version of the format in which scenes are saved, which goes up
+// whenever the format changes (scenes saved by later versions are refused)
+pub const FORMAT_VERSION: u32 = 7;
+
+// Where a ray hit the scene (see: `Scene::raycast`), which, unlike the
+// differential geometry of a hit, borrows nothing from the scene
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    // How far along the ray
+    pub t: Float,
+    pub position: Vector,
+    pub normal: Vector,
+    pub uv: (Float, Float),
+    // The IDs of the primitive and material hit (see: `Scene::add`)
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+// What a scene is made of, and what its BVHs made of it (see: `Scene::stats`),
+// e.g. to see why a large scene renders slowly
+#[derive(Clone, Debug, Default)]
+pub struct SceneStats {
+    // The number of primitives of each kind of shape (see: `Geometry::kind`),
+    // in the order that they first appear
+    pub shapes: Vec<(&'static str, usize)>,
+    pub materials: usize,
+    // The distinct meshes (instances share theirs, see: `Primitive`), the
+    // triangles in them, and the triangles rendered (counting each instance)
+    pub meshes: usize,
+    pub triangles: usize,
+    pub instanced_triangles: usize,
+    // The scene's BVH (if it's been built), and every distinct mesh's
+    pub bvh: Option<BvhStats>,
+    pub mesh_bvhs: BvhStats,
+    // The (approximate) number of bytes that the primitives and their
+    // geometry take, not counting the BVHs
+    pub geometry_memory: usize,
+}
+
+impl SceneStats {
+    pub fn primitives(&self) -> usize {
+        self.shapes.iter().map(|s| s.1).sum()
+    }
+
+    // The (approximate) number of bytes that the scene takes
+    pub fn memory(&self) -> usize {
+        self.geometry_memory + self.bvh.as_ref().map_or(0, |b| b.memory) + self.mesh_bvhs.memory
+    }
+}
+
+fn write_bvh_stats(f: &mut fmt::Formatter, name: &str, bvh: &BvhStats) -> fmt::Result {
+    writeln!(f,
+             "{}: {} nodes, {} leaves ({:.2} primitives each on average), {} deep, {:.2} MB",
+             name,
+             bvh.nodes,
+             bvh.leaves,
+             bvh.mean_leaf_size(),
+             bvh.max_depth(),
+             megabytes(bvh.memory))?;
+    let depths: Vec<String> = bvh.leaf_depths.iter().map(|n| n.to_string()).collect();
+    writeln!(f, "{} leaves at each depth: {}", name, depths.join(" "))
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shapes: Vec<String> = self.shapes.iter().map(|&(kind, n)| format!("{} {}", n, kind)).collect();
+        writeln!(f, "primitives: {} ({})", self.primitives(), shapes.join(", "))?;
+        writeln!(f, "materials: {}", self.materials)?;
+        writeln!(f,
+                 "meshes: {} ({} triangles, {} with instances)",
+                 self.meshes,
+                 self.triangles,
+                 self.instanced_triangles)?;
+        match self.bvh {
+            Some(ref bvh) => write_bvh_stats(f, "BVH", bvh)?,
+            None => writeln!(f, "BVH: not built")?,
+        }
+        if self.meshes > 0 {
+            write_bvh_stats(f, "mesh BVHs", &self.mesh_bvhs)?;
+        }
+        write!(f, "estimated memory: {:.2} MB", megabytes(self.memory()))
+    }
+}
+
+// Scenes contain a list of primitives, along with any number of named
+// cameras from which they can be rendered, lit by their environment
 pub struct Scene {
+    // (The primitives are stored together, but they aren't allocated from
+    // an arena, like the BVH's nodes are: the meshes and materials that they
+    // share through `Arc`s are still released one by one when the scene is
+    // dropped)
     pub items: Vec<Primitive>,
+    // Every material used by the primitives, indexed by their material IDs,
+    // so that hits can borrow their material rather than clone an `Arc`
+    pub materials: Vec<Arc<dyn Material>>,
+    pub cameras: Vec<(String, Camera)>,
+    pub environment: Environment,
+    // Accelerates intersection once built (see: `build_bvh`): until then,
+    // every ray is tested against every primitive
+    pub bvh: Option<Bvh>,
+    // The emissive triangles that light the scene (gathered along with the
+    // BVH, see: `Lights`), apart from its environment
+    pub lights: Lights,
+    // The openings (e.g. windows and doors) through which the environment
+    // lights the scene's interior, which are sampled like lights (see:
+    // `light::sample_portals`): they aren't surfaces, so rays pass through
+    // them, and the environment is seen through everything else as before
+    pub portals: Vec<Quad>,
 }
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene { items: Vec::new() }
+        Scene {
+            items: Vec::new(),
+            materials: Vec::new(),
+            cameras: Vec::new(),
+            environment: Environment::Sky,
+            bvh: None,
+            lights: Lights::default(),
+            portals: Vec::new(),
+        }
+    }
+
+    // (Re)build the BVH over the primitives, and gather their lights, which
+    // must be done again after any of them move
+    //
+    // TODO: an optional Embree backend, which would build the triangles of
+    // the scene's meshes into Embree instead. It's still deferred, even now
+    // that there are meshes to build: Embree (embree3) can't be built or
+    // linked against here, so the backend's hits couldn't be tested against
+    // this BVH's, and it would have to carry over everything that the hits
+    // depend on (each primitive's transform and `Visibility`, the levels of
+    // `LodMesh`es, and `Mesh`'s shading normals and texture coordinates)
+    // without ever being run
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<_> = self.items.iter().map(|item| item.bounds()).collect();
+        self.bvh = Some(Bvh::new(&bounds));
+        self.lights = Lights::new(self);
+    }
+
+    // Pick the level of detail of every instance of a mesh with levels (see:
+    // `LodMesh`), for the given camera rendering an image `height` pixels
+    // tall, which must be done again whenever the camera or the instances
+    // move
+    pub fn select_lods(&mut self, camera: &Camera, height: u32) {
+        for item in &mut self.items {
+            let transform = item.transform;
+            if let Geometry::Lod(ref mut lod) = item.shape {
+                if let Some(bounds) = lod.bounds() {
+                    lod.level = lod.select(&bounds.transformed(&transform), camera, height);
+                }
+            }
+        }
+    }
+
+    // Save the whole scene (see: `Serialize`) as TOML
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let source = toml::to_string(self).map_err(|why| TracerError::InvalidParameter(why.to_string()))?;
+        Ok(fs::write(path, source)?)
+    }
+
+    // Load a scene saved by `save`
+    pub fn load(path: &Path) -> Result<Scene> {
+        toml::from_str(&fs::read_to_string(path)?).map_err(|why| TracerError::Parse(why.to_string()))
+    }
+
+    pub fn add_camera(&mut self, name: &str, camera: Camera) {
+        self.cameras.push((name.to_string(), camera));
+    }
+
+    pub fn camera(&self, name: &str) -> Option<&Camera> {
+        self.cameras.iter().find(|c| c.0 == name).map(|c| &c.1)
+    }
+
+    pub fn stats(&self) -> SceneStats {
+        let mut stats = SceneStats {
+            materials: self.materials.len(),
+            bvh: self.bvh.as_ref().map(|bvh| bvh.stats()),
+            geometry_memory: self.items.capacity() * mem::size_of::<Primitive>(),
+            ..SceneStats::default()
+        };
+        let mut seen = HashSet::new();
+        for item in &self.items {
+            let kind = item.shape.kind();
+            match stats.shapes.iter().position(|s| s.0 == kind) {
+                Some(i) => stats.shapes[i].1 += 1,
+                None => stats.shapes.push((kind, 1)),
+            }
+            let meshes: &[Arc<Mesh>] = match item.shape {
+                Geometry::Mesh(ref mesh) => {
+                    stats.instanced_triangles += mesh.len();
+                    ::std::slice::from_ref(mesh)
+                }
+                Geometry::Lod(ref lod) => {
+                    stats.instanced_triangles += lod.mesh().len();
+                    &lod.levels
+                }
+                Geometry::Spheres(ref spheres) => {
+                    stats.geometry_memory += spheres.len() * (4 * mem::size_of::<Float>() + mem::size_of::<u32>());
+                    &[]
+                }
+                _ => &[],
+            };
+            for mesh in meshes {
+                if seen.insert(Arc::as_ptr(mesh)) {
+                    stats.meshes += 1;
+                    stats.triangles += mesh.len();
+                    stats.mesh_bvhs.add(&mesh.bvh_stats());
+                    stats.geometry_memory += mesh.memory();
+                }
+            }
+        }
+        stats
+    }
+
+    // Add a primitive to the scene, assigning it stable IDs: objects are
+    // numbered in the order that they are added, and materials in the order
+    // that they are first used (primitives that share a material share its ID)
+    pub fn add(&mut self, mut primitive: Primitive) {
+        primitive.object_id = self.items.len() as u32;
+        primitive.material_id = self.add_material(&primitive.material);
+        self.items.push(primitive);
+        self.bvh = None;
+    }
+
+    // Add a material to the material table (if it isn't already there),
+    // returning its ID
+    pub fn add_material(&mut self, material: &Arc<dyn Material>) -> u32 {
+        match self.materials.iter().position(|m| Arc::ptr_eq(m, material)) {
+            Some(id) => id as u32,
+            None => {
+                self.materials.push(material.clone());
+                self.materials.len() as u32 - 1
+            }
+        }
+    }
+
+    // Swap a material for another, wherever it's used, keeping its ID (e.g.
+    // after it's edited, without rebuilding the primitives that use it)
+    pub fn replace_material(&mut self, old: &Arc<dyn Material>, new: &Arc<dyn Material>) {
+        for material in &mut self.materials {
+            if Arc::ptr_eq(material, old) {
+                *material = new.clone();
+            }
+        }
+        for item in &mut self.items {
+            if Arc::ptr_eq(&item.material, old) {
+                item.material = new.clone();
+            }
+        }
+    }
+
+    pub fn material(&self, material_id: u32) -> &dyn Material {
+        &*self.materials[material_id as usize]
     }
 
-    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry, Arc<Material>)> {
+    // Look for mistakes that would otherwise only show up in the image (as
+    // black or NaN pixels, or missing shapes): degenerate or non-finite
+    // shapes and transforms, material IDs outside the material table,
+    // materials with non-finite parameters, lights that emit nothing or that
+    // are never sampled, and textures that can't be loaded, returning a
+    // description of each (which is empty when nothing is wrong)
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // (Gathered afresh, since the scene's own lights are only gathered
+        // once its BVH is built)
+        let lights = Lights::new(self);
+        for item in &self.items {
+            let object = format!("object {}", item.object_id);
+            let scale = item.transform.scale();
+            if !item.transform.translation().is_finite() || !scale.is_finite() {
+                problems.push(format!("{}: its transform isn't finite", object));
+            } else if scale.x * scale.y * scale.z == 0.0 {
+                problems.push(format!("{}: its transform scales it to nothing", object));
+            }
+            for problem in shape_problems(&item.shape, self.materials.len()) {
+                problems.push(format!("{}: {}", object, problem));
+            }
+            // Other shapes only light what paths happen to find them from,
+            // which is bound to be noisy
+            let emits = shape_materials(&item.shape, item.material_id)
+                .iter()
+                .filter_map(|&id| self.materials.get(id as usize))
+                .any(|material| material.emission().luminance() > 0.0);
+            if emits && !lights.contains(item.object_id) {
+                problems.push(format!("{}: it emits light, but only triangles, quads and meshes are sampled as lights",
+                                      object));
+            }
+        }
+        for (id, material) in self.materials.iter().enumerate() {
+            if let Some(problem) = material.data().and_then(|data| material_problem(&data)) {
+                problems.push(format!("material {}: {}", id, problem));
+            }
+        }
+        for (i, portal) in self.portals.iter().enumerate() {
+            for problem in shape_problems(&Geometry::Quad(portal.clone()), self.materials.len()) {
+                problems.push(format!("portal {}: {}", i, problem));
+            }
+        }
+        problems
+    }
+
+    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry<'_>, &dyn Material)> {
+        self.intersect_primitive(incident).map(|(dg, item)| {
+            let material_id = dg.material_id.unwrap_or(item.material_id);
+            (dg, self.material(material_id))
+        })
+    }
+
+    // The closest hit of a ray, for queries other than rendering (e.g. to
+    // pick objects under the cursor, or to place objects on surfaces): this
+    // needs no renderer, and is accelerated by the BVH once it's built
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        self.intersect_primitive(ray).map(|(dg, item)| {
+            Hit {
+                t: dg.t,
+                position: dg.position,
+                normal: dg.normal,
+                uv: dg.uv,
+                object_id: item.object_id,
+                material_id: dg.material_id.unwrap_or(item.material_id),
+            }
+        })
+    }
+
+    // What's under the pixel (x, y) of an image `width` by `height` pixels
+    // rendered from the camera (e.g. to select the object that was clicked
+    // in an editor), through the pixel's center: its material is
+    // `material(hit.material_id)`
+    pub fn pick(&self, camera: &Camera, x: u32, y: u32, width: u32, height: u32) -> Option<Hit> {
+        self.raycast(&camera.pixel_ray(x as Float + 0.5, y as Float + 0.5, width, height))
+    }
+
+    // Is anything between the points `a` and `b` (e.g. between a character
+    // and what it looks at)? Surfaces right at either point don't count, so
+    // that the points may lie on surfaces
+    pub fn occluded(&self, a: &Vector, b: &Vector) -> bool {
+        let d = *b - *a;
+        if d.squared_length() == 0.0 {
+            return false;
+        }
+        // (Leaving `a` along the segment, as if from a surface facing `b`)
+        self.hit_any(&Ray::spawn_to(a, &d.normalize(), b))
+    }
+
+    // Find the closest point of intersection, along with the primitive hit
+    pub fn intersect_primitive(&self, incident: &Ray) -> Option<(DifferentialGeometry<'_>, &Primitive)> {
+        self.intersect_where(incident, |_| true)
+    }
+
+    // Find the closest point of intersection among the primitives that rays
+    // of the given kind see (see: `Visibility`)
+    pub fn intersect_visible(&self, incident: &Ray, kind: RayKind) -> Option<(DifferentialGeometry<'_>, &Primitive)> {
+        self.intersect_where(incident, |item| item.visibility.sees(kind))
+    }
+
+    fn intersect_where<F>(&self, incident: &Ray, visible: F) -> Option<(DifferentialGeometry<'_>, &Primitive)>
+        where F: Fn(&Primitive) -> bool
+    {
+        stats::count(Counter::Rays);
         let mut closest_intersection = None;
         let mut closest_t = incident.t_max;
+        if let Some(ref bvh) = self.bvh {
+            bvh.traverse(incident, |i| {
+                let item = &self.items[i];
+                if !visible(item) {
+                    return closest_t;
+                }
+                if let Some(dg) = item.intersect_shape(incident) {
+                    if dg.t < closest_t {
+                        closest_t = dg.t;
+                        closest_intersection = Some((dg, item));
+                    }
+                }
+                closest_t
+            });
+            return closest_intersection;
+        }
 
         // Test against every object and find the closest point of intersection
-        for item in &self.items {
-            if let Some((dg, mtl)) = item.intersect(&incident) {
+        for item in self.items.iter().filter(|item| visible(item)) {
+            if let Some(dg) = item.intersect_shape(incident) {
                 if dg.t < closest_t {
                     closest_t = dg.t;
-                    closest_intersection = Some((dg, mtl));
+                    closest_intersection = Some((dg, item));
                 }
             }
         }
         closest_intersection
     }
+
+    // Does the ray hit anything closer than its `t_max`? This is cheaper than
+    // finding the closest hit, since it stops at the first hit it finds (and
+    // never works out where it is)
+    pub fn hit_any(&self, incident: &Ray) -> bool {
+        stats::count(Counter::Rays);
+        match self.bvh {
+            Some(ref bvh) => bvh.any(incident, |i| self.items[i].hit_any(incident)),
+            None => self.items.iter().any(|item| item.hit_any(incident)),
+        }
+    }
+
+    // Does a shadow ray hit anything that casts shadows (see: `Visibility`)?
+    pub fn shadowed(&self, incident: &Ray) -> bool {
+        stats::count(Counter::Rays);
+        let casts = |item: &Primitive| item.visibility.shadow && item.hit_any(incident);
+        match self.bvh {
+            Some(ref bvh) => bvh.any(incident, |i| casts(&self.items[i])),
+            None => self.items.iter().any(casts),
+        }
+    }
+
+    // How much of the light along a shadow ray reaches its origin: none if
+    // anything that casts shadows is in the way, but for the surfaces that
+    // let light straight through (see: `Material::transmittance`), each of
+    // which tints it, and the volumes that it passes through (see: `Volume`),
+    // which let through the light that none of their medium scatters away
+    pub fn transmittance(&self, incident: &Ray) -> Color {
+        // (Scenes without any such surfaces needn't find each hit in turn)
+        if !self.lets_light_through() {
+            return if self.shadowed(incident) { Color::black() } else { Color::white() };
+        }
+        let mut transmittance = Color::white();
+        let mut skipped: Option<Ray> = None;
+        loop {
+            let r = skipped.as_ref().unwrap_or(incident);
+            let (dg, item) = match self.intersect_where(r, |item| item.visibility.shadow) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let mtl = self.material(dg.material_id.unwrap_or(item.material_id));
+            // (The rest of the way to where the ray stops, from just past the
+            // surface)
+            let origin = Ray::spawn(&dg.position, &dg.normal, &r.direction, r.t_max).origin;
+            let mut rest = Ray::new(&origin, &r.direction, 0.0, r.t_max - (origin - r.origin).length());
+            match (mtl.transmittance(r.direction.dot(&dg.normal).abs()), mtl.volume()) {
+                (Some(t), _) if !t.is_black() => transmittance *= t,
+                // (Through the volume, to where the ray leaves it or stops)
+                (_, Some(volume)) if r.direction.dot(&dg.normal) < 0.0 => {
+                    let exit = item.intersect_shape(&rest);
+                    let length = exit.as_ref().map_or(rest.t_max, |exit| exit.t.min(rest.t_max));
+                    transmittance *= volume.unscattered(length);
+                    match exit {
+                        Some(ref exit) if exit.t < rest.t_max => {
+                            let origin = Ray::spawn(&exit.position, &exit.normal, &r.direction, r.t_max).origin;
+                            rest = Ray::new(&origin, &r.direction, 0.0, r.t_max - (origin - r.origin).length());
+                        }
+                        _ => break,
+                    }
+                }
+                (_, Some(_)) => {}
+                _ => return Color::black(),
+            }
+            skipped = Some(rest);
+        }
+        transmittance
+    }
+
+    // Find the closest hits of a packet of rays among the primitives that
+    // rays of the given kind see, as `intersect_visible` would for each of
+    // them: coherent rays (e.g. the camera rays of neighboring pixels) share
+    // the work of traversing the BVH (see: `Bvh::traverse_packet`)
+    pub fn intersect_packet(&self, rays: &[Ray], kind: RayKind) -> Vec<Option<(DifferentialGeometry<'_>, &Primitive)>> {
+        for _ in rays {
+            stats::count(Counter::Rays);
+        }
+        let mut closest: Vec<Option<(DifferentialGeometry<'_>, &Primitive)>> = rays.iter().map(|_| None).collect();
+        let mut closest_t: Vec<Float> = rays.iter().map(|r| r.t_max).collect();
+        if let Some(ref bvh) = self.bvh {
+            bvh.traverse_packet(rays, |i, index| {
+                let item = &self.items[index];
+                if !item.visibility.sees(kind) {
+                    return closest_t[i];
+                }
+                if let Some(dg) = item.intersect_shape(&rays[i]) {
+                    if dg.t < closest_t[i] {
+                        closest_t[i] = dg.t;
+                        closest[i] = Some((dg, item));
+                    }
+                }
+                closest_t[i]
+            });
+            return closest;
+        }
+
+        // Test every ray against each object in turn
+        for item in self.items.iter().filter(|item| item.visibility.sees(kind)) {
+            for (i, r) in rays.iter().enumerate() {
+                if let Some(dg) = item.intersect_shape(r) {
+                    if dg.t < closest_t[i] {
+                        closest_t[i] = dg.t;
+                        closest[i] = Some((dg, item));
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    // As `shadowed`, for a packet of shadow rays
+    pub fn shadowed_packet(&self, rays: &[Ray]) -> Vec<bool> {
+        for _ in rays {
+            stats::count(Counter::Rays);
+        }
+        let casts = |item: &Primitive, r: &Ray| item.visibility.shadow && item.hit_any(r);
+        match self.bvh {
+            Some(ref bvh) => bvh.any_packet(rays, |i, index| casts(&self.items[index], &rays[i])),
+            None => rays.iter().map(|r| self.items.iter().any(|item| casts(item, r))).collect(),
+        }
+    }
+
+    // As `transmittance`, for a packet of shadow rays (e.g. those from a
+    // surface towards the points picked on its lights)
+    pub fn transmittance_packet(&self, rays: &[Ray]) -> Vec<Color> {
+        if self.lets_light_through() {
+            return rays.iter().map(|r| self.transmittance(r)).collect();
+        }
+        self.shadowed_packet(rays)
+            .into_iter()
+            .map(|shadowed| if shadowed { Color::black() } else { Color::white() })
+            .collect()
+    }
+
+    // Does any material let light straight through its surfaces, or through
+    // its volume (see: `transmittance`)?
+    fn lets_light_through(&self) -> bool {
+        self.materials.iter().any(|m| m.transmittance(1.0).is_some() || m.volume().is_some())
+    }
+}
+
+// The IDs of the materials that a primitive's shape is made of: its own, and
+// those of the parts that carry their own (see: `SphereList`)
+fn shape_materials(shape: &Geometry, material_id: u32) -> Vec<u32> {
+    let mut ids = vec![material_id];
+    match *shape {
+        Geometry::Spheres(ref s) => ids.extend((0..s.len()).map(|i| s.material_id(i))),
+        Geometry::Mesh(ref mesh) => ids.extend(mesh.material_ids.iter().flatten()),
+        _ => {}
+    }
+    ids
+}
+
+// What's wrong with a shape (see: `Scene::validate`), which may refer to
+// materials `0..materials`
+fn shape_problems(shape: &Geometry, materials: usize) -> Vec<String> {
+    let mut problems = Vec::new();
+    match *shape {
+        Geometry::Sphere(ref s) => {
+            if !s.center.is_finite() || !s.radius.is_finite() {
+                problems.push("the sphere isn't finite".to_string());
+            } else if s.radius <= 0.0 {
+                problems.push(format!("the sphere's radius is {}", s.radius));
+            }
+        }
+        Geometry::Plane(ref s) => {
+            if !s.center.is_finite() || !s.normal.is_finite() {
+                problems.push("the plane isn't finite".to_string());
+            } else if s.normal.squared_length() == 0.0 {
+                problems.push("the plane has no normal".to_string());
+            }
+        }
+        Geometry::Triangle(ref s) => {
+            if let Some(problem) = triangle_problem(s) {
+                problems.push(format!("the triangle {}", problem));
+            }
+        }
+        Geometry::Quad(ref s) => {
+            let n = s.u.cross(&s.v);
+            if !s.corner.is_finite() || !n.is_finite() {
+                problems.push("the quad isn't finite".to_string());
+            } else if n.squared_length() == 0.0 {
+                problems.push("the quad has no area".to_string());
+            }
+        }
+        Geometry::Spheres(ref s) => {
+            let degenerate = (0..s.len())
+                .filter(|&i| !(s.center(i).is_finite() && s.radius(i) > 0.0 && s.radius(i).is_finite()))
+                .count();
+            if degenerate > 0 {
+                problems.push(format!("{} of its {} spheres are degenerate", degenerate, s.len()));
+            }
+            if (0..s.len()).any(|i| s.material_id(i) as usize >= materials) {
+                problems.push("its spheres refer to materials that aren't in the scene".to_string());
+            }
+        }
+        Geometry::Mesh(ref s) => problems.extend(mesh_problems(s, materials)),
+        Geometry::Lod(ref s) => {
+            for (level, mesh) in s.levels.iter().enumerate() {
+                for problem in mesh_problems(mesh, materials) {
+                    problems.push(format!("level {}: {}", level, problem));
+                }
+            }
+        }
+        // (Custom shapes can't be looked into)
+        Geometry::Custom(_) => {}
+    }
+    problems
+}
+
+fn mesh_problems(mesh: &Mesh, materials: usize) -> Vec<String> {
+    let mut problems = Vec::new();
+    if mesh.is_empty() {
+        problems.push("the mesh has no triangles".to_string());
+    }
+    let degenerate = mesh.triangles.iter().filter(|t| triangle_problem(t).is_some()).count();
+    if degenerate > 0 {
+        problems.push(format!("{} of the mesh's {} triangles are degenerate", degenerate, mesh.len()));
+    }
+    if let Some(ref normals) = mesh.normals {
+        if normals.iter().flatten().any(|n| !n.is_finite()) {
+            problems.push("the mesh's normals aren't finite".to_string());
+        }
+    }
+    if let Some(ref material_ids) = mesh.material_ids {
+        if material_ids.iter().any(|&id| id as usize >= materials) {
+            problems.push("the mesh refers to materials that aren't in the scene".to_string());
+        }
+    }
+    problems
+}
+
+fn triangle_problem(triangle: &Triangle) -> Option<&'static str> {
+    let [a, b, c] = triangle.vertices;
+    if !a.is_finite() || !b.is_finite() || !c.is_finite() {
+        Some("isn't finite")
+    } else if (b - a).cross(&(c - a)).squared_length() == 0.0 {
+        Some("has no area")
+    } else {
+        None
+    }
+}
+
+// What's wrong with a material's parameters (or its texture)
+fn material_problem(data: &MaterialData) -> Option<String> {
+    match *data {
+        MaterialData::Lambertian { albedo } |
+        MaterialData::Metallic { albedo, .. } |
+        MaterialData::ShadowCatcher { albedo } |
+        MaterialData::Volume { albedo, .. } if !albedo.is_finite() => {
+            Some("its albedo isn't finite".to_string())
+        }
+        MaterialData::Metallic { glossiness, .. } if !glossiness.is_finite() => {
+            Some("its glossiness isn't finite".to_string())
+        }
+        MaterialData::Dielectric { ior, .. } | MaterialData::ThinDielectric { ior, .. } if !(ior > 0.0 && ior.is_finite()) => {
+            Some(format!("its index of refraction is {}", ior))
+        }
+        // (Dispersion models can blow up, e.g. near a Sellmeier pole)
+        MaterialData::Dielectric { dispersion: Some(ref dispersion), .. } => {
+            (WAVELENGTHS.0 as u32..=WAVELENGTHS.1 as u32).step_by(10).find_map(|nm| {
+                let ior = dispersion.ior(nm as Float);
+                if ior > 0.0 && ior.is_finite() {
+                    None
+                } else {
+                    Some(format!("its index of refraction is {} at {} nm", ior, nm))
+                }
+            })
+        }
+        MaterialData::Volume { density, .. } if !(density > 0.0 && density.is_finite()) => {
+            Some(format!("its density is {}", density))
+        }
+        MaterialData::Volume { phase, .. } => phase.problem(),
+        MaterialData::Emissive { radiance } if !(radiance.is_finite() && radiance.r.min(radiance.g).min(radiance.b) >= 0.0) => {
+            Some(format!("its radiance is {:?}", radiance))
+        }
+        MaterialData::Emissive { radiance } if !(radiance.luminance() > 0.0 && radiance.luminance().is_finite()) => {
+            Some(format!("its power is {}", radiance.luminance()))
+        }
+        MaterialData::TexturedLambertian { ref texture } => {
+            image::image_dimensions(texture)
+                .err()
+                .map(|why| format!("couldn't load the texture {}: {}", texture.display(), why))
+        }
+        MaterialData::Graph(ref graph) => {
+            graph.textures().find_map(|texture| {
+                image::image_dimensions(texture)
+                    .err()
+                    .map(|why| format!("couldn't load the texture {}: {}", texture.display(), why))
+            })
+        }
+        _ => None,
+    }
+}
+
+// Scenes are saved with their materials (see: `MaterialData`) in a table
+// that the primitives refer to by their IDs, so that materials that were
+// shared stay shared once the scene is loaded again: shapes that can't be
+// described (custom shapes and materials) can't be saved, and the BVH is
+// rebuilt when the scene is loaded
+#[derive(Serialize)]
+struct SceneRef<'a> {
+    version: u32,
+    materials: Vec<MaterialData>,
+    items: Vec<PrimitiveRef<'a>>,
+    cameras: &'a [(String, Camera)],
+    environment: Environment,
+    portals: &'a [Quad],
+}
+
+#[derive(Serialize)]
+struct PrimitiveRef<'a> {
+    shape: &'a Geometry,
+    material_id: u32,
+    transform: &'a Transform,
+    visibility: Visibility,
+}
+
+#[derive(Deserialize)]
+struct SceneData {
+    version: u32,
+    materials: Vec<MaterialData>,
+    items: Vec<PrimitiveData>,
+    cameras: Vec<(String, Camera)>,
+    // (Scenes saved before version 2 are all lit by the sky)
+    #[serde(default)]
+    environment: Environment,
+    // (And before version 4 have no portals)
+    #[serde(default)]
+    portals: Vec<Quad>,
+}
+
+#[derive(Deserialize)]
+struct PrimitiveData {
+    shape: Geometry,
+    material_id: u32,
+    transform: Transform,
+    // (Before version 5, every primitive is seen by every ray)
+    #[serde(default)]
+    visibility: Visibility,
+}
+
+impl Serialize for Scene {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let materials = self.materials
+            .iter()
+            .enumerate()
+            .map(|(id, m)| m.data().ok_or_else(|| ser::Error::custom(format!("material {} can't be saved", id))))
+            .collect::<Result<_, _>>()?;
+        SceneRef {
+                version: FORMAT_VERSION,
+                materials,
+                items: self.items
+                    .iter()
+                    .map(|item| {
+                        PrimitiveRef {
+                            shape: &item.shape,
+                            material_id: item.material_id,
+                            transform: &item.transform,
+                            visibility: item.visibility,
+                        }
+                    })
+                    .collect(),
+                cameras: &self.cameras,
+                environment: self.environment,
+                portals: &self.portals,
+            }
+            .serialize(serializer)
+    }
+}
+
+// (Textures are loaded through a cache of their own, of `DEFAULT_BUDGET`)
+impl<'de> Deserialize<'de> for Scene {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Scene, D::Error> {
+        let data = SceneData::deserialize(deserializer)?;
+        if data.version > FORMAT_VERSION {
+            return Err(de::Error::custom(format!("the scene was saved in a later format (version {})", data.version)));
+        }
+        let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+        let materials = data.materials
+            .iter()
+            .map(|m| m.create(&textures).map_err(de::Error::custom))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut scene = Scene::new();
+        for item in data.items {
+            let material = materials.get(item.material_id as usize)
+                .ok_or_else(|| de::Error::custom(format!("there is no material {}", item.material_id)))?;
+            let mut primitive = Primitive::new(item.shape, material.clone());
+            primitive.transform = item.transform;
+            primitive.visibility = item.visibility;
+            scene.add(primitive);
+        }
+        scene.cameras = data.cameras;
+        scene.environment = data.environment;
+        scene.portals = data.portals;
+        scene.build_bvh();
+        Ok(scene)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
+}
+
+#[test]
+fn test_packet_matches_single_rays() {
+    use shape::Sphere;
+    use shape::Plane;
+    use material::Lambertian;
+    use color::Color;
+    use vector::Vector;
+
+    // A floor, which has no bounds, under rows of spheres, some of which
+    // the camera doesn't see and some of which cast no shadows
+    let mut scene = Scene::new();
+    let material = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, -1.0, 0.0), &Vector::new(0.0, 1.0, 0.0)), material.clone()));
+    for i in 0..60 {
+        let center = Vector::new((i % 10) as Float * 0.4 - 2.0, (i / 10 % 2) as Float * 0.5 - 0.5, -2.0 - (i / 20) as Float);
+        let mut sphere = Primitive::new(Sphere::new(&center, 0.15 + (i % 3) as Float * 0.05), material.clone());
+        sphere.visibility.camera = i % 4 != 0;
+        sphere.visibility.shadow = i % 5 != 0;
+        scene.add(sphere);
+    }
+
+    // (A number of rays that doesn't fill the last packet)
+    let rays: Vec<Ray> = (0..90)
+        .map(|i| {
+            let (x, y) = ((i % 15) as Float / 7.0 - 1.0, (i / 15) as Float / 5.0 - 0.7);
+            Ray::new(&Vector::new(0.0, 0.2, 0.5), &Vector::new(x, y, -1.0), 0.001, if i % 7 == 0 { 2.5 } else { Float::MAX })
+        })
+        .collect();
+    for &bvh in &[false, true] {
+        if bvh {
+            scene.build_bvh();
+        }
+        let hits = |kind| {
+            let packet: Vec<Option<(Float, u32)>> = scene.intersect_packet(&rays, kind)
+                .into_iter()
+                .map(|hit| hit.map(|(dg, item)| (dg.t, item.object_id)))
+                .collect();
+            for (r, &packet_hit) in rays.iter().zip(&packet) {
+                assert_eq!(scene.intersect_visible(r, kind).map(|(dg, item)| (dg.t, item.object_id)), packet_hit);
+            }
+            packet
+        };
+        assert_ne!(hits(RayKind::Camera), hits(RayKind::Indirect));
+        let shadowed: Vec<bool> = rays.iter().map(|r| scene.shadowed(r)).collect();
+        assert_eq!(scene.shadowed_packet(&rays), shadowed);
+        assert!(shadowed.contains(&true) && shadowed.contains(&false));
+    }
+}
+
+#[test]
+fn test_hit_any_matches_intersect() {
+    use shape::Sphere;
+    use shape::Quad;
+    use material::Lambertian;
+    use color::Color;
+    use vector::Vector;
+    use transform::Transform;
+
+    let mut scene = Scene::new();
+    let material = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 0.5), material.clone()));
+    let mut quad = Primitive::new(Quad::new(&Vector::new(-1.0, -1.0, -5.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 2.0, 0.0)),
+                                  material.clone());
+    quad.transform = Transform::new(&Vector::zero(), &Vector::new(0.0, 20.0, 0.0), &Vector::new(2.0, 1.0, 1.0), &Vector::zero());
+    scene.add(quad);
+
+    for &bvh in &[false, true] {
+        if bvh {
+            scene.build_bvh();
+        }
+        for i in 0..32 {
+            for &t_max in &[1.0, 2.7, 4.0, Float::MAX] {
+                let d = Vector::new(i as Float / 16.0 - 1.0, 0.05, -1.0);
+                let r = Ray::new(&Vector::zero(), &d, 0.001, t_max);
+                let closest = scene.intersect_primitive(&r).map(|(dg, _)| dg.t);
+                assert_eq!(scene.hit_any(&r), closest.is_some_and(|t| t < t_max));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_visibility() {
+    use shape::Sphere;
+    use material::Lambertian;
+    use color::Color;
+    use vector::Vector;
+
+    // A sphere that the camera doesn't see, in front of one that casts no
+    // shadows
+    let mut scene = Scene::new();
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    let mut blocker = Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 1.0), white.clone());
+    blocker.visibility.camera = false;
+    scene.add(blocker);
+    let mut ghost = Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -6.0), 1.0), white);
+    ghost.visibility.shadow = false;
+    scene.add(ghost);
+    for &bvh in &[false, true] {
+        if bvh {
+            scene.build_bvh();
+        }
+        let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+        let hit = |kind| scene.intersect_visible(&r, kind).map(|(dg, item)| (dg.t, item.object_id));
+        assert_eq!(hit(RayKind::Camera), Some((5.0, 1)));
+        assert_eq!(hit(RayKind::Indirect), Some((2.0, 0)));
+        assert!(scene.shadowed(&r));
+        let behind = Ray::new(&Vector::new(0.0, 0.0, -4.5), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+        assert!(!scene.shadowed(&behind) && scene.hit_any(&behind));
+    }
+}
+
+#[test]
+fn test_transmittance() {
+    use shape::Sphere;
+    use material::Lambertian;
+    use material::ThinDielectric;
+    use vector::Vector;
+    use vector::TEST_EPSILON;
+
+    // Two panes of tinted glass in front of an opaque sphere: light passes
+    // through both of them (less what they reflect), but not the sphere
+    let mut scene = Scene::new();
+    let tint = Color::new(1.0, 0.5, 0.25);
+    let glass: Arc<dyn Material> = Arc::new(ThinDielectric::new(1.5, &tint));
+    let pane = |z: Float| Quad::new(&Vector::new(-1.0, -1.0, z), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 2.0, 0.0));
+    scene.add(Primitive::new(pane(-1.0), glass.clone()));
+    scene.add(Primitive::new(pane(-2.0), glass));
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -5.0), 1.0), Arc::new(Lambertian::new(&Color::white()))));
+    scene.build_bvh();
+    let through = scene.materials[0].transmittance(1.0).unwrap();
+    assert!((through.r - 0.923).abs() < 0.001 && through.g == 0.5 * through.r);
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.0, 3.0);
+    let t = scene.transmittance(&r);
+    assert!((t.g - through.g * through.g).abs() < TEST_EPSILON, "{:?}", t);
+    assert!(scene.shadowed(&r));
+    let blocked = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    assert!(scene.transmittance(&blocked).is_black());
+
+    // Without any glass, only whether something is in the way matters
+    scene.materials[0] = Arc::new(Lambertian::new(&Color::white()));
+    assert!(scene.transmittance(&r).is_black());
+    let clear = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, 1.0), 0.0, Float::MAX);
+    assert_eq!(scene.transmittance(&clear), Color::white());
+}
+
+#[test]
+fn test_scene_round_trip() {
+    use shape::Sphere;
+    use shape::Plane;
+    use shape::Triangle;
+    use mesh::Mesh;
+    use material::Lambertian;
+    use color::Color;
+    use material::Dielectric;
+    use material::Dispersion;
+    use vector::Vector;
+
+    let mut scene = Scene::new();
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    let dispersion = Dispersion::Cauchy { a: 1.5046, b: 0.0042, c: 0.0 };
+    let glass: Arc<dyn Material> = Arc::new(Dielectric { priority: Some(1), ..Dielectric::dispersive(dispersion) });
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, -1.0, 0.0), &Vector::new(0.0, 1.0, 0.0)), white.clone()));
+    let mut sphere = Primitive::new(Sphere::new(&Vector::zero(), 0.5), glass);
+    sphere.transform = Transform::new(&Vector::new(0.0, 0.0, -3.0),
+                                      &Vector::new(0.0, 45.0, 0.0),
+                                      &Vector::new(1.0, 2.0, 1.0),
+                                      &Vector::zero());
+    sphere.visibility.shadow = false;
+    scene.add(sphere);
+    let mesh = Mesh::new(vec![Triangle::new(&Vector::new(-1.0, 0.0, -2.0),
+                                            &Vector::new(1.0, 0.0, -2.0),
+                                            &Vector::new(0.0, 1.0, -2.0))]);
+    scene.add(Primitive::new(mesh, white));
+    scene.add_camera("front", Camera::new(60.0, 1.0));
+    scene.environment = Environment::Uniform { radiance: Color::gray(0.5) };
+    scene.portals.push(Quad::new(&Vector::new(-1.0, 0.0, -4.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0)));
+    scene.build_bvh();
+
+    let path = ::std::env::temp_dir().join("tracer_test_scene_round_trip.toml");
+    scene.save(&path).unwrap();
+    let loaded = Scene::load(&path).unwrap();
+    assert_eq!(loaded.items.len(), 3);
+    // Shared materials stay shared
+    assert_eq!(loaded.materials.len(), 2);
+    assert_eq!(loaded.items[2].material_id, 0);
+    assert_eq!(loaded.materials[1].data(), scene.materials[1].data());
+    assert!(loaded.camera("front").is_some());
+    assert_eq!(loaded.environment, scene.environment);
+    assert_eq!(loaded.portals.len(), 1);
+    assert!(!loaded.items[1].visibility.shadow && loaded.items[1].visibility.camera);
+    for i in 0..64 {
+        let d = Vector::new(i as Float / 32.0 - 1.0, (i % 8) as Float / 8.0 - 0.5, -1.0);
+        let r = Ray::new(&Vector::zero(), &d, 0.001, Float::MAX);
+        let expected = scene.intersect_primitive(&r).map(|(dg, item)| (dg.t, item.object_id));
+        let hit = loaded.intersect_primitive(&r).map(|(dg, item)| (dg.t, item.object_id));
+        assert_eq!(hit, expected);
+    }
+
+    // Scenes from later versions of the format are refused
+    let later = fs::read_to_string(&path).unwrap().replace("version = 7", "version = 8");
+    assert!(toml::from_str::<Scene>(&later).is_err());
+}
+
+#[test]
+fn test_validate() {
+    use shape::Sphere;
+    use shape::Quad;
+    use material::Lambertian;
+    use color::Color;
+    use material::Dielectric;
+    use material::Dispersion;
+    use material::TexturedLambertian;
+    use material::Volume;
+    use material::Emissive;
+    use phase::HenyeyGreenstein;
+    use texture::ImageTexture;
+    use vector::Vector;
+
+    let mut scene = Scene::new();
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -2.0), 0.5), white.clone()));
+    scene.add(Primitive::new(Mesh::cube(), white.clone()));
+    assert!(scene.validate().is_empty());
+
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 0.0), white.clone()));
+    let nan = Vector::new(Float::NAN, 0.0, 0.0);
+    scene.add(Primitive::new(Triangle::new(&nan, &Vector::one(), &Vector::zero()), white.clone()));
+    scene.add(Primitive::new(Quad::new(&Vector::zero(), &Vector::one(), &Vector::one()), white.clone()));
+    let mut flat = Primitive::new(Sphere::new(&Vector::zero(), 1.0), white);
+    flat.transform = Transform::new(&Vector::zero(), &Vector::zero(), &Vector::new(1.0, 0.0, 1.0), &Vector::zero());
+    scene.add(flat);
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(Dielectric::new(Float::NAN))));
+    let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+    let texture = ImageTexture::new(&textures, Path::new("missing.png"));
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(TexturedLambertian::new(texture))));
+    let pole = Dispersion::Sellmeier { b: [1.0, 0.0, 0.0], c: [0.25, 0.0, 0.0] };
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(Dielectric::dispersive(pole))));
+    let spike = Volume::new(&Color::white(), 1.0, Arc::new(HenyeyGreenstein::new(1.0)));
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), Arc::new(spike)));
+    // An emissive sphere, which is never sampled (unlike the quad with the
+    // same material), and a quad that emits nothing
+    let light: Arc<dyn Material> = Arc::new(Emissive::new(&Color::white()));
+    scene.add(Primitive::new(Sphere::new(&Vector::zero(), 1.0), light.clone()));
+    let panel = Quad::new(&Vector::zero(), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0));
+    scene.add(Primitive::new(panel.clone(), light));
+    scene.add(Primitive::new(panel, Arc::new(Emissive::new(&Color::black()))));
+    scene.portals.push(Quad::new(&Vector::zero(), &Vector::one(), &Vector::one()));
+
+    let problems = scene.validate();
+    assert_eq!(problems.len(), 11, "{:?}", problems);
+    assert!(problems[0].starts_with("object 2:"));
+    assert!(problems[3].starts_with("object 5:"));
+    assert!(problems[4].starts_with("object 10: it emits light"), "{}", problems[4]);
+    assert!(problems[5].starts_with("material 1:"));
+    assert!(problems[6].contains("missing.png"));
+    assert!(problems[7].starts_with("material 3:") && problems[7].ends_with(" nm"), "{}", problems[7]);
+    assert_eq!(problems[8], "material 4: its asymmetry is 1");
+    assert_eq!(problems[9], "material 6: its power is 0");
+    assert_eq!(problems[10], "portal 0: the quad has no area");
+}
+
+#[test]
+fn test_raycast() {
+    use shape::Sphere;
+    use shape::Plane;
+    use material::Lambertian;
+    use color::Color;
+
+    let mut scene = Scene::new();
+    let material = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)), material.clone()));
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 1.0, 0.0), 0.5), material));
+    scene.build_bvh();
+
+    let hit = scene.raycast(&Ray::new(&Vector::new(0.0, 5.0, 0.0), &Vector::new(0.0, -1.0, 0.0), 0.0, Float::MAX))
+        .unwrap();
+    assert_eq!(hit.object_id, 1);
+    assert!((hit.t - 3.5).abs() < 1e-6 && (hit.normal.y - 1.0).abs() < 1e-6);
+    let hit = scene.raycast(&Ray::new(&Vector::new(3.0, 5.0, 0.0), &Vector::new(0.0, -1.0, 0.0), 0.0, Float::MAX))
+        .unwrap();
+    assert_eq!(hit.object_id, 0);
+    assert!(scene.raycast(&Ray::new(&Vector::new(3.0, 5.0, 0.0), &Vector::new(0.0, 1.0, 0.0), 0.0, Float::MAX))
+        .is_none());
+
+    // The sphere stands between points either side of it, but points on the
+    // ground see each other (and so do points on the sphere and the ground)
+    assert!(scene.occluded(&Vector::new(-2.0, 1.0, 0.0), &Vector::new(2.0, 1.0, 0.0)));
+    assert!(!scene.occluded(&Vector::new(-2.0, 0.0, 0.0), &Vector::new(2.0, 0.0, 3.0)));
+    assert!(!scene.occluded(&Vector::new(0.0, 0.5, 0.0), &Vector::new(2.0, 0.0, 0.0)));
+
+    // Picking from above: the sphere is in the middle of the image, with the
+    // ground around it
+    let camera = Camera::look_at(&Vector::new(0.0, 10.0, 0.0),
+                                 &Vector::zero(),
+                                 &Vector::new(0.0, 0.0, -1.0),
+                                 30.0,
+                                 4.0 / 3.0);
+    assert_eq!(scene.pick(&camera, 40, 30, 80, 60).map(|hit| hit.object_id), Some(1));
+    let hit = scene.pick(&camera, 2, 57, 80, 60).unwrap();
+    assert_eq!(hit.object_id, 0);
+    // (The bottom-left of the image is toward -x and +z)
+    assert!(hit.position.x < 0.0 && hit.position.z > 0.0);
+}
+
+#[test]
+fn test_scene_stats() {
+    use shape::Sphere;
+    use shape::Plane;
+    use material::Lambertian;
+    use color::Color;
+
+    // Two instances of a cube share its mesh, which is counted once
+    let mut scene = Scene::new();
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    let cube = Arc::new(Mesh::cube());
+    scene.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)), white.clone()));
+    for i in 0..2 {
+        scene.add(Primitive::new(Sphere::new(&Vector::new(i as Float, 1.0, 0.0), 0.5), white.clone()));
+        let mut instance = Primitive::new(cube.clone(), white.clone());
+        instance.transform = Transform::translate(&Vector::new(i as Float * 3.0, 1.0, -4.0));
+        scene.add(instance);
+    }
+    assert!(scene.stats().bvh.is_none());
+    scene.build_bvh();
+
+    let stats = scene.stats();
+    assert_eq!(stats.shapes, vec![("plane", 1), ("sphere", 2), ("mesh", 2)]);
+    assert_eq!((stats.materials, stats.meshes, stats.triangles, stats.instanced_triangles), (1, 1, 12, 24));
+    let bvh = stats.bvh.as_ref().unwrap();
+    assert_eq!((bvh.primitives, bvh.unbounded), (4, 1));
+    assert_eq!(stats.mesh_bvhs.primitives, 12);
+    assert!(stats.memory() > stats.geometry_memory && stats.geometry_memory > 12 * mem::size_of::<Triangle>());
+    assert!(stats.to_string().starts_with("primitives: 5 (1 plane, 2 sphere, 2 mesh)\n"), "{}", stats);
 }
@@ -0,0 +1,729 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Sphere;
+use shape::Plane;
+use shape::Triangle;
+use shape::Quad;
+use material::Material;
+use material::Lambertian;
+use material::TexturedLambertian;
+use material::Metallic;
+use material::Dielectric;
+use material::Dispersion;
+use material::D_LINE;
+use material::Emissive;
+use material::ThinDielectric;
+use material::Volume;
+use material::ShadowCatcher;
+use material_graph::GraphDescription;
+use phase::PhaseData;
+use material_graph::MaterialGraph;
+use material_graph::NodeKind;
+use primitive::Primitive;
+use primitive::Visibility;
+use scene::Scene;
+use camera::Camera;
+use stereo::Stereo;
+use stereo::StereoLayout;
+use transform::Transform;
+use texture::ImageTexture;
+use texture::TextureCache;
+use settings::RenderSettings;
+use sampler::SamplerType;
+use filter::FilterType;
+use obj;
+use ply;
+use stl;
+use pbrt;
+use mitsuba;
+#[cfg(feature = "scripting")]
+use script;
+use error::Result;
+use error::TracerError;
+
+use serde::Deserialize;
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// A scene, as written in a (TOML) scene file, e.g.
+//
+//     [settings]
+//     samples = 64
+//
+//     [[cameras]]
+//     name = "front"
+//     from = [0.0, 0.0, 0.0]
+//     to = [0.0, 0.0, -1.0]
+//
+//     [[materials]]
+//     name = "white"
+//     type = "lambertian"
+//     albedo = [1.0, 1.0, 1.0]
+//
+//     [[objects]]
+//     type = "sphere"
+//     center = [0.0, 0.0, -1.0]
+//     radius = 0.5
+//     material = "white"
+//
+//     [[scripts]]
+//     path = "scatter.rhai"
+//     seed = 7
+//
+// where paths (of textures and models) are relative to the scene file, and
+// everything but the objects may be left out (scripts, which place more
+// objects once the others are built, need the `scripting` feature: see
+// `script`)
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub settings: SettingsDescription,
+    #[serde(default)]
+    pub cameras: Vec<CameraDescription>,
+    #[serde(default)]
+    pub textures: Vec<TextureDescription>,
+    #[serde(default)]
+    pub materials: Vec<MaterialDescription>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+    #[serde(default)]
+    pub scripts: Vec<ScriptDescription>,
+    // The openings through which the sky lights an interior (see:
+    // `Scene::portals`), e.g.
+    //
+    //     [[portals]]
+    //     corner = [-1.0, 0.5, -4.0]
+    //     u = [2.0, 0.0, 0.0]
+    //     v = [0.0, 1.5, 0.0]
+    #[serde(default)]
+    pub portals: Vec<PortalDescription>,
+    // The directory that paths are relative to
+    #[serde(skip)]
+    pub directory: PathBuf,
+}
+
+// Overrides of the render settings (see: `RenderSettings`), where given
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsDescription {
+    pub samples: Option<u32>,
+    pub min_samples: Option<u32>,
+    pub noise_threshold: Option<Float>,
+    pub max_depth: Option<u32>,
+    pub tile_size: Option<u32>,
+    // "random", "halton", or "sobol"
+    pub sampler: Option<SamplerType>,
+    // "box", "tent", "gaussian", or "mitchell"
+    pub filter: Option<FilterType>,
+}
+
+// A scene imported from another renderer's scene format (see: `pbrt`, and
+// `mitsuba`), along with the render settings that it asks for
+pub struct ImportedScene {
+    pub scene: Scene,
+    pub settings: SettingsDescription,
+    // What was skipped (or approximated), since only a subset of each format
+    // is supported: e.g. lights are ignored, as scenes are lit by the sky
+    pub warnings: Vec<String>,
+}
+
+impl SettingsDescription {
+    // Override the render settings with those given
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        settings.samples = self.samples.unwrap_or(settings.samples);
+        settings.min_samples = self.min_samples.unwrap_or(settings.min_samples);
+        settings.noise_threshold = self.noise_threshold.unwrap_or(settings.noise_threshold);
+        settings.max_depth = self.max_depth.unwrap_or(settings.max_depth);
+        settings.tile_size = self.tile_size.unwrap_or(settings.tile_size);
+        settings.sampler = self.sampler.unwrap_or(settings.sampler);
+        settings.filter = self.filter.unwrap_or(settings.filter);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub name: String,
+    pub from: [Float; 3],
+    pub to: [Float; 3],
+    #[serde(default = "default_up")]
+    pub up: [Float; 3],
+    // The vertical field of view, in degrees
+    #[serde(default = "default_fov")]
+    pub fov: Float,
+    // Render a stereo pair instead, e.g.
+    //
+    //     stereo = { separation = 0.065, layout = "anaglyph" }
+    //
+    // (see: `Stereo`), where the convergence distance defaults to the
+    // distance from `from` to `to`
+    #[serde(default)]
+    pub stereo: Option<StereoDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StereoDescription {
+    pub separation: Float,
+    #[serde(default)]
+    pub convergence: Option<Float>,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+fn default_intensity() -> Float {
+    1.0
+}
+
+// (Window glass)
+fn default_ior() -> Float {
+    1.5
+}
+
+fn default_up() -> [Float; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_fov() -> Float {
+    60.0
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortalDescription {
+    pub corner: [Float; 3],
+    pub u: [Float; 3],
+    pub v: [Float; 3],
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDescription {
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    // Either a color, or the name of a texture
+    Lambertian {
+        name: String,
+        #[serde(default)]
+        albedo: Option<[Float; 3]>,
+        #[serde(default)]
+        texture: Option<String>,
+    },
+    Metallic {
+        name: String,
+        albedo: [Float; 3],
+        #[serde(default)]
+        glossiness: Float,
+    },
+    // (Dielectrics with a priority nest inside each other, see:
+    // `MediumStack`, and those with a dispersion model, e.g. `{ model =
+    // "cauchy", a = 1.5046, b = 0.0042 }`, split light into its colors, and
+    // needn't be given an IOR, see: `Dispersion`)
+    Dielectric {
+        name: String,
+        #[serde(default)]
+        ior: Option<Float>,
+        #[serde(default)]
+        priority: Option<u32>,
+        #[serde(default)]
+        dispersion: Option<Dispersion>,
+    },
+    // A light, which every shape with it becomes: its radiance is either a
+    // color, or the color of a black body at a `temperature` in kelvin (see:
+    // `Color::blackbody`), either of which `intensity` scales
+    Emissive {
+        name: String,
+        #[serde(default)]
+        radiance: Option<[Float; 3]>,
+        #[serde(default)]
+        temperature: Option<Float>,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    // A pane of glass (or a bubble, or a soap film) too thin to bend light,
+    // whose tint is the color of what passes through (see: `ThinDielectric`)
+    ThinDielectric {
+        name: String,
+        #[serde(default = "default_ior")]
+        ior: Float,
+        #[serde(default)]
+        tint: Option<[Float; 3]>,
+    },
+    // A cloud, smoke, or fog filling a closed shape, which scatters light
+    // inside it by its phase function, e.g. `{ type = "henyey_greenstein", g
+    // = 0.85 }` (or equally in every direction, by default, see: `Volume`)
+    Volume {
+        name: String,
+        #[serde(default)]
+        albedo: Option<[Float; 3]>,
+        density: Float,
+        #[serde(default)]
+        phase: PhaseData,
+    },
+    // A stand-in for a photographed surface, for compositing (see:
+    // `ShadowCatcher`)
+    ShadowCatcher {
+        name: String,
+        #[serde(default)]
+        albedo: Option<[Float; 3]>,
+    },
+    // A graph of nodes (see: `material_graph`), whose textures' paths are
+    // relative to the scene file
+    Graph {
+        name: String,
+        #[serde(flatten)]
+        graph: GraphDescription,
+    },
+}
+
+impl MaterialDescription {
+    pub fn name(&self) -> &str {
+        match *self {
+            MaterialDescription::Lambertian { ref name, .. } |
+            MaterialDescription::Metallic { ref name, .. } |
+            MaterialDescription::Dielectric { ref name, .. } |
+            MaterialDescription::Emissive { ref name, .. } |
+            MaterialDescription::ThinDielectric { ref name, .. } |
+            MaterialDescription::Volume { ref name, .. } |
+            MaterialDescription::ShadowCatcher { ref name, .. } |
+            MaterialDescription::Graph { ref name, .. } => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShapeDescription {
+    Sphere { center: [Float; 3], radius: Float },
+    Plane { center: [Float; 3], normal: [Float; 3] },
+    Triangle { vertices: [[Float; 3]; 3] },
+    Quad {
+        corner: [Float; 3],
+        u: [Float; 3],
+        v: [Float; 3],
+    },
+    // An OBJ, PLY, or STL file, by its extension: OBJ models bring their own
+    // materials (unless the object names one), while the others need one
+    Model { path: String },
+}
+
+// Moves an object (see: `Transform`), with the rotation in degrees
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformDescription {
+    #[serde(default)]
+    pub translation: [Float; 3],
+    #[serde(default)]
+    pub rotation: [Float; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [Float; 3],
+    #[serde(default)]
+    pub pivot: [Float; 3],
+}
+
+fn default_scale() -> [Float; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptDescription {
+    pub path: String,
+    // Seeds the script's `rand`
+    #[serde(default)]
+    pub seed: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ObjectDescription {
+    #[serde(flatten)]
+    pub shape: ShapeDescription,
+    // The name of one of the scene's materials
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default)]
+    pub transform: Option<TransformDescription>,
+    // Hide the object from some kinds of rays, e.g.
+    // `visibility = { camera = false }` (see: `Visibility`)
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+// Load and build any kind of scene file, by its extension: PBRT (".pbrt"),
+// Mitsuba (".xml"), or otherwise a TOML scene file (see: `SceneDescription`)
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "load_scene", skip_all, fields(path = %path.display())))]
+pub fn load(path: &Path, aspect_ratio: Float, textures: &Arc<TextureCache>) -> Result<ImportedScene> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("pbrt") => pbrt::load(path, aspect_ratio, textures),
+        Some("xml") => mitsuba::load(path, aspect_ratio, textures),
+        _ => {
+            let description = SceneDescription::load(path)?;
+            Ok(ImportedScene {
+                scene: description.build(aspect_ratio, textures)?,
+                settings: description.settings,
+                warnings: Vec::new(),
+            })
+        }
+    }
+}
+
+fn invalid(why: String) -> TracerError {
+    TracerError::Parse(why)
+}
+
+fn vector(v: &[Float; 3]) -> Vector {
+    Vector::new(v[0], v[1], v[2])
+}
+
+impl SceneDescription {
+    pub fn load(path: &Path) -> Result<SceneDescription> {
+        let mut description = SceneDescription::parse(&fs::read_to_string(path)?)?;
+        description.directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        Ok(description)
+    }
+
+    pub fn parse(source: &str) -> Result<SceneDescription> {
+        toml::from_str(source).map_err(|why| invalid(why.to_string()))
+    }
+
+    // Override the render settings with the file's
+    pub fn apply_settings(&self, settings: &mut RenderSettings) {
+        self.settings.apply(settings);
+    }
+
+    // Build the scene (with its cameras, for images of the given aspect
+    // ratio), loading textures through `textures`
+    pub fn build(&self, aspect_ratio: Float, textures: &Arc<TextureCache>) -> Result<Scene> {
+        self.build_with(&self.build_materials(textures)?, aspect_ratio, textures)
+    }
+
+    pub fn build_cameras(&self, aspect_ratio: Float) -> Vec<(String, Camera)> {
+        self.cameras
+            .iter()
+            .map(|camera| {
+                let (from, to) = (vector(&camera.from), vector(&camera.to));
+                let mut built = Camera::look_at(&from, &to, &vector(&camera.up), camera.fov, aspect_ratio);
+                built.stereo = camera.stereo.as_ref().map(|stereo| {
+                    Stereo {
+                        separation: stereo.separation,
+                        convergence: stereo.convergence.unwrap_or_else(|| (to - from).length()),
+                        layout: stereo.layout,
+                    }
+                });
+                (camera.name.clone(), built)
+            })
+            .collect()
+    }
+
+    // Build the named materials (which `build_with` then gives the objects)
+    pub fn build_materials(&self, textures: &Arc<TextureCache>) -> Result<HashMap<String, Arc<dyn Material>>> {
+        let texture_paths: HashMap<&str, PathBuf> =
+            self.textures.iter().map(|t| (t.name.as_str(), self.directory.join(&t.path))).collect();
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        for description in &self.materials {
+            let material: Arc<dyn Material> = match *description {
+                MaterialDescription::Lambertian { texture: Some(ref texture), .. } => {
+                    let path = texture_paths.get(texture.as_str())
+                        .ok_or_else(|| invalid(format!("no texture named {:?}", texture)))?;
+                    Arc::new(TexturedLambertian::new(ImageTexture::new(textures, path)))
+                }
+                MaterialDescription::Lambertian { albedo, .. } => {
+                    Arc::new(Lambertian::new(&albedo.map_or(Color::white(), Color::from)))
+                }
+                MaterialDescription::Metallic { albedo, glossiness, .. } => {
+                    Arc::new(Metallic::new(&Color::from(albedo), glossiness))
+                }
+                MaterialDescription::Dielectric { ref name, ior, priority, dispersion } => {
+                    let ior = match (ior, dispersion) {
+                        (Some(ior), _) => ior,
+                        (None, Some(dispersion)) => dispersion.ior(D_LINE),
+                        (None, None) => return Err(invalid(format!("{:?} needs either an ior or a dispersion", name))),
+                    };
+                    Arc::new(Dielectric { ior, priority, dispersion })
+                }
+                MaterialDescription::Emissive { ref name, radiance, temperature, intensity } => {
+                    let color = match (radiance, temperature) {
+                        (Some(radiance), None) => Color::from(radiance),
+                        (None, Some(temperature)) if temperature > 0.0 => Color::blackbody(temperature),
+                        (None, Some(temperature)) => {
+                            return Err(invalid(format!("{:?} can't have a temperature of {} K", name, temperature)))
+                        }
+                        _ => return Err(invalid(format!("{:?} needs either a radiance or a temperature", name))),
+                    };
+                    Arc::new(Emissive::new(&(color * intensity)))
+                }
+                MaterialDescription::ThinDielectric { ior, tint, .. } => {
+                    Arc::new(ThinDielectric::new(ior, &tint.map_or(Color::white(), Color::from)))
+                }
+                MaterialDescription::Volume { albedo, density, phase, .. } => {
+                    Arc::new(Volume::new(&albedo.map_or(Color::white(), Color::from), density, phase.create()))
+                }
+                MaterialDescription::ShadowCatcher { albedo, .. } => {
+                    Arc::new(ShadowCatcher::new(&albedo.map_or(Color::white(), Color::from)))
+                }
+                MaterialDescription::Graph { ref name, ref graph } => {
+                    let mut graph = graph.clone();
+                    for node in &mut graph.nodes {
+                        if let NodeKind::Texture { ref mut path } = node.kind {
+                            *path = self.directory.join(&*path);
+                        }
+                    }
+                    let graph = MaterialGraph::new(&graph, textures)
+                        .map_err(|why| invalid(format!("material {:?}: {}", name, why)))?;
+                    Arc::new(graph)
+                }
+            };
+            materials.insert(description.name().to_string(), material);
+        }
+        Ok(materials)
+    }
+
+    // Build the scene from materials built by `build_materials`
+    pub fn build_with(&self,
+                      materials: &HashMap<String, Arc<dyn Material>>,
+                      aspect_ratio: Float,
+                      textures: &Arc<TextureCache>)
+                      -> Result<Scene> {
+        let mut scene = Scene::new();
+        scene.cameras = self.build_cameras(aspect_ratio);
+        for object in &self.objects {
+            let material = match object.material {
+                Some(ref name) => {
+                    Some(materials.get(name)
+                        .cloned()
+                        .ok_or_else(|| invalid(format!("no material named {:?}", name)))?)
+                }
+                None => None,
+            };
+            let transform = object.transform.as_ref().map_or_else(Transform::identity, |t| {
+                Transform::new(&vector(&t.translation), &vector(&t.rotation), &vector(&t.scale), &vector(&t.pivot))
+            });
+
+            // Models may add several primitives (one per group of an OBJ)
+            let first = scene.items.len();
+            match object.shape {
+                ShapeDescription::Model { ref path } => {
+                    self.add_model(&mut scene, &self.directory.join(path), material, textures)?
+                }
+                ref shape => {
+                    let material = material.ok_or_else(|| invalid("an object without a material".to_string()))?;
+                    let primitive = match *shape {
+                        ShapeDescription::Sphere { ref center, radius } => {
+                            Primitive::new(Sphere::new(&vector(center), radius), material)
+                        }
+                        ShapeDescription::Plane { ref center, ref normal } => {
+                            Primitive::new(Plane::new(&vector(center), &vector(normal)), material)
+                        }
+                        ShapeDescription::Triangle { ref vertices } => {
+                            Primitive::new(Triangle::new(&vector(&vertices[0]),
+                                                         &vector(&vertices[1]),
+                                                         &vector(&vertices[2])),
+                                           material)
+                        }
+                        ShapeDescription::Quad { ref corner, ref u, ref v } => {
+                            Primitive::new(Quad::new(&vector(corner), &vector(u), &vector(v)), material)
+                        }
+                        ShapeDescription::Model { .. } => unreachable!(),
+                    };
+                    scene.add(primitive);
+                }
+            }
+            for item in &mut scene.items[first..] {
+                item.transform = transform;
+                item.visibility = object.visibility;
+            }
+        }
+        scene.portals = self.portals.iter().map(|p| Quad::new(&vector(&p.corner), &vector(&p.u), &vector(&p.v))).collect();
+        scene.build_bvh();
+        self.run_scripts(scene, materials, textures)
+    }
+
+    #[cfg(feature = "scripting")]
+    fn run_scripts(&self,
+                   mut scene: Scene,
+                   materials: &HashMap<String, Arc<dyn Material>>,
+                   textures: &Arc<TextureCache>)
+                   -> Result<Scene> {
+        for description in &self.scripts {
+            let path = self.directory.join(&description.path);
+            scene = script::run(&path, description.seed, scene, materials, &self.directory, textures)?;
+        }
+        Ok(scene)
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn run_scripts(&self,
+                   scene: Scene,
+                   materials: &HashMap<String, Arc<dyn Material>>,
+                   textures: &Arc<TextureCache>)
+                   -> Result<Scene> {
+        match self.scripts.first() {
+            Some(script) => Err(invalid(format!("{} needs the `scripting` feature", script.path))),
+            None => Ok(scene),
+        }
+    }
+
+    fn add_model(&self,
+                 scene: &mut Scene,
+                 path: &Path,
+                 material: Option<Arc<dyn Material>>,
+                 textures: &Arc<TextureCache>)
+                 -> Result<()> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        let mesh = match extension.as_str() {
+            "obj" => {
+                let model = obj::load(path, textures)?;
+                match material {
+                    Some(material) => {
+                        for group in model.groups {
+                            let mut mesh = group.mesh;
+                            mesh.material_ids = None;
+                            scene.add(Primitive::new(mesh, material.clone()));
+                        }
+                    }
+                    None => {
+                        model.add_to(scene);
+                    }
+                }
+                return Ok(());
+            }
+            "ply" => ply::load(path)?,
+            "stl" => stl::load(path)?,
+            _ => return Err(invalid(format!("unknown model format {:?}", extension))),
+        };
+        let material = material.ok_or_else(|| invalid(format!("{} needs a material", path.display())))?;
+        scene.add(Primitive::new(mesh, material));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_scene_description() {
+    use ray::Ray;
+    use material::MaterialData;
+
+    let description = SceneDescription::parse(r#"
+        [settings]
+        samples = 16
+        filter = "box"
+
+        [[cameras]]
+        name = "side"
+        from = [1.0, 0.0, 0.0]
+        to = [0.0, 0.0, 0.0]
+        fov = 45.0
+        stereo = { separation = 0.1 }
+
+        [[materials]]
+        name = "red"
+        type = "lambertian"
+        albedo = [1.0, 0.0, 0.0]
+
+        [[materials]]
+        name = "glass"
+        type = "dielectric"
+        ior = 1.5
+        priority = 2
+
+        [[materials]]
+        name = "prism"
+        type = "dielectric"
+        dispersion = { model = "cauchy", a = 1.5046, b = 0.0042 }
+
+        [[materials]]
+        name = "window"
+        type = "thin_dielectric"
+        tint = [0.8, 1.0, 0.9]
+
+        [[materials]]
+        name = "cloud"
+        type = "volume"
+        density = 4.0
+        phase = { type = "two_lobe", g1 = 0.85, g2 = -0.3, weight = 0.9 }
+
+        [[materials]]
+        name = "lamp"
+        type = "emissive"
+        temperature = 2700.0
+        intensity = 4.0
+
+        [[objects]]
+        type = "sphere"
+        center = [0.0, 0.0, 0.0]
+        radius = 0.5
+        material = "red"
+        transform = { translation = [0.0, 0.0, -2.0] }
+
+        [[objects]]
+        type = "plane"
+        center = [0.0, -1.0, 0.0]
+        normal = [0.0, 1.0, 0.0]
+        material = "glass"
+        visibility = { shadow = false }
+
+        [[objects]]
+        type = "quad"
+        corner = [-1.0, 2.0, -1.0]
+        u = [2.0, 0.0, 0.0]
+        v = [0.0, 0.0, 2.0]
+        material = "lamp"
+
+        [[portals]]
+        corner = [-1.0, 2.0, -1.0]
+        u = [2.0, 0.0, 0.0]
+        v = [0.0, 0.0, 2.0]
+    "#)
+        .unwrap();
+    let mut settings = RenderSettings::new();
+    description.apply_settings(&mut settings);
+    assert_eq!(settings.samples, 16);
+    assert_eq!(settings.filter, FilterType::Box);
+    assert_eq!(settings.max_depth, RenderSettings::new().max_depth);
+    assert!(SceneDescription::parse("[settings]\nfilter = \"lanczos\"").is_err());
+
+    let textures = Arc::new(TextureCache::new(0));
+    let scene = description.build(1.0, &textures).unwrap();
+    assert_eq!(scene.items.len(), 3);
+    assert_eq!((scene.lights.len(), scene.portals.len()), (2, 1));
+    assert!((scene.lights.power() - 4.0 * 4.0).abs() < 1e-3);
+    assert_eq!(scene.items[1].visibility, Visibility { shadow: false, ..Visibility::new() });
+    assert_eq!(scene.material(scene.items[1].material_id).medium(), Some((1.5, 2)));
+    let materials = description.build_materials(&textures).unwrap();
+    assert_eq!(materials["prism"].dispersion(), Some(&Dispersion::Cauchy { a: 1.5046, b: 0.0042, c: 0.0 }));
+    let cloud = materials["cloud"].volume().unwrap();
+    assert_eq!((cloud.albedo, cloud.density), (Color::white(), 4.0));
+    assert_eq!(cloud.phase.data(), PhaseData::TwoLobe { g1: 0.85, g2: -0.3, weight: 0.9 });
+    assert_eq!(materials["window"].data(), Some(MaterialData::ThinDielectric { ior: 1.5, tint: Color::new(0.8, 1.0, 0.9) }));
+    let stereo = scene.camera("side").unwrap().stereo.unwrap();
+    assert_eq!((stereo.convergence, stereo.layout), (1.0, StereoLayout::SideBySide));
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.001, Float::MAX);
+    let (dg, material) = scene.intersect(&r).unwrap();
+    assert!((dg.t - 1.5).abs() < 1e-6);
+    assert_eq!(material.albedo(), Color::new(1.0, 0.0, 0.0));
+
+    // Typos and missing names are errors, rather than silently ignored
+    assert!(SceneDescription::parse("[settings]\nsampels = 4\n").is_err());
+    let missing = SceneDescription::parse("[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = \
+                                           1.0\nmaterial = \"blue\"\n")
+        .unwrap();
+    assert!(missing.build(1.0, &textures).is_err());
+    let ambiguous = SceneDescription::parse("[[materials]]\nname = \"lamp\"\ntype = \"emissive\"\nradiance = [1.0, \
+                                             1.0, 1.0]\ntemperature = 3000.0\n")
+        .unwrap();
+    assert!(ambiguous.build(1.0, &textures).is_err());
+    let no_ior = SceneDescription::parse("[[materials]]\nname = \"glass\"\ntype = \"dielectric\"\n").unwrap();
+    assert!(no_ior.build(1.0, &textures).is_err());
+}
@@ -0,0 +1,230 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use mesh::Mesh;
+use material::Material;
+use material::Lambertian;
+use material::Metallic;
+use material::Dielectric;
+use scene::Scene;
+use environment::Environment;
+use builder::SceneBuilder;
+use rng::seeded_rng;
+use rng::Rng;
+
+// Canonical scenes, for examples, benchmarks, and regression tests: each is
+// built for images of the given aspect ratio, with a camera named "main"
+pub const NAMES: [&str; 3] = ["random_spheres", "cornell_box", "material_showcase"];
+
+// One of the scenes above, by name
+pub fn named(name: &str, aspect_ratio: Float) -> Option<Scene> {
+    match name {
+        "random_spheres" => Some(random_spheres(1, aspect_ratio)),
+        "cornell_box" => Some(cornell_box(aspect_ratio)),
+        "material_showcase" => Some(material_showcase(aspect_ratio)),
+        _ => None,
+    }
+}
+
+// (The built-in scenes only use shapes that can't fail to build)
+fn build(builder: SceneBuilder) -> Scene {
+    builder.build().expect("a built-in scene is malformed")
+}
+
+// The final scene of "Ray Tracing in One Weekend": a field of small spheres
+// (mostly diffuse, some metal, and a few glass), placed and colored at
+// random from `seed`, around three large spheres
+pub fn random_spheres(seed: u32, aspect_ratio: Float) -> Scene {
+    let mut rng = seeded_rng(seed);
+    let mut random = || rng.next_float();
+    let mut builder = SceneBuilder::new()
+        .aspect_ratio(aspect_ratio)
+        .camera("main", &Vector::new(13.0, 2.0, 3.0), &Vector::zero(), 20.0)
+        .sphere(&Vector::new(0.0, -1000.0, 0.0), 1000.0)
+        .material(Lambertian::new(&Color::gray(0.5)))
+        .define_material("glass", Dielectric::new(1.5));
+    for a in -11..11 {
+        for b in -11..11 {
+            let choice = random();
+            let center = Vector::new(a as Float + 0.9 * random(), 0.2, b as Float + 0.9 * random());
+            if (center - Vector::new(4.0, 0.2, 0.0)).length() <= 0.9 {
+                continue;
+            }
+            builder = builder.sphere(&center, 0.2);
+            builder = if choice < 0.8 {
+                let albedo = Color::new(random() * random(), random() * random(), random() * random());
+                builder.material(Lambertian::new(&albedo))
+            } else if choice < 0.95 {
+                let albedo = Color::new(random(), random(), random()) * 0.5 + Color::gray(0.5);
+                builder.material(Metallic::new(&albedo, random() * 0.5))
+            } else {
+                builder.material_named("glass")
+            };
+        }
+    }
+    build(builder.sphere(&Vector::new(0.0, 1.0, 0.0), 1.0)
+        .material_named("glass")
+        .sphere(&Vector::new(-4.0, 1.0, 0.0), 1.0)
+        .material(Lambertian::new(&Color::new(0.4, 0.2, 0.1)))
+        .sphere(&Vector::new(4.0, 1.0, 0.0), 1.0)
+        .material(Metallic::new(&Color::new(0.7, 0.6, 0.5), 0.0)))
+}
+
+// The Cornell box (two feet wide, with its open side facing +z), around a
+// tall block and a short one: scenes are lit by the sky, so in place of its
+// light there's an opening in the ceiling
+pub fn cornell_box(aspect_ratio: Float) -> Scene {
+    let builder = SceneBuilder::new()
+        .aspect_ratio(aspect_ratio)
+        .camera("main", &Vector::new(0.0, 1.0, 2.9), &Vector::new(0.0, 1.0, -1.0), 40.0)
+        .define_material("white", Lambertian::new(&Color::new(0.73, 0.73, 0.73)));
+
+    // The walls, which all face into the box
+    let builder = builder.quad(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 0.0, -2.0))
+        .material_named("white")
+        .quad(&Vector::new(-1.0, 0.0, -2.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 2.0, 0.0))
+        .material_named("white")
+        .quad(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 2.0, 0.0))
+        .material(Lambertian::new(&Color::new(0.65, 0.05, 0.05)))
+        .quad(&Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 2.0, 0.0), &Vector::new(0.0, 0.0, -2.0))
+        .material(Lambertian::new(&Color::new(0.12, 0.45, 0.15)));
+
+    // The ceiling, in four pieces around the opening
+    let ceiling = [(Vector::new(-1.0, 2.0, 0.0), 0.7, 2.0),
+                   (Vector::new(-1.0, 2.0, -1.3), 0.7, 2.0),
+                   (Vector::new(-1.0, 2.0, -0.7), 0.6, 0.7),
+                   (Vector::new(0.3, 2.0, -0.7), 0.6, 0.7)];
+    let mut builder = builder;
+    for &(corner, depth, width) in &ceiling {
+        builder = builder.quad(&corner, &Vector::new(0.0, 0.0, -depth), &Vector::new(width, 0.0, 0.0))
+            .material_named("white");
+    }
+
+    build(builder.mesh(Mesh::cube())
+        .material_named("white")
+        .scaled(&Vector::new(0.3, 0.6, 0.3))
+        .rotated(&Vector::new(0.0, 15.0, 0.0))
+        .at(&Vector::new(-0.35, 0.6, -1.3))
+        .mesh(Mesh::cube())
+        .material_named("white")
+        .scaled(&Vector::new(0.3, 0.3, 0.3))
+        .rotated(&Vector::new(0.0, -18.0, 0.0))
+        .at(&Vector::new(0.35, 0.3, -0.7)))
+}
+
+// A grid of spheres on a floor: a row of diffuse spheres of different
+// colors, then metal spheres from smooth to rough, then glass spheres of
+// increasing index of refraction
+pub fn material_showcase(aspect_ratio: Float) -> Scene {
+    const COLUMNS: usize = 6;
+    let colors = [Color::new(0.9, 0.27, 0.27),
+                  Color::new(0.9, 0.72, 0.27),
+                  Color::new(0.45, 0.9, 0.27),
+                  Color::new(0.27, 0.9, 0.72),
+                  Color::new(0.27, 0.45, 0.9),
+                  Color::new(0.72, 0.27, 0.9)];
+    let mut builder = SceneBuilder::new()
+        .aspect_ratio(aspect_ratio)
+        .camera("main", &Vector::new(0.0, 2.0, 1.5), &Vector::new(0.0, 0.0, -3.2), 45.0)
+        .plane(&Vector::new(0.0, -0.5, 0.0), &Vector::new(0.0, 1.0, 0.0))
+        .material(Lambertian::new(&Color::gray(0.5)));
+    for (i, color) in colors.iter().enumerate() {
+        let t = i as Float / (COLUMNS - 1) as Float;
+        let x = i as Float - (COLUMNS - 1) as Float * 0.5;
+        builder = builder.sphere(&Vector::new(x, -0.05, -2.0), 0.45)
+            .material(Lambertian::new(color))
+            .sphere(&Vector::new(x, -0.05, -3.2), 0.45)
+            .material(Metallic::new(&Color::new(0.9, 0.9, 0.9), t))
+            .sphere(&Vector::new(x, -0.05, -4.4), 0.45)
+            .material(Dielectric::new(1.2 + t * 1.2));
+    }
+    build(builder)
+}
+
+// The radiance of the furnace's environment
+pub const FURNACE_RADIANCE: Color = Color {
+    r: 0.5,
+    g: 0.5,
+    b: 0.5,
+};
+
+// A furnace test of a material: a sphere of it, alone in a uniform
+// environment (see: `Environment::Uniform`), where a material that reflects
+// all the light reaching it can't be seen (so that the image is
+// `FURNACE_RADIANCE` everywhere), one that absorbs some of it looks darker,
+// and one that makes light looks brighter
+pub fn furnace<M: Material + 'static>(material: M, aspect_ratio: Float) -> Scene {
+    let mut scene = build(SceneBuilder::new()
+        .aspect_ratio(aspect_ratio)
+        .camera("main", &Vector::new(0.0, 0.0, 3.0), &Vector::zero(), 40.0)
+        .sphere(&Vector::zero(), 1.0)
+        .material(material));
+    scene.environment = Environment::Uniform { radiance: FURNACE_RADIANCE };
+    scene
+}
+
+#[test]
+fn test_builtin_scenes() {
+    use ray::Ray;
+
+    for name in &NAMES {
+        let scene = named(name, 1.5).unwrap();
+        let camera = scene.camera("main").unwrap();
+        assert_eq!(camera.aspect_ratio, 1.5);
+        // (Each camera looks at something)
+        assert!(scene.intersect(&camera.generate_ray(0.5, 0.5)).is_some(), "{}", name);
+    }
+    assert!(named("teapot", 1.0).is_none());
+
+    // The random spheres are the same for the same seed
+    let positions = |seed| {
+        random_spheres(seed, 1.0).items.iter().map(|item| item.bounds().unwrap().min).collect::<Vec<_>>()
+    };
+    assert_eq!(positions(3), positions(3));
+    assert!(positions(3) != positions(4));
+
+    // Looking up through the Cornell box's opening reaches the sky
+    let scene = cornell_box(1.0);
+    assert!(scene.intersect(&Ray::new(&Vector::new(0.0, 1.5, -1.0), &Vector::new(0.0, 1.0, 0.0), 0.001, Float::MAX))
+        .is_none());
+    assert!(scene.intersect(&Ray::new(&Vector::new(0.6, 1.5, -1.0), &Vector::new(0.0, 1.0, 0.0), 0.001, Float::MAX))
+        .is_some());
+}
+
+#[test]
+fn test_furnace() {
+    use renderer::Renderer;
+    use film::Film;
+    use filter::FilterType;
+    use std::sync::Arc;
+
+    // The darkest and brightest pixels of a furnace test of the material
+    fn furnace_range<M: Material + 'static>(material: M) -> (Float, Float) {
+        let scene = furnace(material, 1.0);
+        let mut renderer = Renderer::new();
+        renderer.samples = 4;
+        renderer.min_samples = 4;
+        renderer.max_depth = 64;
+        // (A filter without negative lobes, which would ring at the sphere's
+        // edges)
+        renderer.filter = Arc::from(FilterType::Box.create());
+        let mut film = Film::new(16, 16);
+        renderer.render(&mut film, (0, 0, 16, 16), scene.camera("main").unwrap(), &scene, 0, &|_: &_| {});
+        film.to_framebuffer().pixels.iter().fold((Float::MAX, Float::MIN), |(low, high), p| {
+            (low.min(p.r.min(p.g).min(p.b)), high.max(p.max_channel()))
+        })
+    }
+
+    // Materials that reflect everything disappear into the environment...
+    for (low, high) in [furnace_range(Lambertian::new(&Color::white())),
+                            furnace_range(Metallic::new(&Color::white(), 0.0)),
+                            furnace_range(Dielectric::new(1.5))] {
+        assert!((low - FURNACE_RADIANCE.r).abs() < 1e-6 && (high - FURNACE_RADIANCE.r).abs() < 1e-6,
+                "{} to {}",
+                low,
+                high);
+    }
+    // ...while those that absorb some of it show up darker
+    let (low, high) = furnace_range(Lambertian::new(&Color::gray(0.5)));
+    assert!(low < FURNACE_RADIANCE.r * 0.5 + 1e-6 && (high - FURNACE_RADIANCE.r).abs() < 1e-6);
+}
@@ -0,0 +1,155 @@
+use film::Film;
+use film::FilmTile;
+use film::Pixel;
+use filter::Filter;
+use stats;
+use stats::Counter;
+use stats::Counts;
+
+#[cfg(any(not(target_arch = "wasm32"), feature = "wasm-threads"))]
+use rayon;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// A rectangle of pixels rendered as one unit of work, where x1 and y1 are
+// exclusive
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn area(&self) -> u32 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+// Reported whenever a tile finishes
+#[derive(Copy, Clone, Debug)]
+pub struct TileProgress {
+    pub tile: Tile,
+    // The number of tiles finished so far (including this one), out of the
+    // total number of tiles
+    pub completed: usize,
+    pub total: usize,
+    // The rays traced so far, by the tiles that have finished
+    pub rays: u64,
+}
+
+// Split the given bounds, (x0, y0, x1, y1), into tiles of (at most) `size`
+// pixels square, in scanline order
+pub fn tiles(bounds: (u32, u32, u32, u32), size: u32) -> Vec<Tile> {
+    let (x0, y0, x1, y1) = bounds;
+    let mut tiles = Vec::new();
+    for ty in (y0..y1).step_by(size as usize) {
+        for tx in (x0..x1).step_by(size as usize) {
+            tiles.push(Tile {
+                x0: tx,
+                y0: ty,
+                x1: (tx + size).min(x1),
+                y1: (ty + size).min(y1),
+            });
+        }
+    }
+    tiles
+}
+
+// Render the tiles on rayon's threads, each of which repeatedly takes the
+// next tile from a shared queue (so that expensive regions of the image
+// don't hold up the rest of the work) and calls `render` with its pixels (in
+// scanline order), along with a tile to splat samples into: `init` creates
+// each thread's state (e.g. its sampler), and `progress` is called as each
+// tile finishes. What the threads count (see: `stats`) is flushed into
+// `counts` after each tile
+//
+// The tiles' splats are merged into the film in order once every tile is
+// finished, so that the image doesn't depend on which thread finished first
+pub fn render_tiles<S, I, R, P>(film: &mut Film,
+                                tiles: &[Tile],
+                                filter: &dyn Filter,
+                                counts: &Counts,
+                                init: I,
+                                render: R,
+                                progress: P)
+    where I: Fn() -> S + Sync,
+          R: Fn(&mut S, &Tile, &mut [Pixel], &mut FilmTile) + Sync,
+          P: Fn(&TileProgress) + Sync
+{
+    let next = AtomicUsize::new(0);
+    let completed = AtomicUsize::new(0);
+    let splats: Vec<Mutex<Option<FilmTile>>> = tiles.iter().map(|_| Mutex::new(None)).collect();
+    let shared = Mutex::new(film);
+
+    // Each thread takes tiles until there are none left
+    let work = || {
+        let mut state = init();
+        stats::discard();
+        loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            if i >= tiles.len() {
+                break;
+            }
+            let tile = tiles[i];
+
+            // Work on a copy of the tile's pixels, so that the film is only
+            // locked while they are copied in and out
+            let (mut pixels, mut splat) = {
+                let film = shared.lock().unwrap();
+                let mut pixels = Vec::with_capacity(tile.area() as usize);
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        pixels.push(*film.pixel(x, y));
+                    }
+                }
+                (pixels, FilmTile::new(&film, tile.x0, tile.y0, tile.x1, tile.y1, filter))
+            };
+
+            render(&mut state, &tile, &mut pixels, &mut splat);
+
+            {
+                let width = tile.x1 - tile.x0;
+                let mut film = shared.lock().unwrap();
+                for (j, pixel) in pixels.iter().enumerate() {
+                    *film.pixel_mut(tile.x0 + j as u32 % width, tile.y0 + j as u32 / width) = *pixel;
+                }
+            }
+            *splats[i].lock().unwrap() = Some(splat);
+            stats::flush(counts);
+            progress(&TileProgress {
+                tile,
+                completed: completed.fetch_add(1, Ordering::SeqCst) + 1,
+                total: tiles.len(),
+                rays: counts.total(Counter::Rays),
+            });
+        }
+    };
+    #[cfg(any(not(target_arch = "wasm32"), feature = "wasm-threads"))]
+    rayon::scope(|scope| {
+        for _ in 0..rayon::current_num_threads() {
+            scope.spawn(|_| work());
+        }
+    });
+    // (Without threads, the calling thread takes every tile)
+    #[cfg(all(target_arch = "wasm32", not(feature = "wasm-threads")))]
+    work();
+
+    let film = shared.into_inner().unwrap();
+    for splat in splats {
+        if let Some(ref splat) = *splat.lock().unwrap() {
+            film.merge_tile(splat);
+        }
+    }
+}
+
+#[test]
+fn test_tiles_cover_bounds() {
+    let tiles = tiles((2, 1, 70, 40), 32);
+    assert_eq!(tiles.len(), 6);
+    assert_eq!(tiles[0], Tile { x0: 2, y0: 1, x1: 34, y1: 33 });
+    assert_eq!(tiles[5], Tile { x0: 66, y0: 33, x1: 70, y1: 40 });
+    assert_eq!(tiles.iter().map(|t| t.area()).sum::<u32>(), 68 * 39);
+}
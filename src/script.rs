@@ -0,0 +1,249 @@
+use vector::Vector;
+use vector::Float;
+use ray::Ray;
+use material::Material;
+use mesh::Mesh;
+use shape::Sphere;
+use primitive::Primitive;
+use scene::Scene;
+use transform::Transform;
+use texture::TextureCache;
+use rng::seeded_rng;
+use rng::Rng;
+use rng::XorShiftRng;
+use obj;
+use ply;
+use stl;
+use error::Result;
+use error::TracerError;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, FLOAT};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Scripts (in Rhai, see: https://rhai.rs) that a scene file runs once its
+// objects are built, to place more of them procedurally, e.g. to scatter
+// rocks over a terrain:
+//
+//     for i in 0..5000 {
+//         let x = rand() * 100.0 - 50.0;
+//         let z = rand() * 100.0 - 50.0;
+//         let hit = raycast([x, 100.0, z], [0.0, -1.0, 0.0]);
+//         if hit != () {
+//             model("models/rock.obj", "stone", hit.position, [0.0, rand() * 360.0, 0.0], 0.5 + rand());
+//         }
+//     }
+//
+// where scripts can call
+//
+//     sphere(center, radius, material)
+//     model(path, material)
+//     model(path, material, translation)
+//     model(path, material, translation, rotation, scale)
+//     raycast(origin, direction)
+//     rand()
+//
+// with vectors as arrays of three numbers, the rotation as Euler angles in
+// degrees, the scale as a number, materials by their names in the scene
+// file, and paths relative to the scene file. Each model is loaded once, and
+// then instanced (every placement shares its meshes). `raycast` returns the
+// closest hit on the objects that were in the scene before the script ran, as
+// a map (of `position`, `normal`, and `distance`), or `()` if there's none,
+// and `rand` returns a number in [0, 1), from a generator seeded by the
+// script's seed (so that scenes load the same every time)
+
+// What the script has placed, and what it's loaded
+struct Placements {
+    primitives: Vec<Primitive>,
+    models: HashMap<PathBuf, Vec<Arc<Mesh>>>,
+    rng: XorShiftRng,
+}
+
+type Returns<T> = ::std::result::Result<T, Box<EvalAltResult>>;
+
+fn vector(v: &Array) -> Returns<Vector> {
+    let number = |d: &Dynamic| d.as_float().or_else(|_| d.as_int().map(|i| i as FLOAT));
+    match (v.len(), v.iter().map(number).collect::<::std::result::Result<Vec<_>, _>>()) {
+        (3, Ok(v)) => Ok(Vector::new(v[0] as Float, v[1] as Float, v[2] as Float)),
+        _ => Err(format!("{:?} isn't a vector of three numbers", v).into()),
+    }
+}
+
+fn array(v: &Vector) -> Array {
+    vec![Dynamic::from_float(v.x as FLOAT), Dynamic::from_float(v.y as FLOAT), Dynamic::from_float(v.z as FLOAT)]
+}
+
+// The meshes of a model (whose materials are replaced by the one given)
+fn load_model(path: &Path, textures: &Arc<TextureCache>) -> Result<Vec<Arc<Mesh>>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    Ok(match extension.as_str() {
+        "obj" => {
+            obj::load(path, textures)?
+                .groups
+                .into_iter()
+                .map(|group| {
+                    let mut mesh = group.mesh;
+                    mesh.material_ids = None;
+                    Arc::new(mesh)
+                })
+                .collect()
+        }
+        "ply" => vec![Arc::new(ply::load(path)?)],
+        "stl" => vec![Arc::new(stl::load(path)?)],
+        _ => return Err(TracerError::Parse(format!("unknown model format {:?}", extension))),
+    })
+}
+
+// Run the script at `path` on the scene, adding what it places (with
+// `materials`, by name, and loading models relative to `directory`)
+pub fn run(path: &Path,
+           seed: u32,
+           scene: Scene,
+           materials: &HashMap<String, Arc<dyn Material>>,
+           directory: &Path,
+           textures: &Arc<TextureCache>)
+           -> Result<Scene> {
+    let source = fs::read_to_string(path)?;
+    let scene = Rc::new(scene);
+    let placements = Rc::new(RefCell::new(Placements {
+        primitives: Vec::new(),
+        models: HashMap::new(),
+        rng: seeded_rng(seed),
+    }));
+
+    let mut engine = Engine::new();
+    let material = {
+        let materials = materials.clone();
+        move |name: &str| -> Returns<Arc<dyn Material>> {
+            materials.get(name).cloned().ok_or_else(|| format!("no material named {:?}", name).into())
+        }
+    };
+    {
+        let (placements, material) = (placements.clone(), material.clone());
+        engine.register_fn("sphere", move |center: Array, radius: FLOAT, name: &str| -> Returns<()> {
+            let sphere = Sphere::new(&vector(&center)?, radius as Float);
+            placements.borrow_mut().primitives.push(Primitive::new(sphere, material(name)?));
+            Ok(())
+        });
+    }
+    let model = {
+        let (placements, directory, textures) = (placements.clone(), directory.to_path_buf(), textures.clone());
+        Rc::new(move |path: &str, name: &str, transform: Transform| -> Returns<()> {
+            let material = material(name)?;
+            let path = directory.join(path);
+            let mut placements = placements.borrow_mut();
+            if !placements.models.contains_key(&path) {
+                let meshes = load_model(&path, &textures)
+                    .map_err(|why| format!("couldn't load {}: {}", path.display(), why))?;
+                placements.models.insert(path.clone(), meshes);
+            }
+            let meshes = placements.models[&path].clone();
+            for mesh in meshes {
+                let mut primitive = Primitive::new(mesh, material.clone());
+                primitive.transform = transform;
+                placements.primitives.push(primitive);
+            }
+            Ok(())
+        })
+    };
+    {
+        let model = model.clone();
+        engine.register_fn("model", move |path: &str, name: &str| model(path, name, Transform::identity()));
+    }
+    {
+        let model = model.clone();
+        engine.register_fn("model", move |path: &str, name: &str, translation: Array| -> Returns<()> {
+            let transform = Transform::new(&vector(&translation)?, &Vector::zero(), &Vector::one(), &Vector::zero());
+            model(path, name, transform)
+        });
+    }
+    engine.register_fn("model",
+                       move |path: &str, name: &str, translation: Array, rotation: Array, scale: FLOAT| -> Returns<()> {
+        let scale = Vector::one() * scale as Float;
+        let transform = Transform::new(&vector(&translation)?, &vector(&rotation)?, &scale, &Vector::zero());
+        model(path, name, transform)
+    });
+    {
+        let scene = scene.clone();
+        engine.register_fn("raycast", move |origin: Array, direction: Array| -> Returns<Dynamic> {
+            let ray = Ray::new(&vector(&origin)?, &vector(&direction)?.normalize(), 0.0, Float::MAX);
+            Ok(match scene.raycast(&ray) {
+                Some(hit) => {
+                    let mut map = Map::new();
+                    map.insert("position".into(), array(&hit.position).into());
+                    map.insert("normal".into(), array(&hit.normal).into());
+                    map.insert("distance".into(), Dynamic::from_float(hit.t as FLOAT));
+                    map.into()
+                }
+                None => Dynamic::UNIT,
+            })
+        });
+    }
+    {
+        let placements = placements.clone();
+        engine.register_fn("rand", move || placements.borrow_mut().rng.next_f64() as FLOAT);
+    }
+
+    engine.run(&source).map_err(|why| TracerError::Parse(format!("{}: {}", path.display(), why)))?;
+    drop(engine);
+
+    let placed = Rc::try_unwrap(placements).ok().expect("the engine was dropped").into_inner().primitives;
+    let mut scene = Rc::try_unwrap(scene).ok().expect("the engine was dropped");
+    for primitive in placed {
+        scene.add(primitive);
+    }
+    scene.build_bvh();
+    Ok(scene)
+}
+
+#[test]
+fn test_script() {
+    use color::Color;
+    use shape::Plane;
+    use material::Lambertian;
+    use texture::DEFAULT_BUDGET;
+    use std::env;
+
+    let directory = env::temp_dir();
+    let path = directory.join("tracer_test_script.rhai");
+    fs::write(&path,
+              "for i in 0..100 {
+                   let hit = raycast([rand() * 10.0 - 5.0, 10, rand() * 10.0 - 5.0], [0, -1, 0]);
+                   if hit != () {
+                       sphere(hit.position, 0.1, \"white\");
+                   }
+               }")
+        .unwrap();
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&Color::white()));
+    let materials: HashMap<String, Arc<dyn Material>> = vec![("white".to_string(), white.clone())].into_iter().collect();
+    let textures = Arc::new(TextureCache::new(DEFAULT_BUDGET));
+    let build = || {
+        let mut ground = Scene::new();
+        ground.add(Primitive::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0)), white.clone()));
+        run(&path, 7, ground, &materials, &directory, &textures).unwrap()
+    };
+
+    // Every sphere sits on the ground, and scripts place the same spheres
+    // every time
+    let (scene, again) = (build(), build());
+    assert_eq!(scene.items.len(), 101);
+    for (item, other) in scene.items[1..].iter().zip(&again.items[1..]) {
+        match (&item.shape, &other.shape) {
+            (::shape::Geometry::Sphere(s), ::shape::Geometry::Sphere(o)) => {
+                assert!(s.center.y.abs() < 1e-6 && s.center == o.center);
+            }
+            _ => panic!("expected spheres"),
+        }
+    }
+
+    // Mistakes are reported with the script's path
+    fs::write(&path, "sphere([0, 0, 0], 1.0, \"gold\");").unwrap();
+    let why = run(&path, 0, Scene::new(), &materials, &directory, &textures).err().unwrap().to_string();
+    assert!(why.contains("no material named \"gold\"") && why.contains("tracer_test_script"), "{}", why);
+}
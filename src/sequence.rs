@@ -0,0 +1,71 @@
+use vector::Float;
+use camera::Camera;
+use scene::Scene;
+use animation::CameraAnimation;
+use animation::ObjectAnimation;
+
+// An animation over a range of frames: the camera and the transforms of
+// individual primitives are each described by keyframe tracks (see:
+// `animation`)
+pub struct Sequence {
+    // The (inclusive) range of frames rendered
+    pub first_frame: u32,
+    pub last_frame: u32,
+    pub camera: CameraAnimation,
+    pub objects: Vec<ObjectAnimation>,
+    // How long the shutter stays open, as a fraction of a frame (centered on
+    // the frame): 0 renders each frame at a single instant, while larger
+    // values blur whatever moves while the shutter is open
+    pub shutter: Float,
+}
+
+impl Sequence {
+    pub fn new(first_frame: u32, last_frame: u32) -> Sequence {
+        Sequence {
+            first_frame,
+            last_frame,
+            camera: CameraAnimation::default(),
+            objects: Vec::new(),
+            shutter: 0.0,
+        }
+    }
+
+    // Move the animated primitives to where they are at `time` (in frames):
+    // the scene is updated in place, rather than rebuilt (though its BVH,
+    // if it has one, is)
+    pub fn update_scene(&self, scene: &mut Scene, time: Float) {
+        for object in &self.objects {
+            if let Some(item) = scene.items.get_mut(object.object_id as usize) {
+                item.transform = object.evaluate(time);
+            }
+        }
+        if scene.bvh.is_some() {
+            scene.build_bvh();
+        }
+    }
+
+    pub fn camera(&self, time: Float, default_fov: Float, aspect_ratio: Float) -> Camera {
+        self.camera.evaluate(time, default_fov, aspect_ratio)
+    }
+
+    // The time at which to render the given pass of a frame: the passes are
+    // spread evenly over the time the shutter is open, so that together they
+    // accumulate motion blur
+    pub fn shutter_time(&self, frame: u32, pass: u32, passes: u32) -> Float {
+        frame as Float + self.shutter * ((pass as Float + 0.5) / passes.max(1) as Float - 0.5)
+    }
+}
+
+// The path of one frame's image, e.g. "output/render_0042.png"
+pub fn frame_path(stem: &str, frame: u32, extension: &str) -> String {
+    format!("{}_{:04}.{}", stem, frame, extension)
+}
+
+#[test]
+fn test_shutter_time() {
+    let mut sequence = Sequence::new(0, 10);
+    assert_eq!(sequence.shutter_time(3, 1, 4), 3.0);
+    sequence.shutter = 0.5;
+    assert_eq!(sequence.shutter_time(3, 0, 4), 2.8125);
+    assert_eq!(sequence.shutter_time(3, 3, 4), 3.1875);
+}
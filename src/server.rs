@@ -0,0 +1,172 @@
+use film::Film;
+use tonemap::DisplayTransform;
+
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::io;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+// A page that shows the render and its statistics, refreshing itself
+const INDEX: &str = "<!DOCTYPE html>
+<html>
+<head><title>tracer</title></head>
+<body style=\"background: #222; color: #ddd; font-family: monospace\">
+<img id=\"render\" src=\"/render.png\"><pre id=\"stats\"></pre>
+<script>
+setInterval(function() {
+    document.getElementById('render').src = '/render.png?' + Date.now();
+    fetch('/stats').then(r => r.text()).then(t => document.getElementById('stats').textContent = t);
+}, 2000);
+</script>
+</body>
+</html>
+";
+
+// How far along the render is
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Progress {
+    pub pass: u32,
+    pub samples_per_pixel: f64,
+    pub elapsed_seconds: f64,
+    pub finished: bool,
+}
+
+// The most recently published state of the render
+struct Snapshot {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+    progress: Progress,
+}
+
+// Serves the in-progress render over HTTP, so that headless renders can be
+// monitored from a browser: the page at "/" shows the image (also available
+// at "/render.png") and the statistics (at "/stats", as JSON)
+pub struct PreviewServer {
+    pub address: SocketAddr,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl PreviewServer {
+    // Start serving on a background thread
+    pub fn start(address: &str) -> io::Result<PreviewServer> {
+        let listener = TcpListener::bind(address)?;
+        // (Binding to port 0 picks a free port, so ask which one it was)
+        let address = listener.local_addr()?;
+        let snapshot = Arc::new(Mutex::new(Snapshot {
+            width: 0,
+            height: 0,
+            rgb: Vec::new(),
+            progress: Progress::default(),
+        }));
+
+        let shared = snapshot.clone();
+        thread::spawn(move || {
+            // (A client that goes away mid-response only fails its own
+            // request)
+            for stream in listener.incoming().flatten() {
+                match respond(stream, &shared) {
+                    Ok(()) => {}
+                    #[cfg(feature = "tracing")]
+                    Err(why) => tracing::warn!(%why, "the preview server couldn't respond"),
+                    #[cfg(not(feature = "tracing"))]
+                    Err(_) => {}
+                }
+            }
+        });
+
+        Ok(PreviewServer {
+            address,
+            snapshot,
+        })
+    }
+
+    // Make the film's current state visible to clients
+    pub fn publish(&self, film: &Film, display: &DisplayTransform, progress: Progress) {
+        let rgb = film.to_framebuffer().to_rgb8(display);
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.width = film.width;
+        snapshot.height = film.height;
+        snapshot.rgb = rgb;
+        snapshot.progress = progress;
+    }
+}
+
+fn respond(mut stream: TcpStream, snapshot: &Mutex<Snapshot>) -> io::Result<()> {
+    // Only the request line matters: "GET /path HTTP/1.1"
+    let mut request = [0u8; 1024];
+    let n = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap_or(path);
+
+    let (status, content_type, body) = match path {
+        "/" => ("200 OK", "text/html", INDEX.as_bytes().to_vec()),
+        "/stats" => {
+            let snapshot = snapshot.lock().unwrap();
+            let p = snapshot.progress;
+            let json = format!("{{\"width\": {}, \"height\": {}, \"pass\": {}, \"samples_per_pixel\": {:.2}, \
+                                \"elapsed_seconds\": {:.1}, \"finished\": {}}}",
+                               snapshot.width,
+                               snapshot.height,
+                               p.pass,
+                               p.samples_per_pixel,
+                               p.elapsed_seconds,
+                               p.finished);
+            ("200 OK", "application/json", json.into_bytes())
+        }
+        "/render.png" => {
+            // Copy the image so that the render isn't blocked while encoding
+            let (width, height, rgb) = {
+                let snapshot = snapshot.lock().unwrap();
+                (snapshot.width, snapshot.height, snapshot.rgb.clone())
+            };
+            if rgb.is_empty() {
+                ("503 Service Unavailable", "text/plain", b"no image yet".to_vec())
+            } else {
+                let mut png = Vec::new();
+                PngEncoder::new(&mut png)
+                    .write_image(&rgb, width, height, ExtendedColorType::Rgb8)
+                    .map_err(io::Error::other)?;
+                ("200 OK", "image/png", png)
+            }
+        }
+        _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+    };
+
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+           status,
+           content_type,
+           body.len())?;
+    stream.write_all(&body)
+}
+
+#[test]
+fn test_serves_stats() {
+    let server = PreviewServer::start("127.0.0.1:0").unwrap();
+    server.publish(&Film::new(4, 2),
+                   &DisplayTransform::default(),
+                   Progress {
+                       pass: 3,
+                       samples_per_pixel: 3.0,
+                       elapsed_seconds: 1.0,
+                       finished: false,
+                   });
+
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    stream.write_all(b"GET /stats HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("\"pass\": 3"));
+}
@@ -0,0 +1,229 @@
+use vector::Float;
+use renderer::Renderer;
+use sampler::SamplerType;
+use filter::FilterType;
+use tonemap::DisplayTransform;
+use tonemap::ToneMapOperator;
+use bloom::Bloom;
+use framebuffer::Framebuffer;
+use backplate::Backplate;
+use error::Result;
+use error::TracerError;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Everything that decides how an image is rendered (other than the scene
+// and its camera), which builds the renderer that samples the film (see:
+// `renderer`) and the display transform that the image is saved with
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    // The maximum number of samples per pixel, of which each pixel takes at
+    // least `min_samples` before it may stop once its relative error is
+    // below `noise_threshold`
+    pub samples: u32,
+    pub min_samples: u32,
+    pub noise_threshold: Float,
+    pub max_depth: u32,
+    pub sampler: SamplerType,
+    pub filter: FilterType,
+    pub seed: u32,
+    // Render threads take square tiles of this many pixels across
+    pub tile_size: u32,
+    pub display: DisplayTransform,
+    // Glare, added to the image before it's tone mapped (see: `Bloom`)
+    pub bloom: Option<Bloom>,
+    // Render the scene over a transparent background, with the image written
+    // with its alpha (see: `Renderer::transparent`), or over a photograph
+    // loaded from this path (see: `Backplate`), resolved from the working
+    // directory
+    pub transparent: bool,
+    pub backplate: Option<PathBuf>,
+}
+
+impl RenderSettings {
+    // The renderer's own defaults, in an 800 x 800 image
+    pub fn new() -> RenderSettings {
+        let renderer = Renderer::new();
+        RenderSettings {
+            width: 800,
+            height: 800,
+            samples: renderer.samples,
+            min_samples: renderer.min_samples,
+            noise_threshold: renderer.noise_threshold,
+            max_depth: renderer.max_depth,
+            sampler: renderer.sampler,
+            filter: FilterType::Mitchell,
+            seed: 0,
+            tile_size: renderer.tile_size,
+            display: DisplayTransform::default(),
+            bloom: None,
+            transparent: false,
+            backplate: None,
+        }
+    }
+
+    // Few samples and short paths, for a quick look at a scene
+    pub fn preview() -> RenderSettings {
+        RenderSettings {
+            samples: 16,
+            min_samples: 4,
+            noise_threshold: 0.05,
+            max_depth: 3,
+            filter: FilterType::Box,
+            tile_size: 16,
+            ..RenderSettings::new()
+        }
+    }
+
+    // Enough samples to converge (with adaptive sampling stopping each pixel
+    // once it has), and long paths, for final images
+    pub fn production() -> RenderSettings {
+        RenderSettings {
+            samples: 1024,
+            min_samples: 64,
+            noise_threshold: 0.002,
+            max_depth: 16,
+            display: DisplayTransform { operator: ToneMapOperator::Aces, ..DisplayTransform::default() },
+            ..RenderSettings::new()
+        }
+    }
+
+    // One of the presets above, by name ("preview" or "production")
+    pub fn preset(name: &str) -> Option<RenderSettings> {
+        match name {
+            "preview" => Some(RenderSettings::preview()),
+            "production" => Some(RenderSettings::production()),
+            _ => None,
+        }
+    }
+
+    // Load settings from a TOML file, e.g.
+    //
+    //     preset = "production"
+    //     width = 1280
+    //     height = 720
+    //
+    //     [display]
+    //     exposure = 0.5
+    //
+    // where anything left out is taken from the preset (if one is named) or
+    // from `new`
+    pub fn load(path: &Path) -> Result<RenderSettings> {
+        RenderSettings::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<RenderSettings> {
+        let mut table: toml::Table = toml::from_str(source).map_err(|why| invalid(why.to_string()))?;
+        let base = match table.remove("preset") {
+            Some(toml::Value::String(name)) => {
+                RenderSettings::preset(&name).ok_or_else(|| invalid(format!("unknown preset {:?}", name)))?
+            }
+            Some(_) => return Err(invalid("the preset should be a name".to_string())),
+            None => RenderSettings::new(),
+        };
+
+        // Override the preset's settings with those given (as well as those
+        // of its tables, so that e.g. the exposure can be set on its own)
+        let mut merged = match toml::Value::try_from(&base) {
+            Ok(toml::Value::Table(merged)) => merged,
+            _ => unreachable!("the settings are a table"),
+        };
+        for (key, value) in table {
+            match (merged.get_mut(&key), value) {
+                (Some(&mut toml::Value::Table(ref mut base)), toml::Value::Table(overrides)) => base.extend(overrides),
+                (_, value) => {
+                    merged.insert(key, value);
+                }
+            }
+        }
+        toml::Value::Table(merged).try_into().map_err(|why: toml::de::Error| invalid(why.to_string()))
+    }
+
+    pub fn aspect_ratio(&self) -> Float {
+        self.width as Float / self.height as Float
+    }
+
+    // A renderer that samples with these settings (which renders the whole
+    // film, without a blue-noise mask, AOVs, or the backplate, which is
+    // loaded by `load_backplate`)
+    pub fn renderer(&self) -> Renderer {
+        Renderer {
+            samples: self.samples,
+            min_samples: self.min_samples,
+            noise_threshold: self.noise_threshold,
+            max_depth: self.max_depth,
+            tile_size: self.tile_size,
+            sampler: self.sampler,
+            filter: Arc::from(self.filter.create()),
+            transparent: self.transparent,
+            ..Renderer::new()
+        }
+    }
+
+    pub fn load_backplate(&self) -> Result<Option<Arc<Backplate>>> {
+        match self.backplate {
+            Some(ref path) => Ok(Some(Arc::new(Backplate::load(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    // Whether the image has an alpha to be written with it: a backplate fills
+    // in the background of a transparent render
+    pub fn writes_alpha(&self) -> bool {
+        self.transparent && self.backplate.is_none()
+    }
+
+    // Apply the post-processing to a rendered (and resolved) image, before
+    // it's written with the display transform
+    pub fn post_process(&self, image: Framebuffer) -> Framebuffer {
+        match self.bloom {
+            Some(bloom) => bloom.apply(&image),
+            None => image,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings::new()
+    }
+}
+
+fn invalid(why: String) -> TracerError {
+    TracerError::Parse(why)
+}
+
+#[test]
+fn test_render_settings() {
+    let settings = RenderSettings::parse("preset = \"preview\"\nwidth = 320\nheight = 240\n\n[display]\nexposure = 1.0\n")
+        .unwrap();
+    assert_eq!((settings.width, settings.height), (320, 240));
+    assert_eq!(settings.samples, RenderSettings::preview().samples);
+    assert_eq!(settings.display.exposure, 1.0);
+    assert_eq!(settings.display.operator, RenderSettings::preview().display.operator);
+    assert_eq!(settings.renderer().max_depth, 3);
+    assert_eq!(RenderSettings::parse("[bloom]\nintensity = 0.1\n").unwrap().bloom,
+               Some(Bloom { intensity: 0.1, ..Bloom::new() }));
+
+    let transparent = RenderSettings::parse("transparent = true\nbackplate = \"plate.jpg\"\n").unwrap();
+    assert!(transparent.renderer().transparent && !transparent.writes_alpha());
+    assert_eq!(transparent.backplate, Some(PathBuf::from("plate.jpg")));
+    assert!(RenderSettings { backplate: None, ..transparent }.writes_alpha());
+    assert!(!RenderSettings::new().renderer().transparent);
+
+    // Everything that's saved loads again
+    let production = RenderSettings::production();
+    assert_eq!(RenderSettings::parse(&toml::to_string(&production).unwrap()).unwrap(), production);
+
+    // Typos are errors, rather than silently ignored
+    assert!(RenderSettings::parse("sampels = 4\n").is_err());
+    assert!(RenderSettings::parse("preset = \"final\"\n").is_err());
+}
@@ -1,76 +1,166 @@
 use vector::Vector;
+use vector::Float;
+use vector::consts;
+#[cfg(test)]
+use vector::TEST_EPSILON;
 use ray::Ray;
+use aabb::Aabb;
+use sphere_list::SphereList;
+use mesh::Mesh;
+use mesh::LodMesh;
+use stats;
+use stats::Counter;
 
-const EPSILON: f64 = 0.001;
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+// How far along a ray a hit must be (so that rays leaving a surface don't hit
+// it again)
+pub const EPSILON: Float = 0.001;
 
 #[derive(Clone)]
 pub struct DifferentialGeometry<'a> {
     // How far along the ray
-    pub t: f64,
+    pub t: Float,
     // Point of intersection
     pub position: Vector,
     // Normal at point of intersection
     pub normal: Vector,
     // Shape that was hit
-    pub shape: &'a Shape,
+    pub shape: &'a dyn Shape,
+    // The material ID of the part of the shape that was hit, for shapes that
+    // carry their own materials (see: `SphereList`), which overrides the
+    // primitive's material
+    pub material_id: Option<u32>,
+    // Texture coordinates of the point of intersection (see: `texture`), or
+    // zero for shapes without any
+    pub uv: (Float, Float),
 }
 
 impl<'a> DifferentialGeometry<'a> {
-    pub fn new(t: f64, p: &Vector, n: &Vector, s: &'a Shape) -> DifferentialGeometry<'a> {
+    pub fn new(t: Float, p: &Vector, n: &Vector, s: &'a dyn Shape) -> DifferentialGeometry<'a> {
+        if cfg!(feature = "validate") {
+            assert!(t.is_finite() && p.is_finite() && n.is_finite(), "a hit at {:?} (t = {}) with normal {:?}", p, t, n);
+        }
         DifferentialGeometry {
-            t: t,
+            t,
             position: *p,
             normal: *n,
             shape: s,
+            material_id: None,
+            uv: (0.0, 0.0),
         }
     }
 }
 
+// The distance along a ray (with a unit direction `d`) to the nearest point
+// at which it hits the sphere of the given radius, where `f` is the ray's
+// origin relative to the sphere's center
+pub fn sphere_distance(f: &Vector, d: &Vector, radius: Float) -> Option<Float> {
+    // Sphere: dot((p - c), (p - c)) = r * r;
+    // Ray: a + d * t = p
+    // Substitute (with f = a - c): t * t + 2 * t * dot(f, d) + dot(f, f) - r * r = 0
+    //
+    // The discriminant is found from the point at which the ray passes closest
+    // to the center, rather than as b * b - 4 * c, which loses most of its
+    // precision to cancellation when the sphere is large (or the ray starts
+    // far from it), and the second root from the product of the roots (see:
+    // "Precision Improvements for Ray/Sphere Intersection", in Ray Tracing
+    // Gems), so that the points of intersection don't land inside the sphere
+    let b = -f.dot(d);
+    let closest = *f + *d * b;
+    let discriminant = radius * radius - closest.dot(&closest);
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = if b < 0.0 { b - discriminant.sqrt() } else { b + discriminant.sqrt() };
+    if q == 0.0 {
+        return None;
+    }
+    let c = f.dot(f) - radius * radius;
+    let (t_0, t_1) = (c / q, q);
+    let (near, far) = if t_0 < t_1 { (t_0, t_1) } else { (t_1, t_0) };
+
+    if near > EPSILON {
+        Some(near)
+    } else if far > EPSILON {
+        Some(far)
+    } else {
+        None
+    }
+}
+
+// The texture coordinates of the point on a sphere with the given (unit)
+// normal: u wraps around the sphere's y-axis and v runs from its bottom to
+// its top
+pub fn sphere_uv(normal: &Vector) -> (Float, Float) {
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    let phi = (-normal.z).atan2(normal.x) + consts::PI;
+    (phi / (2.0 * consts::PI), theta / consts::PI)
+}
+
+// A plain description of a shape, for backends that can't call back into
+// the shape itself (see: `gpu`)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShapeData {
+    Sphere { center: Vector, radius: Float },
+    Plane { center: Vector, normal: Vector },
+}
+
 pub trait Shape: Sync + Send {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry>;
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>>;
+
+    // Does the ray hit the shape anywhere closer than its `t_max` (e.g.
+    // between a point and a light)? This only needs the distance to the hit,
+    // so shapes override it to skip finding the position and normal
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.intersect(r).is_some_and(|dg| dg.t < r.t_max)
+    }
+
+    // Shapes that can't be described by `ShapeData` return `None`
+    fn data(&self) -> Option<ShapeData> {
+        None
+    }
+
+    // The shape's bounds, or `None` for unbounded shapes (e.g. planes), which
+    // are tested against every ray rather than placed in the scene's BVH
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Sphere {
     pub center: Vector,
-    pub radius: f64,
+    pub radius: Float,
 }
 
 impl Shape for Sphere {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry> {
-        // Sphere: dot((p - c), (p - c)) = r * r;
-        // Ray: a + b * t = p
-        // Substitute: dot((a + b * t - c), (a + b * t - c)) = r * r
-        // Expand: dot(b, b) * t * t + 2 * t * dot(b, a - c) + dot(a - c, a - c) - r * r = 0
-
-        // The discriminant of the resulting quadratic equation will either be
-        // positive (two real solutions), negative (no real solutions), or zero
-        // (one real solution)
-        let b = ((r.origin - self.center) * 2.0).dot(&r.direction);
-        let c = (r.origin - self.center).dot(&(r.origin - self.center)) - self.radius * self.radius;
-        let mut discriminant = b * b - 4.0 * c;
-
-        if discriminant < 0.0 {
-            return None;
-        }
-        discriminant = discriminant.sqrt();
-
-        let solution_0 = -b + discriminant;
-        let solution_1 = -b - discriminant;
-
-        if solution_1 > EPSILON {
-            let t: f64 = solution_1 * 0.5;
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        self.distance(r).map(|t| {
             let position = r.point_at(t);
             let normal = (position - self.center) / self.radius;
-            Some(DifferentialGeometry::new(t, &position, &normal, self))
-        } else if solution_0 > EPSILON {
-            let t: f64 = solution_0 * 0.5;
-            let position = r.point_at(t);
-            let normal = (position - self.center) / self.radius;
-            Some(DifferentialGeometry::new(t, &position, &normal, self))
-        } else {
-            None
-        }
+            let mut dg = DifferentialGeometry::new(t, &position, &normal, self);
+            dg.uv = sphere_uv(&normal);
+            dg
+        })
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.distance(r).is_some_and(|t| t < r.t_max)
+    }
+
+    fn data(&self) -> Option<ShapeData> {
+        Some(ShapeData::Sphere {
+            center: self.center,
+            radius: self.radius,
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vector::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(&(self.center - r), &(self.center + r)))
     }
 }
 
@@ -84,34 +174,39 @@ impl Default for Sphere {
 }
 
 impl Sphere {
-    pub fn new(c: &Vector, r: f64) -> Sphere {
+    pub fn new(c: &Vector, r: Float) -> Sphere {
         Sphere {
             center: *c,
             radius: r,
         }
     }
+
+    // The distance along the ray to the nearest point of intersection
+    fn distance(&self, r: &Ray) -> Option<Float> {
+        sphere_distance(&(r.origin - self.center), &r.direction, self.radius)
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Plane {
     pub center: Vector,
     pub normal: Vector,
 }
 
 impl Shape for Plane {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry> {
-        // Ignore cases where the ray direction is parallel to the plane
-        let denominator = r.direction.dot(&self.normal);
-        if denominator.abs() > EPSILON {
-            let p_minus_l = self.center - r.origin;
-            let t = p_minus_l.dot(&self.normal) / denominator;
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        self.distance(r).map(|t| DifferentialGeometry::new(t, &r.point_at(t), &self.normal, self))
+    }
 
-            // TODO: this is not correct - planes should be infinite
-            if t >= EPSILON && r.point_at(t).y < 1.0 {
-                return Some(DifferentialGeometry::new(t, &r.point_at(t), &self.normal, self));
-            }
-        }
-        None
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.distance(r).is_some_and(|t| t < r.t_max)
+    }
+
+    fn data(&self) -> Option<ShapeData> {
+        Some(ShapeData::Plane {
+            center: self.center,
+            normal: self.normal,
+        })
     }
 }
 
@@ -131,4 +226,340 @@ impl Plane {
             normal: *n,
         }
     }
+
+    fn distance(&self, r: &Ray) -> Option<Float> {
+        // Ignore cases where the ray direction is parallel to the plane
+        let denominator = r.direction.dot(&self.normal);
+        if denominator.abs() > EPSILON {
+            let p_minus_l = self.center - r.origin;
+            let t = p_minus_l.dot(&self.normal) / denominator;
+
+            // TODO: this is not correct - planes should be infinite
+            if t >= EPSILON && r.point_at(t).y < 1.0 {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+// A triangle, whose normal faces the side from which its vertices wind
+// counterclockwise
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Triangle {
+    pub vertices: [Vector; 3],
+}
+
+impl Shape for Triangle {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        self.distance(r).map(|t| {
+            let [a, b, c] = self.vertices;
+            let position = r.point_at(t);
+            let n = (b - a).cross(&(c - a));
+            let mut dg = DifferentialGeometry::new(t, &position, &n.normalize(), self);
+            // The point's barycentric coordinates for b and c, since
+            // p - a = beta * (b - a) + gamma * (c - a)
+            let inverse_area = 1.0 / n.dot(&n);
+            dg.uv = ((position - a).cross(&(c - a)).dot(&n) * inverse_area,
+                     (b - a).cross(&(position - a)).dot(&n) * inverse_area);
+            dg
+        })
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.distance(r).is_some_and(|t| t < r.t_max)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.vertices.iter().fold(Aabb::empty(), |bounds, v| bounds.grow(v)))
+    }
+}
+
+impl Triangle {
+    pub fn new(a: &Vector, b: &Vector, c: &Vector) -> Triangle {
+        Triangle { vertices: [*a, *b, *c] }
+    }
+
+    fn distance(&self, r: &Ray) -> Option<Float> {
+        stats::count(Counter::TriangleTests);
+
+        // Moller-Trumbore: solve for the ray's distance and the point's
+        // barycentric coordinates at once
+        let [a, b, c] = self.vertices;
+        let edge_0 = b - a;
+        let edge_1 = c - a;
+        let p = r.direction.cross(&edge_1);
+        let determinant = edge_0.dot(&p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = r.origin - a;
+        let u = s.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge_0);
+        let v = r.direction.dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge_1.dot(&q) * inverse;
+        if t > EPSILON {
+            return Some(t);
+        }
+        None
+    }
+}
+
+// A parallelogram spanned by the edges `u` and `v` from one of its corners,
+// whose normal is the cross product of `u` and `v`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Quad {
+    pub corner: Vector,
+    pub u: Vector,
+    pub v: Vector,
+}
+
+impl Shape for Quad {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        self.distance(r).map(|t| {
+            let position = r.point_at(t);
+            let mut dg = DifferentialGeometry::new(t, &position, &self.u.cross(&self.v).normalize(), self);
+            dg.uv = self.coordinates(&position);
+            dg
+        })
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        self.distance(r).is_some_and(|t| t < r.t_max)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let c = self.corner;
+        Some(Aabb::empty().grow(&c).grow(&(c + self.u)).grow(&(c + self.v)).grow(&(c + self.u + self.v)))
+    }
+}
+
+impl Quad {
+    pub fn new(corner: &Vector, u: &Vector, v: &Vector) -> Quad {
+        Quad {
+            corner: *corner,
+            u: *u,
+            v: *v,
+        }
+    }
+
+    // The coordinates of a point (on the quad's plane) along the two edges,
+    // which are its texture coordinates
+    fn coordinates(&self, position: &Vector) -> (Float, Float) {
+        let n = self.u.cross(&self.v);
+        let q = *position - self.corner;
+        let w = n / n.dot(&n);
+        (w.dot(&q.cross(&self.v)), w.dot(&self.u.cross(&q)))
+    }
+
+    fn distance(&self, r: &Ray) -> Option<Float> {
+        let n = self.u.cross(&self.v);
+        let denominator = r.direction.dot(&n);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.corner - r.origin).dot(&n) / denominator;
+        if t <= EPSILON {
+            return None;
+        }
+
+        let (alpha, beta) = self.coordinates(&r.point_at(t));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+// The geometry of a primitive: the built-in shapes are matched on directly,
+// so that intersecting them doesn't go through a virtual call, while any
+// other shape can still be used through the `Shape` trait (as `Custom`,
+// which can't be serialized)
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Geometry {
+    Sphere(Sphere),
+    Plane(Plane),
+    Triangle(Triangle),
+    Quad(Quad),
+    Spheres(SphereList),
+    Mesh(Arc<Mesh>),
+    Lod(LodMesh),
+    #[serde(skip)]
+    Custom(Arc<dyn Shape>),
+}
+
+impl Geometry {
+    // What kind of shape this is, e.g. for counting them
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Geometry::Sphere(_) => "sphere",
+            Geometry::Plane(_) => "plane",
+            Geometry::Triangle(_) => "triangle",
+            Geometry::Quad(_) => "quad",
+            Geometry::Spheres(_) => "sphere list",
+            Geometry::Mesh(_) => "mesh",
+            Geometry::Lod(_) => "mesh with levels of detail",
+            Geometry::Custom(_) => "custom",
+        }
+    }
+}
+
+impl Shape for Geometry {
+    #[inline]
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        match *self {
+            Geometry::Sphere(ref s) => s.intersect(r),
+            Geometry::Plane(ref s) => s.intersect(r),
+            Geometry::Triangle(ref s) => s.intersect(r),
+            Geometry::Quad(ref s) => s.intersect(r),
+            Geometry::Spheres(ref s) => s.intersect(r),
+            Geometry::Mesh(ref s) => s.intersect(r),
+            Geometry::Lod(ref s) => s.intersect(r),
+            Geometry::Custom(ref s) => s.intersect(r),
+        }
+    }
+
+    #[inline]
+    fn hit_any(&self, r: &Ray) -> bool {
+        match *self {
+            Geometry::Sphere(ref s) => s.hit_any(r),
+            Geometry::Plane(ref s) => s.hit_any(r),
+            Geometry::Triangle(ref s) => s.hit_any(r),
+            Geometry::Quad(ref s) => s.hit_any(r),
+            Geometry::Spheres(ref s) => s.hit_any(r),
+            Geometry::Mesh(ref s) => s.hit_any(r),
+            Geometry::Lod(ref s) => s.hit_any(r),
+            Geometry::Custom(ref s) => s.hit_any(r),
+        }
+    }
+
+    fn data(&self) -> Option<ShapeData> {
+        match *self {
+            Geometry::Sphere(ref s) => s.data(),
+            Geometry::Plane(ref s) => s.data(),
+            Geometry::Triangle(ref s) => s.data(),
+            Geometry::Quad(ref s) => s.data(),
+            Geometry::Spheres(ref s) => s.data(),
+            Geometry::Mesh(ref s) => s.data(),
+            Geometry::Lod(ref s) => s.data(),
+            Geometry::Custom(ref s) => s.data(),
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match *self {
+            Geometry::Sphere(ref s) => s.bounds(),
+            Geometry::Plane(ref s) => s.bounds(),
+            Geometry::Triangle(ref s) => s.bounds(),
+            Geometry::Quad(ref s) => s.bounds(),
+            Geometry::Spheres(ref s) => s.bounds(),
+            Geometry::Mesh(ref s) => s.bounds(),
+            Geometry::Lod(ref s) => s.bounds(),
+            Geometry::Custom(ref s) => s.bounds(),
+        }
+    }
+}
+
+impl From<Sphere> for Geometry {
+    fn from(s: Sphere) -> Geometry {
+        Geometry::Sphere(s)
+    }
+}
+
+impl From<Plane> for Geometry {
+    fn from(s: Plane) -> Geometry {
+        Geometry::Plane(s)
+    }
+}
+
+impl From<Triangle> for Geometry {
+    fn from(s: Triangle) -> Geometry {
+        Geometry::Triangle(s)
+    }
+}
+
+impl From<Quad> for Geometry {
+    fn from(s: Quad) -> Geometry {
+        Geometry::Quad(s)
+    }
+}
+
+impl From<SphereList> for Geometry {
+    fn from(s: SphereList) -> Geometry {
+        Geometry::Spheres(s)
+    }
+}
+
+impl From<Mesh> for Geometry {
+    fn from(s: Mesh) -> Geometry {
+        Geometry::Mesh(Arc::new(s))
+    }
+}
+
+impl From<Arc<Mesh>> for Geometry {
+    fn from(s: Arc<Mesh>) -> Geometry {
+        Geometry::Mesh(s)
+    }
+}
+
+impl From<LodMesh> for Geometry {
+    fn from(s: LodMesh) -> Geometry {
+        Geometry::Lod(s)
+    }
+}
+
+impl From<Arc<dyn Shape>> for Geometry {
+    fn from(s: Arc<dyn Shape>) -> Geometry {
+        Geometry::Custom(s)
+    }
+}
+
+#[test]
+fn test_triangle_and_quad() {
+    let r = Ray::new(&Vector::new(0.25, 0.25, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    let triangle = Triangle::new(&Vector::zero(), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0));
+    let dg = triangle.intersect(&r).unwrap();
+    assert!((dg.t - 1.0).abs() < TEST_EPSILON);
+    assert_eq!(dg.normal, Vector::new(0.0, 0.0, 1.0));
+    assert!((dg.uv.0 - 0.25).abs() < TEST_EPSILON && (dg.uv.1 - 0.25).abs() < TEST_EPSILON);
+
+    let quad = Quad::new(&Vector::new(0.0, 0.0, -1.0), &Vector::new(0.2, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0));
+    assert!(quad.intersect(&r).is_none());
+    let wide = Geometry::from(Quad { u: Vector::new(1.0, 0.0, 0.0), ..quad });
+    let dg = wide.intersect(&r).unwrap();
+    assert!((dg.t - 2.0).abs() < TEST_EPSILON);
+    assert!((dg.uv.0 - 0.25).abs() < TEST_EPSILON && (dg.uv.1 - 0.25).abs() < TEST_EPSILON);
+}
+
+#[test]
+fn test_sphere_self_intersection() {
+    // A large sphere (like the ground of the random spheres scene), hit at
+    // grazing angles from far away
+    let sphere = Sphere::new(&Vector::new(0.0, -1000.0, 0.0), 1000.0);
+    for i in 0..64 {
+        let x = i as Float * 10.0 - 320.0;
+        let r = Ray::new(&Vector::new(x, 2.0, 500.0), &Vector::new(-x * 0.01, -1.0, -20.0), 0.0, Float::MAX);
+        let dg = match sphere.intersect(&r) {
+            Some(dg) => dg,
+            None => continue,
+        };
+        assert!(((dg.position - sphere.center).length() - sphere.radius).abs() < sphere.radius * TEST_EPSILON);
+
+        // Rays leaving the surface (above it, or into it) don't hit it again
+        // where they start
+        let tangent = dg.normal.cross(&Vector::new(0.0, 0.0, 1.0)).normalize();
+        for d in &[dg.normal, r.direction.reflect(&dg.normal), tangent + dg.normal * 0.01] {
+            assert!(sphere.intersect(&Ray::spawn(&dg.position, &dg.normal, d, Float::MAX)).is_none());
+        }
+        let inside = Ray::spawn(&dg.position, &dg.normal, &(dg.normal * -1.0), Float::MAX);
+        assert!(sphere.intersect(&inside).is_some_and(|dg| dg.t > 1999.0));
+    }
 }
@@ -0,0 +1,192 @@
+use vector::Vector;
+use vector::Float;
+#[cfg(feature = "simd")]
+use vector::Lanes;
+#[cfg(test)]
+use vector::TEST_EPSILON;
+use ray::Ray;
+use aabb::Aabb;
+use shape::Shape;
+use shape::DifferentialGeometry;
+#[cfg(feature = "simd")]
+use shape::EPSILON;
+#[cfg(not(feature = "simd"))]
+use shape::sphere_distance;
+use shape::sphere_uv;
+
+#[cfg(feature = "simd")]
+use wide::{CmpGe, CmpGt};
+
+use serde::{Deserialize, Serialize};
+
+// The number of spheres intersected at once
+const WIDTH: usize = 4;
+
+// Many spheres, each with its own material, stored as a structure of arrays
+// (so that the spheres' centers and radii can be loaded straight into SIMD
+// registers) and intersected `WIDTH` at a time: the arrays are padded to a
+// multiple of `WIDTH`, and the padding is never hit
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SphereList {
+    xs: Vec<Float>,
+    ys: Vec<Float>,
+    zs: Vec<Float>,
+    radii: Vec<Float>,
+    // Indices into the scene's material table (see: `Scene::add_material`)
+    material_ids: Vec<u32>,
+    len: usize,
+}
+
+impl SphereList {
+    pub fn new() -> SphereList {
+        SphereList::default()
+    }
+
+    pub fn push(&mut self, center: &Vector, radius: Float, material_id: u32) {
+        if self.len.is_multiple_of(WIDTH) {
+            for array in &mut [&mut self.xs, &mut self.ys, &mut self.zs, &mut self.radii] {
+                array.resize(self.len + WIDTH, 0.0);
+            }
+        }
+        self.xs[self.len] = center.x;
+        self.ys[self.len] = center.y;
+        self.zs[self.len] = center.z;
+        self.radii[self.len] = radius;
+        self.material_ids.push(material_id);
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn center(&self, i: usize) -> Vector {
+        Vector::new(self.xs[i], self.ys[i], self.zs[i])
+    }
+
+    pub fn radius(&self, i: usize) -> Float {
+        self.radii[i]
+    }
+
+    pub fn material_id(&self, i: usize) -> u32 {
+        self.material_ids[i]
+    }
+
+    // The distances to the spheres starting at `first` (or `Float::MAX`,
+    // where there is no hit), solved as in `Sphere::intersect`
+    #[cfg(feature = "simd")]
+    fn intersect_chunk(&self, r: &Ray, first: usize) -> [Float; WIDTH] {
+        let load = |array: &[Float]| Lanes::new([array[first], array[first + 1], array[first + 2], array[first + 3]]);
+        let ocx = Lanes::splat(r.origin.x) - load(&self.xs);
+        let ocy = Lanes::splat(r.origin.y) - load(&self.ys);
+        let ocz = Lanes::splat(r.origin.z) - load(&self.zs);
+        let radius = load(&self.radii);
+
+        let (dx, dy, dz) = (Lanes::splat(r.direction.x), Lanes::splat(r.direction.y), Lanes::splat(r.direction.z));
+        let b = -(ocx * dx + ocy * dy + ocz * dz);
+        let (cx, cy, cz) = (ocx + dx * b, ocy + dy * b, ocz + dz * b);
+        let discriminant = radius * radius - (cx * cx + cy * cy + cz * cz);
+        let root = discriminant.max(Lanes::splat(0.0)).sqrt();
+        let q = b.cmp_ge(Lanes::splat(0.0)).blend(b + root, b - root);
+        let t_0 = (ocx * ocx + ocy * ocy + ocz * ocz - radius * radius) / q;
+        let (near, far) = (t_0.min(q), t_0.max(q));
+
+        let epsilon = Lanes::splat(EPSILON);
+        let miss = Lanes::splat(Float::MAX);
+        let far = far.cmp_gt(epsilon).blend(far, miss);
+        let t = near.cmp_gt(epsilon).blend(near, far);
+        discriminant.cmp_ge(Lanes::splat(0.0)).blend(t, miss).to_array()
+    }
+
+    #[cfg(not(feature = "simd"))]
+    fn intersect_chunk(&self, r: &Ray, first: usize) -> [Float; WIDTH] {
+        let mut ts = [Float::MAX; WIDTH];
+        for (lane, t) in ts.iter_mut().enumerate() {
+            let i = first + lane;
+            let oc = r.origin - Vector::new(self.xs[i], self.ys[i], self.zs[i]);
+            if let Some(distance) = sphere_distance(&oc, &r.direction, self.radii[i]) {
+                *t = distance;
+            }
+        }
+        ts
+    }
+}
+
+impl Shape for SphereList {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let mut closest = None;
+        let mut closest_t = Float::MAX;
+        for first in (0..self.len).step_by(WIDTH) {
+            let ts = self.intersect_chunk(r, first);
+            for (lane, &t) in ts.iter().enumerate().take(self.len - first) {
+                if t < closest_t {
+                    closest_t = t;
+                    closest = Some(first + lane);
+                }
+            }
+        }
+
+        closest.map(|i| {
+            let position = r.point_at(closest_t);
+            let normal = (position - self.center(i)) / self.radii[i];
+            let mut dg = DifferentialGeometry::new(closest_t, &position, &normal, self);
+            dg.material_id = Some(self.material_ids[i]);
+            dg.uv = sphere_uv(&normal);
+            dg
+        })
+    }
+
+    fn hit_any(&self, r: &Ray) -> bool {
+        (0..self.len).step_by(WIDTH).any(|first| {
+            let ts = self.intersect_chunk(r, first);
+            ts.iter().take(self.len - first).any(|&t| t < r.t_max)
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if self.is_empty() {
+            return None;
+        }
+        Some((0..self.len).fold(Aabb::empty(), |bounds, i| {
+            let r = Vector::new(self.radii[i], self.radii[i], self.radii[i]);
+            bounds.union(&Aabb::new(&(self.center(i) - r), &(self.center(i) + r)))
+        }))
+    }
+}
+
+#[test]
+fn test_sphere_list_matches_spheres() {
+    use shape::Sphere;
+
+    let mut list = SphereList::new();
+    let mut spheres = Vec::new();
+    for i in 0..11 {
+        let center = Vector::new(i as Float * 0.4 - 2.0, (i % 3) as Float * 0.2, -3.0 - (i % 4) as Float);
+        let radius = 0.15 + (i % 5) as Float * 0.05;
+        list.push(&center, radius, i);
+        spheres.push(Sphere::new(&center, radius));
+    }
+    assert_eq!(list.len(), 11);
+
+    for i in 0..64 {
+        let d = Vector::new(i as Float / 32.0 - 1.0, (i % 8) as Float / 40.0, -1.0);
+        let r = Ray::new(&Vector::zero(), &d, 0.001, Float::MAX);
+        let expected = spheres.iter()
+            .enumerate()
+            .filter_map(|(j, s)| s.intersect(&r).map(|dg| (dg.t, j as u32)))
+            .fold(None, |closest: Option<(Float, u32)>, hit| match closest {
+                Some(c) if c.0 <= hit.0 => Some(c),
+                _ => Some(hit),
+            });
+        let hit = list.intersect(&r).map(|dg| (dg.t, dg.material_id.unwrap()));
+        assert_eq!(hit.is_some(), expected.is_some());
+        if let (Some(hit), Some(expected)) = (hit, expected) {
+            assert!((hit.0 - expected.0).abs() < TEST_EPSILON);
+            assert_eq!(hit.1, expected.1);
+        }
+    }
+}
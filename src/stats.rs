@@ -0,0 +1,218 @@
+use vector::Float;
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+// The events that the renderer counts
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Counter {
+    // Rays traced through the scene (including shadow rays)
+    Rays,
+    BvhNodeVisits,
+    // Rays tested against a primitive
+    PrimitiveTests,
+    TriangleTests,
+    // Samples that weren't finite, which were taken as black
+    InvalidSamples,
+}
+
+const COUNTERS: usize = 5;
+
+// Each thread counts into its own counters, which are only added to the
+// totals of the render that it's working on when the thread flushes them
+// (e.g. after every tile, see: `scheduler`), so that counting costs the hot
+// loops next to nothing
+thread_local! {
+    static LOCAL: [Cell<u64>; COUNTERS] = const { [const { Cell::new(0) }; COUNTERS] };
+}
+
+// The totals of one render (or one pass of it), which the threads rendering
+// it flush their counts into: renders that run at the same time (e.g. on
+// other threads, or in other tests) each keep their own
+#[derive(Debug, Default)]
+pub struct Counts {
+    totals: [AtomicU64; COUNTERS],
+}
+
+impl Counts {
+    pub fn new() -> Counts {
+        Counts::default()
+    }
+
+    // What's been counted so far, by the threads that have flushed
+    pub fn total(&self, counter: Counter) -> u64 {
+        self.totals[counter as usize].load(Ordering::Relaxed)
+    }
+}
+
+#[inline]
+pub fn count(counter: Counter) {
+    LOCAL.with(|counters| {
+        let c = &counters[counter as usize];
+        c.set(c.get() + 1);
+    });
+}
+
+// What the calling thread has counted since it last flushed, e.g. to measure
+// what a single ray costs
+pub fn local(counter: Counter) -> u64 {
+    LOCAL.with(|counters| counters[counter as usize].get())
+}
+
+// Add the calling thread's counts to the render's totals
+pub fn flush(into: &Counts) {
+    LOCAL.with(|counters| {
+        for (local, total) in counters.iter().zip(into.totals.iter()) {
+            total.fetch_add(local.replace(0), Ordering::Relaxed);
+        }
+    });
+}
+
+// Forget what the calling thread has counted since it last flushed, e.g.
+// before it starts on a render, so that the render isn't charged for what
+// the thread did beforehand
+pub fn discard() {
+    LOCAL.with(|counters| {
+        for local in counters {
+            local.set(0);
+        }
+    });
+}
+
+// Measures how long a stage of the render takes: WebAssembly has no clock
+// that `std` can read (see: `wasm`), so there every stage takes no time
+#[derive(Copy, Clone, Debug)]
+pub struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+}
+
+// What a render did, and how long each stage of it took, so that
+// optimizations can be measured
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    pub rays: u64,
+    pub bvh_node_visits: u64,
+    pub primitive_tests: u64,
+    pub triangle_tests: u64,
+    pub invalid_samples: u64,
+    // The total time spent in each stage, in the order that the stages
+    // first ran
+    pub stages: Vec<(String, Duration)>,
+}
+
+impl RenderStats {
+    pub fn new() -> RenderStats {
+        RenderStats::default()
+    }
+
+    // Everything that the threads of a render counted (once they've all
+    // flushed)
+    pub fn collect(counts: &Counts) -> RenderStats {
+        RenderStats {
+            rays: counts.total(Counter::Rays),
+            bvh_node_visits: counts.total(Counter::BvhNodeVisits),
+            primitive_tests: counts.total(Counter::PrimitiveTests),
+            triangle_tests: counts.total(Counter::TriangleTests),
+            invalid_samples: counts.total(Counter::InvalidSamples),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, other: &RenderStats) {
+        self.rays += other.rays;
+        self.bvh_node_visits += other.bvh_node_visits;
+        self.primitive_tests += other.primitive_tests;
+        self.triangle_tests += other.triangle_tests;
+        self.invalid_samples += other.invalid_samples;
+        for &(ref stage, duration) in &other.stages {
+            self.add_time(stage, duration);
+        }
+    }
+
+    pub fn add_time(&mut self, stage: &str, duration: Duration) {
+        match self.stages.iter_mut().find(|s| s.0 == stage) {
+            Some(s) => s.1 += duration,
+            None => self.stages.push((stage.to_string(), duration)),
+        }
+    }
+
+    pub fn time(&self, stage: &str) -> Duration {
+        self.stages.iter().find(|s| s.0 == stage).map(|s| s.1).unwrap_or_default()
+    }
+
+    // The rays traced per second spent rendering
+    pub fn rays_per_second(&self) -> Float {
+        let seconds = self.time("render").as_secs_f64();
+        if seconds > 0.0 {
+            (self.rays as f64 / seconds) as Float
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rays traced: {} ({:.2} million per second)", self.rays, self.rays_per_second() / 1e6)?;
+        writeln!(f, "BVH node visits: {}", self.bvh_node_visits)?;
+        writeln!(f, "primitive tests: {}", self.primitive_tests)?;
+        writeln!(f, "triangle tests: {}", self.triangle_tests)?;
+        write!(f, "invalid samples: {}", self.invalid_samples)?;
+        for &(ref stage, duration) in &self.stages {
+            write!(f, "\n{}: {:.3} seconds", stage, duration.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_collect_counts() {
+    use std::thread;
+
+    // Each render only sees what was flushed into its own counts
+    discard();
+    let (ours, theirs) = (Counts::new(), Counts::new());
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            count(Counter::TriangleTests);
+            flush(&theirs);
+        });
+    });
+    for _ in 0..3 {
+        count(Counter::TriangleTests);
+    }
+    flush(&ours);
+    let mut stats = RenderStats::collect(&ours);
+    assert_eq!((stats.triangle_tests, stats.rays), (3, 0));
+    assert_eq!(theirs.total(Counter::TriangleTests), 1);
+
+    stats.add_time("render", Duration::from_secs(2));
+    stats.add_time("render", Duration::from_secs(1));
+    stats.rays = 6;
+    assert_eq!(stats.time("render"), Duration::from_secs(3));
+    assert_eq!(stats.rays_per_second(), 2.0);
+}
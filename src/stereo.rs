@@ -0,0 +1,98 @@
+use vector::Float;
+use color::Color;
+use camera::Camera;
+use framebuffer::Framebuffer;
+
+use serde::{Deserialize, Serialize};
+
+// How a stereo camera's two images are put together into one
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoLayout {
+    // The left eye's image beside the right's, each at the full resolution
+    // (for VR headsets and 3D displays)
+    #[default]
+    SideBySide,
+    // The left eye's red over the right eye's green and blue, for red/cyan
+    // glasses
+    Anaglyph,
+}
+
+impl StereoLayout {
+    // The size of the composed image, from the size of each eye's
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            StereoLayout::SideBySide => (2 * width, height),
+            StereoLayout::Anaglyph => (width, height),
+        }
+    }
+}
+
+// A stereo camera renders the scene from two eyes either side of the camera,
+// `separation` apart, whose images agree on what is `convergence` away (which
+// appears at the depth of the screen, with anything nearer in front of it):
+// each eye's frustum is sheared towards the other's (rather than toed in), so
+// that there's no vertical parallax
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stereo {
+    pub separation: Float,
+    pub convergence: Float,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+impl Stereo {
+    // The left and right eyes' cameras
+    pub fn eyes(&self, camera: &Camera) -> (Camera, Camera) {
+        let offset = 0.5 * self.separation;
+        (camera.shifted(-offset, self.convergence), camera.shifted(offset, self.convergence))
+    }
+}
+
+// Put the eyes' images (which should be the same size) together
+pub fn compose(layout: StereoLayout, left: &Framebuffer, right: &Framebuffer) -> Framebuffer {
+    let (width, height) = layout.size(left.width, left.height);
+    let mut image = Framebuffer::new(width, height);
+    for y in 0..left.height {
+        for x in 0..left.width {
+            let (l, r) = (left.get(x, y), right.get(x, y));
+            match layout {
+                StereoLayout::SideBySide => {
+                    image.set(x, y, &l);
+                    image.set(left.width + x, y, &r);
+                }
+                // (The channels are taken before tone mapping, which is the
+                // same as after it for the operators that map each channel on
+                // its own)
+                StereoLayout::Anaglyph => image.set(x, y, &Color::new(l.r, r.g, r.b)),
+            }
+        }
+    }
+    image
+}
+
+#[test]
+fn test_stereo() {
+    use vector::Vector;
+    use vector::TEST_EPSILON;
+
+    // The eyes see the point at the convergence distance in the same place,
+    // and nearer points further apart, with the left eye seeing them to the
+    // right
+    let camera = Camera::look_at(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), &Vector::new(0.0, 1.0, 0.0), 60.0, 1.5);
+    let stereo = Stereo { separation: 0.1, convergence: 4.0, layout: StereoLayout::Anaglyph };
+    let (left, right) = stereo.eyes(&camera);
+    let (l, r) = (left.project(&Vector::new(0.5, 0.2, -4.0)).unwrap(), right.project(&Vector::new(0.5, 0.2, -4.0)).unwrap());
+    assert!((l.0 - r.0).abs() < TEST_EPSILON && (l.1 - r.1).abs() < TEST_EPSILON);
+    let (l, r) = (left.project(&Vector::new(0.5, 0.2, -2.0)).unwrap(), right.project(&Vector::new(0.5, 0.2, -2.0)).unwrap());
+    assert!(l.0 > r.0 + 0.01 && (l.1 - r.1).abs() < TEST_EPSILON);
+
+    let mut red = Framebuffer::new(2, 1);
+    red.pixels = vec![Color::new(1.0, 0.0, 0.0); 2];
+    let mut cyan = Framebuffer::new(2, 1);
+    cyan.pixels = vec![Color::new(0.0, 1.0, 1.0); 2];
+    assert!(compose(StereoLayout::Anaglyph, &red, &cyan).pixels.iter().all(|p| *p == Color::white()));
+    let sbs = compose(StereoLayout::SideBySide, &red, &cyan);
+    assert_eq!((sbs.width, sbs.height), (4, 1));
+    assert_eq!((sbs.get(1, 0), sbs.get(2, 0)), (red.get(1, 0), cyan.get(0, 0)));
+}
@@ -0,0 +1,106 @@
+use vector::Vector;
+use vector::Float;
+use shape::Triangle;
+use mesh::Mesh;
+use error::Result;
+use error::TracerError;
+
+use std::fs;
+use std::path::Path;
+
+fn invalid(why: &str) -> TracerError {
+    TracerError::Parse(why.to_string())
+}
+
+// Load a (binary or ASCII) STL mesh: the facets' normals are ignored, since
+// they are often missing or wrong, so each triangle faces the side from
+// which its vertices wind counterclockwise (as STL requires anyway)
+pub fn load(path: &Path) -> Result<Mesh> {
+    let bytes = fs::read(path)?;
+
+    // Binary files start with an 80-byte header (which may well begin with
+    // "solid", like an ASCII file), followed by the number of triangles and
+    // 50 bytes for each, so that their size gives them away
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() == 84 + count * 50 {
+            let read = |offset: usize| {
+                let f = |i: usize| {
+                    let b = &bytes[offset + i * 4..offset + i * 4 + 4];
+                    f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as Float
+                };
+                Vector::new(f(0), f(1), f(2))
+            };
+            let triangles = (0..count)
+                .map(|i| {
+                    // (After the normal, and before two bytes of attributes)
+                    let offset = 84 + i * 50 + 12;
+                    Triangle::new(&read(offset), &read(offset + 12), &read(offset + 24))
+                })
+                .collect();
+            return Ok(Mesh::new(triangles));
+        }
+    }
+
+    let source = String::from_utf8(bytes).map_err(|_| invalid("not an STL file"))?;
+    if !source.trim_start().starts_with("solid") {
+        return Err(invalid("not an STL file"));
+    }
+    let mut triangles = Vec::new();
+    let mut vertices = Vec::new();
+    for line in source.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["vertex", x, y, z] => {
+                let parse = |w: &str| w.parse::<Float>().map_err(|_| invalid("expected a number"));
+                vertices.push(Vector::new(parse(x)?, parse(y)?, parse(z)?));
+            }
+            ["endfacet"] => {
+                if vertices.len() != 3 {
+                    return Err(invalid("a facet without three vertices"));
+                }
+                triangles.push(Triangle::new(&vertices[0], &vertices[1], &vertices[2]));
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(Mesh::new(triangles))
+}
+
+#[test]
+fn test_load_stl() {
+    use shape::Shape;
+    use ray::Ray;
+
+    let vertices = [[-1.0f32, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]];
+    let mut ascii = String::from("solid triangle\nfacet normal 0 0 1\nouter loop\n");
+    // (A binary file whose header starts like an ASCII file's)
+    let mut binary = b"solid triangle".to_vec();
+    binary.resize(80, 0);
+    binary.extend_from_slice(&1u32.to_le_bytes());
+    binary.extend_from_slice(&[0; 12]);
+    for v in &vertices {
+        ascii += &format!("vertex {} {} {}\n", v[0], v[1], v[2]);
+        for f in v {
+            binary.extend_from_slice(&f.to_le_bytes());
+        }
+    }
+    ascii += "endloop\nendfacet\nendsolid triangle\n";
+    binary.extend_from_slice(&[0; 2]);
+
+    let directory = ::std::env::temp_dir();
+    let r = Ray::new(&Vector::new(0.0, 0.0, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, Float::MAX);
+    for (name, contents) in [("ascii", ascii.into_bytes()), ("binary", binary)] {
+        let path = directory.join(format!("tracer_test_load_stl_{}.stl", name));
+        fs::write(&path, contents).unwrap();
+        let mesh = load(&path).unwrap();
+        assert_eq!(mesh.len(), 1);
+        let dg = mesh.intersect(&r).unwrap();
+        assert_eq!(dg.normal, Vector::new(0.0, 0.0, 1.0));
+    }
+
+    let path = directory.join("tracer_test_load_stl_broken.stl");
+    fs::write(&path, "solid broken\nfacet normal 0 0 1\nvertex 0 0 0\nendfacet\n").unwrap();
+    assert!(load(&path).is_err());
+}
@@ -0,0 +1,237 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use camera::Camera;
+use scene::Scene;
+use film::Film;
+use framebuffer::Framebuffer;
+
+// Primary hits that miss the scene are reprojected as if they were points at
+// this (very large) distance from the camera
+const DISTANT: Float = 1.0e6;
+
+// A previously rendered frame, along with what was needed to produce it
+struct Frame {
+    camera: Camera,
+    image: Framebuffer,
+    // The world-space position of the surface seen through each pixel's
+    // center (or `None` if the pixel's center sees the background)
+    positions: Vec<Option<Vector>>,
+}
+
+// When rendering a sequence with small camera motions, blending each frame
+// with the reprojected result of the previous frame reduces flickering: this
+// is an exponential moving average over time, where history is rejected
+// wherever the surface seen through a pixel changed (i.e. disocclusions)
+pub struct TemporalAccumulator {
+    // The weight given to the current frame, in (0, 1]
+    pub alpha: Float,
+    // How far (relative to its distance from the camera) a reprojected
+    // surface may move before its history is discarded
+    pub tolerance: Float,
+    history: Option<Frame>,
+}
+
+impl TemporalAccumulator {
+    pub fn new(alpha: Float, tolerance: Float) -> TemporalAccumulator {
+        TemporalAccumulator {
+            alpha: alpha.clamp(0.0, 1.0).max(1e-3),
+            tolerance,
+            history: None,
+        }
+    }
+
+    // Forget the previous frame (e.g. after a camera cut)
+    pub fn reset(&mut self) {
+        self.history = None;
+    }
+
+    // Blend the current frame (rendered into `film` from `camera`) with the
+    // history and return the resulting image, which becomes the new history
+    pub fn accumulate(&mut self, film: &Film, camera: &Camera, scene: &Scene) -> Framebuffer {
+        let (width, height) = (film.width, film.height);
+        let positions = primary_positions(camera, scene, width, height);
+        let mut image = film.to_framebuffer();
+
+        if let Some(ref previous) = self.history {
+            if previous.image.width == width && previous.image.height == height {
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = (y * width + x) as usize;
+                        let p = match positions[i] {
+                            Some(p) => p,
+                            None => {
+                                let (u, v) = pixel_center(x, y, width, height);
+                                camera.origin() + camera.generate_ray(u, v).direction * DISTANT
+                            }
+                        };
+                        if let Some(history) = self.reproject(previous, &p, positions[i].is_some()) {
+                            image.pixels[i] = history.lerp(&image.pixels[i], self.alpha);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.history = Some(Frame {
+            camera: *camera,
+            image: image.clone(),
+            positions,
+        });
+        image
+    }
+
+    // Look up the color of the world-space point `p` in the previous frame,
+    // bilinearly interpolating between the pixels that still see it
+    fn reproject(&self, previous: &Frame, p: &Vector, is_surface: bool) -> Option<Color> {
+        let (u, v) = previous.camera.project(p)?;
+        let px = u * previous.image.width as Float - 0.5;
+        let py = (1.0 - v) * previous.image.height as Float - 0.5;
+        let (x0, y0) = (px.floor(), py.floor());
+        let (fx, fy) = (px - x0, py - y0);
+
+        let mut color = Color::black();
+        let mut total = 0.0;
+        for &(dx, dy, w) in &[(0, 0, (1.0 - fx) * (1.0 - fy)),
+                              (1, 0, fx * (1.0 - fy)),
+                              (0, 1, (1.0 - fx) * fy),
+                              (1, 1, fx * fy)] {
+            let x = x0 as i64 + dx;
+            let y = y0 as i64 + dy;
+            if w <= 0.0 || x < 0 || y < 0 || x >= previous.image.width as i64 || y >= previous.image.height as i64 {
+                continue;
+            }
+
+            let i = (y as u32 * previous.image.width + x as u32) as usize;
+            let consistent = match previous.positions[i] {
+                Some(q) => {
+                    let distance = (previous.camera.origin() - *p).length();
+                    is_surface && (q - *p).length() <= self.tolerance * distance
+                }
+                None => !is_surface,
+            };
+            if consistent {
+                color += previous.image.pixels[i] * w;
+                total += w;
+            }
+        }
+
+        if total > 0.0 { Some(color / total) } else { None }
+    }
+}
+
+fn pixel_center(x: u32, y: u32, width: u32, height: u32) -> (Float, Float) {
+    ((x as Float + 0.5) / width as Float, (height as Float - (y as Float + 0.5)) / height as Float)
+}
+
+// Find the surface seen through each pixel's center
+fn primary_positions(camera: &Camera, scene: &Scene, width: u32, height: u32) -> Vec<Option<Vector>> {
+    let mut positions = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (u, v) = pixel_center(x, y, width, height);
+            let r = camera.generate_ray(u, v);
+            positions.push(scene.intersect(&r).map(|(dg, _)| dg.position));
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+fn uniform_film(width: u32, height: u32, value: &dyn Fn(u32, u32) -> Float) -> Film {
+    let mut film = Film::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = film.pixel_mut(x, y);
+            pixel.weighted_sum = Color::gray(value(x, y));
+            pixel.weight = 1.0;
+        }
+    }
+    film
+}
+
+#[test]
+fn test_static_camera() {
+    use shape::Plane;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    // Seen from a camera that doesn't move, each frame is blended with the
+    // last by alpha: after a black frame, n white ones reach 1 - (1 - alpha)^n
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, 0.0, -5.0), &Vector::new(0.0, 0.0, 1.0)),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    let camera = Camera::new(90.0, 1.0);
+    let mut temporal = TemporalAccumulator::new(0.25, 0.01);
+    temporal.accumulate(&uniform_film(8, 8, &|_, _| 0.0), &camera, &scene);
+    let mut image = Framebuffer::new(8, 8);
+    for _ in 0..4 {
+        image = temporal.accumulate(&uniform_film(8, 8, &|_, _| 1.0), &camera, &scene);
+    }
+    let expected = 1.0 - Float::powi(0.75, 4);
+    assert!(image.pixels.iter().all(|c| (c.r - expected).abs() < 1e-4), "{:?}", image.get(3, 3));
+
+    // A cut forgets the history
+    temporal.reset();
+    let image = temporal.accumulate(&uniform_film(8, 8, &|_, _| 0.0), &camera, &scene);
+    assert_eq!(image.get(3, 3), Color::black());
+}
+
+#[test]
+fn test_camera_pan() {
+    use shape::Plane;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    // A plane five units away, across which each of the 8 pixels of a 90
+    // degree view is 1.25 units wide: panning by half of one, the history of
+    // each pixel lies halfway between two of the last frame's pixels (whose
+    // surfaces are half a pixel from it, within a looser tolerance)
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, 0.0, -5.0), &Vector::new(0.0, 0.0, 1.0)),
+                             Arc::new(Lambertian::new(&Color::white()))));
+    let up = Vector::new(0.0, 1.0, 0.0);
+    let camera = Camera::look_at(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), &up, 90.0, 1.0);
+    let panned = Camera::look_at(&Vector::new(0.625, 0.0, 0.0), &Vector::new(0.625, 0.0, -1.0), &up, 90.0, 1.0);
+    let mut temporal = TemporalAccumulator::new(0.5, 0.2);
+    temporal.accumulate(&uniform_film(8, 8, &|x, _| x as Float), &camera, &scene);
+    let image = temporal.accumulate(&uniform_film(8, 8, &|_, _| 0.0), &panned, &scene);
+    for x in 0..7 {
+        let expected = 0.5 * (x as Float + 0.5);
+        assert!((image.get(x, 4).r - expected).abs() < 1e-3, "{} {:?}", x, image.get(x, 4));
+    }
+    // (At the edge, only the one pixel that's still in the last frame)
+    assert!((image.get(7, 4).r - 3.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_disocclusion() {
+    use shape::Plane;
+    use shape::Sphere;
+    use primitive::Primitive;
+    use material::Lambertian;
+    use std::sync::Arc;
+
+    // A sphere that appears in front of the plane in the second frame: where
+    // the surface seen moved by more than the tolerance, the history is
+    // thrown away, while the rest of the plane keeps it
+    let white = Arc::new(Lambertian::new(&Color::white()));
+    let plane = Plane::new(&Vector::new(0.0, 0.0, -5.0), &Vector::new(0.0, 0.0, 1.0));
+    let mut scene = Scene::new();
+    scene.add(Primitive::new(plane, white.clone()));
+    let camera = Camera::new(90.0, 1.0);
+    let mut temporal = TemporalAccumulator::new(0.5, 0.01);
+    temporal.accumulate(&uniform_film(8, 8, &|_, _| 1.0), &camera, &scene);
+    scene.add(Primitive::new(Sphere::new(&Vector::new(0.0, 0.0, -3.0), 1.0), white));
+    let image = temporal.accumulate(&uniform_film(8, 8, &|_, _| 0.0), &camera, &scene);
+    assert_eq!(image.get(4, 4), Color::black());
+    assert!((image.get(0, 0).r - 0.5).abs() < 1e-4);
+
+    // Where the background was seen, a surface has no history either
+    let mut empty = TemporalAccumulator::new(0.5, 0.01);
+    empty.accumulate(&uniform_film(8, 8, &|_, _| 1.0), &camera, &Scene::new());
+    let image = empty.accumulate(&uniform_film(8, 8, &|_, _| 0.0), &camera, &scene);
+    assert_eq!(image.get(4, 4), Color::black());
+}
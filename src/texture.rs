@@ -0,0 +1,526 @@
+use vector::Float;
+use color::Color;
+use color::srgb_to_linear;
+use error::Result;
+
+#[cfg(feature = "tracing")]
+use tracing;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Textures are cached in square tiles of this many texels across
+pub const TILE_SIZE: u32 = 64;
+
+// The memory budget of the caches made for scenes that are loaded without
+// one (see: `Scene`'s `Deserialize` impl)
+pub const DEFAULT_BUDGET: usize = 256 << 20;
+
+// Looked up when a texture can't be loaded, so that it stands out
+const MISSING: Color = Color {
+    r: 1.0,
+    g: 0.0,
+    b: 1.0,
+};
+
+// Identifies a texture registered with a cache
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId(pub u32);
+
+// One tile of a texture's (linear) texels, in scanline order: tiles along
+// the right and bottom edges of a texture are cut short
+struct Tile {
+    width: u32,
+    texels: Vec<[f32; 3]>,
+}
+
+impl Tile {
+    fn bytes(&self) -> usize {
+        self.texels.len() * 12
+    }
+}
+
+// Each texture is only decoded once a lookup needs one of its tiles (and
+// one that can't be keeps why, see: `TextureCache::failures`)
+#[derive(Clone, Debug, PartialEq)]
+enum Status {
+    Unloaded,
+    // One thread is decoding the image, and any others that need it wait
+    // for it to finish (see: `TextureCache::decoded`), rather than decoding
+    // copies of their own
+    Decoding,
+    Loaded { width: u32, height: u32 },
+    Failed(String),
+}
+
+struct Texture {
+    path: PathBuf,
+    status: Status,
+    // Where the tiles were written once the image was decoded, so that a
+    // tile that misses can be read back on its own (if the file couldn't be
+    // written, it's cut from a freshly decoded image instead)
+    tiles: Option<PathBuf>,
+}
+
+// How well the cache is doing: tiles that miss are read back from their
+// texture's tile file (or cut from a freshly decoded image, without one)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // The number of times an image was decoded
+    pub decodes: u64,
+    // The number of tiles read back from tile files
+    pub reads: u64,
+    pub evictions: u64,
+    // The size of the tiles currently held
+    pub resident_bytes: usize,
+}
+
+struct CacheState {
+    textures: Vec<Texture>,
+    // The resident tiles, keyed by texture and tile coordinates, along with
+    // when each was last looked up
+    tiles: HashMap<(TextureId, u32, u32), (Arc<Tile>, u64)>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+// Tells apart the tile files of the caches in this process
+static CACHES: AtomicUsize = AtomicUsize::new(0);
+
+// Holds the tiles of many (potentially large) textures within a memory
+// budget: textures are registered up front, but nothing is decoded until a
+// lookup needs it, and once the budget is spent the least recently used
+// tiles are evicted (to be read back from disk if they are needed later)
+pub struct TextureCache {
+    // The most memory that the resident tiles may take up, in bytes
+    pub budget: usize,
+    state: Mutex<CacheState>,
+    // Notified whenever a texture finishes decoding
+    decoded: Condvar,
+    number: usize,
+}
+
+impl TextureCache {
+    pub fn new(budget: usize) -> TextureCache {
+        TextureCache {
+            budget,
+            state: Mutex::new(CacheState {
+                textures: Vec::new(),
+                tiles: HashMap::new(),
+                clock: 0,
+                stats: CacheStats::default(),
+            }),
+            decoded: Condvar::new(),
+            number: CACHES.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    // Register the image at `path` (without reading it), returning the same
+    // ID for every registration of the same path
+    pub fn load(&self, path: &Path) -> TextureId {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = state.textures.iter().position(|t| t.path == path) {
+            return TextureId(i as u32);
+        }
+        state.textures.push(Texture {
+            path: path.to_path_buf(),
+            status: Status::Unloaded,
+            tiles: None,
+        });
+        TextureId(state.textures.len() as u32 - 1)
+    }
+
+    // The path of a registered texture
+    pub fn path(&self, id: TextureId) -> PathBuf {
+        self.state.lock().unwrap().textures[id.0 as usize].path.clone()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    // The textures that couldn't be loaded (which look up as magenta), along
+    // with why, for the caller to report once it's done rendering
+    pub fn failures(&self) -> Vec<(PathBuf, String)> {
+        let state = self.state.lock().unwrap();
+        state.textures
+            .iter()
+            .filter_map(|texture| match texture.status {
+                Status::Failed(ref why) => Some((texture.path.clone(), why.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The texel nearest to the texture coordinates (u, v), which wrap around
+    // the texture, where v runs from the bottom of the image to its top
+    pub fn lookup(&self, id: TextureId, u: Float, v: Float) -> Color {
+        self.lookup_in(id, self.dimensions(id), u, v)
+    }
+
+    // As `lookup`, given the texture's dimensions (see: `ImageTexture`,
+    // which only asks for them once)
+    fn lookup_in(&self, id: TextureId, dimensions: Option<(u32, u32)>, u: Float, v: Float) -> Color {
+        let (width, height) = match dimensions {
+            Some(dimensions) => dimensions,
+            None => return MISSING,
+        };
+        let x = (((u - u.floor()) * width as Float) as u32).min(width - 1);
+        let y = (((1.0 - (v - v.floor())) * height as Float) as u32).min(height - 1);
+        match self.tile(id, x / TILE_SIZE, y / TILE_SIZE) {
+            Some(tile) => {
+                let t = tile.texels[((y % TILE_SIZE) * tile.width + x % TILE_SIZE) as usize];
+                Color::new(t[0] as Float, t[1] as Float, t[2] as Float)
+            }
+            None => MISSING,
+        }
+    }
+
+    // The size of the texture (decoding it, if it hasn't been yet), or
+    // `None` if it can't be loaded
+    fn dimensions(&self, id: TextureId) -> Option<(u32, u32)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.textures[id.0 as usize].status {
+                Status::Loaded { width, height } => return Some((width, height)),
+                Status::Failed(_) => return None,
+                Status::Decoding => state = self.decoded.wait(state).unwrap(),
+                Status::Unloaded => {
+                    state.textures[id.0 as usize].status = Status::Decoding;
+                    drop(state);
+                    self.decode(id, None);
+                    state = self.state.lock().unwrap();
+                }
+            }
+        }
+    }
+
+    fn tile(&self, id: TextureId, tx: u32, ty: u32) -> Option<Arc<Tile>> {
+        let key = (id, tx, ty);
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(entry) = state.tiles.get_mut(&key) {
+            entry.1 = clock;
+            let tile = entry.0.clone();
+            state.stats.hits += 1;
+            return Some(tile);
+        }
+        state.stats.misses += 1;
+        loop {
+            // (Which the decode that this thread waited for may have cached)
+            if let Some(entry) = state.tiles.get(&key) {
+                return Some(entry.0.clone());
+            }
+            let texture = &mut state.textures[id.0 as usize];
+            match (texture.status.clone(), texture.tiles.clone()) {
+                (Status::Failed(_), _) => return None,
+                (Status::Decoding, _) => state = self.decoded.wait(state).unwrap(),
+                (Status::Loaded { width, height }, Some(file)) => {
+                    drop(state);
+                    let read = read_tile(&file, (width, height), (tx, ty));
+                    state = self.state.lock().unwrap();
+                    match read {
+                        Ok(tile) => {
+                            let tile = Arc::new(tile);
+                            state.stats.reads += 1;
+                            self.insert(&mut state, key, tile.clone());
+                            return Some(tile);
+                        }
+                        // (e.g. the file was removed from under the cache,
+                        // in which case the image is decoded again)
+                        Err(_) => state.textures[id.0 as usize].tiles = None,
+                    }
+                }
+                _ => {
+                    texture.status = Status::Decoding;
+                    drop(state);
+                    return self.decode(id, Some((tx, ty)));
+                }
+            }
+        }
+    }
+
+    // Decode the texture's image, once the caller has marked it as decoding
+    // (without holding the lock, so that lookups of resident tiles carry on
+    // in the meantime), write its tiles to a tile file, and cache the
+    // requested tile, along with as many of the image's other tiles as fit
+    // in the budget without evicting anything (since they are likely to be
+    // needed soon)
+    fn decode(&self, id: TextureId, wanted: Option<(u32, u32)>) -> Option<Arc<Tile>> {
+        let path = self.path(id);
+        let decoded = image::open(&path)
+            .map_err(|why| why.to_string())
+            .and_then(|image| {
+                let image = image.to_rgb8();
+                if image.width() == 0 || image.height() == 0 {
+                    return Err("the image is empty".to_string());
+                }
+                Ok(image)
+            });
+        let image = match decoded {
+            Ok(image) => image,
+            Err(why) => {
+                let mut state = self.state.lock().unwrap();
+                let texture = &mut state.textures[id.0 as usize];
+                #[cfg(feature = "tracing")]
+                tracing::warn!(texture = %texture.path.display(), %why, "couldn't load the texture");
+                texture.status = Status::Failed(why);
+                self.decoded.notify_all();
+                return None;
+            }
+        };
+        let (width, height) = image.dimensions();
+        let cut = |tx: u32, ty: u32| {
+            let (x0, y0) = (tx * TILE_SIZE, ty * TILE_SIZE);
+            let (x1, y1) = ((x0 + TILE_SIZE).min(width), (y0 + TILE_SIZE).min(height));
+            let mut texels = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let p = image.get_pixel(x, y).0;
+                    // (8-bit images are stored sRGB-encoded)
+                    texels.push([srgb_to_linear(p[0] as Float / 255.0) as f32,
+                                 srgb_to_linear(p[1] as Float / 255.0) as f32,
+                                 srgb_to_linear(p[2] as Float / 255.0) as f32]);
+                }
+            }
+            Tile {
+                width: x1 - x0,
+                texels,
+            }
+        };
+        let file = self.tile_file(id);
+        let written = write_tiles(&file, (width, height), &cut).is_ok();
+        if !written {
+            let _ = fs::remove_file(&file);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        {
+            let texture = &mut state.textures[id.0 as usize];
+            texture.status = Status::Loaded { width, height };
+            texture.tiles = if written { Some(file) } else { None };
+        }
+        state.stats.decodes += 1;
+        self.decoded.notify_all();
+        let wanted = wanted.map(|(tx, ty)| {
+            state.tiles.get(&(id, tx, ty)).map(|entry| entry.0.clone()).unwrap_or_else(|| {
+                let tile = Arc::new(cut(tx, ty));
+                self.insert(&mut state, (id, tx, ty), tile.clone());
+                tile
+            })
+        });
+        for ty in 0..height.div_ceil(TILE_SIZE) {
+            for tx in 0..width.div_ceil(TILE_SIZE) {
+                if state.tiles.contains_key(&(id, tx, ty)) {
+                    continue;
+                }
+                let bytes = ((TILE_SIZE.min(width - tx * TILE_SIZE)) * (TILE_SIZE.min(height - ty * TILE_SIZE))) as usize *
+                            12;
+                if state.stats.resident_bytes + bytes > self.budget {
+                    return wanted;
+                }
+                // (Prefetched tiles count as least recently used)
+                let tile = Arc::new(cut(tx, ty));
+                state.stats.resident_bytes += tile.bytes();
+                state.tiles.insert((id, tx, ty), (tile, 0));
+            }
+        }
+        wanted
+    }
+
+    // Where a texture's tiles are written, which is removed along with the
+    // cache
+    fn tile_file(&self, id: TextureId) -> PathBuf {
+        env::temp_dir().join(format!("tracer-{}-{}-{}.tiles", process::id(), self.number, id.0))
+    }
+
+    // Cache a tile as the most recently used, first evicting the least
+    // recently used tiles until it fits in the budget
+    fn insert(&self, state: &mut CacheState, key: (TextureId, u32, u32), tile: Arc<Tile>) {
+        // (Another thread may have read the same tile in the meantime)
+        if let Some((replaced, _)) = state.tiles.remove(&key) {
+            state.stats.resident_bytes -= replaced.bytes();
+        }
+        while state.stats.resident_bytes + tile.bytes() > self.budget {
+            let oldest = match state.tiles.iter().min_by_key(|entry| (entry.1).1) {
+                Some((&key, _)) => key,
+                None => break,
+            };
+            if let Some((evicted, _)) = state.tiles.remove(&oldest) {
+                state.stats.resident_bytes -= evicted.bytes();
+                state.stats.evictions += 1;
+            }
+        }
+        state.clock += 1;
+        state.stats.resident_bytes += tile.bytes();
+        state.tiles.insert(key, (tile, state.clock));
+    }
+}
+
+impl Drop for TextureCache {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.lock() {
+            for file in state.textures.iter().filter_map(|texture| texture.tiles.as_ref()) {
+                let _ = fs::remove_file(file);
+            }
+        }
+    }
+}
+
+// Tile files hold every tile of a texture, in scanline order, each taking
+// up as much room as a full tile (which those along the right and bottom
+// edges don't fill), so that any one tile can be found without an index
+const TILE_BYTES: u64 = (TILE_SIZE * TILE_SIZE) as u64 * 12;
+
+fn write_tiles(file: &Path, dimensions: (u32, u32), cut: &dyn Fn(u32, u32) -> Tile) -> io::Result<()> {
+    let (width, height) = dimensions;
+    let mut writer = BufWriter::new(File::create(file)?);
+    for ty in 0..height.div_ceil(TILE_SIZE) {
+        for tx in 0..width.div_ceil(TILE_SIZE) {
+            let tile = cut(tx, ty);
+            for texel in &tile.texels {
+                for channel in texel {
+                    writer.write_all(&channel.to_le_bytes())?;
+                }
+            }
+            writer.write_all(&vec![0; TILE_BYTES as usize - tile.bytes()])?;
+        }
+    }
+    writer.flush()
+}
+
+fn read_tile(file: &Path, dimensions: (u32, u32), tile: (u32, u32)) -> io::Result<Tile> {
+    let ((width, height), (tx, ty)) = (dimensions, tile);
+    let w = TILE_SIZE.min(width - tx * TILE_SIZE);
+    let h = TILE_SIZE.min(height - ty * TILE_SIZE);
+    let mut file = File::open(file)?;
+    file.seek(SeekFrom::Start((ty * width.div_ceil(TILE_SIZE) + tx) as u64 * TILE_BYTES))?;
+    let mut bytes = vec![0; (w * h) as usize * 12];
+    file.read_exact(&mut bytes)?;
+    let channel = |c: &[u8]| f32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+    Ok(Tile {
+        width: w,
+        texels: bytes.chunks(12).map(|t| [channel(&t[0..4]), channel(&t[4..8]), channel(&t[8..12])]).collect(),
+    })
+}
+
+// A texture looked up through a (shared) cache
+#[derive(Clone)]
+pub struct ImageTexture {
+    pub cache: Arc<TextureCache>,
+    pub id: TextureId,
+    // The texture's dimensions, once the first lookup has decoded it, so
+    // that later lookups only need the cache for the texel's tile
+    dimensions: OnceLock<Option<(u32, u32)>>,
+}
+
+impl ImageTexture {
+    pub fn new(cache: &Arc<TextureCache>, path: &Path) -> ImageTexture {
+        ImageTexture {
+            cache: cache.clone(),
+            id: cache.load(path),
+            dimensions: OnceLock::new(),
+        }
+    }
+
+    // Register the image at `path`, once it's been checked that it can be
+    // read (from its header, without decoding it): otherwise, a missing or
+    // broken image is only noticed when it's first looked up
+    pub fn open(cache: &Arc<TextureCache>, path: &Path) -> Result<ImageTexture> {
+        image::image_dimensions(path)?;
+        Ok(ImageTexture::new(cache, path))
+    }
+
+    pub fn lookup(&self, u: Float, v: Float) -> Color {
+        let dimensions = *self.dimensions.get_or_init(|| self.cache.dimensions(self.id));
+        self.cache.lookup_in(self.id, dimensions, u, v)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.cache.path(self.id)
+    }
+}
+
+#[test]
+fn test_texture_cache_budget() {
+    use image::{Rgb, RgbImage};
+
+    // A 160 x 96 image (3 x 2 tiles) that is black, apart from a white
+    // top-right texel
+    let mut image = RgbImage::new(160, 96);
+    image.put_pixel(159, 0, Rgb([255, 255, 255]));
+    let path = ::std::env::temp_dir().join("tracer_test_texture_cache_budget.png");
+    image.save(&path).unwrap();
+
+    // Room for only one full tile
+    let cache = Arc::new(TextureCache::new((TILE_SIZE * TILE_SIZE) as usize * 12));
+    let texture = ImageTexture::new(&cache, &path);
+    assert_eq!(cache.load(&path), texture.id);
+    assert_eq!(cache.stats().decodes, 0);
+
+    assert_eq!(texture.lookup(0.999, 0.999), Color::white());
+    assert_eq!(texture.lookup(0.0, 0.0), Color::black());
+    assert_eq!(texture.lookup(1.25, 0.5), Color::black());
+    let stats = cache.stats();
+    assert!(stats.resident_bytes <= cache.budget);
+    assert!(stats.evictions > 0);
+    // Tiles that were evicted are read back, without decoding the image again
+    assert_eq!(texture.lookup(-0.001, -0.001), Color::white());
+    assert_eq!(cache.stats().decodes, 1);
+    assert!(cache.stats().reads > stats.reads);
+
+    let missing = ImageTexture::new(&cache, Path::new("no/such/texture.png"));
+    assert!(cache.failures().is_empty());
+    assert_eq!(missing.lookup(0.5, 0.5), MISSING);
+    let failures = cache.failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, Path::new("no/such/texture.png"));
+    assert!(ImageTexture::open(&cache, Path::new("no/such/texture.png")).is_err());
+    assert_eq!(ImageTexture::open(&cache, &path).unwrap().id, texture.id);
+}
+
+#[test]
+fn test_texture_decoded_once() {
+    use image::RgbImage;
+    use std::thread;
+
+    let path = ::std::env::temp_dir().join("tracer_test_texture_decoded_once.png");
+    RgbImage::new(256, 256).save(&path).unwrap();
+
+    // However many threads miss at the same time
+    let cache = Arc::new(TextureCache::new(0));
+    let texture = ImageTexture::new(&cache, &path);
+    thread::scope(|scope| {
+        for i in 0..8 {
+            let texture = texture.clone();
+            scope.spawn(move || {
+                assert_eq!(texture.lookup(i as Float / 8.0, 0.5), Color::black());
+            });
+        }
+    });
+    assert_eq!(cache.stats().decodes, 1);
+
+    let file = cache.tile_file(texture.id);
+    assert!(file.exists());
+    drop((texture, cache));
+    assert!(!file.exists());
+}
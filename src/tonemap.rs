@@ -0,0 +1,113 @@
+use vector::Float;
+use color::Color;
+use color::linear_to_srgb;
+use color::srgb_to_linear;
+
+use serde::{Deserialize, Serialize};
+
+// Tone mapping operators compress the unbounded range of radiance values into
+// the [0, 1] range of a display
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapOperator {
+    // Clip everything above 1.0
+    Linear,
+    // L / (1 + L), applied to luminance so that hues are preserved
+    Reinhard,
+    // A fit of the ACES filmic curve (see: "ACES Filmic Tone Mapping Curve",
+    // Narkowicz)
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub fn apply(&self, c: &Color) -> Color {
+        match *self {
+            ToneMapOperator::Linear => *c,
+            ToneMapOperator::Reinhard => {
+                let l = c.luminance();
+                if l <= 0.0 {
+                    return Color::black();
+                }
+                *c * (1.0 / (1.0 + l))
+            }
+            ToneMapOperator::Aces => c.map(aces),
+        }
+    }
+}
+
+fn aces(x: Float) -> Float {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    let x = x.max(0.0);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+// Transfer functions encode linear values for storage in a display image
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferFunction {
+    // Store values as they are, e.g. for data passes such as normals
+    Linear,
+    // A pure power curve, i.e. v^(1 / gamma)
+    Gamma(Float),
+    // The piecewise sRGB curve (a linear segment near black followed by a
+    // power curve)
+    Srgb,
+}
+
+impl TransferFunction {
+    pub fn encode(&self, v: Float) -> Float {
+        match *self {
+            TransferFunction::Linear => v,
+            TransferFunction::Gamma(gamma) => v.powf(1.0 / gamma),
+            TransferFunction::Srgb => linear_to_srgb(v),
+        }
+    }
+
+    pub fn decode(&self, v: Float) -> Float {
+        match *self {
+            TransferFunction::Linear => v,
+            TransferFunction::Gamma(gamma) => v.powf(gamma),
+            TransferFunction::Srgb => srgb_to_linear(v),
+        }
+    }
+}
+
+// Everything needed to convert linear radiance into displayable values
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DisplayTransform {
+    // Exposure adjustment, in stops (each stop doubles the brightness)
+    pub exposure: Float,
+    pub operator: ToneMapOperator,
+    pub transfer: TransferFunction,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure: Float, operator: ToneMapOperator, transfer: TransferFunction) -> DisplayTransform {
+        DisplayTransform {
+            exposure,
+            operator,
+            transfer,
+        }
+    }
+
+    // Convert with no tone mapping or encoding at all, for data passes
+    pub fn raw() -> DisplayTransform {
+        DisplayTransform::new(0.0, ToneMapOperator::Linear, TransferFunction::Linear)
+    }
+
+    // Scale radiance by the exposure (which also applies to HDR outputs)
+    pub fn expose(&self, c: &Color) -> Color {
+        *c * Float::powf(2.0, self.exposure)
+    }
+
+    // Map radiance to display values in [0, 1]
+    pub fn apply(&self, c: &Color) -> Color {
+        self.operator.apply(&self.expose(c)).clamp(0.0, 1.0).map(|v| self.transfer.encode(v))
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform::new(0.0, ToneMapOperator::Linear, TransferFunction::Srgb)
+    }
+}
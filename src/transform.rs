@@ -0,0 +1,114 @@
+use vector::Vector;
+use vector::consts;
+#[cfg(test)]
+use vector::TEST_EPSILON;
+
+use serde::{Deserialize, Serialize};
+
+
+// Places a shape in the scene without rebuilding it: the shape is scaled and
+// then rotated about the pivot, and finally translated
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    translation: Vector,
+    // The rotation (Euler angles, in degrees, applied about the x axis, then
+    // the y axis, then the z axis), as the rows of a matrix
+    rotation: [Vector; 3],
+    scale: Vector,
+    pivot: Vector,
+}
+
+impl Transform {
+    pub fn new(translation: &Vector, rotation: &Vector, scale: &Vector, pivot: &Vector) -> Transform {
+        let to_radians = consts::PI / 180.0;
+        let (sx, cx) = (rotation.x * to_radians).sin_cos();
+        let (sy, cy) = (rotation.y * to_radians).sin_cos();
+        let (sz, cz) = (rotation.z * to_radians).sin_cos();
+        Transform {
+            translation: *translation,
+            // Rz * Ry * Rx
+            rotation: [Vector::new(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+                       Vector::new(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+                       Vector::new(-sy, cy * sx, cy * cx)],
+            scale: *scale,
+            pivot: *pivot,
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(&Vector::zero(), &Vector::zero(), &Vector::one(), &Vector::zero())
+    }
+
+    pub fn translate(t: &Vector) -> Transform {
+        Transform::new(t, &Vector::zero(), &Vector::one(), &Vector::zero())
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::identity()
+    }
+
+    // Is this only a translation (which rays can be moved by cheaply)?
+    pub fn is_translation(&self) -> bool {
+        Transform {
+                translation: Vector::zero(),
+                ..*self
+            }
+            .is_identity()
+    }
+
+    pub fn translation(&self) -> Vector {
+        self.translation
+    }
+
+    pub fn scale(&self) -> Vector {
+        self.scale
+    }
+
+    pub fn point_to_world(&self, p: &Vector) -> Vector {
+        self.pivot + self.translation + self.rotate(&((*p - self.pivot) * self.scale))
+    }
+
+    pub fn point_to_local(&self, p: &Vector) -> Vector {
+        self.rotate_inverse(&(*p - self.translation - self.pivot)) / self.scale + self.pivot
+    }
+
+    pub fn vector_to_local(&self, v: &Vector) -> Vector {
+        self.rotate_inverse(v) / self.scale
+    }
+
+    // Normals are transformed by the inverse transpose (which for a rotation
+    // is the rotation itself, and for a scale is its reciprocal)
+    pub fn normal_to_world(&self, n: &Vector) -> Vector {
+        self.rotate(&(*n / self.scale)).normalize()
+    }
+
+    fn rotate(&self, v: &Vector) -> Vector {
+        Vector::new(self.rotation[0].dot(v), self.rotation[1].dot(v), self.rotation[2].dot(v))
+    }
+
+    // (The inverse of a rotation matrix is its transpose)
+    fn rotate_inverse(&self, v: &Vector) -> Vector {
+        self.rotation[0] * v.x + self.rotation[1] * v.y + self.rotation[2] * v.z
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+#[test]
+fn test_transform_round_trip() {
+    let transform = Transform::new(&Vector::new(1.0, -2.0, 0.5),
+                                   &Vector::new(30.0, 45.0, -60.0),
+                                   &Vector::new(2.0, 0.5, 1.5),
+                                   &Vector::new(0.0, 0.0, -1.0));
+    let p = Vector::new(0.3, 0.7, -1.2);
+    assert!((transform.point_to_local(&transform.point_to_world(&p)) - p).length() < TEST_EPSILON);
+
+    let quarter_turn = Transform::new(&Vector::zero(), &Vector::new(0.0, 0.0, 90.0), &Vector::one(), &Vector::zero());
+    assert!((quarter_turn.point_to_world(&Vector::new(1.0, 0.0, 0.0)) - Vector::new(0.0, 1.0, 0.0)).length() < TEST_EPSILON);
+    assert!(Transform::default().is_identity());
+    assert!(Transform::translate(&Vector::one()).is_translation());
+}
@@ -1,25 +1,138 @@
-extern crate rand;
+use serde::{Deserialize, Serialize};
+
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg, Index, IndexMut};
+
+// A SIMD register of four `Float`s (see: `componentwise!`, and `SphereList`,
+// which intersects four spheres at once)
+#[cfg(all(feature = "simd", not(feature = "f32")))]
+pub use wide::f64x4 as Lanes;
+#[cfg(all(feature = "simd", feature = "f32"))]
+pub use wide::f32x4 as Lanes;
+
+// The precision of the renderer's arithmetic: with the `f32` feature, single
+// precision halves the memory taken by the scene and the film (and doubles
+// the width of SIMD operations), at the cost of some accuracy
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+
+// How closely tests expect values that differ only by rounding to agree
+#[cfg(all(test, not(feature = "f32")))]
+pub const TEST_EPSILON: Float = 1e-9;
+#[cfg(all(test, feature = "f32"))]
+pub const TEST_EPSILON: Float = 1e-4;
+
+// Apply an arithmetic operator to each component of a vector, with the other
+// operand being either another vector or a scalar: with the `simd` feature,
+// all three components are computed at once in the lanes of a SIMD register
+// (the fourth lane is unused)
+#[cfg(feature = "simd")]
+macro_rules! componentwise {
+    ($a:expr, $op:tt, vector $b:expr) => { Vector::from_lanes($a.lanes() $op $b.lanes()) };
+    ($a:expr, $op:tt, scalar $b:expr) => { Vector::from_lanes($a.lanes() $op Lanes::splat($b)) };
+}
 
-use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
-use rand::Rng;
+#[cfg(not(feature = "simd"))]
+macro_rules! componentwise {
+    ($a:expr, $op:tt, vector $b:expr) => { Vector::new($a.x $op $b.x, $a.y $op $b.y, $a.z $op $b.z) };
+    ($a:expr, $op:tt, scalar $b:expr) => { Vector::new($a.x $op $b, $a.y $op $b, $a.z $op $b) };
+}
 
-#[derive(Copy, Clone, Debug)]
+// (Saved as an array of its three components)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[Float; 3]", into = "[Float; 3]")]
 pub struct Vector {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl From<[Float; 3]> for Vector {
+    fn from(v: [Float; 3]) -> Vector {
+        Vector::new(v[0], v[1], v[2])
+    }
+}
+
+impl From<Vector> for [Float; 3] {
+    fn from(v: Vector) -> [Float; 3] {
+        [v.x, v.y, v.z]
+    }
+}
+
+// The types of other crates' vectors are converted to and from whatever the
+// precision of `Float` (rather than only the types of the same precision)
+#[cfg(feature = "glam")]
+impl From<glam::Vec3> for Vector {
+    fn from(v: glam::Vec3) -> Vector {
+        Vector::new(v.x as Float, v.y as Float, v.z as Float)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<Vector> for glam::Vec3 {
+    fn from(v: Vector) -> glam::Vec3 {
+        glam::Vec3::new(v.x as f32, v.y as f32, v.z as f32)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<glam::DVec3> for Vector {
+    fn from(v: glam::DVec3) -> Vector {
+        Vector::new(v.x as Float, v.y as Float, v.z as Float)
+    }
+}
+
+#[cfg(feature = "glam")]
+impl From<Vector> for glam::DVec3 {
+    fn from(v: Vector) -> glam::DVec3 {
+        glam::DVec3::new(v.x as f64, v.y as f64, v.z as f64)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Vector3<Float>> for Vector {
+    fn from(v: nalgebra::Vector3<Float>) -> Vector {
+        Vector::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Vector> for nalgebra::Vector3<Float> {
+    fn from(v: Vector) -> nalgebra::Vector3<Float> {
+        nalgebra::Vector3::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Point3<Float>> for Vector {
+    fn from(p: nalgebra::Point3<Float>) -> Vector {
+        Vector::new(p.x, p.y, p.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Vector> for nalgebra::Point3<Float> {
+    fn from(v: Vector) -> nalgebra::Point3<Float> {
+        nalgebra::Point3::new(v.x, v.y, v.z)
+    }
 }
 
 impl Vector {
-    pub fn new(x: f64, y: f64, z: f64) -> Vector {
-        Vector { x: x, y: y, z: z }
+    pub fn new(x: Float, y: Float, z: Float) -> Vector {
+        Vector { x, y, z }
     }
 
-    pub fn squared_length(&self) -> f64 {
+    pub fn squared_length(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Float {
         self.squared_length().sqrt()
     }
 
@@ -27,23 +140,52 @@ impl Vector {
         *self / self.length()
     }
 
-    pub fn min_component(&self) -> f64 {
+    pub fn min_component(&self) -> Float {
         self.x.min(self.y).min(self.z)
     }
 
-    pub fn max_component(&self) -> f64 {
+    pub fn max_component(&self) -> Float {
         self.x.max(self.y).max(self.z)
     }
 
-    pub fn powf(&self, exp: f64) -> Vector {
+    // Are all of the components finite (neither infinite nor NaN)?
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
+    // The componentwise minimum and maximum of two vectors
+    pub fn min(&self, rhs: &Vector) -> Vector {
+        Vector::new(self.x.min(rhs.x), self.y.min(rhs.y), self.z.min(rhs.z))
+    }
+
+    pub fn max(&self, rhs: &Vector) -> Vector {
+        Vector::new(self.x.max(rhs.x), self.y.max(rhs.y), self.z.max(rhs.z))
+    }
+
+    // The x, y, or z component (for axis 0, 1, or 2)
+    pub fn component(&self, axis: usize) -> Float {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn powf(&self, exp: Float) -> Vector {
         Vector::new(self.x.powf(exp), self.y.powf(exp), self.z.powf(exp))
     }
 
-    pub fn dot(&self, rhs: &Vector) -> f64 {
+    #[cfg(not(feature = "simd"))]
+    pub fn dot(&self, rhs: &Vector) -> Float {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
-    pub fn abs_dot(&self, rhs: &Vector) -> f64 {
+    #[cfg(feature = "simd")]
+    pub fn dot(&self, rhs: &Vector) -> Float {
+        (self.lanes() * rhs.lanes()).reduce_add()
+    }
+
+    pub fn abs_dot(&self, rhs: &Vector) -> Float {
         self.dot(rhs).abs()
     }
 
@@ -64,25 +206,10 @@ impl Vector {
         // TODO
     }
 
-    pub fn lerp(&self, rhs: &Vector, t: f64) -> Vector {
+    pub fn lerp(&self, rhs: &Vector, t: Float) -> Vector {
         *self * (1.0 - t) + *rhs * t
     }
 
-    pub fn random_in_unit_sphere() -> Vector {
-        // Rejection method for finding a random point in a
-        // unit sphere: pick a point inside of the unit cube
-        // and return if it is also inside of the unit sphere
-        let mut rng = rand::thread_rng();
-        let mut p = Vector::origin();
-        loop {
-            p = Vector::new(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 2.0 - Vector::one();
-            if p.squared_length() <= 1.0 {
-                break;
-            }
-        }
-        p
-    }
-
     pub fn origin() -> Vector {
         Vector::new(0.0, 0.0, 0.0)
     }
@@ -94,6 +221,19 @@ impl Vector {
     pub fn one() -> Vector {
         Vector::new(1.0, 1.0, 1.0)
     }
+
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn lanes(&self) -> Lanes {
+        Lanes::new([self.x, self.y, self.z, 0.0])
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn from_lanes(lanes: Lanes) -> Vector {
+        let [x, y, z, _] = lanes.to_array();
+        Vector::new(x, y, z)
+    }
 }
 
 // Vector + Vector
@@ -101,33 +241,23 @@ impl Add for Vector {
     type Output = Vector;
 
     fn add(self, other: Vector) -> Vector {
-        Vector {
-            x: self.x + other.x,
-            y: self.y + other.y,
-            z: self.z + other.z,
-        }
+        componentwise!(self, +, vector other)
     }
 }
 
-// Vector + f64
-impl Add<f64> for Vector {
+// Vector + Float
+impl Add<Float> for Vector {
     type Output = Vector;
 
-    fn add(self, other: f64) -> Vector {
-        Vector {
-            x: self.x + other,
-            y: self.y + other,
-            z: self.z + other,
-        }
+    fn add(self, other: Float) -> Vector {
+        componentwise!(self, +, scalar other)
     }
 }
 
 // Vector += Vector
 impl AddAssign for Vector {
     fn add_assign(&mut self, other: Vector) {
-        self.x += other.x;
-        self.y += other.y;
-        self.z += other.z;
+        *self = componentwise!(*self, +, vector other);
     }
 }
 
@@ -136,33 +266,23 @@ impl Sub for Vector {
     type Output = Vector;
 
     fn sub(self, other: Vector) -> Vector {
-        Vector {
-            x: self.x - other.x,
-            y: self.y - other.y,
-            z: self.z - other.z,
-        }
+        componentwise!(self, -, vector other)
     }
 }
 
-// Vector - f64
-impl Sub<f64> for Vector {
+// Vector - Float
+impl Sub<Float> for Vector {
     type Output = Vector;
 
-    fn sub(self, other: f64) -> Vector {
-        Vector {
-            x: self.x - other,
-            y: self.y - other,
-            z: self.z - other,
-        }
+    fn sub(self, other: Float) -> Vector {
+        componentwise!(self, -, scalar other)
     }
 }
 
 // Vector -= Vector
 impl SubAssign for Vector {
     fn sub_assign(&mut self, other: Vector) {
-        self.x -= other.x;
-        self.y -= other.y;
-        self.z -= other.z;
+        *self = componentwise!(*self, -, vector other);
     }
 }
 
@@ -171,33 +291,23 @@ impl Mul for Vector {
     type Output = Vector;
 
     fn mul(self, other: Vector) -> Vector {
-        Vector {
-            x: self.x * other.x,
-            y: self.y * other.y,
-            z: self.z * other.z,
-        }
+        componentwise!(self, *, vector other)
     }
 }
 
-// Vector * f64
-impl Mul<f64> for Vector {
+// Vector * Float
+impl Mul<Float> for Vector {
     type Output = Vector;
 
-    fn mul(self, other: f64) -> Vector {
-        Vector {
-            x: self.x * other,
-            y: self.y * other,
-            z: self.z * other,
-        }
+    fn mul(self, other: Float) -> Vector {
+        componentwise!(self, *, scalar other)
     }
 }
 
-// Vector *= f64
-impl MulAssign<f64> for Vector {
-    fn mul_assign(&mut self, other: f64) {
-        self.x *= other;
-        self.y *= other;
-        self.z *= other;
+// Vector *= Float
+impl MulAssign<Float> for Vector {
+    fn mul_assign(&mut self, other: Float) {
+        *self = componentwise!(*self, *, scalar other);
     }
 }
 
@@ -206,33 +316,23 @@ impl Div for Vector {
     type Output = Vector;
 
     fn div(self, other: Vector) -> Vector {
-        Vector {
-            x: self.x / other.x,
-            y: self.y / other.y,
-            z: self.z / other.z,
-        }
+        componentwise!(self, /, vector other)
     }
 }
 
-// Vector / f64
-impl Div<f64> for Vector {
+// Vector / Float
+impl Div<Float> for Vector {
     type Output = Vector;
 
-    fn div(self, other: f64) -> Vector {
-        Vector {
-            x: self.x / other,
-            y: self.y / other,
-            z: self.z / other,
-        }
+    fn div(self, other: Float) -> Vector {
+        componentwise!(self, /, scalar other)
     }
 }
 
-// Vector /= f64
-impl DivAssign<f64> for Vector {
-    fn div_assign(&mut self, other: f64) {
-        self.x /= other;
-        self.y /= other;
-        self.z /= other;
+// Vector /= Float
+impl DivAssign<Float> for Vector {
+    fn div_assign(&mut self, other: Float) {
+        *self = componentwise!(*self, /, scalar other);
     }
 }
 
@@ -249,6 +349,31 @@ impl Neg for Vector {
     }
 }
 
+// The x, y, or z component, by index (0, 1, or 2)
+impl Index<usize> for Vector {
+    type Output = Float;
+
+    fn index(&self, axis: usize) -> &Float {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("a vector has no component {}", axis),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vector {
+    fn index_mut(&mut self, axis: usize) -> &mut Float {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("a vector has no component {}", axis),
+        }
+    }
+}
+
 #[test]
 fn test_add() {
     let lhs = Vector {
@@ -268,3 +393,24 @@ fn test_add() {
                    z: 5.0,
                });
 }
+
+#[test]
+fn test_index_and_conversions() {
+    let mut v = Vector::new(1.0, 2.0, 3.0);
+    assert_eq!((v[0], v[1], v[2]), (1.0, 2.0, 3.0));
+    v[1] = 5.0;
+    assert_eq!(v, Vector::new(1.0, 5.0, 3.0));
+    assert_eq!(Vector::from([1.0, 5.0, 3.0]), v);
+    assert_eq!(<[Float; 3]>::from(v), [1.0, 5.0, 3.0]);
+
+    #[cfg(feature = "glam")]
+    {
+        assert_eq!(glam::DVec3::from(v), glam::DVec3::new(1.0, 5.0, 3.0));
+        assert_eq!(Vector::from(glam::Vec3::new(1.0, 5.0, 3.0)), v);
+    }
+    #[cfg(feature = "nalgebra")]
+    {
+        assert_eq!(Vector::from(nalgebra::Vector3::<Float>::from(v)), v);
+        assert_eq!(nalgebra::Point3::<Float>::from(v), nalgebra::Point3::new(1.0, 5.0, 3.0));
+    }
+}
@@ -0,0 +1,118 @@
+use vector::Vector;
+use vector::Float;
+use color::Color;
+use shape::Sphere;
+use shape::Plane;
+use material::Lambertian;
+use material::Metallic;
+use primitive::Primitive;
+use scene::Scene;
+use camera::Camera;
+use film::Film;
+use renderer::Renderer;
+use tonemap::DisplayTransform;
+
+use wasm_bindgen::prelude::*;
+
+use std::sync::Arc;
+
+// With the `wasm-threads` feature, the page must start rayon's threads (as Web
+// Workers) before rendering, by awaiting `initThreadPool(navigator.hardwareConcurrency)`
+#[cfg(feature = "wasm-threads")]
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+// The scene rendered by the demo page (see: `web/index.html`), which matches
+// the one rendered by `main`
+fn demo_scene() -> Scene {
+    let mut scene = Scene::new();
+    let red = Arc::new(Lambertian::new(&Color::new(1.0, 0.0, 0.0)));
+    let green = Arc::new(Lambertian::new(&Color::new(0.0, 1.0, 0.0)));
+    let white = Arc::new(Lambertian::new(&Color::white()));
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, -0.6, 0.0), &Vector::new(0.0, 1.0, 0.0)), white.clone()));
+    scene.add(Primitive::new(Plane::new(&Vector::new(1.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0)), red));
+    scene.add(Primitive::new(Plane::new(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(-1.0, 0.0, 0.0)), green));
+    scene.add(Primitive::new(Plane::new(&Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 0.0, -1.0)), white));
+    for i in 0..7 {
+        let pct = i as Float / 7.0;
+        let x = pct * 2.0 - 1.0;
+        scene.add(Primitive::new(Sphere::new(&Vector::new(x + 0.05, 0.0, -1.0), (pct * 0.5 + 0.1) * 0.25),
+                                 Arc::new(Metallic::new(&Color::white(), x))));
+    }
+    scene.build_bvh();
+    scene
+}
+
+// Renders the demo scene progressively from JavaScript: since the page
+// can't be blocked for long, it calls `render_pass` once per animation frame
+// and draws `pixels` (RGBA, for an `ImageData`) after each pass
+#[wasm_bindgen]
+pub struct WebRenderer {
+    renderer: Renderer,
+    scene: Scene,
+    camera: Camera,
+    film: Film,
+    display: DisplayTransform,
+    seed: u32,
+    passes: u32,
+}
+
+#[wasm_bindgen]
+impl WebRenderer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, seed: u32) -> WebRenderer {
+        let mut renderer = Renderer::new();
+        // Every pass takes one more sample, so never stop early
+        renderer.min_samples = u32::MAX;
+        WebRenderer {
+            renderer,
+            scene: demo_scene(),
+            camera: Camera::new(60.0, width as Float / height.max(1) as Float),
+            film: Film::new(width, height),
+            display: DisplayTransform::default(),
+            seed,
+            passes: 0,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.film.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.film.height
+    }
+
+    // Take one more sample per pixel, returning the number of passes so far
+    pub fn render_pass(&mut self) -> u32 {
+        let bounds = self.renderer.bounds(self.film.width, self.film.height);
+        self.renderer.render_pass(&mut self.film, bounds, &self.camera, &self.scene, self.seed, &|_| {});
+        self.passes += 1;
+        self.passes
+    }
+
+    // The image so far, as 8-bit RGBA
+    pub fn pixels(&self) -> Vec<u8> {
+        to_rgba8(&self.film, &self.display)
+    }
+}
+
+// Render the demo scene with the given number of samples per pixel in one
+// call, returning 8-bit RGBA pixels
+#[wasm_bindgen]
+pub fn render(width: u32, height: u32, samples: u32, seed: u32) -> Vec<u8> {
+    let mut renderer = WebRenderer::new(width, height, seed);
+    for _ in 0..samples {
+        renderer.render_pass();
+    }
+    renderer.pixels()
+}
+
+fn to_rgba8(film: &Film, display: &DisplayTransform) -> Vec<u8> {
+    let rgb = film.to_framebuffer().to_rgb8(display);
+    let mut rgba = Vec::with_capacity(rgb.len() / 3 * 4);
+    for texel in rgb.chunks(3) {
+        rgba.extend_from_slice(texel);
+        rgba.push(255);
+    }
+    rgba
+}
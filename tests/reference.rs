@@ -0,0 +1,90 @@
+// Renders each of the built-in scenes (see: `scenes`) small, at a fixed seed,
+// and compares it with its reference image in `tests/references` (or in
+// `tests/references/f32`, when built with the `f32` feature), so that changes
+// to the integrator or the materials can't change renders unnoticed: after a
+// change that's meant to change them, look over the new renders and then
+// replace the references with them by running
+//
+//     UPDATE_REFERENCES=1 cargo test --test reference
+//     UPDATE_REFERENCES=1 cargo test --features f32 --test reference
+
+extern crate image;
+extern crate raytracer;
+
+use raytracer::vector::Float;
+use raytracer::film::Film;
+use raytracer::renderer::Renderer;
+use raytracer::filter::FilterType;
+use raytracer::tonemap::DisplayTransform;
+use raytracer::scenes;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 32;
+const SAMPLES: u32 = 16;
+const SEED: u32 = 0;
+
+// The root-mean-square difference allowed between a render and its
+// reference, in 8-bit levels: renders at the same seed and precision are the
+// same on any number of threads, so this only allows for the rounding of
+// libm's functions, which may differ between platforms. Renders in the other
+// precision aren't comparable at all (they differ by up to ~50 levels, as the
+// paths' random decisions diverge, like renders at another seed), which is
+// why each precision has its own references
+const TOLERANCE: f64 = 1.0;
+
+fn render(name: &str) -> Vec<u8> {
+    let mut scene = scenes::named(name, WIDTH as Float / HEIGHT as Float).unwrap();
+    let camera = *scene.camera("main").unwrap();
+    let mut renderer = Renderer::new();
+    renderer.samples = SAMPLES;
+    renderer.min_samples = SAMPLES;
+    renderer.filter = Arc::from(FilterType::Box.create());
+    let mut film = Film::new(WIDTH, HEIGHT);
+    scene.select_lods(&camera, HEIGHT);
+    renderer.render(&mut film, (0, 0, WIDTH, HEIGHT), &camera, &scene, SEED, &|_: &_| {});
+    film.to_framebuffer().to_rgb8(&DisplayTransform::default())
+}
+
+fn rms_difference(a: &[u8], b: &[u8]) -> f64 {
+    let sum: f64 = a.iter().zip(b).map(|(&a, &b)| (a as f64 - b as f64).powi(2)).sum();
+    (sum / a.len() as f64).sqrt()
+}
+
+#[test]
+fn test_reference_images() {
+    let mut directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("references");
+    if cfg!(feature = "f32") {
+        directory.push("f32");
+    }
+    let update = env::var_os("UPDATE_REFERENCES").is_some();
+    let mut failures = Vec::new();
+    for name in &scenes::NAMES {
+        let rgb = render(name);
+        let path = directory.join(format!("{}.png", name));
+        if update {
+            fs::create_dir_all(&directory).unwrap();
+            image::save_buffer(&path, &rgb, WIDTH, HEIGHT, image::ExtendedColorType::Rgb8).unwrap();
+            continue;
+        }
+        let reference = image::open(&path)
+            .unwrap_or_else(|why| panic!("couldn't open {} ({})", path.display(), why))
+            .to_rgb8();
+        assert_eq!(reference.dimensions(), (WIDTH, HEIGHT), "{}", name);
+        let difference = rms_difference(&rgb, reference.as_raw());
+        if difference > TOLERANCE {
+            // (Kept next to the reference, to compare them)
+            let actual = directory.join(format!("{}.actual.png", name));
+            image::save_buffer(&actual, &rgb, WIDTH, HEIGHT, image::ExtendedColorType::Rgb8).unwrap();
+            failures.push(format!("{} differs from its reference by {:.2} levels (see: {})",
+                                  name,
+                                  difference,
+                                  actual.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}